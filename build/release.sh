@@ -4,3 +4,4 @@ codesign --force --entitlement resources/vz.entitlements --sign - target/release
 
 sudo cp ./target/release/vz /usr/local/bin
 vz generate-zsh-completion | sudo tee /usr/local/share/zsh/site-functions/_vz
+sudo vz generate-man-page /usr/local/share/man/man1
@@ -0,0 +1,188 @@
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::time::Duration;
+
+use libc::pid_t;
+
+use crate::config::vm_config::VmConfig;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::dhcp_lease;
+use crate::util::exception::Exception;
+use crate::vm::control;
+use crate::vm::control::Request;
+use crate::vm::control::Response;
+use crate::vm::runner;
+
+// vm in ~/.vm, e.g. Vm::open("debian")?.start(&StartOptions::default())?
+pub struct Vm {
+    dir: VmDir,
+}
+
+// running vm, each one has its own runner process holding lock of vm dir
+pub struct VmHandle {
+    dir: VmDir,
+    pid: pid_t,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VmState {
+    Stopped,
+    // saved by vz suspend, next start resumes it
+    Suspended,
+    Running,
+    // runner is alive but guest stopped responding, and runner is about to restart or stop it
+    Unresponsive,
+}
+
+// vm runs in runner process of vz binary, as Virtualization.framework needs main run loop for each vm
+#[derive(Debug, Clone, Default)]
+pub struct StartOptions {
+    // default to vz in PATH
+    pub executable: Option<PathBuf>,
+    // override config for this run only, memory in bytes
+    pub cpu: Option<usize>,
+    pub memory: Option<u64>,
+    pub no_network: bool,
+}
+
+impl Vm {
+    pub fn open(name: &str) -> Result<Vm, Exception> {
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        Ok(Vm { dir })
+    }
+
+    // sorted by name
+    pub fn list() -> Result<Vec<Vm>, Exception> {
+        let mut dirs = vm_dir::vm_dirs()?;
+        dirs.sort_by_key(|dir| dir.name());
+        Ok(dirs.into_iter().map(|dir| Vm { dir }).collect())
+    }
+
+    pub fn name(&self) -> String {
+        self.dir.name()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.dir.dir
+    }
+
+    pub fn config(&self) -> Result<VmConfig, Exception> {
+        self.dir.load_config()
+    }
+
+    // config of running vm takes effect on next start
+    pub fn save_config(&self, config: &VmConfig) -> Result<(), Exception> {
+        config.validate_paths()?;
+        config.validate_host_limits()?;
+        self.dir.save_config(config)
+    }
+
+    pub fn state(&self) -> VmState {
+        match self.dir.pid() {
+            None if self.dir.state_path.exists() => VmState::Suspended,
+            None => VmState::Stopped,
+            Some(_) if self.dir.unresponsive_path.exists() => VmState::Unresponsive,
+            Some(_) => VmState::Running,
+        }
+    }
+
+    // current lease of NAT network, or last ip seen by runner, e.g. of stopped vm
+    pub fn ip(&self) -> Result<Option<String>, Exception> {
+        let config = self.config()?;
+        match dhcp_lease::find_ip(&config.mac_address)? {
+            Some(ip) => Ok(Some(ip)),
+            None => Ok(self.dir.last_ip()),
+        }
+    }
+
+    // none if vm is not running
+    pub fn handle(&self) -> Option<VmHandle> {
+        self.dir.pid().map(|pid| VmHandle {
+            dir: vm_dir::vm_dir(&self.name()),
+            pid,
+        })
+    }
+
+    // returns once runner holds vm, guest may still be booting
+    pub fn start(&self, options: &StartOptions) -> Result<VmHandle, Exception> {
+        let name = self.name();
+        if let Some(pid) = self.dir.pid() {
+            return Err(Exception::ValidationError(format!("vm is already running, name={name}, pid={pid}")));
+        }
+        let mut args = vec!["run".to_string(), name.clone(), "--detach".to_string()];
+        if let Some(cpu) = options.cpu {
+            args.extend(["--cpu".to_string(), cpu.to_string()]);
+        }
+        if let Some(memory) = options.memory {
+            args.extend(["--memory".to_string(), format!("{}M", memory / 1024 / 1024)]);
+        }
+        if options.no_network {
+            args.push("--no-network".to_string());
+        }
+        let executable = options.executable.clone().unwrap_or_else(|| PathBuf::from("vz"));
+        // vz run --detach validates config and limits, launches runner and exits, so runner is not child of embedding process
+        let output = Command::new(&executable).args(&args).stdin(Stdio::null()).output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let stderr = stderr.trim();
+            return Err(Exception::ValidationError(format!(
+                "failed to start vm, name={name}, error={}",
+                stderr.strip_prefix("Error: ").unwrap_or(stderr)
+            )));
+        }
+        let pid = runner::wait_until_started(&self.dir, Duration::from_secs(5))
+            .ok_or_else(|| Exception::ValidationError(format!("runner did not start, name={name}, log={}", self.dir.log_path.to_string_lossy())))?;
+        Ok(VmHandle {
+            dir: vm_dir::vm_dir(&name),
+            pid,
+        })
+    }
+}
+
+impl VmHandle {
+    // pid of runner
+    pub fn pid(&self) -> pid_t {
+        self.pid
+    }
+
+    // none if runner doesn't respond in time
+    pub fn status(&self) -> Option<Response> {
+        control::status(&self.dir)
+    }
+
+    pub fn pause(&self) -> Result<(), Exception> {
+        control::send(&self.dir, &Request::Pause).map(|_| ())
+    }
+
+    pub fn resume(&self) -> Result<(), Exception> {
+        control::send(&self.dir, &Request::Resume).map(|_| ())
+    }
+
+    // guest is asked to shut down, and force stopped after timeout
+    pub fn stop(&self, timeout: Duration) -> Result<(), Exception> {
+        runner::request_stop(&self.dir, false, timeout);
+        self.wait_until_stopped(timeout.as_secs() as u32 + runner::FORCE_STOP_TIMEOUT)
+    }
+
+    pub fn force_stop(&self) -> Result<(), Exception> {
+        runner::request_stop(&self.dir, true, Duration::ZERO);
+        self.wait_until_stopped(runner::FORCE_STOP_TIMEOUT)
+    }
+
+    fn wait_until_stopped(&self, seconds: u32) -> Result<(), Exception> {
+        if !runner::wait_until_stopped(&self.dir, seconds) {
+            return Err(Exception::ValidationError(format!(
+                "failed to stop vm, name={}, pid={}",
+                self.dir.name(),
+                self.pid
+            )));
+        }
+        Ok(())
+    }
+}
@@ -1,8 +1,52 @@
+pub mod autostart;
+pub mod balloon;
+pub mod bench;
+pub mod build;
+pub mod clipboard_agent;
+pub mod clone;
+pub mod completion;
+pub mod console;
+pub mod cp;
 pub mod create;
+pub mod daemon;
+pub mod delete;
+pub mod disk;
+pub mod display;
+pub mod doctor;
+pub mod edit;
+pub mod exec;
+pub mod exec_agent;
+pub mod export;
+pub mod gc;
+pub mod generate_man_page;
 pub mod generate_zsh_completion;
+pub mod host;
+pub mod hosts;
+pub mod import;
 pub mod install;
+pub mod ip;
 pub mod ipsw;
 pub mod list;
+pub mod logs;
+pub mod mount;
+pub mod net;
+pub mod pause;
+pub mod pull;
 pub mod resize;
+pub mod rosetta;
 pub mod run;
+pub mod selftest;
+pub mod set;
+pub mod share;
+pub mod shell;
+pub mod snapshot;
+pub mod ssh;
+pub mod stats;
+pub mod status;
 pub mod stop;
+pub mod suspend;
+pub mod usb;
+pub mod verify;
+pub mod vsock;
+pub mod wait;
+pub mod web;
@@ -0,0 +1,159 @@
+use std::env::current_exe;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+use clap::Args;
+use clap::Subcommand;
+use tracing::info;
+
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+use crate::util::path::PathExtension;
+
+#[derive(Args)]
+pub struct Autostart {
+    #[command(subcommand)]
+    command: AutostartCommand,
+}
+
+#[derive(Subcommand)]
+enum AutostartCommand {
+    #[command(about = "start vm in background at login by launchd, and start it now if stopped")]
+    Enable {
+        #[arg(help = "vm name")]
+        name: String,
+    },
+    #[command(about = "stop starting vm at login, running vm is kept")]
+    Disable {
+        #[arg(help = "vm name")]
+        name: String,
+    },
+}
+
+impl Autostart {
+    pub fn execute(&self) -> Result<(), Exception> {
+        match &self.command {
+            AutostartCommand::Enable { name } => enable(&initialized_vm_dir(name)?),
+            AutostartCommand::Disable { name } => disable(&initialized_vm_dir(name)?),
+        }
+    }
+}
+
+pub fn enabled(name: &str) -> bool {
+    plist_path(name).exists()
+}
+
+fn initialized_vm_dir(name: &str) -> Result<VmDir, Exception> {
+    let dir = vm_dir::vm_dir(name);
+    if !dir.initialized() {
+        return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+    }
+    Ok(dir)
+}
+
+fn label(name: &str) -> String {
+    format!("vz.{name}")
+}
+
+fn plist_path(name: &str) -> PathBuf {
+    PathBuf::from("~/Library/LaunchAgents")
+        .to_absolute_path()
+        .join(format!("{}.plist", label(name)))
+}
+
+// launchd runs agents of gui domain at login of current user
+fn domain() -> String {
+    format!("gui/{}", unsafe { libc::getuid() })
+}
+
+// agent runs vz run -d and exits, runner is in its own session, so it outlives agent and disable doesn't stop vm
+fn enable(dir: &VmDir) -> Result<(), Exception> {
+    let name = dir.name();
+    let path = plist_path(&name);
+    if path.exists() {
+        // reload to pick up current binary path
+        launchctl(&["bootout", &format!("{}/{}", domain(), label(&name))])?;
+    }
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, plist(&label(&name), &current_exe()?, &name, &dir.log_path))?;
+    launchctl(&["bootstrap", &domain(), &path.to_string_lossy()])?;
+    info!("autostart enabled, name={name}, plist={}", path.to_string_lossy());
+    Ok(())
+}
+
+pub fn disable(dir: &VmDir) -> Result<(), Exception> {
+    let name = dir.name();
+    let path = plist_path(&name);
+    if !path.exists() {
+        return Err(Exception::ValidationError(format!("autostart is not enabled, name={name}")));
+    }
+    launchctl(&["bootout", &format!("{}/{}", domain(), label(&name))])?;
+    fs::remove_file(&path)?;
+    info!("autostart disabled, name={name}");
+    Ok(())
+}
+
+fn launchctl(args: &[&str]) -> Result<(), Exception> {
+    let output = Command::new("launchctl").args(args).output()?;
+    if !output.status.success() {
+        return Err(Exception::ValidationError(format!(
+            "failed to run launchctl, args={}, error={}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+fn plist(label: &str, exe: &Path, name: &str, log_path: &Path) -> String {
+    let log_path = escape(&log_path.to_string_lossy());
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{}</string>
+        <string>run</string>
+        <string>{}</string>
+        <string>--detach</string>
+    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>AbandonProcessGroup</key>
+    <true/>
+    <key>StandardOutPath</key>
+    <string>{log_path}</string>
+    <key>StandardErrorPath</key>
+    <string>{log_path}</string>
+</dict>
+</plist>
+"#,
+        escape(label),
+        escape(&exe.to_string_lossy()),
+        escape(name)
+    )
+}
+
+fn escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    #[test]
+    fn plist() {
+        let plist = super::plist("vz.a&b", Path::new("/opt/homebrew/bin/vz"), "a&b", Path::new("/Users/dev/.vm/a&b/vz.log"));
+        assert!(plist.contains("<string>vz.a&amp;b</string>"));
+        assert!(plist.contains("<string>/opt/homebrew/bin/vz</string>\n        <string>run</string>\n        <string>a&amp;b</string>"));
+        assert!(plist.contains("<key>StandardOutPath</key>\n    <string>/Users/dev/.vm/a&amp;b/vz.log</string>"));
+    }
+}
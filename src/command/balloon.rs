@@ -0,0 +1,45 @@
+use clap::Args;
+use tracing::info;
+
+use crate::command::set;
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+use crate::vm::control;
+use crate::vm::control::Request;
+
+#[derive(Args)]
+pub struct Balloon {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(long, help = "target memory of guest with unit, e.g. 4G or 512M, up to memory in config", value_parser = set::parse_memory)]
+    target: u64,
+}
+
+impl Balloon {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        if dir.pid().is_none() {
+            return Err(Exception::ValidationError(format!("vm is not running, name={name}")));
+        }
+        let config = dir.load_config()?;
+        // balloon can only take memory from guest, memory in config is upper bound
+        if self.target > config.memory {
+            return Err(Exception::ValidationError(format!(
+                "target exceeds memory of vm, change memory with vz set {name} --memory, target={}, memory={}",
+                set::format_memory(self.target),
+                set::format_memory(config.memory)
+            )));
+        }
+        control::send(&dir, &Request::Balloon { target: self.target })?;
+        info!(
+            "memory balloon target changed, name={name}, target={}, guest releases memory above target if balloon driver is loaded",
+            set::format_memory(self.target)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use std::time::Instant;
+
+use clap::Args;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::command::build;
+use crate::command::run;
+use crate::command::run::Overrides;
+use crate::command::ssh;
+use crate::config::vm_config::DiskCaching;
+use crate::config::vm_config::DiskSync;
+use crate::config::vm_config::Os;
+use crate::config::vm_config::Share;
+use crate::config::vm_config::VmConfig;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+use crate::util::json;
+
+const SHARE_NAME: &str = "vz-bench";
+
+// measured value by column key
+type Values = HashMap<String, String>;
+
+const MODES: [(DiskCaching, DiskSync); 5] = [
+    (DiskCaching::Automatic, DiskSync::Fsync),
+    (DiskCaching::Cached, DiskSync::Fsync),
+    (DiskCaching::Uncached, DiskSync::Fsync),
+    (DiskCaching::Automatic, DiskSync::Full),
+    (DiskCaching::Automatic, DiskSync::None),
+];
+
+// columns of result table, key printed by guest script or measured on host
+const COLUMNS: [(&str, &str); 7] = [
+    ("disk_write", "seq write MB/s"),
+    ("disk_read", "seq read MB/s"),
+    ("disk_random_read", "rand read iops"),
+    ("disk_random_write", "rand write iops"),
+    ("virtiofs_write", "virtiofs write MB/s"),
+    ("virtiofs_read", "virtiofs read MB/s"),
+    ("network", "network MB/s"),
+];
+
+// prints "<key> <value>" lines, random io requires fio, dropping page cache and mounting virtiofs require passwordless sudo
+const GUEST_SCRIPT: &str = r#"
+size=$1
+now() { date +%s%N; }
+rate() { echo "$1 $(( size * 1000000000 / ($3 - $2) ))"; }
+drop_cache() { sync; sudo -n sh -c 'echo 3 > /proc/sys/vm/drop_caches' 2>/dev/null; }
+file=/var/tmp/vz-bench
+start=$(now); dd if=/dev/zero of=$file bs=1M count=$size conv=fsync 2>/dev/null; rate disk_write $start $(now)
+drop_cache
+start=$(now); dd if=$file of=/dev/null bs=1M 2>/dev/null; rate disk_read $start $(now)
+if command -v fio >/dev/null; then
+    fio --name=vz --filename=$file --size=${size}M --bs=4k --rw=randread --direct=1 --runtime=10 --time_based --output-format=terse --terse-version=3 | awk -F';' '{print "disk_random_read", $8}'
+    fio --name=vz --filename=$file --size=${size}M --bs=4k --rw=randwrite --direct=1 --runtime=10 --time_based --output-format=terse --terse-version=3 | awk -F';' '{print "disk_random_write", $49}'
+fi
+rm -f $file
+if sudo -n mkdir -p /mnt/vz-bench 2>/dev/null && sudo -n mount -t virtiofs com.apple.virtio-fs.automount /mnt/vz-bench 2>/dev/null; then
+    file=/mnt/vz-bench/vz-bench/file
+    start=$(now); dd if=/dev/zero of=$file bs=1M count=$size conv=fsync 2>/dev/null; rate virtiofs_write $start $(now)
+    drop_cache
+    start=$(now); dd if=$file of=/dev/null bs=1M 2>/dev/null; rate virtiofs_read $start $(now)
+    rm -f $file
+    sudo -n umount /mnt/vz-bench
+fi
+"#;
+
+#[derive(Args)]
+pub struct Bench {
+    #[arg(help = "vm name, linux vm with ssh access")]
+    name: String,
+
+    #[arg(long, help = "size of test files in mb", default_value_t = 1024)]
+    size: u64,
+
+    #[arg(long, help = "seconds to wait for guest ssh", default_value_t = 300)]
+    ssh_timeout: u64,
+}
+
+impl Bench {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        if !matches!(dir.load_config()?.os, Os::Linux) {
+            return Err(Exception::ValidationError("bench requires linux vm".to_string()));
+        }
+        if dir.pid().is_some() {
+            return Err(Exception::ValidationError(format!("vm is running, name={name}")));
+        }
+
+        // each mode boots vm with modified config, original config is restored at end
+        let original_config = fs::read_to_string(&dir.config_path)?;
+        let share_dir = env::temp_dir().join(format!("vz-bench-{}", Uuid::new_v4()));
+        fs::create_dir_all(&share_dir)?;
+        let result = self.bench_modes(&dir, &original_config, &share_dir);
+        fs::write(&dir.config_path, original_config)?;
+        fs::remove_dir_all(&share_dir)?;
+
+        print_results(&result?);
+        Ok(())
+    }
+
+    fn bench_modes(&self, dir: &VmDir, original_config: &str, share_dir: &Path) -> Result<Vec<(String, Values)>, Exception> {
+        let mut results = vec![];
+        for (caching, sync) in MODES {
+            // restore original before each mode, load_config merges profile
+            fs::write(&dir.config_path, original_config)?;
+            let mut config = dir.load_config()?;
+            config.disk_caching = Some(caching);
+            config.disk_sync = Some(sync);
+            config
+                .sharing
+                .insert(SHARE_NAME.to_string(), Share::Path(share_dir.to_string_lossy().to_string()));
+            let mode = format!("{}/{}", json::to_json_value(&caching)?, json::to_json_value(&sync)?);
+            info!("bench disk mode, name={}, mode={mode}", dir.name());
+            dir.save_config(&config)?;
+            results.push((mode, self.bench(dir, &config)?));
+        }
+        Ok(results)
+    }
+
+    fn bench(&self, dir: &VmDir, config: &VmConfig) -> Result<Values, Exception> {
+        run::run_in_background(&dir.name(), &Overrides::default())?;
+        let result = self.measure(dir, config);
+        if !build::shutdown(dir) {
+            return Err(Exception::ValidationError(format!("failed to stop vm, name={}", dir.name())));
+        }
+        result
+    }
+
+    fn measure(&self, dir: &VmDir, config: &VmConfig) -> Result<Values, Exception> {
+        let ip = build::wait_for_ssh(dir, &config.mac_address, Duration::from_secs(self.ssh_timeout))?;
+        let destination = ssh::destination(config, &ip, None);
+
+        let output = ssh::ssh_command(dir, config)
+            .args(["-o", "BatchMode=yes"])
+            .arg(&destination)
+            .args(["sh", "-s", "--", &self.size.to_string()])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                child.stdin.take().unwrap().write_all(GUEST_SCRIPT.as_bytes())?;
+                child.wait_with_output()
+            })?;
+        if !output.status.success() {
+            return Err(Exception::ValidationError(format!("bench script failed, status={}", output.status)));
+        }
+        let mut values: Values = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(key, value)| (key.to_string(), value.trim().to_string()))
+            .collect();
+
+        // host to guest over ssh, includes cost of ssh encryption
+        let start = Instant::now();
+        let mut child = ssh::ssh_command(dir, config)
+            .args(["-o", "BatchMode=yes"])
+            .arg(&destination)
+            .args(["cat", ">", "/dev/null"])
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        io::copy(&mut io::repeat(0).take(self.size * 1024 * 1024), &mut stdin)?;
+        drop(stdin);
+        let status = child.wait()?;
+        if status.success() {
+            let rate = self.size as f64 / start.elapsed().as_secs_f64();
+            values.insert("network".to_string(), format!("{rate:.0}"));
+        }
+        Ok(values)
+    }
+}
+
+fn print_results(results: &[(String, Values)]) {
+    print!("{:<20}", "mode");
+    for (_, title) in COLUMNS {
+        print!("{title:<22}");
+    }
+    println!();
+    for (mode, values) in results {
+        print!("{mode:<20}");
+        for (key, _) in COLUMNS {
+            print!("{:<22}", values.get(key).map_or("-", String::as_str));
+        }
+        println!();
+    }
+}
@@ -0,0 +1,194 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use clap::Args;
+use clap::ValueHint;
+use tracing::info;
+
+use crate::command::create::Create;
+use crate::command::run;
+use crate::command::run::Overrides;
+use crate::command::ssh;
+use crate::config::vm_config::Os;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::dhcp_lease;
+use crate::util::exception::Exception;
+use crate::util::otlp;
+use crate::util::path;
+use crate::util::path::PathExtension;
+use crate::util::tcp;
+use crate::vm::runner;
+
+// with --machine-readable, stdout only contains lines of "<unix timestamp>,<vm name>,<type>,<data>...",
+// commas in data are escaped as %!(PACKER_COMMA), logs are written to stderr
+// types: step (create, boot, ssh, provision, shutdown, export), ip, artifact, error, done
+#[derive(Args)]
+pub struct Build {
+    #[command(flatten)]
+    create: Create,
+
+    #[arg(long, help = "shell script run in guest over ssh, e.g. --provision=setup.sh", value_hint = ValueHint::FilePath)]
+    provision: Option<PathBuf>,
+
+    #[arg(long, help = "export built vm into dir, e.g. --output=images/debian", value_hint = ValueHint::DirPath)]
+    output: Option<PathBuf>,
+
+    #[arg(long, help = "seconds to wait for guest ssh", default_value_t = 300)]
+    ssh_timeout: u64,
+
+    #[arg(long, help = "print progress as stable comma separated lines, e.g. for packer", default_value_t = false)]
+    pub machine_readable: bool,
+}
+
+impl Build {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let progress = Progress {
+            name: self.create.name().to_string(),
+            machine_readable: self.machine_readable,
+        };
+        let result = self.build(&progress);
+        match &result {
+            Ok(_) => progress.report("done", &[]),
+            Err(err) => progress.report("error", &[&err.to_string()]),
+        }
+        result
+    }
+
+    fn build(&self, progress: &Progress) -> Result<(), Exception> {
+        if !matches!(self.create.os(), Os::Linux) {
+            return Err(Exception::ValidationError("build requires linux vm".to_string()));
+        }
+        if let Some(output) = self.output.as_ref().filter(|output| output.exists()) {
+            return Err(Exception::ValidationError(format!(
+                "output already exists, path={}",
+                output.to_string_lossy()
+            )));
+        }
+
+        let name = self.create.name();
+        otlp::set_vm_name(name);
+        progress.report("step", &["create"]);
+        self.create.execute()?;
+
+        let dir = vm_dir::vm_dir(name);
+        progress.report("step", &["boot"]);
+        run::run_in_background(name, &Overrides::default())?;
+        if let Err(err) = self.provision(&dir, progress) {
+            shutdown(&dir);
+            return Err(err);
+        }
+
+        progress.report("step", &["shutdown"]);
+        if !shutdown(&dir) {
+            return Err(Exception::ValidationError(format!("failed to stop vm, name={name}")));
+        }
+
+        if let Some(output) = &self.output {
+            progress.report("step", &["export"]);
+            let output = output.to_absolute_path();
+            export(&dir, &output)?;
+            progress.report("artifact", &[&output.to_string_lossy()]);
+        }
+        Ok(())
+    }
+
+    fn provision(&self, dir: &VmDir, progress: &Progress) -> Result<(), Exception> {
+        progress.report("step", &["ssh"]);
+        let config = dir.load_config()?;
+        let start = Instant::now();
+        let ip = wait_for_ssh(dir, &config.mac_address, Duration::from_secs(self.ssh_timeout))?;
+        otlp::gauge("vz.vm.boot_to_ssh.duration", "s", start.elapsed().as_secs_f64());
+        progress.report("ip", &[&ip]);
+
+        if let Some(script) = &self.provision {
+            progress.report("step", &["provision"]);
+            let mut command = ssh::ssh_command(dir, &config);
+            command
+                .args(["-o", "BatchMode=yes"])
+                .arg(ssh::destination(&config, &ip, None))
+                .args(["sh", "-s"])
+                .stdin(File::open(script.to_absolute_path())?);
+            // keep stdout for progress lines only
+            if self.machine_readable {
+                command.stdout(Stdio::from(io::stderr()));
+            }
+            let status = command.status()?;
+            if !status.success() {
+                return Err(Exception::ValidationError(format!("provision failed, status={status}")));
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Progress {
+    name: String,
+    machine_readable: bool,
+}
+
+impl Progress {
+    fn report(&self, kind: &str, data: &[&str]) {
+        if self.machine_readable {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+            let mut line = format!("{timestamp},{},{kind}", self.name);
+            for value in data {
+                line.push(',');
+                line.push_str(&value.replace(',', "%!(PACKER_COMMA)").replace('\n', "\\n"));
+            }
+            println!("{line}");
+        } else {
+            info!("build {kind}, name={}, data={}", self.name, data.join(","));
+        }
+    }
+}
+
+// guest is ready once it got dhcp lease and accepts connection on ssh port
+pub fn wait_for_ssh(dir: &VmDir, mac_address: &str, timeout: Duration) -> Result<String, Exception> {
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        sleep(Duration::from_secs(1));
+        // runner takes lock shortly after launched
+        if dir.pid().is_none() && start.elapsed() > Duration::from_secs(5) {
+            return Err(Exception::ValidationError(format!("vm stopped during boot, name={}", dir.name())));
+        }
+        let Some(ip) = dhcp_lease::find_ip(mac_address)? else {
+            continue;
+        };
+        if tcp::port_open(&ip, tcp::SSH_PORT) {
+            return Ok(ip);
+        }
+    }
+    Err(Exception::ValidationError(format!("timeout waiting for ssh, name={}", dir.name())))
+}
+
+pub fn shutdown(dir: &VmDir) -> bool {
+    let Some(pid) = dir.pid() else {
+        return true;
+    };
+    info!("stop vm, name={}, pid={pid}", dir.name());
+    unsafe {
+        libc::kill(pid, libc::SIGINT);
+    }
+    runner::wait_until_stopped(dir, 60)
+}
+
+fn export(dir: &VmDir, output: &Path) -> Result<(), Exception> {
+    info!("export vm, from={}, to={}", dir.dir.to_string_lossy(), output.to_string_lossy());
+    fs::create_dir_all(output)?;
+    for path in [&dir.nvram_path, &dir.disk_path, &dir.config_path, &dir.kernel_path, &dir.initrd_path] {
+        if path.exists() {
+            path::clone_file(path, &output.join(path.file_name().unwrap()))?;
+        }
+    }
+    Ok(())
+}
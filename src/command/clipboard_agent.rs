@@ -0,0 +1,14 @@
+use clap::Args;
+
+use crate::util::exception::Exception;
+use crate::vm::clipboard;
+
+// run inside macOS guest, e.g. by launch agent of user
+#[derive(Args)]
+pub struct ClipboardAgent;
+
+impl ClipboardAgent {
+    pub fn execute(&self) -> Result<(), Exception> {
+        clipboard::serve()
+    }
+}
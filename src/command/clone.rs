@@ -0,0 +1,82 @@
+use std::fs;
+
+use clap::Args;
+use tracing::info;
+use tracing::warn;
+
+use crate::command::create;
+use crate::config::vm_config::SerialBackend;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+use crate::util::path;
+
+#[derive(Args)]
+pub struct CloneVm {
+    #[arg(help = "source vm name")]
+    source: String,
+
+    #[arg(help = "new vm name")]
+    name: String,
+}
+
+impl CloneVm {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let source = vm_dir::vm_dir(&self.source);
+        if !source.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={}", self.source)));
+        }
+        let name = &self.name;
+        let _name_lock = vm_dir::lock_name(name)?;
+        let dir = vm_dir::vm_dir(name);
+        if dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm already exists, name={name}")));
+        }
+        // disk of running vm is inconsistent, and source can't start while cloning
+        let _lock = source.lock("clone")?;
+
+        let temp_dir = vm_dir::create_temp_vm_dir()?;
+        if let Err(err) = clone(&source, &temp_dir) {
+            fs::remove_dir_all(&temp_dir.dir)?;
+            return Err(err);
+        }
+
+        info!("move vm dir, from={}, to={}", temp_dir.dir.to_string_lossy(), dir.dir.to_string_lossy());
+        fs::rename(&temp_dir.dir, &dir.dir)?;
+        info!("vm cloned, from={}, name={name}", self.source);
+        Ok(())
+    }
+}
+
+// clone shares disk blocks with source until either writes, identity is regenerated so both can run at same time
+fn clone(source: &VmDir, dir: &VmDir) -> Result<(), Exception> {
+    info!("clone vm dir, from={}, to={}", source.dir.to_string_lossy(), dir.dir.to_string_lossy());
+    let mut config = source.load_config()?;
+    let disks = config.disks.iter().map(|name| source.extra_disk_path(name));
+    let paths = [
+        &source.nvram_path,
+        &source.disk_path,
+        &source.kernel_path,
+        &source.initrd_path,
+        &source.uninstalled_path,
+    ]
+    .into_iter()
+    .cloned()
+    .chain(disks);
+    for path in paths {
+        if path.exists() {
+            path::clone_file(&path, &dir.dir.join(path.file_name().unwrap()))?;
+        }
+    }
+
+    create::regenerate_identity(&mut config);
+    create::check_mac_addresses(&dir.name(), &config)?;
+    let host_paths = !config.vsock_forwards.is_empty()
+        || !config.vsock_exposes.is_empty()
+        || config.serial_ports.iter().any(|port| !matches!(port.backend, SerialBackend::Pty));
+    if host_paths {
+        warn!("vsock and serial port paths are same as source vm, change them with vz edit before running both");
+    }
+    dir.save_config(&config)?;
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use clap::Args;
+use clap_complete::generate;
+use clap_complete::Shell;
+
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+
+const CARGO_PKG_NAME: &str = env!("CARGO_PKG_NAME");
+
+#[derive(Args)]
+pub struct Completion {
+    #[arg(help = "shell of completion script, e.g. zsh", required_unless_present = "vms")]
+    shell: Option<Shell>,
+
+    // called by completion scripts to complete vm names
+    #[arg(long, hide = true, default_value_t = false)]
+    vms: bool,
+}
+
+impl Completion {
+    pub fn execute(&self, cli: clap::Command) -> Result<(), Exception> {
+        if self.vms {
+            for dir in vm_dir::vm_dirs()? {
+                println!("{}", dir.name());
+            }
+            return Ok(());
+        }
+        print(self.shell.unwrap(), cli);
+        Ok(())
+    }
+}
+
+pub fn print(shell: Shell, mut cli: clap::Command) {
+    let mut script = vec![];
+    generate(shell, &mut cli, CARGO_PKG_NAME, &mut script);
+    let script = String::from_utf8_lossy(&script);
+    let commands = vm_name_commands(&cli);
+    let script = match shell {
+        Shell::Bash => bash(&script, &commands),
+        Shell::Zsh => zsh(&script),
+        Shell::Fish => fish(&script, &commands),
+        _ => script.to_string(),
+    };
+    print!("{script}");
+}
+
+// commands taking vm name as first argument, e.g. run, stop and ssh
+fn vm_name_commands(cli: &clap::Command) -> Vec<String> {
+    cli.get_subcommands()
+        .filter(|command| {
+            command
+                .get_positionals()
+                .next()
+                .is_some_and(|arg| arg.get_id() == "name" && arg.get_help().is_some_and(|help| help.to_string() == "vm name"))
+        })
+        .flat_map(|command| {
+            let mut names = vec![command.get_name().to_string()];
+            names.extend(command.get_visible_aliases().map(str::to_string));
+            names
+        })
+        .collect()
+}
+
+// wraps generated function, vm names are completed right after command
+fn bash(script: &str, commands: &[String]) -> String {
+    format!(
+        r#"{script}
+_vz_vms() {{
+    local current="${{COMP_WORDS[COMP_CWORD]}}"
+    if [[ ${{COMP_CWORD}} -eq 2 && "${{current}}" != -* && " {} " == *" ${{COMP_WORDS[1]}} "* ]]; then
+        COMPREPLY=($(compgen -W "$(vz completion --vms 2>/dev/null)" -- "${{current}}"))
+        return 0
+    fi
+    _vz "$@"
+}}
+complete -F _vz_vms -o bashdefault -o default vz
+"#,
+        commands.join(" ")
+    )
+}
+
+// generated script completes all vm name arguments by _default, e.g. ':name -- vm name:_default'
+fn zsh(script: &str) -> String {
+    let function = r#"_vz_vms() {
+    local -a vms
+    vms=(${(f)"$(vz completion --vms 2>/dev/null)"})
+    _describe 'vm' vms
+}
+
+"#;
+    let script = script.replace("-- vm name:_default'", "-- vm name:_vz_vms'");
+    // define before script calls _vz or registers it
+    match script.find("if [ \"$funcstack[1]\" = \"_vz\" ]") {
+        Some(index) => format!("{}{function}{}", &script[..index], &script[index..]),
+        None => format!("{script}\n{function}"),
+    }
+}
+
+fn fish(script: &str, commands: &[String]) -> String {
+    format!(
+        "{script}complete -c vz -n \"__fish_seen_subcommand_from {}\" -f -a \"(vz completion --vms 2>/dev/null)\"\n",
+        commands.join(" ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn zsh() {
+        let script = "#compdef vz\n':name -- vm name:_default' \\\nif [ \"$funcstack[1]\" = \"_vz\" ]; then\n";
+        let script = super::zsh(script);
+        assert!(script.contains("':name -- vm name:_vz_vms'"));
+        assert!(script.find("_vz_vms() {").unwrap() < script.find("if [ \"$funcstack[1]\"").unwrap());
+    }
+}
@@ -0,0 +1,49 @@
+use clap::Args;
+
+use crate::config::vm_config::Os;
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+use crate::vm::console;
+
+#[derive(Args)]
+pub struct Console {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(
+        long,
+        help = "guest device number, 0 is /dev/hvc0 console, others are pty serial ports in config",
+        default_value_t = 0
+    )]
+    port: usize,
+}
+
+impl Console {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        if dir.pid().is_none() {
+            return Err(Exception::ValidationError(format!("vm not running, name={name}")));
+        }
+        let config = dir.load_config()?;
+        if !matches!(config.os, Os::Linux) {
+            return Err(Exception::ValidationError("console requires linux guest".to_string()));
+        }
+
+        // pty of console and serial ports is linked in vm dir by runner, see run
+        let path = match self.port {
+            0 => dir.console_path.clone(),
+            port => dir.dir.join(format!("serial{port}")),
+        };
+        if !path.exists() {
+            return Err(Exception::ValidationError(format!(
+                "console is not available, only pty backend can be attached, name={name}, port={}",
+                self.port
+            )));
+        }
+        console::attach(&path)
+    }
+}
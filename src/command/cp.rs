@@ -0,0 +1,250 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use clap::Args;
+use tracing::info;
+
+use crate::command::ssh;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::dhcp_lease;
+use crate::util::exception::Exception;
+use crate::util::tcp;
+use crate::util::terminal;
+use crate::vm::exec;
+
+#[derive(Args)]
+pub struct Cp {
+    #[arg(help = "source, host path or <vm>:<guest path>")]
+    source: String,
+
+    #[arg(help = "destination, host path or <vm>:<guest path>")]
+    destination: String,
+
+    #[arg(long, short, help = "copy dir recursively", default_value_t = false)]
+    recursive: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum Location<'a> {
+    Host(&'a str),
+    Guest { name: &'a str, path: &'a str },
+}
+
+impl Cp {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let (name, upload, host_path, guest_path) = match (location(&self.source), location(&self.destination)) {
+            (Location::Host(host_path), Location::Guest { name, path }) => (name, true, host_path, path),
+            (Location::Guest { name, path }, Location::Host(host_path)) => (name, false, host_path, path),
+            _ => {
+                return Err(Exception::ValidationError(
+                    "one of source and destination must be <vm>:<guest path>, other is host path".to_string(),
+                ))
+            }
+        };
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        if dir.pid().is_none() {
+            return Err(Exception::ValidationError(format!("vm is not running, name={name}")));
+        }
+
+        let config = dir.load_config()?;
+        if let Some(ip) = dhcp_lease::find_ip(&config.mac_address)?.filter(|ip| tcp::port_open(ip, tcp::SSH_PORT)) {
+            info!("copy by scp, name={name}, ip={ip}");
+            let remote = format!("{}:{guest_path}", ssh::destination(&config, &ip, None));
+            let mut command = ssh::scp_command(&dir, &config);
+            if self.recursive {
+                command.arg("-r");
+            }
+            if upload {
+                command.arg(host_path).arg(remote);
+            } else {
+                command.arg(remote).arg(host_path);
+            }
+            let status = command.status()?;
+            if !status.success() {
+                return Err(Exception::ValidationError(format!("failed to copy by scp, status={status}")));
+            }
+            return Ok(());
+        }
+
+        info!("ssh is not reachable, copy by exec agent, name={name}");
+        let copied = Arc::new(AtomicU64::new(0));
+        let code = if upload {
+            upload_by_agent(&dir, Path::new(host_path), guest_path, self.recursive, &copied)?
+        } else {
+            download_by_agent(&dir, guest_path, Path::new(host_path), self.recursive, &copied)?
+        };
+        if terminal::interactive() {
+            eprintln!();
+        }
+        if code != 0 {
+            return Err(Exception::ValidationError(format!("failed to copy by exec agent, exit_code={code}")));
+        }
+        info!("copied, size={}", format_progress(copied.load(Ordering::Relaxed), None));
+        Ok(())
+    }
+}
+
+// same as scp, prefix without slash before colon is vm name, e.g. dev:/tmp, ./dev:file is host path
+fn location(value: &str) -> Location<'_> {
+    match value.split_once(':') {
+        Some((name, path)) if !name.is_empty() && !name.contains('/') => Location::Guest { name, path },
+        _ => Location::Host(value),
+    }
+}
+
+// guest path is passed as arg of sh, so it's not interpreted by shell
+fn guest_shell(script: &str, path: &str) -> Vec<String> {
+    ["sh", "-c", script, "sh", path].into_iter().map(String::from).collect()
+}
+
+fn upload_by_agent(dir: &VmDir, source: &Path, destination: &str, recursive: bool, copied: &Arc<AtomicU64>) -> Result<i32, Exception> {
+    if source.is_dir() {
+        if !recursive {
+            return Err(Exception::ValidationError(format!(
+                "source is dir, copy with -r, source={}",
+                source.to_string_lossy()
+            )));
+        }
+        // bsdtar of macOS adds ._ files for extended attributes without COPYFILE_DISABLE
+        let mut tar = Command::new("tar")
+            .env("COPYFILE_DISABLE", "1")
+            .arg("-C")
+            .arg(source)
+            .args(["-cf", "-", "."])
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let output = Progress::new(tar.stdout.take().unwrap(), None, Arc::clone(copied));
+        let command = guest_shell(r#"mkdir -p "$1" && tar -C "$1" -xf -"#, destination);
+        let code = exec::run(dir, &command, Some(output), &mut io::sink())?;
+        tar.wait()?;
+        return Ok(code);
+    }
+    let file = File::open(source)?;
+    let total = file.metadata()?.len();
+    let destination = match (destination.ends_with('/'), source.file_name()) {
+        (true, Some(file_name)) => format!("{destination}{}", file_name.to_string_lossy()),
+        _ => destination.to_string(),
+    };
+    let input = Progress::new(file, Some(total), Arc::clone(copied));
+    exec::run(dir, &guest_shell(r#"cat > "$1""#, &destination), Some(input), &mut io::sink())
+}
+
+fn download_by_agent(dir: &VmDir, source: &str, destination: &Path, recursive: bool, copied: &Arc<AtomicU64>) -> Result<i32, Exception> {
+    let no_stdin: Option<io::Empty> = None;
+    if recursive {
+        fs::create_dir_all(destination)?;
+        let mut tar = Command::new("tar")
+            .arg("-C")
+            .arg(destination)
+            .args(["-xf", "-"])
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let mut input = Progress::new(tar.stdin.take().unwrap(), None, Arc::clone(copied));
+        let command = guest_shell(r#"COPYFILE_DISABLE=1 tar -C "$1" -cf - ."#, source);
+        let code = exec::run(dir, &command, no_stdin, &mut input)?;
+        drop(input);
+        let status = tar.wait()?;
+        return Ok(if status.success() { code } else { status.code().unwrap_or(1) });
+    }
+    let destination = match (destination.is_dir(), Path::new(source).file_name()) {
+        (true, Some(file_name)) => destination.join(file_name),
+        _ => destination.to_path_buf(),
+    };
+    let mut output = Progress::new(File::create(&destination)?, None, Arc::clone(copied));
+    exec::run(dir, &guest_shell(r#"cat -- "$1""#, source), no_stdin, &mut output)
+}
+
+// counts copied bytes, and prints them in place if terminal
+struct Progress<T> {
+    inner: T,
+    total: Option<u64>,
+    copied: Arc<AtomicU64>,
+    printed: Instant,
+}
+
+impl<T> Progress<T> {
+    fn new(inner: T, total: Option<u64>, copied: Arc<AtomicU64>) -> Self {
+        Progress {
+            inner,
+            total,
+            copied,
+            printed: Instant::now(),
+        }
+    }
+
+    fn add(&mut self, length: usize) {
+        let copied = self.copied.fetch_add(length as u64, Ordering::Relaxed) + length as u64;
+        if terminal::interactive() && self.printed.elapsed() >= Duration::from_millis(200) {
+            eprint!("\r{}", format_progress(copied, self.total));
+            self.printed = Instant::now();
+        }
+    }
+}
+
+impl<T: Read> Read for Progress<T> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let length = self.inner.read(buffer)?;
+        self.add(length);
+        Ok(length)
+    }
+}
+
+impl<T: Write> Write for Progress<T> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        let length = self.inner.write(buffer)?;
+        self.add(length);
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn format_progress(copied: u64, total: Option<u64>) -> String {
+    let size = |bytes: u64| format!("{:.2}M", bytes as f64 / 1_000_000.0);
+    match total.filter(|total| *total > 0) {
+        Some(total) => format!("{} / {} {}%", size(copied), size(total), copied * 100 / total),
+        None => size(copied),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Location;
+
+    #[test]
+    fn location() {
+        assert_eq!(
+            super::location("dev:/tmp/a.txt"),
+            Location::Guest {
+                name: "dev",
+                path: "/tmp/a.txt"
+            }
+        );
+        assert_eq!(super::location("dev:"), Location::Guest { name: "dev", path: "" });
+        assert_eq!(super::location("./dev:a.txt"), Location::Host("./dev:a.txt"));
+        assert_eq!(super::location("/tmp/a.txt"), Location::Host("/tmp/a.txt"));
+    }
+
+    #[test]
+    fn format_progress() {
+        assert_eq!(super::format_progress(1_500_000, None), "1.50M");
+        assert_eq!(super::format_progress(25_000_000, Some(100_000_000)), "25.00M / 100.00M 25%");
+    }
+}
@@ -1,35 +1,44 @@
 use std::cmp::max;
 use std::collections::HashMap;
+use std::env;
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::mpsc::channel;
 
-use block2::StackBlock;
 use clap::Args;
 use clap::ValueHint;
 use objc2::exception::catch;
-use objc2::rc::Id;
-use objc2::rc::Retained;
 use objc2::ClassType;
 use objc2_foundation::NSDataBase64EncodingOptions;
-use objc2_foundation::NSError;
-use objc2_virtualization::VZEFIVariableStore;
-use objc2_virtualization::VZEFIVariableStoreInitializationOptions;
-use objc2_virtualization::VZMACAddress;
 use objc2_virtualization::VZMacAuxiliaryStorage;
 use objc2_virtualization::VZMacAuxiliaryStorageInitializationOptions;
 use objc2_virtualization::VZMacMachineIdentifier;
-use objc2_virtualization::VZMacOSRestoreImage;
+use serde_json::Map;
+use serde_json::Value;
 use tracing::info;
 
+use crate::config::cloud_init;
+use crate::config::image_cache;
+use crate::config::ipsw_cache;
+use crate::config::profile;
+use crate::config::settings;
+use crate::config::vm_config;
 use crate::config::vm_config::Os;
+use crate::config::vm_config::Share;
 use crate::config::vm_config::VmConfig;
 use crate::config::vm_dir;
 use crate::config::vm_dir::VmDir;
+use crate::util::disk_image;
 use crate::util::exception::Exception;
+use crate::util::oci_image;
 use crate::util::path::PathExtension;
 use crate::vm::mac_os;
+use crate::vm::platform::Platform;
+use crate::vm::platform::Virtualization;
+
+mod auto_size;
+mod host_locale;
+mod wizard;
 
 #[derive(Args)]
 pub struct Create {
@@ -42,26 +51,145 @@ pub struct Create {
     #[arg(long, help = "disk size in gb", default_value_t = 50)]
     disk_size: u64,
 
-    #[arg(long, help = "macOS restore image file, e.g. --ipsw=UniversalMac_14.5_23F79_Restore.ipsw", value_hint = ValueHint::FilePath)]
+    #[arg(
+        long,
+        help = "macOS restore image file, or version of cached ipsw, e.g. --ipsw=UniversalMac_14.5_23F79_Restore.ipsw or --ipsw=14.5, use --ipsw=latest or omit to download latest supported image",
+        value_hint = ValueHint::FilePath
+    )]
     ipsw: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "macOS version to install, restore image is taken from cache or downloaded from apple, e.g. --macos=14.6.1",
+        conflicts_with = "ipsw"
+    )]
+    macos: Option<String>,
+
+    #[arg(long, help = "use existing raw, qcow2, vmdk or vhdx disk image as boot disk, e.g. --disk-image=rootfs.img", value_hint = ValueHint::FilePath)]
+    disk_image: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "create linux vm from oci image with bundled kernel, e.g. --oci=docker.io/library/ubuntu:24.04",
+        conflicts_with = "disk_image"
+    )]
+    oci: Option<String>,
+
+    #[arg(
+        long,
+        help = "kernel booted by vm of --oci, it must have virtio, ext4 and ip autoconfig built in, default to share/vz/vmlinuz installed along with vz",
+        requires = "oci",
+        value_hint = ValueHint::FilePath
+    )]
+    kernel: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "use cached image pulled by vz pull as boot disk, e.g. --image=ubuntu-24.04",
+        conflicts_with_all = ["disk_image", "oci"]
+    )]
+    image: Option<String>,
+
+    #[arg(long, help = "prompt for os, size, cpu, memory, image, shares and network", default_value_t = false)]
+    interactive: bool,
+
+    #[arg(
+        long,
+        help = "create vm with settings of config file, os is taken from it, mac address and machine identifier are regenerated, e.g. --config=vm.json",
+        value_hint = ValueHint::FilePath,
+        conflicts_with = "interactive"
+    )]
+    config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "size cpu, memory and disk by host capacity, instead of 1 cpu, 1G memory and --disk-size",
+        default_value_t = false,
+        conflicts_with_all = ["interactive", "config", "disk_size"]
+    )]
+    auto_size: bool,
+
+    #[arg(long, help = "timezone of guest, e.g. --timezone=Europe/Berlin, --timezone=host uses timezone of host")]
+    timezone: Option<String>,
+
+    #[arg(long, help = "locale of guest, e.g. --locale=de_DE.UTF-8, --locale=host uses locale of host")]
+    locale: Option<String>,
+
+    #[arg(long, help = "keyboard layout of guest, e.g. --keyboard=de")]
+    keyboard: Option<String>,
 }
 
 impl Create {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn os(&self) -> &Os {
+        &self.os
+    }
+
     pub fn execute(&self) -> Result<(), Exception> {
+        if self.interactive {
+            return self.create_interactively();
+        }
+        if let Some(path) = &self.config {
+            return self.create_from_config(path);
+        }
+        if self.auto_size {
+            return self.create_auto_sized();
+        }
         self.validate()?;
 
         let name = &self.name;
+        // concurrent create of same name fails here, instead of when moving temp dir
+        let _lock = vm_dir::lock_name(name)?;
         let dir = vm_dir::vm_dir(name);
         if dir.initialized() {
             return Err(Exception::ValidationError(format!("vm already exists, name={name}")));
         }
 
+        // resolve ipsw before creating temp dir, it may download latest restore image
+        let ipsw = match self.os {
+            Os::MacOs => Some(self.ipsw()?),
+            Os::Linux => None,
+        };
+
+        settings::check_storage_quota(self.disk_size * 1_000_000_000)?;
         let temp_dir = vm_dir::create_temp_vm_dir()?;
+        if let Some(disk_image) = self.disk_image()? {
+            let size = disk_image::convert_to_raw(&disk_image, &temp_dir.disk_path)?;
+            if size > self.disk_size * 1_000_000_000 {
+                fs::remove_dir_all(&temp_dir.dir)?;
+                return Err(Exception::ValidationError(format!(
+                    "disk size must not be smaller than disk image, image_size={size}"
+                )));
+            }
+        }
         temp_dir.resize(self.disk_size * 1_000_000_000)?;
 
         match self.os {
             Os::Linux => create_linux(&temp_dir)?,
-            Os::MacOs => create_macos(&temp_dir, &self.ipsw.as_ref().unwrap().to_absolute_path())?,
+            Os::MacOs => {
+                create_macos(&temp_dir, ipsw.as_ref().unwrap())?;
+                // converted disk image is expected to have macOS already
+                if self.disk_image.is_none() {
+                    fs::write(&temp_dir.uninstalled_path, "")?;
+                }
+            }
+        }
+
+        if let Err(err) = check_mac_addresses(name, &temp_dir.load_config()?) {
+            fs::remove_dir_all(&temp_dir.dir)?;
+            return Err(err);
+        }
+
+        self.apply_locale(&temp_dir)?;
+
+        if let Some(image) = &self.oci {
+            if let Err(err) = create_oci(&temp_dir, name, image, &self.oci_kernel()?) {
+                fs::remove_dir_all(&temp_dir.dir)?;
+                return Err(err);
+            }
         }
 
         let dir = vm_dir::vm_dir(&self.name);
@@ -72,44 +200,298 @@ impl Create {
         Ok(())
     }
 
+    fn create_interactively(&self) -> Result<(), Exception> {
+        let answers = wizard::ask_all(&self.name)?;
+        let create = Create {
+            name: self.name.clone(),
+            os: answers.os,
+            disk_size: answers.disk_size,
+            ipsw: answers.ipsw,
+            macos: None,
+            disk_image: answers.disk_image,
+            oci: answers.oci,
+            kernel: None,
+            image: None,
+            interactive: false,
+            config: None,
+            auto_size: false,
+            timezone: self.timezone.clone(),
+            locale: self.locale.clone(),
+            keyboard: self.keyboard.clone(),
+        };
+        create.execute()?;
+
+        let dir = vm_dir::vm_dir(&self.name);
+        let mut config = dir.load_config()?;
+        config.cpu = answers.cpu;
+        config.memory = answers.memory;
+        config.sharing = answers.sharing.into_iter().map(|(name, path)| (name, Share::Path(path))).collect();
+        if !answers.network {
+            config.network = Some(false);
+        }
+        dir.save_config(&config)?;
+        Ok(())
+    }
+
+    fn create_auto_sized(&self) -> Result<(), Exception> {
+        let size = auto_size::recommend(&Virtualization)?;
+        for line in &size.rationale {
+            println!("{line}");
+        }
+        let create = Create {
+            name: self.name.clone(),
+            os: self.os.clone(),
+            disk_size: size.disk_size,
+            ipsw: self.ipsw.clone(),
+            macos: self.macos.clone(),
+            disk_image: self.disk_image.clone(),
+            oci: self.oci.clone(),
+            kernel: self.kernel.clone(),
+            image: self.image.clone(),
+            interactive: false,
+            config: None,
+            auto_size: false,
+            timezone: self.timezone.clone(),
+            locale: self.locale.clone(),
+            keyboard: self.keyboard.clone(),
+        };
+        create.execute()?;
+
+        // macOS vm is created with minimum of restore image, which is kept if larger
+        let dir = vm_dir::vm_dir(&self.name);
+        let mut config = dir.load_config()?;
+        config.cpu = max(config.cpu, size.cpu);
+        config.memory = max(config.memory, size.memory);
+        if let Os::Linux = config.os {
+            (config.cpu, config.memory) = (size.cpu, size.memory);
+        }
+        dir.save_config(&config)?;
+        Ok(())
+    }
+
+    fn create_from_config(&self, path: &Path) -> Result<(), Exception> {
+        let template = load_template(&path.to_absolute_path())?;
+        let create = Create {
+            name: self.name.clone(),
+            os: template.os.clone(),
+            disk_size: self.disk_size,
+            ipsw: self.ipsw.clone(),
+            macos: self.macos.clone(),
+            disk_image: self.disk_image.clone(),
+            oci: self.oci.clone(),
+            kernel: self.kernel.clone(),
+            image: self.image.clone(),
+            interactive: false,
+            config: None,
+            auto_size: false,
+            timezone: self.timezone.clone(),
+            locale: self.locale.clone(),
+            keyboard: self.keyboard.clone(),
+        };
+        create.execute()?;
+
+        // identity and hardware model come from created vm, nvram is bound to them
+        let dir = vm_dir::vm_dir(&self.name);
+        let created = dir.load_config()?;
+        let config = VmConfig {
+            mac_address: created.mac_address,
+            hardware_model: created.hardware_model,
+            machine_identifier: created.machine_identifier,
+            kernel_command_line: template.kernel_command_line.or(created.kernel_command_line),
+            // options of command line take precedence over template
+            timezone: created.timezone.or(template.timezone),
+            locale: created.locale.or(template.locale),
+            keyboard: created.keyboard.or(template.keyboard),
+            ..template
+        };
+        dir.save_config(&config)?;
+        Ok(())
+    }
+
+    // no kernel is shipped with vz, package or user puts one at bundled path, or passes it by --kernel
+    fn oci_kernel(&self) -> Result<PathBuf, Exception> {
+        let kernel = match &self.kernel {
+            Some(kernel) => kernel.to_absolute_path(),
+            None => bundled_kernel()?,
+        };
+        if !kernel.is_file() {
+            return Err(Exception::ValidationError(format!(
+                "kernel for oci image not found, pass --kernel=<path> of arm64 kernel image with virtio, ext4 and ip autoconfig built in, or put it at share/vz/vmlinuz next to bin of vz, path={}",
+                kernel.to_string_lossy()
+            )));
+        }
+        Ok(kernel)
+    }
+
     pub fn validate(&self) -> Result<(), Exception> {
+        if self.macos.is_some() && !matches!(self.os, Os::MacOs) {
+            return Err(Exception::ValidationError("--macos requires --os=macOS".to_string()));
+        }
         if let Os::MacOs = self.os {
-            match &self.ipsw {
-                Some(path) => {
-                    if !path.exists() {
-                        return Err(Exception::ValidationError(format!(
-                            "ipsw does not exist, path={}",
-                            path.to_string_lossy()
-                        )));
-                    }
-                }
-                None => return Err(Exception::ValidationError("ipsw is required for macOS vm".to_string())),
+            if let Some(path) = self.ipsw.as_ref().filter(|path| !is_latest(path)) {
+                ipsw_cache::resolve(path)?;
             }
         };
+        if self.oci.is_some() {
+            if !matches!(self.os, Os::Linux) {
+                return Err(Exception::ValidationError("oci image is only supported for linux vm".to_string()));
+            }
+            self.oci_kernel()?;
+        }
+        if let Some(disk_image) = self.disk_image()? {
+            if !matches!(self.os, Os::Linux) {
+                return Err(Exception::ValidationError("disk image is only supported for linux vm".to_string()));
+            }
+            let size = disk_image
+                .metadata()
+                .map_err(|_| Exception::ValidationError(format!("disk image does not exist, path={}", disk_image.to_string_lossy())))?
+                .len();
+            if size > self.disk_size * 1_000_000_000 {
+                return Err(Exception::ValidationError(format!(
+                    "disk size must not be smaller than disk image, image_size={size}"
+                )));
+            }
+        }
         Ok(())
     }
+
+    // cached image is raw already, so it's cloned by path::clone_file instead of converted
+    fn disk_image(&self) -> Result<Option<PathBuf>, Exception> {
+        match &self.image {
+            Some(image) => Ok(Some(image_cache::resolve(image)?)),
+            None => Ok(self.disk_image.as_ref().map(|path| path.to_absolute_path())),
+        }
+    }
+
+    // linux guest applies it by cloud-init on first boot, macOS guest is set up manually after installation
+    fn apply_locale(&self, dir: &VmDir) -> Result<(), Exception> {
+        let timezone = match self.timezone.as_deref() {
+            Some("host") => Some(host_locale::timezone()?),
+            timezone => timezone.map(str::to_string),
+        };
+        let locale = match self.locale.as_deref() {
+            Some("host") => Some(host_locale::locale()?),
+            locale => locale.map(str::to_string),
+        };
+        let keyboard = self.keyboard.clone();
+        match self.os {
+            Os::Linux => {
+                if timezone.is_none() && locale.is_none() && keyboard.is_none() {
+                    return Ok(());
+                }
+                let mut config = dir.load_config()?;
+                config.timezone = timezone;
+                config.locale = locale;
+                config.keyboard = keyboard;
+                // validate before vm is created
+                cloud_init::guest_config(&config)?;
+                dir.save_config(&config)?;
+            }
+            Os::MacOs => {
+                if let Some(timezone) = timezone {
+                    info!("set timezone in guest after installation, run: sudo systemsetup -settimezone {timezone}");
+                }
+                if let Some(locale) = locale {
+                    info!("set language and region in guest after installation, locale={locale}, in System Settings > General > Language & Region");
+                }
+                if let Some(keyboard) = keyboard {
+                    info!("add keyboard layout in guest after installation, layout={keyboard}, in System Settings > Keyboard > Text Input");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn ipsw(&self) -> Result<PathBuf, Exception> {
+        if let Some(version) = &self.macos {
+            return ipsw_cache::version(version);
+        }
+        match self.ipsw.as_ref().filter(|path| !is_latest(path)) {
+            Some(path) => ipsw_cache::resolve(path),
+            None => {
+                info!("fetch latest supported restore image");
+                let url = mac_os::latest_restore_image_url()?;
+                ipsw_cache::download(&url)
+            }
+        }
+    }
 }
 
-fn create_linux(dir: &VmDir) -> Result<(), Exception> {
-    info!("create nvram.bin");
-    unsafe {
-        catch(|| {
-            VZEFIVariableStore::initCreatingVariableStoreAtURL_options_error(
-                VZEFIVariableStore::alloc(),
-                &dir.nvram_path.to_ns_url(),
-                VZEFIVariableStoreInitializationOptions::empty(),
-            )
-        })??;
+// template may omit identity fields, e.g. config checked into repo
+fn load_template(path: &Path) -> Result<VmConfig, Exception> {
+    let invalid = |err| Exception::ValidationError(format!("invalid config, path={}, error={err}", path.to_string_lossy()));
+    let json = fs::read_to_string(path).map_err(|err| invalid(err.to_string()))?;
+    let mut fields: Map<String, Value> = serde_json::from_str(&json).map_err(|err| invalid(err.to_string()))?;
+    for field in ["macAddress", "hardware_model", "machine_identifier"] {
+        fields.remove(field);
     }
+    fields.insert("macAddress".to_string(), Value::String(String::new()));
+    if fields.contains_key("extends") {
+        fields = profile::merge(fields)?;
+    }
+    let mut config = vm_config::from_value(Value::Object(fields)).map_err(|err| invalid(err.to_string()))?;
+    // mac addresses of extra networks are copied from template too, every vm needs its own
+    regenerate_identity(&mut config);
+    config.validate_paths()?;
+    config.validate_host_limits()?;
+    Ok(config)
+}
+
+fn is_latest(ipsw: &Path) -> bool {
+    ipsw.as_os_str() == "latest"
+}
+
+pub fn create_linux(dir: &VmDir) -> Result<(), Exception> {
+    create_linux_on(&Virtualization, dir)
+}
+
+fn create_linux_on(platform: &dyn Platform, dir: &VmDir) -> Result<(), Exception> {
+    info!("create nvram.bin");
+    platform.create_efi_variable_store(&dir.nvram_path)?;
 
     info!("create config.json");
     let config = VmConfig {
+        extends: None,
+        labels: HashMap::new(),
         os: Os::Linux,
         cpu: 1,
         memory: 1024 * 1024 * 1024,
-        mac_address: random_mac_address(),
+        mac_address: platform.random_mac_address(),
         sharing: HashMap::new(),
+        network: None,
+        networks: vec![],
+        disks: vec![],
+        vsock_forwards: vec![],
+        vsock_exposes: vec![],
+        auto_forward_ports: vec![],
+        ssh_user: None,
+        ssh_key: None,
+        ssh_args: vec![],
+        guest_env: HashMap::new(),
+        timezone: None,
+        locale: None,
+        keyboard: None,
         rosetta: Some(false),
+        nested: None,
+        graphics: None,
+        kernel_command_line: None,
+        kernel: None,
+        initrd: None,
+        os_log: None,
+        serial_ports: vec![],
+        clipboard: None,
+        entropy: None,
+        memory_balloon: None,
+        spice_agent: None,
+        sound: None,
+        notify: None,
+        cpu_limit_percent: None,
+        disk_caching: None,
+        disk_sync: None,
+        readiness_probe: None,
+        start_timeout: None,
+        heartbeat_timeout: None,
+        restart: None,
         hardware_model: None,
         machine_identifier: None,
     };
@@ -119,7 +501,7 @@ fn create_linux(dir: &VmDir) -> Result<(), Exception> {
 }
 
 fn create_macos(dir: &VmDir, ipsw: &Path) -> Result<(), Exception> {
-    let image = load_mac_os_restore_image(ipsw)?;
+    let image = mac_os::load_restore_image(ipsw)?;
 
     let requirements = unsafe {
         image
@@ -148,44 +530,159 @@ fn create_macos(dir: &VmDir, ipsw: &Path) -> Result<(), Exception> {
     }
 
     info!("create config.json");
-    let machine_identifier = unsafe {
-        VZMacMachineIdentifier::new()
-            .dataRepresentation()
-            .base64EncodedStringWithOptions(NSDataBase64EncodingOptions::empty())
-            .to_string()
-    };
     let config = VmConfig {
+        extends: None,
+        labels: HashMap::new(),
         os: Os::MacOs,
         cpu: max(4, unsafe { requirements.minimumSupportedCPUCount() }),
         memory: max(8 * 1024 * 1024 * 1024, unsafe { requirements.minimumSupportedMemorySize() }),
         mac_address: random_mac_address(),
         sharing: HashMap::new(),
+        network: None,
+        networks: vec![],
+        disks: vec![],
+        vsock_forwards: vec![],
+        vsock_exposes: vec![],
+        auto_forward_ports: vec![],
+        ssh_user: None,
+        ssh_key: None,
+        ssh_args: vec![],
+        guest_env: HashMap::new(),
+        timezone: None,
+        locale: None,
+        keyboard: None,
         rosetta: None,
+        nested: None,
+        graphics: None,
+        kernel_command_line: None,
+        kernel: None,
+        initrd: None,
+        os_log: None,
+        serial_ports: vec![],
+        clipboard: None,
+        entropy: None,
+        memory_balloon: None,
+        spice_agent: None,
+        sound: None,
+        notify: None,
+        cpu_limit_percent: None,
+        disk_caching: None,
+        disk_sync: None,
+        readiness_probe: None,
+        start_timeout: None,
+        heartbeat_timeout: None,
+        restart: None,
         hardware_model: Some(hardware_model),
-        machine_identifier: Some(machine_identifier),
+        machine_identifier: Some(random_machine_identifier()),
     };
     dir.save_config(&config)?;
 
     Ok(())
 }
 
-fn random_mac_address() -> String {
-    unsafe { VZMACAddress::randomLocallyAdministeredAddress().string().to_string() }
+// pull image into ext4 root disk, and boot bundled kernel with image entrypoint as init
+fn create_oci(dir: &VmDir, name: &str, image: &str, kernel: &Path) -> Result<(), Exception> {
+    let image_dir = dir.dir.join("oci");
+    fs::create_dir(&image_dir)?;
+    let image = oci_image::pull(&oci_image::Reference::parse(image), &image_dir)?;
+    let rootfs = image_dir.join("rootfs.tar");
+    oci_image::create_rootfs(&image, name, &rootfs)?;
+    disk_image::create_ext4(&rootfs, &dir.disk_path)?;
+    fs::remove_dir_all(&image_dir)?;
+
+    info!("copy kernel, from={}, to={}", kernel.to_string_lossy(), dir.kernel_path.to_string_lossy());
+    fs::copy(kernel, &dir.kernel_path)?;
+    let mut config = dir.load_config()?;
+    config.kernel_command_line = Some(format!(
+        "console=hvc0 root=/dev/vda rootfstype=ext4 rw ip=dhcp panic=-1 init=/{}",
+        oci_image::INIT_PATH
+    ));
+    dir.save_config(&config)?;
+    Ok(())
+}
+
+// kernel installed along with vz binary, e.g. /usr/local/share/vz/vmlinuz for /usr/local/bin/vz,
+// it must have virtio, ext4 and ip autoconfig built in, as oci image has no initrd
+fn bundled_kernel() -> Result<PathBuf, Exception> {
+    let exe = env::current_exe()?;
+    let prefix = exe.parent().and_then(Path::parent).unwrap_or(Path::new("/"));
+    Ok(prefix.join("share/vz/vmlinuz"))
 }
 
-fn load_mac_os_restore_image(ipsw: &Path) -> Result<Retained<VZMacOSRestoreImage>, Exception> {
-    let (tx, rx) = channel();
+pub fn random_mac_address() -> String {
+    Virtualization.random_mac_address()
+}
+
+pub fn random_machine_identifier() -> String {
     unsafe {
-        let block = StackBlock::new(move |image: *mut VZMacOSRestoreImage, err: *mut NSError| {
-            if !err.is_null() {
-                tx.send(Err(Exception::from_ns_error(err))).unwrap();
-            } else {
-                let image = Id::from_raw(image).unwrap();
-                tx.send(Ok(image)).unwrap();
-            }
-        });
-        VZMacOSRestoreImage::loadFileURL_completionHandler(&ipsw.to_ns_url(), &block);
-    };
-    let image = rx.recv()??;
-    Ok(image)
+        VZMacMachineIdentifier::new()
+            .dataRepresentation()
+            .base64EncodedStringWithOptions(NSDataBase64EncodingOptions::empty())
+            .to_string()
+    }
+}
+
+// copy of vm must not share mac addresses and machine identifier with original, so both can run at same time
+pub fn regenerate_identity(config: &mut VmConfig) {
+    config.mac_address = random_mac_address();
+    for network in &mut config.networks {
+        network.mac_address = random_mac_address();
+    }
+    if let Os::MacOs = config.os {
+        config.machine_identifier = Some(random_machine_identifier());
+    }
+}
+
+// vms on NAT network are told apart by mac address, vms with same address get same ip and break each other
+pub fn check_mac_addresses(name: &str, config: &VmConfig) -> Result<(), Exception> {
+    let addresses = mac_addresses(config);
+    for dir in vm_dir::vm_dirs()? {
+        if dir.name() == name {
+            continue;
+        }
+        // vm with invalid config can't run, vz verify reports it
+        let Ok(other) = dir.load_config() else {
+            continue;
+        };
+        if let Some(address) = mac_addresses(&other).into_iter().find(|address| addresses.contains(address)) {
+            return Err(Exception::ValidationError(format!(
+                "mac address is used by other vm, name={}, mac_address={address}",
+                dir.name()
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn mac_addresses(config: &VmConfig) -> Vec<String> {
+    let mut addresses = vec![config.mac_address.to_lowercase()];
+    addresses.extend(config.networks.iter().map(|network| network.mac_address.to_lowercase()));
+    addresses
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use crate::config::vm_dir::VmDir;
+    use crate::vm::platform::mock::MockPlatform;
+
+    #[test]
+    fn create_linux() {
+        let dir = VmDir::new(env::temp_dir().join(format!("vz-test-{}", Uuid::new_v4())));
+        fs::create_dir_all(&dir.dir).unwrap();
+        let platform = MockPlatform::default();
+        let result = super::create_linux_on(&platform, &dir);
+        let config = dir.load_config();
+        fs::remove_dir_all(&dir.dir).unwrap();
+
+        assert!(result.is_ok());
+        assert_eq!(vec![dir.nvram_path.clone()], *platform.efi_variable_stores.borrow());
+        let config = config.unwrap();
+        assert_eq!("02:00:00:00:00:01", config.mac_address);
+        assert_eq!(1, config.cpu);
+    }
 }
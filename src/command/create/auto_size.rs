@@ -0,0 +1,90 @@
+use std::process::Command;
+
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+use crate::vm::platform::Platform;
+
+const GB: u64 = 1024 * 1024 * 1024;
+
+pub struct Size {
+    pub cpu: usize,
+    pub memory: u64,
+    pub disk_size: u64,
+    pub rationale: Vec<String>,
+}
+
+// half of performance cores, quarter of memory and free disk space, rest is left to host
+pub fn recommend(platform: &dyn Platform) -> Result<Size, Exception> {
+    // apple silicon reports performance cores as perflevel0
+    let cores = sysctl("hw.perflevel0.physicalcpu").or_else(|_| sysctl("hw.physicalcpu"))?;
+    let memory = sysctl("hw.memsize")?;
+    let free_disk = free_disk_space()?;
+    Ok(size(cores as usize, memory, free_disk, platform.cpu_range(), platform.memory_range()))
+}
+
+fn size(cores: usize, memory: u64, free_disk: u64, (min_cpu, max_cpu): (usize, usize), (min_memory, max_memory): (u64, u64)) -> Size {
+    let cpu = (cores / 2).clamp(min_cpu.max(1), max_cpu);
+    // whole gb, so config is readable
+    let vm_memory = (memory / 4 / GB * GB).clamp(min_memory.max(GB), max_memory);
+    let disk_size = (free_disk / 4 / 1_000_000_000).clamp(10, 100);
+    let rationale = vec![
+        format!("cpu={cpu}, half of {cores} performance cores"),
+        format!("memory={}G, quarter of {}G host memory", vm_memory / GB, memory / GB),
+        format!(
+            "disk_size={disk_size}G, quarter of {}G free disk space, 10G-100G",
+            free_disk / 1_000_000_000
+        ),
+    ];
+    Size {
+        cpu,
+        memory: vm_memory,
+        disk_size,
+        rationale,
+    }
+}
+
+fn sysctl(name: &str) -> Result<u64, Exception> {
+    let output = Command::new("sysctl").args(["-n", name]).output()?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || value.is_empty() {
+        return Err(Exception::ValidationError(format!("failed to read sysctl, name={name}")));
+    }
+    Ok(value.parse()?)
+}
+
+// free space of volume of vm home dir, where disk image is created
+fn free_disk_space() -> Result<u64, Exception> {
+    let mut path = vm_dir::home_dir();
+    while !path.exists() {
+        path.pop();
+    }
+    // df -k prints header, then "<filesystem> <blocks> <used> <available> ..."
+    let output = Command::new("df").arg("-k").arg(&path).output()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let available = output
+        .lines()
+        .nth(1)
+        .and_then(|line| line.split_whitespace().nth(3))
+        .and_then(|value| value.parse::<u64>().ok());
+    available
+        .map(|available| available * 1024)
+        .ok_or_else(|| Exception::ValidationError(format!("failed to get free disk space, path={}", path.to_string_lossy())))
+}
+
+#[cfg(test)]
+mod tests {
+    const GB: u64 = super::GB;
+
+    #[test]
+    fn size() {
+        let size = super::size(8, 32 * GB, 400_000_000_000, (1, 12), (GB / 2, 30 * GB));
+        assert_eq!(4, size.cpu);
+        assert_eq!(8 * GB, size.memory);
+        assert_eq!(100, size.disk_size);
+
+        let size = super::size(1, 3 * GB, 20_000_000_000, (1, 12), (GB / 2, 30 * GB));
+        assert_eq!(1, size.cpu);
+        assert_eq!(GB, size.memory);
+        assert_eq!(10, size.disk_size);
+    }
+}
@@ -0,0 +1,50 @@
+use std::fs;
+use std::process::Command;
+
+use crate::util::exception::Exception;
+
+// e.g. /etc/localtime -> /var/db/timezone/zoneinfo/Europe/Berlin
+pub fn timezone() -> Result<String, Exception> {
+    let target = fs::read_link("/etc/localtime")?;
+    zone_name(&target.to_string_lossy())
+        .ok_or_else(|| Exception::ValidationError(format!("failed to get host timezone, localtime={}", target.to_string_lossy())))
+}
+
+pub fn locale() -> Result<String, Exception> {
+    let output = Command::new("defaults").args(["read", "-g", "AppleLocale"]).output()?;
+    if !output.status.success() {
+        return Err(Exception::ValidationError(format!("failed to get host locale, status={}", output.status)));
+    }
+    Ok(posix_locale(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+fn zone_name(path: &str) -> Option<String> {
+    path.split_once("zoneinfo/")
+        .map(|(_, zone)| zone.to_string())
+        .filter(|zone| !zone.is_empty())
+}
+
+// e.g. en_US@rg=dezzzz -> en_US.UTF-8, region override of macOS is not part of posix locale
+fn posix_locale(apple_locale: &str) -> String {
+    let locale = apple_locale.split('@').next().unwrap_or_default();
+    format!("{locale}.UTF-8")
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn zone_name() {
+        assert_eq!(
+            super::zone_name("/var/db/timezone/zoneinfo/Europe/Berlin").as_deref(),
+            Some("Europe/Berlin")
+        );
+        assert_eq!(super::zone_name("/usr/share/zoneinfo/UTC").as_deref(), Some("UTC"));
+        assert_eq!(super::zone_name("/etc/localtime"), None);
+    }
+
+    #[test]
+    fn posix_locale() {
+        assert_eq!(super::posix_locale("en_US"), "en_US.UTF-8");
+        assert_eq!(super::posix_locale("en_US@rg=dezzzz"), "en_US.UTF-8");
+    }
+}
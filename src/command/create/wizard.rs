@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+
+use crate::config::vm_config::Os;
+use crate::util::exception::Exception;
+use crate::util::path;
+use crate::vm::platform::Platform;
+use crate::vm::platform::Virtualization;
+
+pub struct Answers {
+    pub os: Os,
+    pub disk_size: u64,
+    pub ipsw: Option<PathBuf>,
+    pub disk_image: Option<PathBuf>,
+    pub oci: Option<String>,
+    pub cpu: usize,
+    pub memory: u64,
+    pub sharing: HashMap<String, String>,
+    pub network: bool,
+}
+
+// empty answer takes default in brackets, invalid answer is asked again
+pub fn ask_all(name: &str) -> Result<Answers, Exception> {
+    println!("create vm, name={name}");
+    let os = ask("os, linux or macOS", "linux", |answer| match answer {
+        "linux" => Ok(Os::Linux),
+        "macOS" | "macos" => Ok(Os::MacOs),
+        _ => Err("os must be linux or macOS".to_string()),
+    })?;
+    let (cpu, memory) = match os {
+        Os::Linux => ("1", "1"),
+        Os::MacOs => ("4", "8"),
+    };
+    let (_, max_cpu) = Virtualization.cpu_range();
+    let max_memory = Virtualization.memory_range().1 / GB;
+    let cpu = ask(&format!("cpu, 1-{max_cpu}"), cpu, |answer| match answer.parse() {
+        Ok(cpu) if (1..=max_cpu).contains(&cpu) => Ok(cpu),
+        _ => Err(format!("cpu must be 1-{max_cpu}")),
+    })?;
+    let memory = ask(&format!("memory in gb, 1-{max_memory}"), memory, |answer| match answer.parse() {
+        Ok(memory) if (1..=max_memory).contains(&memory) => Ok(memory * GB),
+        _ => Err(format!("memory must be 1-{max_memory}")),
+    })?;
+    let disk_size = ask("disk size in gb", "50", |answer| match answer.parse() {
+        Ok(size) if size > 0 => Ok(size),
+        _ => Err("disk size must be positive number".to_string()),
+    })?;
+
+    let (mut ipsw, mut disk_image, mut oci) = (None, None, None);
+    match os {
+        Os::Linux => {
+            let question = "image source, none, path of raw/qcow2/vmdk/vhdx disk image, or oci:<image>";
+            let source = ask(question, "none", |answer| match answer {
+                "none" => Ok(None),
+                _ if answer.starts_with("oci:") => Ok(Some(answer.to_string())),
+                _ => existing_path(answer).map(|path| Some(path.to_string_lossy().to_string())),
+            })?;
+            match source {
+                Some(source) if source.starts_with("oci:") => oci = Some(source["oci:".len()..].to_string()),
+                Some(source) => disk_image = Some(PathBuf::from(source)),
+                None => {}
+            }
+        }
+        Os::MacOs => {
+            ipsw = ask("restore image, path of ipsw or latest", "latest", |answer| match answer {
+                "latest" => Ok(None),
+                _ => existing_path(answer).map(Some),
+            })?;
+        }
+    }
+
+    let sharing = ask("shared dirs, comma separated name=path, e.g. code=~/code", "none", |answer| {
+        let mut sharing = HashMap::new();
+        if answer == "none" {
+            return Ok(sharing);
+        }
+        for share in answer.split(',').map(str::trim) {
+            let Some((name, path)) = share.split_once('=') else {
+                return Err(format!("share must be name=path, share={share}"));
+            };
+            existing_path(path)?;
+            sharing.insert(name.to_string(), path.to_string());
+        }
+        Ok(sharing)
+    })?;
+    let network = ask("network, nat or none", "nat", |answer| match answer {
+        "nat" => Ok(true),
+        "none" => Ok(false),
+        _ => Err("network must be nat or none".to_string()),
+    })?;
+
+    Ok(Answers {
+        os,
+        disk_size,
+        ipsw,
+        disk_image,
+        oci,
+        cpu,
+        memory,
+        sharing,
+        network,
+    })
+}
+
+const GB: u64 = 1024 * 1024 * 1024;
+
+fn ask<T>(question: &str, default: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<T, Exception> {
+    loop {
+        print!("{question} [{default}]: ");
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            return Err(Exception::ValidationError("create cancelled".to_string()));
+        }
+        let answer = match answer.trim() {
+            "" => default,
+            answer => answer,
+        };
+        match parse(answer) {
+            Ok(value) => return Ok(value),
+            Err(err) => println!("{err}"),
+        }
+    }
+}
+
+fn existing_path(answer: &str) -> Result<PathBuf, String> {
+    let path = path::expand(answer)?;
+    if !path.exists() {
+        return Err(format!("path does not exist, path={}", path.to_string_lossy()));
+    }
+    Ok(path)
+}
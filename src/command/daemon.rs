@@ -0,0 +1,401 @@
+use std::env::current_exe;
+use std::fs;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+use std::thread;
+use std::thread::sleep;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+use clap::Args;
+use clap::ValueHint;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use serde_json::Value;
+use tracing::error;
+use tracing::info;
+
+use crate::command::stats;
+use crate::command::status;
+use crate::config::vm_config;
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+use crate::util::path::PathExtension;
+
+// error codes of json-rpc 2.0
+const PARSE_ERROR: i32 = -32700;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const SERVER_ERROR: i32 = -32000;
+// command is killed after timeout, so blocking command doesn't hold connection forever
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(600);
+const MIB: u64 = 1024 * 1024;
+
+#[derive(Args)]
+pub struct Daemon {
+    #[arg(long, help = "unix socket to listen on, default to ~/.vm/vz.sock", value_hint = ValueHint::FilePath)]
+    socket: Option<PathBuf>,
+}
+
+// json-rpc 2.0, one request per line, e.g. {"jsonrpc": "2.0", "id": 1, "method": "run", "params": {"name": "debian"}},
+// list and status are answered by daemon, other methods run vz command, so each vm still has its own runner process
+#[derive(Deserialize, Debug)]
+struct RpcRequest {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Serialize, Debug)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct RpcError {
+    code: i32,
+    message: String,
+}
+
+impl From<Exception> for RpcError {
+    fn from(err: Exception) -> Self {
+        RpcError {
+            code: SERVER_ERROR,
+            message: err.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NameParams {
+    name: String,
+}
+
+// memory in bytes, same as config.json
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RunParams {
+    name: String,
+    cpu: Option<usize>,
+    memory: Option<u64>,
+    #[serde(default)]
+    no_network: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StopParams {
+    name: String,
+    timeout: Option<u64>,
+    #[serde(default)]
+    force: bool,
+}
+
+// any non interactive vz command, e.g. {"args": ["snapshot", "create", "debian", "clean"]}, timeout in seconds
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CommandParams {
+    args: Vec<String>,
+    timeout: Option<u64>,
+}
+
+impl Daemon {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let path = self
+            .socket
+            .as_ref()
+            .map_or_else(|| vm_dir::home_dir().join("vz.sock"), |path| path.to_absolute_path());
+        if path.symlink_metadata().is_ok() {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(Exception::ValidationError(format!(
+                    "daemon is already running, socket={}",
+                    path.to_string_lossy()
+                )));
+            }
+            fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        // other users on host must not control vms
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        info!("daemon started, socket={}", path.to_string_lossy());
+        for stream in listener.incoming() {
+            let stream = stream?;
+            thread::spawn(move || {
+                if let Err(err) = serve(stream) {
+                    error!("failed to serve connection, error={err}");
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+// connection stays open for multiple requests, until client closes it
+fn serve(stream: UnixStream) -> Result<(), Exception> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        writeln!(writer, "{}", process(&line))?;
+    }
+    Ok(())
+}
+
+fn process(line: &str) -> String {
+    let (id, result) = match serde_json::from_str::<RpcRequest>(line) {
+        Ok(request) => {
+            info!("handle request, method={}", request.method);
+            (request.id.clone(), call(&request))
+        }
+        Err(err) => (
+            Value::Null,
+            Err(RpcError {
+                code: PARSE_ERROR,
+                message: format!("invalid request, error={err}"),
+            }),
+        ),
+    };
+    let (result, error) = match result {
+        Ok(result) => (Some(result), None),
+        Err(error) => (None, Some(error)),
+    };
+    let response = RpcResponse {
+        jsonrpc: "2.0",
+        id,
+        result,
+        error,
+    };
+    serde_json::to_string(&response).unwrap_or_default()
+}
+
+fn call(request: &RpcRequest) -> Result<Value, RpcError> {
+    match request.method.as_str() {
+        "list" => Ok(to_value(stats::snapshot()?)?),
+        "status" => {
+            let params: NameParams = params(&request.params)?;
+            Ok(to_value(status::status(&params.name)?)?)
+        }
+        "run" => {
+            let params: RunParams = params(&request.params)?;
+            // vz run --detach exits once runner is launched
+            let mut args = vec!["run".to_string(), params.name, "--detach".to_string()];
+            if let Some(cpu) = params.cpu {
+                args.extend(["--cpu".to_string(), cpu.to_string()]);
+            }
+            if let Some(memory) = params.memory {
+                args.extend(["--memory".to_string(), memory_arg(memory)?]);
+            }
+            if params.no_network {
+                args.push("--no-network".to_string());
+            }
+            run_command(&args, true, COMMAND_TIMEOUT)
+        }
+        "stop" => {
+            let params: StopParams = params(&request.params)?;
+            let mut args = vec!["stop".to_string(), params.name];
+            if params.force {
+                args.push("--force".to_string());
+            }
+            if let Some(timeout) = params.timeout {
+                args.extend(["--timeout".to_string(), timeout.to_string()]);
+            }
+            run_command(&args, true, COMMAND_TIMEOUT)
+        }
+        "command" => {
+            let params: CommandParams = params(&request.params)?;
+            validate_command(&params.args)?;
+            let timeout = params.timeout.map_or(COMMAND_TIMEOUT, Duration::from_secs);
+            run_command(&params.args, false, timeout)
+        }
+        method => Err(RpcError {
+            code: METHOD_NOT_FOUND,
+            message: format!("method not found, method={method}"),
+        }),
+    }
+}
+
+fn params<T: DeserializeOwned>(params: &Value) -> Result<T, RpcError> {
+    serde_json::from_value(params.clone()).map_err(|err| RpcError {
+        code: INVALID_PARAMS,
+        message: format!("invalid params, error={err}"),
+    })
+}
+
+fn to_value<T: Serialize>(value: T) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|err| RpcError {
+        code: SERVER_ERROR,
+        message: err.to_string(),
+    })
+}
+
+// vz run takes memory with unit, e.g. 4096M
+fn memory_arg(memory: u64) -> Result<String, RpcError> {
+    if memory == 0 || !memory.is_multiple_of(MIB) {
+        return Err(RpcError {
+            code: INVALID_PARAMS,
+            message: format!("memory must be multiple of 1MiB in bytes, memory={memory}"),
+        });
+    }
+    Ok(format!("{}M", memory / MIB))
+}
+
+// commands attaching terminal or running until ctrl-c never exit with stdin of daemon
+fn validate_command(args: &[String]) -> Result<(), RpcError> {
+    let (options, guest_args) = args.split_at(args.iter().position(|arg| arg == "--").unwrap_or(args.len()));
+    let has = |flags: &[&str]| options.iter().any(|arg| flags.contains(&arg.as_str()));
+    let blocking = match options.iter().find(|arg| !arg.starts_with('-')).map(String::as_str) {
+        Some("console" | "web" | "daemon" | "edit" | "shell") => true,
+        Some("run") => !has(&["-d", "--detach"]),
+        Some("ssh") => guest_args.len() <= 1,
+        Some("logs") => has(&["-f", "--follow"]),
+        Some("stats") => has(&["-w", "--watch"]),
+        _ => false,
+    };
+    if blocking {
+        return Err(RpcError {
+            code: INVALID_PARAMS,
+            message: format!("interactive or blocking command is not supported, args={args:?}"),
+        });
+    }
+    Ok(())
+}
+
+// result of command method is exit code and output, other methods fail with error of vz
+fn run_command(args: &[String], check: bool, timeout: Duration) -> Result<Value, RpcError> {
+    let mut command = Command::new(current_exe().map_err(Exception::from)?);
+    // global flags go before args, args after -- are passed to guest, e.g. ["ssh", "debian", "--", "ls"]
+    if !vm_config::strict() {
+        command.arg("--strict=false");
+    }
+    command.args(args);
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(Exception::from)?;
+    // read pipes while waiting, otherwise command blocks once pipe buffer is full
+    let stdout = read_in_background(child.stdout.take());
+    let stderr = read_in_background(child.stderr.take());
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(Exception::from)? {
+            break status;
+        }
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(RpcError {
+                code: SERVER_ERROR,
+                message: format!("command timed out, args={args:?}, timeout={}s", timeout.as_secs()),
+            });
+        }
+        sleep(Duration::from_millis(100));
+    };
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    let stderr = String::from_utf8_lossy(&stderr);
+    if check && !status.success() {
+        return Err(RpcError {
+            code: SERVER_ERROR,
+            message: command_error(&stderr),
+        });
+    }
+    if check {
+        return Ok(Value::Null);
+    }
+    Ok(json!({
+        "code": status.code(),
+        "stdout": String::from_utf8_lossy(&stdout),
+        "stderr": stderr,
+    }))
+}
+
+fn read_in_background(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut output = vec![];
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut output);
+        }
+        output
+    })
+}
+
+// main prints returned error to stderr, e.g. "Error: vm not running, name=debian"
+fn command_error(stderr: &str) -> String {
+    let stderr = stderr.trim();
+    stderr.strip_prefix("Error: ").unwrap_or(stderr).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use serde_json::Value;
+
+    fn response(line: &str) -> Value {
+        serde_json::from_str(&super::process(line)).unwrap()
+    }
+
+    #[test]
+    fn process() {
+        assert_eq!(response("{")["error"]["code"], json!(super::PARSE_ERROR));
+        assert_eq!(
+            response(r#"{"jsonrpc": "2.0", "id": 1, "method": "delete"}"#),
+            json!({"jsonrpc": "2.0", "id": 1, "error": {"code": super::METHOD_NOT_FOUND, "message": "method not found, method=delete"}})
+        );
+        assert_eq!(
+            response(r#"{"jsonrpc": "2.0", "id": "a", "method": "status", "params": {}}"#)["error"]["code"],
+            json!(super::INVALID_PARAMS)
+        );
+    }
+
+    #[test]
+    fn memory_arg() {
+        assert_eq!(super::memory_arg(4096 * super::MIB).unwrap(), "4096M");
+        assert_eq!(super::memory_arg(4_000_000_000).unwrap_err().code, super::INVALID_PARAMS);
+        assert!(super::memory_arg(0).is_err());
+    }
+
+    #[test]
+    fn validate_command() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert!(super::validate_command(&args(&["snapshot", "create", "debian", "clean"])).is_ok());
+        assert!(super::validate_command(&args(&["run", "debian", "-d"])).is_ok());
+        assert!(super::validate_command(&args(&["ssh", "debian", "--", "ls"])).is_ok());
+        assert!(super::validate_command(&args(&["exec", "debian", "--", "console"])).is_ok());
+        assert!(super::validate_command(&args(&["run", "debian"])).is_err());
+        assert!(super::validate_command(&args(&["--strict=false", "console", "debian"])).is_err());
+        assert!(super::validate_command(&args(&["ssh", "debian"])).is_err());
+        assert!(super::validate_command(&args(&["logs", "debian", "-f"])).is_err());
+    }
+
+    #[test]
+    fn command_error() {
+        assert_eq!(
+            super::command_error("Error: vm not running, name=debian\n"),
+            "vm not running, name=debian"
+        );
+        assert_eq!(super::command_error("thread 'main' panicked"), "thread 'main' panicked");
+    }
+}
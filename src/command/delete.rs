@@ -0,0 +1,148 @@
+use std::fs;
+use std::io;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+use clap::Args;
+use clap::ValueHint;
+use tracing::info;
+use tracing::warn;
+
+use crate::command::autostart;
+use crate::config::vm_archive;
+use crate::config::vm_archive::Compression;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::dhcp_lease;
+use crate::util::dhcp_lease::BOOTPTAB_PATH;
+use crate::util::exception::Exception;
+use crate::util::path::PathExtension;
+use crate::util::terminal;
+
+#[derive(Args)]
+pub struct Delete {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(long, short, help = "delete without confirmation", default_value_t = false)]
+    force: bool,
+
+    #[arg(
+        long,
+        help = "move disk images out of vm dir before deleting, e.g. ~/.vm/<name>.img",
+        default_value_t = false
+    )]
+    keep_disk: bool,
+
+    #[arg(long, help = "export vm as zstd archive before deleting, e.g. --export=debian.tar.zst", value_hint = ValueHint::FilePath)]
+    export: Option<PathBuf>,
+}
+
+impl Delete {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        if let Some(pid) = dir.pid() {
+            return Err(Exception::ValidationError(format!(
+                "vm is running, stop it first, name={name}, pid={pid}"
+            )));
+        }
+        // vm can't start, and other commands can't change vm while deleting
+        let _lock = dir.lock("delete")?;
+        let config = dir.load_config()?;
+
+        let disks = self.kept_disks(&dir, &config.disks)?;
+        if !self.force && !confirm(name, self.keep_disk)? {
+            return Ok(());
+        }
+
+        if let Some(file) = &self.export {
+            let file = file.to_absolute_path();
+            if file.exists() {
+                return Err(Exception::ValidationError(format!(
+                    "file already exists, path={}",
+                    file.to_string_lossy()
+                )));
+            }
+            let options = vm_archive::Options {
+                compression: Compression::Zstd,
+                level: None,
+                threads: 0,
+                encrypt: false,
+                base: None,
+            };
+            vm_archive::export(&dir, &file, &options)?;
+            info!("vm exported, name={name}, file={}", file.to_string_lossy());
+        }
+        for (from, to) in &disks {
+            info!("keep disk, from={}, to={}", from.to_string_lossy(), to.to_string_lossy());
+            fs::rename(from, to)?;
+        }
+
+        if autostart::enabled(name) {
+            autostart::disable(&dir)?;
+        }
+        release_reservation(name, &config.mac_address)?;
+
+        info!("delete vm dir, dir={}", dir.dir.to_string_lossy());
+        fs::remove_dir_all(&dir.dir)?;
+        info!("vm deleted, name={name}");
+        Ok(())
+    }
+
+    // disks are moved next to vm dirs, named by vm, e.g. debian.img and debian-data.img
+    fn kept_disks(&self, dir: &VmDir, disks: &[String]) -> Result<Vec<(PathBuf, PathBuf)>, Exception> {
+        if !self.keep_disk {
+            return Ok(vec![]);
+        }
+        let name = dir.name();
+        let home_dir = vm_dir::home_dir();
+        let mut kept = vec![(dir.disk_path.clone(), home_dir.join(format!("{name}.img")))];
+        kept.extend(
+            disks
+                .iter()
+                .map(|disk| (dir.extra_disk_path(disk), home_dir.join(format!("{name}-{disk}.img")))),
+        );
+        if let Some((_, path)) = kept.iter().find(|(_, path)| path.exists()) {
+            return Err(Exception::ValidationError(format!(
+                "file already exists, path={}",
+                path.to_string_lossy()
+            )));
+        }
+        Ok(kept)
+    }
+}
+
+fn confirm(name: &str, keep_disk: bool) -> Result<bool, Exception> {
+    if !io::stdin().is_terminal() {
+        return Err(Exception::ValidationError(format!(
+            "delete requires confirmation, use --force to delete without it, name={name}"
+        )));
+    }
+    let data = if keep_disk {
+        "snapshots and config, disks are kept"
+    } else {
+        "disks, snapshots and config"
+    };
+    terminal::confirm(&format!("delete vm {name} with its {data}?"), false)
+}
+
+// reserved ip would be given to next vm with same mac address
+fn release_reservation(name: &str, mac_address: &str) -> Result<(), Exception> {
+    let bootptab = dhcp_lease::read_bootptab()?;
+    if !dhcp_lease::reservations(&bootptab).iter().any(|reservation| reservation.name == name) {
+        return Ok(());
+    }
+    let updated = dhcp_lease::update_reservation(&bootptab, name, mac_address, None);
+    match fs::write(BOOTPTAB_PATH, updated) {
+        Ok(()) => info!("ip reservation released, name={name}"),
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            warn!("{BOOTPTAB_PATH} is not writable, remove entry of vm manually with sudo, name={name}")
+        }
+        Err(err) => return Err(err.into()),
+    }
+    Ok(())
+}
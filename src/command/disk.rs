@@ -0,0 +1,198 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+
+use clap::Args;
+use clap::Subcommand;
+use tracing::info;
+
+use crate::config::settings;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::disk_image;
+use crate::util::exception::Exception;
+use crate::util::file_lock::FileLock;
+
+#[derive(Args)]
+pub struct Disk {
+    #[command(subcommand)]
+    command: DiskCommand,
+}
+
+#[derive(Subcommand)]
+enum DiskCommand {
+    #[command(about = "deallocate zero filled chunks of disk image, e.g. space freed by guest without discard")]
+    Punch {
+        #[arg(help = "vm name")]
+        name: String,
+    },
+    #[command(about = "add disk to stopped vm, attached as next virtio block device")]
+    Add {
+        #[arg(help = "vm name")]
+        name: String,
+
+        #[arg(long, help = "disk size in gb")]
+        size: u64,
+
+        #[arg(long = "name", help = "disk name, file is <name>.img in vm dir, default to disk<N>")]
+        disk: Option<String>,
+    },
+    #[command(about = "list disks of vm")]
+    List {
+        #[arg(help = "vm name")]
+        name: String,
+    },
+    #[command(about = "remove added disk from stopped vm, its data is deleted")]
+    Remove {
+        #[arg(help = "vm name")]
+        name: String,
+
+        #[arg(help = "disk name")]
+        disk: String,
+    },
+}
+
+impl Disk {
+    pub fn execute(&self) -> Result<(), Exception> {
+        match &self.command {
+            DiskCommand::Punch { name } => punch(name),
+            DiskCommand::Add { name, size, disk } => add(name, *size, disk.as_deref()),
+            DiskCommand::List { name } => list(name),
+            DiskCommand::Remove { name, disk } => remove(name, disk),
+        }
+    }
+}
+
+// vm can't start while disk is changed
+fn stopped_vm_dir(name: &str) -> Result<(VmDir, FileLock), Exception> {
+    let dir = vm_dir::vm_dir(name);
+    if !dir.initialized() {
+        return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+    }
+    let lock = dir.lock("disk")?;
+    dir.check_not_suspended()?;
+    Ok((dir, lock))
+}
+
+fn add(name: &str, size: u64, disk: Option<&str>) -> Result<(), Exception> {
+    let (dir, _lock) = stopped_vm_dir(name)?;
+    let mut config = dir.load_config()?;
+    let disk = match disk {
+        Some(disk) => {
+            validate_disk_name(disk)?;
+            if config.disks.iter().any(|name| name == disk) {
+                return Err(Exception::ValidationError(format!("disk already exists, disk={disk}")));
+            }
+            disk.to_string()
+        }
+        None => next_disk_name(&config.disks),
+    };
+    let path = dir.extra_disk_path(&disk);
+    if path.exists() {
+        return Err(Exception::ValidationError(format!(
+            "disk file already exists, path={}",
+            path.to_string_lossy()
+        )));
+    }
+    if size == 0 {
+        return Err(Exception::ValidationError("disk size must be larger than 0".to_string()));
+    }
+
+    settings::check_storage_quota(size * 1_000_000_000)?;
+    info!("create disk, file={}, size={size}G", path.to_string_lossy());
+    fs::File::create_new(&path)?.set_len(size * 1_000_000_000)?;
+    config.disks.push(disk);
+    dir.save_config(&config)?;
+    Ok(())
+}
+
+fn list(name: &str) -> Result<(), Exception> {
+    let dir = vm_dir::vm_dir(name);
+    if !dir.initialized() {
+        return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+    }
+    let config = dir.load_config()?;
+    println!("{:<16}{:<12}{:<12}file", "name", "size", "allocated");
+    let disks = [("disk".to_string(), dir.disk_path.clone())]
+        .into_iter()
+        .chain(config.disks.iter().map(|disk| (disk.clone(), dir.extra_disk_path(disk))));
+    for (disk, path) in disks {
+        let (size, allocated) = match path.metadata() {
+            Ok(metadata) => (
+                format!("{:.2}G", metadata.len() as f32 / 1_000_000_000.0),
+                format!("{:.2}G", (metadata.blocks() * 512) as f32 / 1_000_000_000.0),
+            ),
+            Err(_) => ("-".to_string(), "-".to_string()),
+        };
+        println!("{:<16}{:<12}{:<12}{}", disk, size, allocated, path.to_string_lossy());
+    }
+    Ok(())
+}
+
+fn remove(name: &str, disk: &str) -> Result<(), Exception> {
+    let (dir, _lock) = stopped_vm_dir(name)?;
+    let mut config = dir.load_config()?;
+    let Some(index) = config.disks.iter().position(|name| name == disk) else {
+        return Err(Exception::ValidationError(format!("disk not found, name={name}, disk={disk}")));
+    };
+    config.disks.remove(index);
+    dir.save_config(&config)?;
+    let path = dir.extra_disk_path(disk);
+    if path.exists() {
+        info!("remove disk, file={}", path.to_string_lossy());
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+// name is file name in vm dir, disk is main disk.img
+fn validate_disk_name(disk: &str) -> Result<(), Exception> {
+    let valid = !disk.is_empty() && disk.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid || disk == "disk" {
+        return Err(Exception::ValidationError(format!(
+            "invalid disk name, it must be letters, digits, - or _, and not disk, disk={disk}"
+        )));
+    }
+    Ok(())
+}
+
+pub fn next_disk_name(disks: &[String]) -> String {
+    (1..).map(|index| format!("disk{index}")).find(|name| !disks.contains(name)).unwrap()
+}
+
+fn punch(name: &str) -> Result<(), Exception> {
+    let dir = vm_dir::vm_dir(name);
+    if !dir.initialized() {
+        return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+    }
+    // guest may write into chunk between read and punch
+    let _lock = dir.lock("disk")?;
+
+    let before = dir.disk_path.metadata()?.blocks() * 512;
+    info!("punch zero chunks, disk={}", dir.disk_path.to_string_lossy());
+    disk_image::punch_zero_chunks(&dir.disk_path)?;
+    let after = dir.disk_path.metadata()?.blocks() * 512;
+    info!(
+        "disk punched, before={:.2}G, after={:.2}G",
+        before as f32 / 1_000_000_000.0,
+        after as f32 / 1_000_000_000.0
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn validate_disk_name() {
+        assert!(super::validate_disk_name("data").is_ok());
+        assert!(super::validate_disk_name("data-1_a").is_ok());
+        assert!(super::validate_disk_name("disk").is_err());
+        assert!(super::validate_disk_name("../data").is_err());
+        assert!(super::validate_disk_name("").is_err());
+    }
+
+    #[test]
+    fn next_disk_name() {
+        assert_eq!(super::next_disk_name(&[]), "disk1");
+        assert_eq!(super::next_disk_name(&["disk1".to_string(), "data".to_string()]), "disk2");
+    }
+}
@@ -0,0 +1,63 @@
+use clap::Args;
+use clap::Subcommand;
+
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+use crate::vm::control;
+use crate::vm::control::Request;
+
+#[derive(Args)]
+pub struct Display {
+    #[command(subcommand)]
+    command: DisplayCommand,
+}
+
+#[derive(Subcommand)]
+enum DisplayCommand {
+    #[command(about = "change display resolution of running vm, linux guest requires virtio-gpu driver support")]
+    Resize {
+        #[arg(help = "vm name")]
+        name: String,
+
+        #[arg(help = "resolution in pixels, e.g. 2560x1440")]
+        size: String,
+    },
+}
+
+impl Display {
+    pub fn execute(&self) -> Result<(), Exception> {
+        match &self.command {
+            DisplayCommand::Resize { name, size } => resize(name, size),
+        }
+    }
+}
+
+fn resize(name: &str, size: &str) -> Result<(), Exception> {
+    let dir = vm_dir::vm_dir(name);
+    if !dir.initialized() {
+        return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+    }
+    if dir.pid().is_none() {
+        return Err(Exception::ValidationError(format!("vm is not running, name={name}")));
+    }
+    let Some((width, height)) = parse_size(size) else {
+        return Err(Exception::ValidationError(format!("invalid display size, size={size}")));
+    };
+    control::send(&dir, &Request::ResizeDisplay { width, height })?;
+    Ok(())
+}
+
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let (width, height) = size.split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parse_size() {
+        assert_eq!(super::parse_size("2560x1440"), Some((2560, 1440)));
+        assert_eq!(super::parse_size("2560"), None);
+        assert_eq!(super::parse_size("2560xhigh"), None);
+    }
+}
@@ -0,0 +1,134 @@
+use std::fs;
+use std::path::PathBuf;
+
+use clap::Args;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::command::gc;
+use crate::command::verify;
+use crate::command::verify::Issue;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+use crate::util::file_lock;
+
+#[derive(Args)]
+pub struct Doctor {
+    #[arg(long, help = "remove stale files and temp dirs, other issues are only reported", default_value_t = false)]
+    fix: bool,
+}
+
+// leftover of crashed runner or aborted create, safe to remove
+struct Stale {
+    problem: String,
+    path: PathBuf,
+}
+
+impl Doctor {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let home_dir = vm_dir::home_dir();
+        if !home_dir.exists() {
+            return Err(Exception::ValidationError(format!("{} does not exist", home_dir.to_string_lossy())));
+        }
+
+        let mut issues = vec![];
+        let mut stale = vec![];
+        let mut entries: Vec<_> = fs::read_dir(&home_dir)?.collect::<Result<_, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if path.is_dir() && Uuid::parse_str(&file_name).is_err() {
+                let dir = vm_dir::vm_dir(&file_name);
+                if !dir.initialized() {
+                    issues.push(Issue {
+                        problem: format!(
+                            "vm dir is incomplete, config.json, disk.img or nvram.bin not found, path={}",
+                            path.to_string_lossy()
+                        ),
+                        fix: "restore missing files from backup, or remove dir".to_string(),
+                    });
+                    continue;
+                }
+                issues.extend(verify::check(&dir).into_iter().map(|issue| Issue {
+                    problem: format!("{file_name}: {}", issue.problem),
+                    fix: issue.fix,
+                }));
+                if dir.pid().is_none() {
+                    stale.extend(stale_files(&dir));
+                }
+            } else if is_name_lock(&file_name) && file_lock::owner(&path).is_none() {
+                stale.push(Stale {
+                    problem: "lock of vm name left by killed create, clone or import".to_string(),
+                    path,
+                });
+            }
+        }
+        for path in gc::temp_vm_dirs()? {
+            stale.push(Stale {
+                problem: "temp vm dir left by aborted create or import".to_string(),
+                path,
+            });
+        }
+
+        for item in &stale {
+            let path = item.path.to_string_lossy();
+            if self.fix {
+                if item.path.is_dir() {
+                    fs::remove_dir_all(&item.path)?;
+                } else {
+                    fs::remove_file(&item.path)?;
+                }
+                println!("{}, path={path}\n  fixed: removed", item.problem);
+            } else {
+                println!("{}, path={path}\n  fix: run vz doctor --fix to remove", item.problem);
+            }
+        }
+        for issue in &issues {
+            println!("{}\n  fix: {}", issue.problem, issue.fix);
+        }
+
+        // stale files don't break vms, only issues fail
+        if !issues.is_empty() {
+            return Err(Exception::ValidationError(format!("found {} issues", issues.len())));
+        }
+        if stale.is_empty() || self.fix {
+            info!("no issues found");
+        }
+        Ok(())
+    }
+}
+
+// created by runner, left in vm dir once it exits, unresponsive marker is only left if runner crashed or was killed
+fn stale_files(dir: &VmDir) -> Vec<Stale> {
+    let name = dir.name();
+    let files = [
+        (&dir.unresponsive_path, "unresponsive marker"),
+        (&dir.control_path, "control socket"),
+        (&dir.console_path, "console link"),
+    ];
+    files
+        .into_iter()
+        .filter(|(path, _)| path.symlink_metadata().is_ok())
+        .map(|(path, file)| Stale {
+            problem: format!("{name}: {file} of stopped vm"),
+            path: path.clone(),
+        })
+        .collect()
+}
+
+// lock file of vm_dir::lock_name, e.g. .debian.lock
+fn is_name_lock(file_name: &str) -> bool {
+    file_name.len() > ".lock".len() + 1 && file_name.starts_with('.') && file_name.ends_with(".lock")
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn is_name_lock() {
+        assert!(super::is_name_lock(".debian.lock"));
+        assert!(!super::is_name_lock(".lock"));
+        assert!(!super::is_name_lock(".list-cache.json"));
+    }
+}
@@ -0,0 +1,103 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use clap::Args;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::config::vm_config::VmConfig;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+use crate::util::terminal;
+
+#[derive(Args)]
+pub struct Edit {
+    #[arg(help = "vm name")]
+    name: String,
+}
+
+impl Edit {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        let original = fs::read_to_string(&dir.config_path)?;
+        let current = dir.parse_config(&original)?;
+
+        // edit copy, so config is never left half written or invalid
+        let file = env::temp_dir().join(format!("vz-{name}-config-{}.json", Uuid::new_v4()));
+        fs::write(&file, &original)?;
+        let result = edit(&dir, &current, &file);
+        fs::remove_file(&file)?;
+        let Some(json) = result? else {
+            return Ok(());
+        };
+        if json == original {
+            info!("config not changed, name={name}");
+            return Ok(());
+        }
+        fs::write(&dir.config_path, json)?;
+        info!("config saved, name={name}, path={}", dir.config_path.to_string_lossy());
+        if dir.pid().is_some() {
+            info!("vm is running, changes take effect on next start, name={name}");
+        }
+        Ok(())
+    }
+}
+
+// reopen editor until edited config is valid, or user gives up
+fn edit(dir: &VmDir, current: &VmConfig, file: &Path) -> Result<Option<String>, Exception> {
+    loop {
+        open_editor(file)?;
+        let json = fs::read_to_string(file)?;
+        match validate(dir, current, &json) {
+            Ok(_) => return Ok(Some(json)),
+            Err(err) => {
+                println!("{err}");
+                if !terminal::confirm("edit again?", true)? {
+                    info!("edit cancelled, config not changed, name={}", dir.name());
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+fn validate(dir: &VmDir, current: &VmConfig, json: &str) -> Result<(), Exception> {
+    let config = dir.parse_config(json)?;
+    config.validate_host_limits()?;
+    // nvram and disk are created for os, macOS nvram is also bound to hardware model and machine identifier
+    if config.os != current.os {
+        return Err(Exception::ValidationError("os can not be changed".to_string()));
+    }
+    if config.hardware_model != current.hardware_model || config.machine_identifier != current.machine_identifier {
+        return Err(Exception::ValidationError(
+            "hardware_model and machine_identifier can not be changed".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// same as git, VISUAL or EDITOR may contain args, e.g. "code --wait"
+fn open_editor(file: &Path) -> Result<(), Exception> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$@\""))
+        .arg(&editor)
+        .arg(file)
+        .status()?;
+    if !status.success() {
+        return Err(Exception::ValidationError(format!("editor failed, editor={editor}, status={status}")));
+    }
+    Ok(())
+}
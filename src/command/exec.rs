@@ -0,0 +1,41 @@
+use std::io;
+use std::io::IsTerminal;
+use std::process;
+
+use clap::Args;
+
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+use crate::vm::exec;
+
+#[derive(Args)]
+pub struct Exec {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(
+        help = "command and args, e.g. vz exec dev -- uname -a",
+        required = true,
+        trailing_var_arg = true,
+        allow_hyphen_values = true
+    )]
+    command: Vec<String>,
+}
+
+impl Exec {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        if dir.pid().is_none() {
+            return Err(Exception::ValidationError(format!("vm is not running, name={name}")));
+        }
+
+        // no pty, terminal stdin is not forwarded, so command doesn't wait for input
+        let stdin = Some(io::stdin()).filter(|stdin| !stdin.is_terminal());
+        let code = exec::run(&dir, &self.command, stdin, &mut io::stdout())?;
+        process::exit(code);
+    }
+}
@@ -0,0 +1,14 @@
+use clap::Args;
+
+use crate::util::exception::Exception;
+use crate::vm::exec;
+
+// run inside macOS guest, e.g. by launch daemon
+#[derive(Args)]
+pub struct ExecAgent;
+
+impl ExecAgent {
+    pub fn execute(&self) -> Result<(), Exception> {
+        exec::serve()
+    }
+}
@@ -0,0 +1,79 @@
+use std::path::PathBuf;
+
+use clap::Args;
+use clap::ValueHint;
+use tracing::info;
+
+use crate::config::vm_archive;
+use crate::config::vm_archive::Compression;
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+use crate::util::path::PathExtension;
+
+#[derive(Args)]
+pub struct Export {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(help = "archive file, e.g. debian.tar.zst", value_hint = ValueHint::FilePath)]
+    file: PathBuf,
+
+    #[arg(long, help = "compression", default_value = "zstd")]
+    compression: Compression,
+
+    #[arg(long, help = "compression level, zstd 1-19, gzip 1-9, default is 3 for zstd, 6 for gzip")]
+    level: Option<u32>,
+
+    #[arg(long, help = "zstd compression threads, 0 uses all cores", default_value_t = 0)]
+    threads: u32,
+
+    #[arg(
+        long,
+        help = "encrypt archive with passphrase, requires age, e.g. brew install age",
+        default_value_t = false
+    )]
+    encrypt: bool,
+
+    #[arg(
+        long,
+        help = "only export disk extents changed since base archive",
+        requires = "base",
+        default_value_t = false
+    )]
+    incremental: bool,
+
+    #[arg(long, help = "previous archive of vm, e.g. --base=debian-monday.tar.zst", requires = "incremental", value_hint = ValueHint::FilePath)]
+    base: Option<PathBuf>,
+}
+
+impl Export {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        // disk must not be archived while written by running vm
+        if dir.pid().is_some() {
+            return Err(Exception::ValidationError(format!("vm is running, name={name}")));
+        }
+        let file = self.file.to_absolute_path();
+        if file.exists() {
+            return Err(Exception::ValidationError(format!(
+                "file already exists, path={}",
+                file.to_string_lossy()
+            )));
+        }
+
+        let options = vm_archive::Options {
+            compression: self.compression,
+            level: self.level,
+            threads: self.threads,
+            encrypt: self.encrypt,
+            base: self.base.as_ref().map(|base| base.to_absolute_path()),
+        };
+        vm_archive::export(&dir, &file, &options)?;
+        info!("vm exported, name={name}, file={}", file.to_string_lossy());
+        Ok(())
+    }
+}
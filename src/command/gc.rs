@@ -0,0 +1,171 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use clap::Args;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::config::ipsw_cache;
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+use crate::util::terminal;
+
+// temp vm dir of create or import in progress is updated constantly
+const TEMP_DIR_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Args)]
+pub struct Gc {
+    #[arg(long, help = "remove cached ipsw not used within days", default_value_t = 30)]
+    retention: u64,
+
+    #[arg(long, help = "only list files to remove", default_value_t = false)]
+    dry_run: bool,
+
+    #[arg(short, long, help = "remove without confirmation", default_value_t = false)]
+    yes: bool,
+}
+
+struct Garbage {
+    path: PathBuf,
+    reason: &'static str,
+    size: u64,
+}
+
+impl Gc {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let mut garbage = vec![];
+        self.collect_ipsw(&mut garbage)?;
+        collect_temp_vm_dirs(&mut garbage)?;
+        collect_ephemeral_vm_dirs(&mut garbage)?;
+        if garbage.is_empty() {
+            info!("nothing to remove");
+            return Ok(());
+        }
+
+        println!("{:<16}{:<12}path", "reason", "size");
+        for item in &garbage {
+            println!(
+                "{:<16}{:<12}{}",
+                item.reason,
+                terminal::format_size(item.size),
+                item.path.to_string_lossy()
+            );
+        }
+        let total: u64 = garbage.iter().map(|item| item.size).sum();
+        println!("\ntotal: {} items, {}", garbage.len(), terminal::format_size(total));
+        if self.dry_run || !self.yes && !terminal::confirm("remove?", false)? {
+            return Ok(());
+        }
+
+        for item in &garbage {
+            info!("remove, path={}", item.path.to_string_lossy());
+            if item.path.is_dir() {
+                fs::remove_dir_all(&item.path)?;
+            } else {
+                fs::remove_file(&item.path)?;
+            }
+        }
+        info!("removed {} items, size={}", garbage.len(), terminal::format_size(total));
+        Ok(())
+    }
+
+    // vm doesn't depend on ipsw after creation, ipsw is kept only to create more vms
+    fn collect_ipsw(&self, garbage: &mut Vec<Garbage>) -> Result<(), Exception> {
+        let dir = ipsw_cache::cache_dir();
+        if !dir.exists() {
+            return Ok(());
+        }
+        let retention = Duration::from_secs(self.retention * 24 * 60 * 60);
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            // metadata is removed along with its ipsw
+            if !path.is_file() || path.extension().is_some_and(|extension| extension == "json") {
+                continue;
+            }
+            let reason = if path.extension().is_some_and(|extension| extension == "download") {
+                "partial ipsw"
+            } else {
+                "unused ipsw"
+            };
+            if unused_for(&path)? > retention {
+                let metadata_path = ipsw_cache::metadata_path(&path);
+                if metadata_path.exists() {
+                    garbage.push(Garbage {
+                        size: disk_usage(&metadata_path)?,
+                        path: metadata_path,
+                        reason,
+                    });
+                }
+                garbage.push(Garbage {
+                    size: disk_usage(&path)?,
+                    path,
+                    reason,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn collect_temp_vm_dirs(garbage: &mut Vec<Garbage>) -> Result<(), Exception> {
+    for path in temp_vm_dirs()? {
+        garbage.push(Garbage {
+            size: disk_usage(&path)?,
+            path,
+            reason: "temp vm",
+        });
+    }
+    Ok(())
+}
+
+// create and import build vm in temp dir named by uuid under home dir, then rename it to vm name, left behind if aborted
+pub fn temp_vm_dirs() -> Result<Vec<PathBuf>, Exception> {
+    let home_dir = vm_dir::home_dir();
+    if !home_dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut dirs = vec![];
+    for entry in fs::read_dir(home_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_dir() && Uuid::parse_str(&entry.file_name().to_string_lossy()).is_ok() && unused_for(&path)? > TEMP_DIR_RETENTION {
+            dirs.push(path);
+        }
+    }
+    Ok(dirs)
+}
+
+fn collect_ephemeral_vm_dirs(garbage: &mut Vec<Garbage>) -> Result<(), Exception> {
+    for dir in vm_dir::ephemeral_vm_dirs()? {
+        if dir.pid().is_none() {
+            garbage.push(Garbage {
+                size: disk_usage(&dir.dir)?,
+                path: dir.dir,
+                reason: "ephemeral vm",
+            });
+        }
+    }
+    Ok(())
+}
+
+fn unused_for(path: &Path) -> Result<Duration, Exception> {
+    let metadata = path.metadata()?;
+    let last_used = metadata.modified()?.max(metadata.accessed()?);
+    Ok(SystemTime::now().duration_since(last_used).unwrap_or_default())
+}
+
+// allocated blocks, disk images are sparse
+fn disk_usage(path: &Path) -> Result<u64, Exception> {
+    let metadata = path.symlink_metadata()?;
+    let mut size = metadata.blocks() * 512;
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            size += disk_usage(&entry?.path())?;
+        }
+    }
+    Ok(size)
+}
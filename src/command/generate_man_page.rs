@@ -0,0 +1,24 @@
+use std::fs;
+use std::path::PathBuf;
+
+use clap::Args;
+use clap::ValueHint;
+use tracing::info;
+
+use crate::util::exception::Exception;
+
+#[derive(Args)]
+pub struct GenerateManPage {
+    #[arg(help = "dir to write man pages into, e.g. /usr/local/share/man/man1", value_hint = ValueHint::DirPath)]
+    dir: PathBuf,
+}
+
+impl GenerateManPage {
+    // writes vz.1 and vz-<command>.1 for each command
+    pub fn execute(&self, cli: clap::Command) -> Result<(), Exception> {
+        fs::create_dir_all(&self.dir)?;
+        clap_mangen::generate_to(cli, &self.dir)?;
+        info!("man pages generated, dir={}", self.dir.to_string_lossy());
+        Ok(())
+    }
+}
@@ -1,21 +1,15 @@
-use std::io;
-
 use clap::Args;
-use clap::CommandFactory;
-use clap_complete::generate;
-use clap_complete::shells::Zsh;
 
+use crate::command::completion;
 use crate::util::exception::Exception;
-use crate::Cli;
-
-const CARGO_PKG_NAME: &str = env!("CARGO_PKG_NAME");
 
+// kept for existing setups, vz completion zsh generates same script
 #[derive(Args)]
 pub struct GenerateZshCompletion {}
 
 impl GenerateZshCompletion {
-    pub fn execute(&self) -> Result<(), Exception> {
-        generate(Zsh, &mut Cli::command(), CARGO_PKG_NAME, &mut io::stdout());
+    pub fn execute(&self, cli: clap::Command) -> Result<(), Exception> {
+        completion::print(clap_complete::Shell::Zsh, cli);
         Ok(())
     }
 }
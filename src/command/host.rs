@@ -0,0 +1,122 @@
+use std::env;
+use std::process::Command;
+
+use clap::Args;
+use clap::Subcommand;
+use objc2::runtime::AnyClass;
+use objc2::sel;
+use objc2::ClassType;
+use objc2_foundation::NSProcessInfo;
+use objc2_virtualization::VZLinuxRosettaAvailability;
+use objc2_virtualization::VZLinuxRosettaDirectoryShare;
+use objc2_virtualization::VZVirtualMachine;
+use objc2_virtualization::VZVirtualMachineConfiguration;
+use serde::Serialize;
+
+use crate::util::exception::Exception;
+use crate::util::json;
+use crate::vm::linux;
+
+#[derive(Args)]
+pub struct Host {
+    #[command(subcommand)]
+    command: HostCommand,
+}
+
+#[derive(Subcommand)]
+enum HostCommand {
+    #[command(about = "print host capabilities relevant to vms")]
+    Info {
+        #[arg(long, help = "print as json", default_value_t = false)]
+        json: bool,
+    },
+}
+
+#[derive(Serialize, Debug)]
+struct HostInfo {
+    architecture: String,
+    chip: String,
+    macos_version: String,
+    min_cpu: usize,
+    max_cpu: usize,
+    min_memory: u64,
+    max_memory: u64,
+    nested_virtualization: bool,
+    save_restore: bool,
+    usb_controller: bool,
+    rosetta: String,
+    vmnet_entitlement: bool,
+}
+
+impl Host {
+    pub fn execute(&self) -> Result<(), Exception> {
+        match self.command {
+            HostCommand::Info { json } => info(json),
+        }
+    }
+}
+
+fn info(json: bool) -> Result<(), Exception> {
+    let info = host_info()?;
+    if json {
+        println!("{}", json::to_json_pretty(&info)?);
+        return Ok(());
+    }
+    let gb = |bytes: u64| format!("{:.2}G", bytes as f64 / (1024.0 * 1024.0 * 1024.0));
+    let rows = [
+        ("architecture", info.architecture),
+        ("chip", info.chip),
+        ("macOS version", info.macos_version),
+        ("cpu", format!("{}-{}", info.min_cpu, info.max_cpu)),
+        ("memory", format!("{}-{}", gb(info.min_memory), gb(info.max_memory))),
+        ("nested virtualization", supported(info.nested_virtualization)),
+        ("save/restore", supported(info.save_restore)),
+        ("usb controller", supported(info.usb_controller)),
+        ("rosetta", info.rosetta),
+        ("vmnet entitlement", supported(info.vmnet_entitlement)),
+    ];
+    for (key, value) in rows {
+        println!("{key:<24}{value}");
+    }
+    Ok(())
+}
+
+fn supported(value: bool) -> String {
+    if value { "supported" } else { "not supported" }.to_string()
+}
+
+// newer apis are detected by class and selector, so same binary reports correctly on older macOS
+fn host_info() -> Result<HostInfo, Exception> {
+    let chip = Command::new("sysctl").args(["-n", "machdep.cpu.brand_string"]).output()?;
+    let nested_virtualization = linux::nested_virtualization_supported();
+    let rosetta = match unsafe { VZLinuxRosettaDirectoryShare::availability() } {
+        VZLinuxRosettaAvailability::Installed => "installed",
+        VZLinuxRosettaAvailability::NotInstalled => "not installed, install with softwareupdate --install-rosetta",
+        _ => "not supported",
+    };
+    unsafe {
+        Ok(HostInfo {
+            architecture: env::consts::ARCH.to_string(),
+            chip: String::from_utf8_lossy(&chip.stdout).trim().to_string(),
+            macos_version: NSProcessInfo::processInfo().operatingSystemVersionString().to_string(),
+            min_cpu: VZVirtualMachineConfiguration::minimumAllowedCPUCount(),
+            max_cpu: VZVirtualMachineConfiguration::maximumAllowedCPUCount(),
+            min_memory: VZVirtualMachineConfiguration::minimumAllowedMemorySize(),
+            max_memory: VZVirtualMachineConfiguration::maximumAllowedMemorySize(),
+            nested_virtualization,
+            save_restore: VZVirtualMachine::class().responds_to(sel!(saveMachineStateToURL:completionHandler:)),
+            usb_controller: AnyClass::get("VZXHCIControllerConfiguration").is_some(),
+            rosetta: rosetta.to_string(),
+            vmnet_entitlement: vmnet_entitlement()?,
+        })
+    }
+}
+
+// bridged network requires com.apple.vm.networking, which is only granted by apple to signed binaries
+fn vmnet_entitlement() -> Result<bool, Exception> {
+    let output = Command::new("codesign")
+        .args(["-d", "--entitlements", "-", "--xml"])
+        .arg(env::current_exe()?)
+        .output()?;
+    Ok(String::from_utf8_lossy(&output.stdout).contains("com.apple.vm.networking"))
+}
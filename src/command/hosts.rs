@@ -0,0 +1,113 @@
+use std::fs;
+use std::io;
+
+use clap::Args;
+use clap::Subcommand;
+use tracing::info;
+
+use crate::config::vm_dir;
+use crate::util::dhcp_lease;
+use crate::util::exception::Exception;
+
+const HOSTS_PATH: &str = "/etc/hosts";
+const BEGIN_MARKER: &str = "# BEGIN vz managed block, do not edit";
+const END_MARKER: &str = "# END vz managed block";
+
+#[derive(Args)]
+pub struct Hosts {
+    #[command(subcommand)]
+    command: HostsCommand,
+}
+
+#[derive(Subcommand)]
+enum HostsCommand {
+    #[command(about = "map <vm>.vz to ip of running vms in /etc/hosts, requires sudo")]
+    Sync {
+        // vm being stopped by its runner, e.g. by syncHosts of settings.json
+        #[arg(long, hide = true)]
+        exclude: Option<String>,
+    },
+}
+
+impl Hosts {
+    pub fn execute(&self) -> Result<(), Exception> {
+        match &self.command {
+            HostsCommand::Sync { exclude } => sync(exclude.as_deref()),
+        }
+    }
+}
+
+fn sync(exclude: Option<&str>) -> Result<(), Exception> {
+    let mut entries = vec![];
+    let home_dir = vm_dir::home_dir();
+    if home_dir.exists() {
+        for entry in fs::read_dir(home_dir)? {
+            let dir = vm_dir::vm_dir(&entry?.file_name().to_string_lossy());
+            if dir.initialized() && dir.pid().is_some() && exclude != Some(dir.name().as_str()) {
+                let config = dir.load_config()?;
+                if let Some(ip) = dhcp_lease::find_ip(&config.mac_address)? {
+                    entries.push(format!("{ip} {}.vz", dir.name()));
+                }
+            }
+        }
+    }
+    entries.sort();
+
+    let hosts = fs::read_to_string(HOSTS_PATH)?;
+    let hosts = update_block(&hosts, &entries);
+    fs::write(HOSTS_PATH, hosts).map_err(|err| {
+        if err.kind() == io::ErrorKind::PermissionDenied {
+            Exception::ValidationError(format!("{HOSTS_PATH} is not writable, run with sudo"))
+        } else {
+            err.into()
+        }
+    })?;
+    info!("hosts synced, entries={}", entries.len());
+    Ok(())
+}
+
+// replace managed block, or append if not exists, remove block if no entries
+fn update_block(hosts: &str, entries: &[String]) -> String {
+    let mut lines = vec![];
+    let mut in_block = false;
+    for line in hosts.lines() {
+        if line == BEGIN_MARKER {
+            in_block = true;
+        } else if line == END_MARKER {
+            in_block = false;
+        } else if !in_block {
+            lines.push(line);
+        }
+    }
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+
+    let mut result = lines.join("\n");
+    result.push('\n');
+    if !entries.is_empty() {
+        result.push_str(&format!("\n{BEGIN_MARKER}\n{}\n{END_MARKER}\n", entries.join("\n")));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn update_block() {
+        let hosts = "127.0.0.1 localhost\n";
+        let entries = vec!["192.168.64.2 debian.vz".to_string()];
+        let updated = super::update_block(hosts, &entries);
+        assert_eq!(
+            updated,
+            "127.0.0.1 localhost\n\n# BEGIN vz managed block, do not edit\n192.168.64.2 debian.vz\n# END vz managed block\n"
+        );
+
+        let entries = vec!["192.168.64.3 debian.vz".to_string()];
+        assert_eq!(
+            super::update_block(&updated, &entries),
+            "127.0.0.1 localhost\n\n# BEGIN vz managed block, do not edit\n192.168.64.3 debian.vz\n# END vz managed block\n"
+        );
+        assert_eq!(super::update_block(&updated, &[]), hosts);
+    }
+}
@@ -0,0 +1,272 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+use clap::ArgGroup;
+use clap::Args;
+use clap::ValueHint;
+use serde_json::Value;
+use tracing::info;
+use tracing::warn;
+
+use crate::command::create;
+use crate::command::disk;
+use crate::config::settings;
+use crate::config::vm_archive;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::disk_image;
+use crate::util::exception::Exception;
+use crate::util::json;
+use crate::util::path::PathExtension;
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("source").required(true).args(["vagrant", "utm", "archive"])))]
+pub struct Import {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(
+        long,
+        help = "arm64 libvirt/qemu vagrant box file, or name of box installed by vagrant, e.g. --vagrant=generic/debian12",
+        value_hint = ValueHint::FilePath
+    )]
+    vagrant: Option<String>,
+
+    #[arg(long, help = "UTM bundle of linux vm, e.g. --utm=debian.utm", value_hint = ValueHint::DirPath)]
+    utm: Option<PathBuf>,
+
+    #[arg(long, help = "archive created by vz export, prompt passphrase if encrypted, e.g. --archive=debian.tar", value_hint = ValueHint::FilePath)]
+    archive: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "regenerate mac addresses and machine identifier of archived vm, e.g. to run it next to original",
+        requires = "archive",
+        default_value_t = false
+    )]
+    new_identity: bool,
+}
+
+impl Import {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let _lock = vm_dir::lock_name(name)?;
+        let dir = vm_dir::vm_dir(name);
+        if dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm already exists, name={name}")));
+        }
+
+        let temp_dir = vm_dir::create_temp_vm_dir()?;
+        let result = match (&self.vagrant, &self.utm, &self.archive) {
+            (Some(vagrant_box), _, _) => import_vagrant_box(&temp_dir, vagrant_box),
+            (_, Some(bundle), _) => import_utm_bundle(&temp_dir, &bundle.to_absolute_path()),
+            (_, _, Some(archive)) => import_archive(&temp_dir, &archive.to_absolute_path(), self.new_identity),
+            _ => unreachable!(),
+        };
+        let result = result
+            .and_then(|_| settings::check_storage_quota(disks_size(&temp_dir)?))
+            .and_then(|_| create::check_mac_addresses(name, &temp_dir.load_config()?));
+        if let Err(err) = result {
+            fs::remove_dir_all(&temp_dir.dir)?;
+            return Err(err);
+        }
+
+        info!("move vm dir, from={}, to={}", temp_dir.dir.to_string_lossy(), dir.dir.to_string_lossy());
+        fs::rename(&temp_dir.dir, &dir.dir)?;
+        info!("vm imported, name={name}, config={}", dir.config_path.to_string_lossy());
+        Ok(())
+    }
+}
+
+// main disk and extra disks, e.g. of UTM bundle with multiple drives
+fn disks_size(dir: &VmDir) -> Result<u64, Exception> {
+    let mut size = dir.disk_path.metadata()?.len();
+    for disk in dir.load_config()?.disks {
+        size += dir.extra_disk_path(&disk).metadata()?.len();
+    }
+    Ok(size)
+}
+
+fn import_archive(dir: &VmDir, archive: &Path, new_identity: bool) -> Result<(), Exception> {
+    vm_archive::extract(archive, dir)?;
+    if !dir.initialized() {
+        return Err(Exception::ValidationError(format!(
+            "invalid vm archive, path={}",
+            archive.to_string_lossy()
+        )));
+    }
+    if new_identity {
+        info!("regenerate mac addresses and machine identifier");
+        let mut config = dir.load_config()?;
+        create::regenerate_identity(&mut config);
+        dir.save_config(&config)?;
+    }
+    Ok(())
+}
+
+fn import_vagrant_box(dir: &VmDir, vagrant_box: &str) -> Result<(), Exception> {
+    let box_file = PathBuf::from(vagrant_box).to_absolute_path();
+    if box_file.is_file() {
+        let box_dir = dir.dir.join("box");
+        fs::create_dir(&box_dir)?;
+        info!("extract vagrant box, file={}", box_file.to_string_lossy());
+        let status = Command::new("tar").arg("-xf").arg(&box_file).arg("-C").arg(&box_dir).status()?;
+        if !status.success() {
+            return Err(Exception::ValidationError(format!("failed to extract vagrant box, status={status}")));
+        }
+        convert_box_image(dir, &box_dir)?;
+        fs::remove_dir_all(&box_dir)?;
+    } else {
+        convert_box_image(dir, &installed_box_dir(vagrant_box)?)?;
+    }
+
+    create::create_linux(dir)?;
+    let mut config = dir.load_config()?;
+    config.memory = 2 * 1024 * 1024 * 1024;
+    // vagrant boxes authorize vagrant insecure key for vagrant user
+    config.ssh_user = Some("vagrant".to_string());
+    let insecure_key = PathBuf::from("~/.vagrant.d/insecure_private_key");
+    if insecure_key.to_absolute_path().exists() {
+        config.ssh_key = Some(insecure_key.to_string_lossy().to_string());
+    }
+    dir.save_config(&config)?;
+    Ok(())
+}
+
+fn convert_box_image(dir: &VmDir, box_dir: &Path) -> Result<(), Exception> {
+    let image = find_box_image(box_dir)?.ok_or_else(|| {
+        Exception::ValidationError(format!(
+            "disk image not found in vagrant box, only libvirt/qemu box is supported, dir={}",
+            box_dir.to_string_lossy()
+        ))
+    })?;
+    disk_image::convert_to_raw(&image, &dir.disk_path)?;
+    Ok(())
+}
+
+// vagrant stores box as ~/.vagrant.d/boxes/<name>/<version>/<provider>/box.img, pick latest version
+fn installed_box_dir(name: &str) -> Result<PathBuf, Exception> {
+    let dir = PathBuf::from("~/.vagrant.d/boxes")
+        .to_absolute_path()
+        .join(name.replace('/', "-VAGRANTSLASH-"));
+    if !dir.is_dir() {
+        return Err(Exception::ValidationError(format!("vagrant box not found, name={name}")));
+    }
+    let mut versions = vec![];
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            versions.push(path);
+        }
+    }
+    // versions are compared by number, e.g. 1.10.0 is newer than 1.9.3
+    versions.sort_by_key(|path| version_components(&path.file_name().unwrap().to_string_lossy()));
+    versions
+        .pop()
+        .ok_or_else(|| Exception::ValidationError(format!("vagrant box has no version, name={name}")))
+}
+
+// non numeric part is compared as 0, e.g. 2.0.0-beta is same as 2.0.0.0
+fn version_components(version: &str) -> Vec<u64> {
+    version.split(['.', '-']).map(|part| part.parse().unwrap_or(0)).collect()
+}
+
+fn find_box_image(dir: &Path) -> Result<Option<PathBuf>, Exception> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir)? {
+        paths.push(entry?.path());
+    }
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            if let Some(image) = find_box_image(&path)? {
+                return Ok(Some(image));
+            }
+        } else if path.extension().is_some_and(|extension| extension == "img") {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+// refer to UTM config.plist, https://github.com/utmapp/UTM/tree/main/Configuration
+fn import_utm_bundle(dir: &VmDir, bundle: &Path) -> Result<(), Exception> {
+    let plist = bundle.join("config.plist");
+    if !plist.exists() {
+        return Err(Exception::ValidationError(format!(
+            "invalid UTM bundle, path={}",
+            bundle.to_string_lossy()
+        )));
+    }
+    let output = Command::new("plutil").args(["-convert", "json", "-o", "-"]).arg(&plist).output()?;
+    if !output.status.success() {
+        return Err(Exception::ValidationError(format!(
+            "failed to read UTM config, path={}",
+            plist.to_string_lossy()
+        )));
+    }
+    let utm: Value = json::from_json(&String::from_utf8_lossy(&output.stdout))?;
+
+    if let Some(architecture) = utm["System"]["Architecture"].as_str().filter(|architecture| *architecture != "aarch64") {
+        return Err(Exception::ValidationError(format!(
+            "only aarch64 vm is supported, architecture={architecture}"
+        )));
+    }
+    if let Some(os) = utm["System"]["Boot"]["OperatingSystem"].as_str().filter(|os| *os != "Linux") {
+        return Err(Exception::ValidationError(format!("only linux vm is supported, os={os}")));
+    }
+    let drives = utm["Drive"].as_array().map(Vec::as_slice).unwrap_or_default();
+    // first drive is boot disk, others are added as extra disks in order
+    let images: Vec<PathBuf> = drives
+        .iter()
+        .filter(|drive| drive["ImageType"].as_str() != Some("CD"))
+        .filter_map(|drive| drive["ImageName"].as_str())
+        .map(|image| bundle.join("Data").join(image))
+        .collect();
+    let (image, extra_images) = images
+        .split_first()
+        .ok_or_else(|| Exception::ValidationError("disk not found in UTM config".to_string()))?;
+    disk_image::convert_to_raw(image, &dir.disk_path)?;
+
+    create::create_linux(dir)?;
+    let mut config = dir.load_config()?;
+    for image in extra_images {
+        let name = disk::next_disk_name(&config.disks);
+        info!("import extra disk, image={}, disk={name}", image.to_string_lossy());
+        disk_image::convert_to_raw(image, &dir.extra_disk_path(&name))?;
+        config.disks.push(name);
+    }
+    if let Some(cpu) = utm["System"]["CPUCount"].as_u64().filter(|cpu| *cpu > 0) {
+        config.cpu = cpu as usize;
+    }
+    if let Some(memory) = utm["System"]["MemorySize"].as_u64() {
+        config.memory = memory * 1024 * 1024;
+    }
+    let networks = utm["Network"].as_array().map(Vec::as_slice).unwrap_or_default();
+    if let Some(mac_address) = networks.first().and_then(|network| network["MacAddress"].as_str()) {
+        config.mac_address = mac_address.to_lowercase();
+    }
+    if networks.len() > 1 {
+        warn!("only first network is imported, networks={}", networks.len());
+    }
+    for key in ["Display", "Sound", "Serial", "Input"] {
+        if utm[key].as_array().is_some_and(|devices| !devices.is_empty()) {
+            warn!("{key} devices are not imported, vz uses its own defaults");
+        }
+    }
+    dir.save_config(&config)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn version_components() {
+        assert_eq!(super::version_components("1.10.0"), vec![1, 10, 0]);
+        assert!(super::version_components("1.10.0") > super::version_components("1.9.3"));
+        assert!(super::version_components("2024.05.1") > super::version_components("2024.5"));
+        assert_eq!(super::version_components("2.0.0-beta"), vec![2, 0, 0, 0]);
+    }
+}
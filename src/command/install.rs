@@ -1,56 +1,80 @@
+use std::path::Path;
 use std::path::PathBuf;
 
 use clap::Args;
 use clap::ValueHint;
-use objc2_foundation::MainThreadMarker;
 use tracing::info;
 
+use crate::config::ipsw_cache;
 use crate::config::vm_config::Os;
 use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
 use crate::util::exception::Exception;
-use crate::util::path::PathExtension;
-use crate::vm::mac_os;
-use crate::vm::mac_os_installer;
+use crate::vm::platform::Platform;
+use crate::vm::platform::Virtualization;
 
 #[derive(Args)]
 pub struct Install {
     #[arg(help = "vm name")]
     name: String,
 
-    #[arg(long, help = "macOS restore image file, e.g. --ipsw=UniversalMac_14.5_23F79_Restore.ipsw", value_hint = ValueHint::FilePath)]
+    #[arg(
+        long,
+        help = "macOS restore image file, or version of cached ipsw, e.g. --ipsw=UniversalMac_14.5_23F79_Restore.ipsw or --ipsw=14.5",
+        value_hint = ValueHint::FilePath
+    )]
     ipsw: PathBuf,
 }
 
 impl Install {
     pub fn execute(&self) -> Result<(), Exception> {
-        self.validate()?;
+        let ipsw = ipsw_cache::resolve(&self.ipsw)?;
 
         let name = &self.name;
         let dir = vm_dir::vm_dir(name);
         if !dir.initialized() {
             return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
         }
-        let config = dir.load_config()?;
-        if !matches!(config.os, Os::MacOs) {
-            return Err(Exception::ValidationError("install requires macOS guest".to_string()));
-        }
-        let _lock = dir.lock()?;
-
-        info!("instal macOS");
-        let marker = MainThreadMarker::new().unwrap();
-        let vm = mac_os::create_vm(&dir, &config, marker)?;
-        mac_os_installer::install(vm, &self.ipsw.to_absolute_path(), marker)?;
+        install(&Virtualization, &dir, &ipsw)
+    }
+}
 
-        Ok(())
+fn install(platform: &dyn Platform, dir: &VmDir, ipsw: &Path) -> Result<(), Exception> {
+    let config = dir.load_config()?;
+    if !matches!(config.os, Os::MacOs) {
+        return Err(Exception::ValidationError("install requires macOS guest".to_string()));
     }
+    let _lock = dir.lock("install")?;
 
-    fn validate(&self) -> Result<(), Exception> {
-        if !self.ipsw.exists() {
-            return Err(Exception::ValidationError(format!(
-                "ipsw does not exist, path={}",
-                self.ipsw.to_string_lossy()
-            )));
-        }
-        Ok(())
+    info!("instal macOS");
+    platform.install(dir, &config, ipsw)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    use uuid::Uuid;
+
+    use crate::config::vm_dir::VmDir;
+    use crate::vm::platform::mock::MockPlatform;
+
+    #[test]
+    fn install() {
+        let dir = VmDir::new(env::temp_dir().join(format!("vz-test-{}", Uuid::new_v4())));
+        fs::create_dir_all(&dir.dir).unwrap();
+        let config = |os: &str| format!(r#"{{"os": "{os}", "cpu": 1, "memory": 1073741824, "macAddress": "", "sharing": {{}}}}"#);
+        let platform = MockPlatform::default();
+        fs::write(&dir.config_path, config("linux")).unwrap();
+        let linux = super::install(&platform, &dir, Path::new("/tmp/restore.ipsw"));
+        fs::write(&dir.config_path, config("macOS")).unwrap();
+        let mac_os = super::install(&platform, &dir, Path::new("/tmp/restore.ipsw"));
+        fs::remove_dir_all(&dir.dir).unwrap();
+
+        assert!(linux.is_err());
+        assert!(mac_os.is_ok());
+        assert_eq!(vec![format!("install {}: /tmp/restore.ipsw", dir.name())], *platform.calls.borrow());
     }
 }
@@ -0,0 +1,55 @@
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::Instant;
+
+use clap::Args;
+
+use crate::config::vm_dir;
+use crate::util::dhcp_lease;
+use crate::util::exception::Exception;
+
+#[derive(Args)]
+pub struct Ip {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(long, help = "wait until vm gets ip after boot", default_value_t = false)]
+    wait: bool,
+
+    #[arg(long, help = "seconds to wait for ip, with --wait", default_value_t = 120)]
+    timeout: u64,
+}
+
+impl Ip {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        let config = dir.load_config()?;
+
+        let start = Instant::now();
+        loop {
+            if dir.pid().is_none() {
+                return Err(Exception::ValidationError(format!("vm not running, name={name}")));
+            }
+            // dhcp lease first, arp table covers guest with static ip
+            let ip = match dhcp_lease::find_ip(&config.mac_address)? {
+                Some(ip) => Some(ip),
+                None => dhcp_lease::find_arp_ip(&config.mac_address)?,
+            };
+            if let Some(ip) = ip {
+                println!("{ip}");
+                return Ok(());
+            }
+            if !self.wait || start.elapsed() > Duration::from_secs(self.timeout) {
+                return Err(Exception::ValidationError(format!(
+                    "vm ip not found, name={name}, mac_address={}",
+                    config.mac_address
+                )));
+            }
+            sleep(Duration::from_secs(1));
+        }
+    }
+}
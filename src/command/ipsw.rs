@@ -1,31 +1,105 @@
-use std::sync::mpsc::channel;
+use std::path::Path;
+use std::path::PathBuf;
 
-use block2::StackBlock;
 use clap::Args;
-use objc2_foundation::NSError;
-use objc2_virtualization::VZMacOSRestoreImage;
+use clap::Subcommand;
+use clap::ValueHint;
 
+use crate::config::ipsw_cache;
 use crate::util::exception::Exception;
+use crate::vm::mac_os;
 
 #[derive(Args)]
-pub struct Ipsw;
+pub struct Ipsw {
+    #[command(subcommand)]
+    command: Option<IpswCommand>,
+}
+
+#[derive(Subcommand)]
+enum IpswCommand {
+    #[command(
+        about = "download ipsw into cache, interrupted download is resumed by running it again",
+        visible_alias = "download"
+    )]
+    Pull {
+        #[arg(help = "ipsw url, default to latest supported")]
+        url: Option<String>,
+    },
+    #[command(about = "list cached ipsw and latest supported version")]
+    List {
+        #[arg(long, help = "only list cached ipsw, without fetching latest supported version", default_value_t = false)]
+        local: bool,
+    },
+    #[command(about = "show version and requirements of ipsw, and whether current host supports it")]
+    Info {
+        #[arg(help = "ipsw file, or version or build of cached ipsw, e.g. 14.5", value_hint = ValueHint::FilePath)]
+        ipsw: PathBuf,
+    },
+    #[command(about = "remove cached ipsw")]
+    Rm {
+        #[arg(help = "version, build or file name of cached ipsw, e.g. 14.5")]
+        ipsw: String,
+    },
+}
 
 impl Ipsw {
     pub fn execute(&self) -> Result<(), Exception> {
-        let (tx, rx) = channel();
-        let block = StackBlock::new(move |image: *mut VZMacOSRestoreImage, err: *mut NSError| {
-            if !err.is_null() {
-                tx.send(Err(Exception::from_ns_error(err))).unwrap();
-            } else {
-                let url = unsafe { (*image).URL().absoluteString().unwrap() };
-                tx.send(Ok(url)).unwrap();
+        match &self.command {
+            None => {
+                let url = mac_os::latest_restore_image_url()?;
+                println!("{}", url);
             }
-        });
-        unsafe {
-            VZMacOSRestoreImage::fetchLatestSupportedWithCompletionHandler(&block);
-        };
-        let url = rx.recv()??;
-        println!("{}", url);
+            Some(IpswCommand::Pull { url }) => {
+                let url = match url {
+                    Some(url) => url.clone(),
+                    None => mac_os::latest_restore_image_url()?,
+                };
+                let path = ipsw_cache::download(&url)?;
+                println!("{}", path.to_string_lossy());
+            }
+            Some(IpswCommand::List { local }) => list(*local)?,
+            Some(IpswCommand::Info { ipsw }) => info(ipsw)?,
+            Some(IpswCommand::Rm { ipsw }) => ipsw_cache::remove(ipsw)?,
+        }
         Ok(())
     }
 }
+
+fn list(local: bool) -> Result<(), Exception> {
+    println!("{:<10}{:<10}{:<12}file", "version", "build", "size");
+    for entry in ipsw_cache::entries()? {
+        println!(
+            "{:<10}{:<10}{:<12}{}",
+            entry.metadata.version,
+            entry.metadata.build,
+            format!("{:.2}G", entry.path.metadata()?.len() as f32 / 1_000_000_000.0),
+            entry.path.file_name().unwrap().to_string_lossy()
+        );
+    }
+    if !local {
+        println!("\nlatest supported: {}", mac_os::latest_restore_image_url()?);
+    }
+    Ok(())
+}
+
+fn info(ipsw: &Path) -> Result<(), Exception> {
+    let path = ipsw_cache::resolve(ipsw)?;
+    let image = mac_os::load_restore_image(&path)?;
+    let (version, build) = mac_os::restore_image_version(&image);
+    println!("file: {}", path.to_string_lossy());
+    println!("version: {version}");
+    println!("build: {build}");
+    // requirements are only available for configuration supported by current host
+    match unsafe { image.mostFeaturefulSupportedConfiguration() } {
+        Some(requirements) => unsafe {
+            println!("supported by host: {}", requirements.hardwareModel().isSupported());
+            println!("minimum cpu: {}", requirements.minimumSupportedCPUCount());
+            println!(
+                "minimum memory: {:.2}G",
+                requirements.minimumSupportedMemorySize() as f32 / (1024.0 * 1024.0 * 1024.0)
+            );
+        },
+        None => println!("supported by host: false"),
+    }
+    Ok(())
+}
@@ -1,14 +1,129 @@
+use std::collections::HashMap;
 use std::fs;
 use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::thread;
+use std::time::UNIX_EPOCH;
 
 use clap::Args;
+use libc::pid_t;
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::warn;
 
+use crate::command::autostart;
+use crate::command::status;
+use crate::config::vm_config::Os;
 use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::dhcp_lease;
 use crate::util::exception::Exception;
 use crate::util::json;
+use crate::vm::platform::Platform;
+use crate::vm::platform::Virtualization;
+
+const CACHE_FILE: &str = ".list-cache.json";
+const ERROR_STATUS: &str = "error";
 
 #[derive(Args)]
-pub struct List;
+pub struct List {
+    #[arg(long, help = "read all configs instead of cached ones", default_value_t = false)]
+    no_cache: bool,
+
+    #[arg(long, short, help = "output format, json prints array of vms for scripts", default_value = "table")]
+    output: Output,
+
+    #[arg(long, help = "show labels column", default_value_t = false)]
+    labels: bool,
+
+    #[arg(
+        long,
+        help = "only list vms matching all filters, label=<key>, label=<key>=<value> or status=<status>, e.g. --filter=label=team=ci --filter=status=running",
+        value_parser = parse_filter
+    )]
+    filter: Vec<Filter>,
+
+    #[arg(long, short, help = "print names only, e.g. for xargs", default_value_t = false)]
+    quiet: bool,
+
+    #[arg(long, short, help = "show mac address and uptime columns", default_value_t = false)]
+    wide: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Label(String, Option<String>),
+    Status(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum Output {
+    Table,
+    Json,
+}
+
+// vm with unreadable config or disk is listed as error, e.g. corrupt config.json
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum Entry {
+    Vm(Record),
+    Error(ErrorRecord),
+}
+
+// sizes are in bytes, pid is null if vm is not running, uptime is in seconds, only known by runner of running vm
+#[derive(Serialize, Debug)]
+struct Record {
+    name: String,
+    os: Os,
+    cpu: usize,
+    memory: u64,
+    disk_used: u64,
+    disk_total: u64,
+    status: &'static str,
+    pid: Option<i32>,
+    mac_address: String,
+    labels: HashMap<String, String>,
+    // last ip seen by runner, kept after vm stops
+    ip: Option<String>,
+    reserved_ip: Option<String>,
+    uptime: Option<u64>,
+    autostart: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorRecord {
+    name: String,
+    status: &'static str,
+    pid: Option<i32>,
+    error: String,
+    ip: Option<String>,
+    reserved_ip: Option<String>,
+    autostart: bool,
+}
+
+struct Vm {
+    config: CachedConfig,
+    disk_used: u64,
+    disk_total: u64,
+    status: &'static str,
+    pid: Option<i32>,
+    ip: Option<String>,
+    uptime: Option<u64>,
+}
+
+// fields of config shown by list, keyed by vm name, reused while config mtime is unchanged
+type Cache = HashMap<String, CachedConfig>;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CachedConfig {
+    modified: u128,
+    os: Os,
+    cpu: usize,
+    memory: u64,
+    mac_address: String,
+    #[serde(default)]
+    labels: HashMap<String, String>,
+}
 
 impl List {
     pub fn execute(&self) -> Result<(), Exception> {
@@ -16,30 +131,346 @@ impl List {
         if !home_dir.exists() {
             return Err(Exception::ValidationError(format!("{} does not exist", home_dir.to_string_lossy())));
         }
-        println!("{:<16}{:<8}{:<8}{:<8}{:<16}{:<16}", "name", "os", "cpu", "memory", "disk", "status");
-        for entry in fs::read_dir(home_dir)? {
-            let path = entry?.path();
-            if path.is_dir() {
-                let dir = vm_dir::vm_dir(&path.file_name().unwrap().to_string_lossy());
-                if dir.initialized() {
-                    let name = dir.name();
-
-                    let config = dir.load_config()?;
-                    let os = json::to_json_value(&config.os)?;
-                    let cpu = config.cpu;
-                    let memory = format!("{:.2}G", config.memory as f32 / (1024.0 * 1024.0 * 1024.0));
-                    let metadata = dir.disk_path.metadata()?;
-                    let disk = format!(
-                        "{:0.2}G/{:.2}G",
-                        metadata.blocks() as f32 * 512.0 / 1_000_000_000.0,
-                        metadata.len() as f32 / 1_000_000_000.0
+        let table = self.output == Output::Table && !self.quiet;
+        if table {
+            print!(
+                "{:<16}{:<8}{:<8}{:<8}{:<16}{:<16}{:<16}{:<16}{:<16}",
+                "name", "os", "cpu", "memory", "disk", "ip", "reserved ip", "status", "autostart"
+            );
+            if self.wide {
+                print!("{:<20}{:<16}", "mac", "uptime");
+            }
+            println!("{}", if self.labels { "labels" } else { "" });
+        }
+        let mut records = vec![];
+        let bootptab = dhcp_lease::read_bootptab()?;
+        let reservations = dhcp_lease::reservations(&bootptab);
+        let mut summary = Summary::default();
+        let cache_path = home_dir.join(CACHE_FILE);
+        let mut cache = if self.no_cache { Cache::new() } else { load_cache(&cache_path) };
+        let mut updated_cache = Cache::new();
+
+        let mut dirs = vm_dir::vm_dirs()?;
+        dirs.sort_by_key(|dir| dir.name());
+        // each vm is loaded by its own thread, status of running vm waits for its runner
+        let vms: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = dirs
+                .into_iter()
+                .map(|dir| {
+                    let cached = cache.remove(&dir.name());
+                    scope.spawn(move || {
+                        let vm = load_vm(&dir, cached);
+                        (dir, vm)
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+        });
+
+        for (dir, vm) in vms {
+            let name = dir.name();
+            let reserved_ip = reservations
+                .iter()
+                .find(|reservation| reservation.name == name)
+                .map(|reservation| reservation.ip);
+            let autostart = autostart::enabled(&name);
+            // one broken vm doesn't hide others
+            let vm = match vm {
+                Ok(vm) => vm,
+                Err(err) => {
+                    warn!("failed to load vm, name={name}, error={err}");
+                    if !self.filter.iter().all(|filter| filter.matches(&HashMap::new(), ERROR_STATUS)) {
+                        continue;
+                    }
+                    let ip = dir.last_ip();
+                    if self.quiet {
+                        println!("{name}");
+                    } else if table {
+                        print!(
+                            "{:<16}{:<8}{:<8}{:<8}{:<16}{:<16}{:<16}{:<16}{:<16}",
+                            name,
+                            "-",
+                            "-",
+                            "-",
+                            "-",
+                            ip.as_deref().unwrap_or("-"),
+                            reserved_ip.unwrap_or("-"),
+                            ERROR_STATUS,
+                            if autostart { "on" } else { "-" }
+                        );
+                        if self.wide {
+                            print!("{:<20}{:<16}", "-", "-");
+                        }
+                        println!();
+                    } else {
+                        records.push(Entry::Error(ErrorRecord {
+                            name: name.clone(),
+                            status: ERROR_STATUS,
+                            pid: dir.pid(),
+                            error: err.to_string(),
+                            ip,
+                            reserved_ip: reserved_ip.map(str::to_string),
+                            autostart,
+                        }));
+                    }
+                    summary.vms += 1;
+                    continue;
+                }
+            };
+            let config = vm.config;
+            if !self.filter.iter().all(|filter| filter.matches(&config.labels, vm.status)) {
+                updated_cache.insert(name, config);
+                continue;
+            }
+            if self.quiet {
+                println!("{name}");
+            } else if table {
+                let os = json::to_json_value(&config.os)?;
+                let memory = format!("{:.2}G", config.memory as f32 / (1024.0 * 1024.0 * 1024.0));
+                let disk = format!(
+                    "{:0.2}G/{:.2}G",
+                    vm.disk_used as f32 / 1_000_000_000.0,
+                    vm.disk_total as f32 / 1_000_000_000.0
+                );
+                print!(
+                    "{:<16}{:<8}{:<8}{:<8}{:<16}{:<16}{:<16}{:<16}{:<16}",
+                    name,
+                    os,
+                    config.cpu,
+                    memory,
+                    disk,
+                    vm.ip.as_deref().unwrap_or("-"),
+                    reserved_ip.unwrap_or("-"),
+                    vm.status,
+                    if autostart { "on" } else { "-" }
+                );
+                if self.wide {
+                    print!(
+                        "{:<20}{:<16}",
+                        config.mac_address,
+                        vm.uptime.map_or("-".to_string(), status::format_uptime)
                     );
-                    let status = if dir.pid().is_some() { "running" } else { "stopped" };
-                    println!("{:<16}{:<8}{:<8}{:<8}{:<16}{:<16}", name, os, cpu, memory, disk, status)
                 }
+                println!("{}", if self.labels { format_labels(&config.labels) } else { String::new() });
+            } else {
+                records.push(Entry::Vm(Record {
+                    name: name.clone(),
+                    os: config.os.clone(),
+                    cpu: config.cpu,
+                    memory: config.memory,
+                    disk_used: vm.disk_used,
+                    disk_total: vm.disk_total,
+                    status: vm.status,
+                    pid: vm.pid,
+                    mac_address: config.mac_address.clone(),
+                    labels: config.labels.clone(),
+                    ip: vm.ip,
+                    reserved_ip: reserved_ip.map(str::to_string),
+                    uptime: vm.uptime,
+                    autostart,
+                }));
+            }
+
+            summary.vms += 1;
+            summary.disk += vm.disk_used;
+            if vm.pid.is_some() {
+                summary.running += 1;
+                summary.cpu += config.cpu;
+                summary.memory += config.memory;
             }
+            updated_cache.insert(name, config);
+        }
+
+        // removed vms are dropped from cache
+        if let Err(err) = json::to_json(&updated_cache).and_then(|json| Ok(fs::write(&cache_path, json)?)) {
+            warn!("failed to write list cache, path={}, error={err}", cache_path.to_string_lossy());
+        }
+
+        if self.quiet {
+            return Ok(());
         }
+        if !table {
+            println!("{}", json::to_json_pretty(&records)?);
+            return Ok(());
+        }
+        println!(
+            "\ntotal: {} vms, {} running, running cpu={}, running memory={:.2}G, disk used={:.2}G",
+            summary.vms,
+            summary.running,
+            summary.cpu,
+            summary.memory as f32 / (1024.0 * 1024.0 * 1024.0),
+            summary.disk as f32 / 1_000_000_000.0
+        );
 
         Ok(())
     }
 }
+
+fn load_vm(dir: &VmDir, cached: Option<CachedConfig>) -> Result<Vm, Exception> {
+    let config = cached_config(dir, cached)?;
+    let metadata = dir.disk_path.metadata()?;
+    let (status, pid, uptime) = vm_status(&Virtualization, dir);
+    Ok(Vm {
+        config,
+        disk_used: metadata.blocks() * 512,
+        disk_total: metadata.len(),
+        status,
+        pid,
+        ip: dir.last_ip(),
+        uptime,
+    })
+}
+
+// status, pid and uptime of vm
+fn vm_status(platform: &dyn Platform, dir: &VmDir) -> (&'static str, Option<pid_t>, Option<u64>) {
+    let pid = platform.runner_pid(dir);
+    let running = pid.is_some();
+    // runner of unresponsive vm would not answer
+    let response = if running && !dir.unresponsive_path.exists() {
+        platform.runner_status(dir)
+    } else {
+        None
+    };
+    let status = if !running && dir.state_path.exists() {
+        "suspended"
+    } else if !running && dir.uninstalled_path.exists() {
+        "uninstalled"
+    } else if !running {
+        "stopped"
+    } else if dir.unresponsive_path.exists() {
+        "unresponsive"
+    } else if response.as_ref().and_then(|response| response.state.as_deref()) == Some("paused") {
+        "paused"
+    } else {
+        "running"
+    };
+    (status, pid, response.and_then(|response| response.uptime))
+}
+
+impl Filter {
+    fn matches(&self, labels: &HashMap<String, String>, status: &str) -> bool {
+        match self {
+            Filter::Label(key, None) => labels.contains_key(key),
+            Filter::Label(key, Some(value)) => labels.get(key) == Some(value),
+            Filter::Status(expected) => expected == status,
+        }
+    }
+}
+
+fn parse_filter(value: &str) -> Result<Filter, String> {
+    match value.split_once('=') {
+        Some(("label", label)) if !label.is_empty() => Ok(match label.split_once('=') {
+            Some((key, label)) => Filter::Label(key.to_string(), Some(label.to_string())),
+            None => Filter::Label(label.to_string(), None),
+        }),
+        Some(("status", status)) if !status.is_empty() => Ok(Filter::Status(status.to_string())),
+        _ => Err(format!(
+            "invalid filter, it must be label=<key>, label=<key>=<value> or status=<status>, filter={value}"
+        )),
+    }
+}
+
+// sorted, e.g. owner=dev,team=ci
+fn format_labels(labels: &HashMap<String, String>) -> String {
+    let mut labels: Vec<_> = labels.iter().map(|(key, value)| format!("{key}={value}")).collect();
+    labels.sort();
+    labels.join(",")
+}
+
+// cache is only optimization, invalid or missing cache is ignored
+fn load_cache(path: &Path) -> Cache {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|json| json::from_json(&json).ok())
+        .unwrap_or_default()
+}
+
+// config extends profile is not cached, as profile may change without touching config
+fn cached_config(dir: &VmDir, cached: Option<CachedConfig>) -> Result<CachedConfig, Exception> {
+    let modified = dir
+        .config_path
+        .metadata()?
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_nanos());
+    if let Some(config) = cached.filter(|config| config.modified == modified) {
+        return Ok(config);
+    }
+    let config = dir.load_config()?;
+    Ok(CachedConfig {
+        modified: if config.extends.is_some() { 0 } else { modified },
+        os: config.os,
+        cpu: config.cpu,
+        memory: config.memory,
+        mac_address: config.mac_address,
+        labels: config.labels,
+    })
+}
+
+#[derive(Default)]
+struct Summary {
+    vms: usize,
+    running: usize,
+    cpu: usize,
+    memory: u64,
+    // allocated blocks of sparse disks, host storage used by vms
+    disk: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use super::Filter;
+    use crate::config::vm_dir::VmDir;
+    use crate::vm::platform::mock::MockPlatform;
+
+    #[test]
+    fn parse_filter() {
+        assert_eq!(
+            super::parse_filter("label=team=ci"),
+            Ok(Filter::Label("team".to_string(), Some("ci".to_string())))
+        );
+        assert_eq!(super::parse_filter("label=team"), Ok(Filter::Label("team".to_string(), None)));
+        assert_eq!(super::parse_filter("status=running"), Ok(Filter::Status("running".to_string())));
+        assert!(super::parse_filter("label=").is_err());
+        assert!(super::parse_filter("os=linux").is_err());
+    }
+
+    #[test]
+    fn matches() {
+        let labels = HashMap::from([("team".to_string(), "ci".to_string())]);
+        assert!(Filter::Label("team".to_string(), None).matches(&labels, "stopped"));
+        assert!(!Filter::Label("team".to_string(), Some("web".to_string())).matches(&labels, "stopped"));
+        assert!(!Filter::Status("running".to_string()).matches(&labels, "stopped"));
+    }
+
+    #[test]
+    fn format_labels() {
+        let labels = HashMap::from([("team".to_string(), "ci".to_string()), ("owner".to_string(), "dev".to_string())]);
+        assert_eq!(super::format_labels(&labels), "owner=dev,team=ci");
+    }
+
+    #[test]
+    fn vm_status() {
+        let dir = VmDir::new(env::temp_dir().join(format!("vz-test-{}", Uuid::new_v4())));
+        fs::create_dir_all(&dir.dir).unwrap();
+        let name = dir.name();
+        let stopped = super::vm_status(&MockPlatform::default(), &dir);
+        let paused = super::vm_status(&MockPlatform::running(&[(&name, "paused")]), &dir);
+        fs::write(&dir.state_path, []).unwrap();
+        let suspended = super::vm_status(&MockPlatform::default(), &dir);
+        fs::write(&dir.unresponsive_path, []).unwrap();
+        let unresponsive = super::vm_status(&MockPlatform::running(&[(&name, "running")]), &dir);
+        fs::remove_dir_all(&dir.dir).unwrap();
+
+        assert_eq!(("stopped", None, None), stopped);
+        assert_eq!(("paused", Some(1000), Some(60)), paused);
+        assert_eq!(("suspended", None, None), suspended);
+        assert_eq!(("unresponsive", Some(1000), None), unresponsive);
+    }
+}
@@ -0,0 +1,161 @@
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use clap::Args;
+
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+use crate::util::log_file;
+
+#[derive(Args)]
+pub struct Logs {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(long, short, help = "keep printing new output, press ctrl-c to quit", default_value_t = false)]
+    follow: bool,
+
+    #[arg(long, short = 'n', help = "number of last lines to print", default_value_t = 100)]
+    lines: u32,
+
+    #[arg(
+        long,
+        help = "print output since duration ago instead of last lines, including rotated logs, e.g. 30m, 1h, 2d"
+    )]
+    since: Option<String>,
+}
+
+impl Logs {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        if !dir.log_path.exists() {
+            return Err(Exception::ValidationError(format!(
+                "log not found, vm was not started yet, name={name}, path={}",
+                dir.log_path.to_string_lossy()
+            )));
+        }
+
+        let mut command = Command::new("tail");
+        if let Some(since) = &self.since {
+            let since = SystemTime::now() - parse_since(since)?;
+            let cutoff = format_timestamp(since.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs()));
+            // oldest rotated file first
+            let mut paths: Vec<_> = (1..=log_file::MAX_ROTATED_FILES)
+                .rev()
+                .map(|index| log_file::rotated_path(&dir.log_path, index))
+                .filter(|path| path.exists())
+                .collect();
+            paths.push(dir.log_path.clone());
+            // lines without timestamp, e.g. guest output, belong to previous log line
+            let mut included = false;
+            for path in paths {
+                for line in BufReader::new(File::open(path)?).lines() {
+                    let line = line?;
+                    if let Some(timestamp) = timestamp(&line) {
+                        included = timestamp >= cutoff.as_str();
+                    }
+                    if included {
+                        println!("{line}");
+                    }
+                }
+            }
+            if !self.follow {
+                return Ok(());
+            }
+            command.args(["-n", "0"]);
+        } else {
+            command.arg("-n").arg(self.lines.to_string());
+        }
+        if self.follow {
+            // -F keeps following after log is rotated
+            command.arg("-F");
+        }
+        command.arg(&dir.log_path);
+        Err(command.exec().into())
+    }
+}
+
+fn parse_since(value: &str) -> Result<Duration, Exception> {
+    let invalid = || Exception::ValidationError(format!("invalid since, it must be number with s, m, h or d, e.g. 1h, since={value}"));
+    let unit = value.chars().last().ok_or_else(invalid)?;
+    let number: u64 = value[..value.len() - unit.len_utf8()].parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86400,
+        _ => return Err(invalid()),
+    };
+    Ok(Duration::from_secs(number * seconds))
+}
+
+// log lines start with utc timestamp of tracing, e.g. 2024-05-01T10:00:00.123456Z
+fn timestamp(line: &str) -> Option<&str> {
+    let timestamp = line.get(..19)?;
+    let valid = timestamp.char_indices().all(|(index, c)| match index {
+        4 | 7 => c == '-',
+        10 => c == 'T',
+        13 | 16 => c == ':',
+        _ => c.is_ascii_digit(),
+    });
+    valid.then_some(timestamp)
+}
+
+// utc timestamp in same format as log lines, to compare as string
+fn format_timestamp(seconds: u64) -> String {
+    // civil from days, days since 0000-03-01
+    let days = seconds / 86400 + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+        seconds % 86400 / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[test]
+    fn parse_since() {
+        assert_eq!(super::parse_since("30m").unwrap(), Duration::from_secs(1800));
+        assert_eq!(super::parse_since("2d").unwrap(), Duration::from_secs(2 * 86400));
+        assert!(super::parse_since("1w").is_err());
+        assert!(super::parse_since("h").is_err());
+    }
+
+    #[test]
+    fn timestamp() {
+        assert_eq!(
+            super::timestamp("2024-05-01T10:00:00.123456Z  INFO ThreadId(01) vz::vm: vm started"),
+            Some("2024-05-01T10:00:00")
+        );
+        assert_eq!(super::timestamp("[  OK  ] Started ssh.service"), None);
+    }
+
+    #[test]
+    fn format_timestamp() {
+        assert_eq!(super::format_timestamp(0), "1970-01-01T00:00:00");
+        assert_eq!(super::format_timestamp(1714557600), "2024-05-01T10:00:00");
+        assert_eq!(super::format_timestamp(951782400), "2000-02-29T00:00:00");
+    }
+}
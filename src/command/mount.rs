@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+use clap::Args;
+use clap::ValueHint;
+use tracing::info;
+
+use crate::config::vm_config::Os;
+use crate::config::vm_config::VmConfig;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::dhcp_lease;
+use crate::util::exception::Exception;
+use crate::util::path;
+use crate::util::path::PathExtension;
+
+#[derive(Args)]
+pub struct Mount {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(help = "mount point on host, created if not exists", value_hint = ValueHint::DirPath)]
+    mount_point: PathBuf,
+
+    #[arg(long, help = "guest dir to mount, only for running vm", default_value = "/")]
+    path: String,
+
+    #[arg(long, short, help = "guest user, default to ssh_user in config")]
+    user: Option<String>,
+}
+
+impl Mount {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        let config = dir.load_config()?;
+        let mount_point = self.mount_point.to_absolute_path();
+        fs::create_dir_all(&mount_point)?;
+
+        if dir.pid().is_some() {
+            self.mount_sshfs(&dir, &config, &mount_point)
+        } else {
+            attach_disk(&dir, &config, &mount_point)
+        }
+    }
+
+    // running guest is mounted by sftp over ssh, it requires macFUSE and sshfs on host
+    fn mount_sshfs(&self, dir: &VmDir, config: &VmConfig, mount_point: &Path) -> Result<(), Exception> {
+        let name = dir.name();
+        let ip = dhcp_lease::find_ip(&config.mac_address)?
+            .ok_or_else(|| Exception::ValidationError(format!("vm ip not found, name={name}, mac_address={}", config.mac_address)))?;
+        let user = self.user.as_ref().or(config.ssh_user.as_ref());
+        let destination = match user {
+            Some(user) => format!("{user}@{ip}:{}", self.path),
+            None => format!("{ip}:{}", self.path),
+        };
+
+        let mut command = Command::new("sshfs");
+        command
+            .arg(&destination)
+            .arg(mount_point)
+            .arg("-o")
+            .arg(format!("volname={name},UserKnownHostsFile={}", dir.known_hosts_path.to_string_lossy()))
+            .args(["-o", "StrictHostKeyChecking=accept-new,CheckHostIP=no,reconnect"]);
+        // path is validated by load_config
+        if let Some(key) = config.ssh_key.as_ref().and_then(|key| path::expand(key).ok()) {
+            command.arg("-o").arg(format!("IdentityFile={}", key.to_string_lossy()));
+        }
+        info!("mount guest dir, source={destination}, mount_point={}", mount_point.to_string_lossy());
+        let status = command
+            .status()
+            .map_err(|err| Exception::ValidationError(format!("failed to run sshfs, install macFUSE and sshfs to mount running vm, error={err}")))?;
+        if !status.success() {
+            return Err(Exception::ValidationError(format!("failed to mount guest dir, status={status}")));
+        }
+        info!("unmount by umount {}", mount_point.to_string_lossy());
+        Ok(())
+    }
+}
+
+// stopped macOS guest disk is attached read only, its APFS volumes are mounted under mount point
+fn attach_disk(dir: &VmDir, config: &VmConfig, mount_point: &Path) -> Result<(), Exception> {
+    let name = dir.name();
+    if config.os == Os::Linux {
+        return Err(Exception::ValidationError(format!(
+            "disk of stopped linux vm can't be mounted, host doesn't support guest filesystem, run vm to mount by sshfs, name={name}"
+        )));
+    }
+    info!(
+        "attach disk read only, disk={}, mount_point={}",
+        dir.disk_path.to_string_lossy(),
+        mount_point.to_string_lossy()
+    );
+    let status = Command::new("hdiutil")
+        .args(["attach", "-readonly", "-noverify", "-noautoopen"])
+        .args(["-imagekey", "diskimage-class=CRawDiskImage", "-mountroot"])
+        .arg(mount_point)
+        .arg(&dir.disk_path)
+        .status()?;
+    if !status.success() {
+        return Err(Exception::ValidationError(format!("failed to attach disk, name={name}, status={status}")));
+    }
+    info!("detach by hdiutil detach with first disk device above, detach before starting vm");
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use std::fs;
+use std::io;
+use std::net::Ipv4Addr;
+
+use clap::Args;
+use clap::Subcommand;
+use tracing::info;
+
+use crate::config::vm_dir;
+use crate::util::dhcp_lease;
+use crate::util::dhcp_lease::BOOTPTAB_PATH;
+use crate::util::exception::Exception;
+
+#[derive(Args)]
+pub struct Net {
+    #[command(subcommand)]
+    command: NetCommand,
+}
+
+#[derive(Subcommand)]
+enum NetCommand {
+    #[command(about = "reserve fixed ip of vm in NAT dhcp server, requires sudo")]
+    Reserve {
+        #[arg(help = "vm name")]
+        name: String,
+
+        #[arg(help = "ip in NAT subnet, e.g. 192.168.64.10, default to last ip of vm, so it keeps current address")]
+        ip: Option<Ipv4Addr>,
+    },
+    #[command(about = "remove ip reservation of vm, requires sudo")]
+    Release {
+        #[arg(help = "vm name")]
+        name: String,
+    },
+}
+
+impl Net {
+    pub fn execute(&self) -> Result<(), Exception> {
+        match &self.command {
+            NetCommand::Reserve { name, ip } => {
+                let ip = match ip {
+                    Some(ip) => *ip,
+                    None => last_ip(name)?,
+                };
+                update(name, Some(ip))
+            }
+            NetCommand::Release { name } => update(name, None),
+        }
+    }
+}
+
+fn last_ip(name: &str) -> Result<Ipv4Addr, Exception> {
+    let ip = vm_dir::vm_dir(name)
+        .last_ip()
+        .ok_or_else(|| Exception::ValidationError(format!("ip of vm is unknown, run vm first or specify ip, name={name}")))?;
+    ip.parse()
+        .map_err(|_| Exception::ValidationError(format!("invalid last ip of vm, name={name}, ip={ip}")))
+}
+
+fn update(name: &str, ip: Option<Ipv4Addr>) -> Result<(), Exception> {
+    let dir = vm_dir::vm_dir(name);
+    if !dir.initialized() {
+        return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+    }
+    let config = dir.load_config()?;
+    let bootptab = dhcp_lease::read_bootptab()?;
+    let ip = ip.map(|ip| ip.to_string());
+    if let Some(ip) = &ip {
+        if let Some(reservation) = dhcp_lease::reservations(&bootptab)
+            .into_iter()
+            .find(|reservation| reservation.ip == ip && reservation.name != name)
+        {
+            return Err(Exception::ValidationError(format!(
+                "ip is already reserved, ip={ip}, name={}",
+                reservation.name
+            )));
+        }
+    }
+
+    let bootptab = dhcp_lease::update_reservation(&bootptab, name, &config.mac_address, ip.as_deref());
+    fs::write(BOOTPTAB_PATH, bootptab).map_err(|err| {
+        if err.kind() == io::ErrorKind::PermissionDenied {
+            Exception::ValidationError(format!("{BOOTPTAB_PATH} is not writable, run with sudo"))
+        } else {
+            err.into()
+        }
+    })?;
+    // bootpd reads bootptab on each request, running guest gets reserved ip when it renews lease
+    info!(
+        "ip reservation updated, name={name}, mac_address={}, ip={}",
+        config.mac_address,
+        ip.as_deref().unwrap_or("none")
+    );
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use clap::Args;
+use tracing::info;
+
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+use crate::vm::control;
+use crate::vm::control::Request;
+
+#[derive(Args)]
+pub struct Pause {
+    #[arg(help = "vm name")]
+    name: String,
+}
+
+impl Pause {
+    pub fn execute(&self) -> Result<(), Exception> {
+        send(&self.name, Request::Pause)?;
+        info!("vm paused, resume by vz resume, name={}", self.name);
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+pub struct Resume {
+    #[arg(help = "vm name")]
+    name: String,
+}
+
+impl Resume {
+    pub fn execute(&self) -> Result<(), Exception> {
+        send(&self.name, Request::Resume)?;
+        info!("vm resumed, name={}", self.name);
+        Ok(())
+    }
+}
+
+fn send(name: &str, request: Request) -> Result<(), Exception> {
+    let dir = vm_dir::vm_dir(name);
+    if !dir.initialized() {
+        return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+    }
+    if dir.pid().is_none() {
+        return Err(Exception::ValidationError(format!("vm is not running, name={name}")));
+    }
+    control::send(&dir, &request)?;
+    Ok(())
+}
@@ -0,0 +1,24 @@
+use clap::Args;
+
+use crate::config::image_cache;
+use crate::util::exception::Exception;
+
+#[derive(Args)]
+pub struct Pull {
+    #[arg(help = "image name to refer in create --image, e.g. ubuntu-24.04")]
+    name: String,
+
+    #[arg(help = "url of raw, qcow2, vmdk or vhdx disk image, e.g. https://cloud-images.ubuntu.com/noble/current/noble-server-cloudimg-arm64.img")]
+    url: String,
+
+    #[arg(long, help = "expected sha256 of downloaded file, image is rejected if it doesn't match")]
+    sha256: Option<String>,
+}
+
+impl Pull {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let path = image_cache::pull(&self.name, &self.url, self.sha256.as_deref())?;
+        println!("{}", path.to_string_lossy());
+        Ok(())
+    }
+}
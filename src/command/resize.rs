@@ -1,7 +1,17 @@
+use std::fs;
+use std::path::PathBuf;
+
 use clap::Args;
 use tracing::info;
 
+use crate::command::set::format_memory;
+use crate::command::set::parse_memory;
+use crate::config::cloud_init;
+use crate::config::settings;
+use crate::config::vm_config::Os;
+use crate::config::vm_config::VmConfig;
 use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
 use crate::util::exception::Exception;
 
 #[derive(Args)]
@@ -9,8 +19,37 @@ pub struct Resize {
     #[arg(help = "vm name")]
     name: String,
 
-    #[arg(long, help = "disk size in gb")]
-    disk_size: u64,
+    #[arg(long, help = "disk size in gb, disk can only grow")]
+    disk_size: Option<u64>,
+
+    #[arg(
+        long,
+        help = "name of added disk to resize with --disk-size, default to main disk",
+        requires = "disk_size"
+    )]
+    disk: Option<String>,
+
+    #[arg(long, help = "cpu count")]
+    cpu: Option<usize>,
+
+    #[arg(long, help = "memory with unit, e.g. 16G or 512M, gb if without unit", value_parser = parse_memory)]
+    memory: Option<u64>,
+
+    #[arg(
+        long,
+        help = "change cpu and memory of running vm, they take effect on next start",
+        default_value_t = false
+    )]
+    next_boot: bool,
+
+    #[arg(
+        long,
+        help = "grow guest partition and filesystem on next boot, requires cloud-init in linux guest",
+        default_value_t = false,
+        requires = "disk_size",
+        conflicts_with = "disk"
+    )]
+    grow_partition: bool,
 }
 
 impl Resize {
@@ -20,14 +59,93 @@ impl Resize {
         if !dir.initialized() {
             return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
         }
+        if self.disk_size.is_none() && self.cpu.is_none() && self.memory.is_none() {
+            return Err(Exception::ValidationError(
+                "nothing to resize, specify --disk-size, --cpu or --memory".to_string(),
+            ));
+        }
+        dir.check_not_suspended()?;
+        let running = dir.pid().is_some();
+        // guest sees disk size only at boot, and may write beyond old size while file grows
+        if running && self.disk_size.is_some() {
+            return Err(Exception::ValidationError(format!(
+                "vm is running, stop it first to resize disk, name={name}"
+            )));
+        }
+        if running && !self.next_boot {
+            return Err(Exception::ValidationError(format!(
+                "vm is running, stop it first or use --next-boot, name={name}"
+            )));
+        }
+        // vm must not start with half applied changes
+        let _lock = if running { None } else { Some(dir.lock("resize")?) };
+        let mut config = dir.load_config()?;
 
-        let size = dir.disk_path.metadata()?.len();
-        if size >= self.disk_size * 1_000_000_000 {
-            return Err(Exception::ValidationError(format!("disk size must larger than current, current={size}")));
+        // validate all changes before applying any
+        let disk = match self.disk_size {
+            Some(disk_size) => Some(self.validate_disk(&dir, &config, disk_size)?),
+            None => None,
+        };
+        if self.cpu.is_some() || self.memory.is_some() {
+            self.resize_cpu_and_memory(&dir, &mut config)?;
+        }
+        if let Some((path, size)) = disk {
+            info!("increase disk size, file={}, size={}", path.to_string_lossy(), format_disk_size(size));
+            // extended range is hole, it's not allocated until guest writes
+            fs::OpenOptions::new().write(true).open(&path)?.set_len(size)?;
+            if self.grow_partition {
+                cloud_init::update_seed(&dir, &config, Some(cloud_init::GROW_PARTITION))?;
+            }
+        }
+        if running {
+            info!("vm is running, changes take effect on next start, name={name}");
         }
+        Ok(())
+    }
 
-        info!("increase disk size, file={}, size={}G", dir.disk_path.to_string_lossy(), self.disk_size);
-        dir.resize(self.disk_size * 1_000_000_000)?;
+    fn resize_cpu_and_memory(&self, dir: &VmDir, config: &mut VmConfig) -> Result<(), Exception> {
+        let (cpu, memory) = (config.cpu, config.memory);
+        if let Some(cpu) = self.cpu {
+            config.cpu = cpu;
+        }
+        if let Some(memory) = self.memory {
+            config.memory = memory;
+        }
+        config.validate_host_limits()?;
+        dir.save_config(config)?;
+        info!(
+            "resize vm, name={}, cpu={cpu}->{}, memory={}->{}",
+            dir.name(),
+            config.cpu,
+            format_memory(memory),
+            format_memory(config.memory)
+        );
         Ok(())
     }
+
+    // path and new size in bytes of disk to grow
+    fn validate_disk(&self, dir: &VmDir, config: &VmConfig, disk_size: u64) -> Result<(PathBuf, u64), Exception> {
+        if self.grow_partition && !matches!(config.os, Os::Linux) {
+            return Err(Exception::ValidationError("grow partition requires linux guest".to_string()));
+        }
+        let path = match &self.disk {
+            Some(disk) if config.disks.contains(disk) => dir.extra_disk_path(disk),
+            Some(disk) => return Err(Exception::ValidationError(format!("disk not found, disk={disk}"))),
+            None => dir.disk_path.clone(),
+        };
+
+        let size = path.metadata()?.len();
+        if size >= disk_size * 1_000_000_000 {
+            return Err(Exception::ValidationError(format!(
+                "disk can only grow, size must be larger than current, size={disk_size}G, current={}",
+                format_disk_size(size)
+            )));
+        }
+        settings::check_storage_quota(disk_size * 1_000_000_000 - size)?;
+        Ok((path, disk_size * 1_000_000_000))
+    }
+}
+
+fn format_disk_size(size: u64) -> String {
+    format!("{:.2}G", size as f64 / 1_000_000_000.0)
 }
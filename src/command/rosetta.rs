@@ -0,0 +1,64 @@
+use std::process::Command;
+
+use clap::Args;
+use clap::Subcommand;
+use objc2_virtualization::VZLinuxRosettaAvailability;
+use objc2_virtualization::VZLinuxRosettaDirectoryShare;
+use tracing::info;
+
+use crate::util::exception::Exception;
+use crate::vm::linux;
+
+#[derive(Args)]
+pub struct Rosetta {
+    #[command(subcommand)]
+    command: RosettaCommand,
+}
+
+#[derive(Subcommand)]
+enum RosettaCommand {
+    #[command(about = "print whether rosetta for linux vm is available on host")]
+    Status,
+    #[command(about = "install rosetta on host by softwareupdate, it asks to agree to license")]
+    Install,
+}
+
+impl Rosetta {
+    pub fn execute(&self) -> Result<(), Exception> {
+        match self.command {
+            RosettaCommand::Status => {
+                println!("{}", status());
+                Ok(())
+            }
+            RosettaCommand::Install => install(),
+        }
+    }
+}
+
+fn status() -> &'static str {
+    match unsafe { VZLinuxRosettaDirectoryShare::availability() } {
+        VZLinuxRosettaAvailability::Installed => "installed",
+        VZLinuxRosettaAvailability::NotInstalled => "not installed, install with vz rosetta install",
+        _ => "not supported, rosetta requires apple silicon host",
+    }
+}
+
+fn install() -> Result<(), Exception> {
+    match unsafe { VZLinuxRosettaDirectoryShare::availability() } {
+        VZLinuxRosettaAvailability::Installed => {
+            info!("rosetta is already installed");
+            return Ok(());
+        }
+        VZLinuxRosettaAvailability::NotInstalled => {}
+        _ => return Err(Exception::ValidationError(status().to_string())),
+    }
+    info!("install rosetta, run: softwareupdate --install-rosetta");
+    let status = Command::new("softwareupdate").arg("--install-rosetta").status()?;
+    if !status.success() || !linux::rosetta_installed() {
+        return Err(Exception::ValidationError(format!(
+            "failed to install rosetta, status={status}, install manually with softwareupdate --install-rosetta"
+        )));
+    }
+    info!("rosetta installed, enable it for linux vm with vz set <name> --rosetta=on");
+    Ok(())
+}
@@ -1,10 +1,17 @@
-use std::env::current_exe;
+use std::fs;
 use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::IsTerminal;
+use std::os::unix::fs::symlink;
 use std::path::PathBuf;
-use std::process::Command;
-use std::process::Stdio;
+use std::sync::mpsc::channel;
 use std::sync::Arc;
 use std::thread;
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::Instant;
 
 use clap::Args;
 use clap::ValueHint;
@@ -28,6 +35,7 @@ use objc2_foundation::MainThreadBound;
 use objc2_foundation::MainThreadMarker;
 use objc2_foundation::NSRect;
 use objc2_foundation::NSString;
+use objc2_virtualization::VZSerialPortConfiguration;
 use objc2_virtualization::VZVirtualMachine;
 use objc2_virtualization::VZVirtualMachineDelegate;
 use objc2_virtualization::VZVirtualMachineView;
@@ -35,67 +43,312 @@ use signal_hook::consts::signal::SIGINT;
 use signal_hook::consts::signal::SIGQUIT;
 use signal_hook::consts::signal::SIGTERM;
 use signal_hook::iterator::Signals;
+use tracing::error;
 use tracing::info;
+use tracing::warn;
 
+use crate::command::create;
+use crate::command::set;
+use crate::command::wait;
+use crate::command::wait::Condition;
+use crate::config::cloud_init;
+use crate::config::image_cache;
+use crate::config::settings;
+use crate::config::vm_config;
 use crate::config::vm_config::Os;
+use crate::config::vm_config::RestartPolicy;
+use crate::config::vm_config::SerialBackend;
+use crate::config::vm_config::VmConfig;
 use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::dhcp_lease;
 use crate::util::exception::Exception;
-use crate::util::path::PathExtension;
+use crate::util::hosts_sync;
+use crate::util::notification;
+use crate::util::os_log;
+use crate::util::otlp;
+use crate::util::path;
 use crate::vm;
+use crate::vm::clipboard;
+use crate::vm::console;
+use crate::vm::console::Pty;
+use crate::vm::control;
+use crate::vm::cpu_limit;
 use crate::vm::gui_delegate::GuiDelegate;
 use crate::vm::linux;
 use crate::vm::mac_os;
+use crate::vm::platform::Platform;
+use crate::vm::platform::Virtualization;
+use crate::vm::runner;
 use crate::vm::vm_delegate::VmDelegate;
+use crate::vm::vsock;
+
+mod auto_forward;
+
+const READY_TIMEOUT: Duration = Duration::from_secs(600);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+const IP_INTERVAL: Duration = Duration::from_secs(5);
+// same as timeout(1)
+const TIMEOUT_EXIT_CODE: i32 = 124;
 
 #[derive(Args)]
 pub struct Run {
-    #[arg(help = "vm name")]
-    name: String,
+    #[arg(help = "vm name", required_unless_present = "image")]
+    name: Option<String>,
     #[arg(long, help = "open UI window", default_value_t = false)]
     gui: bool,
-    #[arg(short, help = "run vm in background", default_value_t = false)]
+    #[arg(
+        short,
+        long = "detach",
+        help = "run vm in background, output goes to vz.log in vm dir, view with vz logs",
+        default_value_t = false
+    )]
     detached: bool,
-    #[arg(long, help = "attach disk image in read only mode, e.g. --mount=debian.iso", value_hint = ValueHint::FilePath)]
-    mount: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "with -d, wait until vm is ready, ip or ssh, or port:<port>, e.g. --wait=ssh, ready uses readiness probe of config",
+        requires = "detached",
+        value_parser = wait::parse_condition
+    )]
+    wait: Option<Condition>,
+    #[arg(
+        long,
+        help = "attach disk image as read only usb storage to linux vm, can be repeated, e.g. --mount=debian.iso",
+        value_hint = ValueHint::FilePath
+    )]
+    mount: Vec<PathBuf>,
+    #[arg(long, help = "run vm without network devices", default_value_t = false)]
+    no_network: bool,
+    #[arg(long, help = "cpu count of this run, config is not changed")]
+    cpu: Option<usize>,
+    #[arg(long, help = "memory of this run, config is not changed, e.g. 12G", value_parser = set::parse_memory)]
+    memory: Option<u64>,
+    #[arg(
+        long,
+        help = "boot macOS vm into recoveryOS, e.g. to disable SIP, use with --gui",
+        default_value_t = false
+    )]
+    recovery: bool,
+    #[arg(
+        long,
+        help = "run vm in background and attach serial console, press ctrl-] to detach",
+        default_value_t = false
+    )]
+    console: bool,
+    #[arg(
+        long,
+        help = "run command in throwaway clone of linux vm, then destroy clone, requires cloud-init in guest",
+        default_value_t = false
+    )]
+    rm: bool,
+    #[arg(
+        long,
+        help = "with --rm, boot throwaway vm from image pulled by vz pull instead of clone of vm, e.g. vz run --rm --image=ubuntu-24.04 -- uname -a",
+        requires = "rm",
+        conflicts_with = "name"
+    )]
+    image: Option<String>,
+    #[arg(last = true, help = "command to run with --rm, e.g. vz run --rm debian -- make test")]
+    command: Vec<String>,
+}
+
+// hardware of single run, config.json is not changed, background runner gets them as args
+#[derive(Default)]
+pub struct Overrides {
+    pub no_network: bool,
+    pub cpu: Option<usize>,
+    pub memory: Option<u64>,
+}
+
+impl Overrides {
+    fn apply(&self, config: &mut VmConfig) -> Result<(), Exception> {
+        if self.no_network {
+            config.network = Some(false);
+        }
+        if self.cpu.is_none() && self.memory.is_none() {
+            return Ok(());
+        }
+        if let Some(cpu) = self.cpu {
+            config.cpu = cpu;
+        }
+        if let Some(memory) = self.memory {
+            config.memory = memory;
+        }
+        config.validate_host_limits()?;
+        info!(
+            "override hardware of this run, cpu={}, memory={}",
+            config.cpu,
+            set::format_memory(config.memory)
+        );
+        Ok(())
+    }
+
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![];
+        if self.no_network {
+            args.push("--no-network".to_string());
+        }
+        if let Some(cpu) = self.cpu {
+            args.extend(["--cpu".to_string(), cpu.to_string()]);
+        }
+        if let Some(memory) = self.memory {
+            args.extend(["--memory".to_string(), set::format_memory(memory)]);
+        }
+        args
+    }
 }
 
 impl Run {
     pub fn execute(&self) -> Result<(), Exception> {
         self.validate()?;
 
-        let name = &self.name;
+        // ephemeral vm dir is removed if vm fails to start
+        if let Some(image) = &self.image {
+            return self.run_ephemeral_image(image).inspect_err(|_| vm::remove_ephemeral_dir());
+        }
+        // clap requires name without --image
+        let name = self.name.as_deref().unwrap_or_default();
         let dir = vm_dir::vm_dir(name);
         if !dir.initialized() {
             return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
         }
 
+        if self.console {
+            return run_with_console(&dir, &self.overrides());
+        }
+
+        if self.rm {
+            return self.run_ephemeral(&dir).inspect_err(|_| vm::remove_ephemeral_dir());
+        }
+
         if self.detached {
-            return run_in_background(name);
+            run_in_background(name, &self.overrides())?;
+            if let Some(condition) = self.wait {
+                runner::wait_until_started(&dir, Duration::from_secs(5));
+                wait::wait(name, condition, Duration::from_secs(wait::DEFAULT_TIMEOUT))?;
+            }
+            return Ok(());
         }
 
-        let config = dir.load_config()?;
+        let mut config = dir.load_config()?;
+        self.overrides().apply(&mut config)?;
+        if let Some(false) = config.network {
+            info!("network is disabled, vm is isolated");
+        }
+        if self.recovery && !matches!(config.os, Os::MacOs) {
+            return Err(Exception::ValidationError("--recovery is only supported for macOS vm".to_string()));
+        }
+        // saved state resumes running macOS, recoveryOS requires cold boot
+        if self.recovery && dir.state_path.exists() {
+            return Err(Exception::ValidationError(format!(
+                "vm is suspended, resume and shut it down before booting into recoveryOS, name={name}"
+            )));
+        }
+        if let (Os::MacOs, true) = (&config.os, dir.uninstalled_path.exists()) {
+            return Err(Exception::ValidationError(format!(
+                "macOS is not installed, run vz install {name} --ipsw=<ipsw> first"
+            )));
+        }
+        if !self.mount.is_empty() && !matches!(config.os, Os::Linux) {
+            return Err(Exception::ValidationError("--mount is only supported for linux vm".to_string()));
+        }
+        validate_cpu_limit(config.cpu_limit_percent)?;
+        settings::check_running_limits(name, &config)?;
+        if let Os::Linux = config.os {
+            cloud_init::update_seed(&dir, &config, None)?;
+        }
+
+        // must hold lock reference, lock is released once it's dropped
+        let _lock = dir.lock("run")?;
+        settings::record_resources(&dir, &config)?;
+        otlp::set_vm_name(name);
+        if let Some(true) = config.os_log {
+            os_log::enable(name);
+        }
+        if let Some(true) = config.notify {
+            notification::enable(name);
+        }
+        if settings::load()?.sync_hosts == Some(true) && config.network != Some(false) {
+            hosts_sync::enable(name);
+        }
+        if let Some(policy) = config.restart {
+            vm::set_restart_policy(policy);
+        }
 
-        // must after vm_dir.load_config(), it cloese config file and release all fd
-        // must hold lock reference, otherwise fd will be deallocated, and release all locks
-        let _lock = dir.lock()?;
+        let console = match config.os {
+            Os::Linux => Some(create_console(&dir)?),
+            Os::MacOs => None,
+        };
+
+        let mut serial_ports = vec![];
+        match &console {
+            Some(pty) if config.os_log == Some(true) => serial_ports.push(console::mirrored_serial_port(pty, os_log::info)?),
+            Some(pty) => serial_ports.push(console::serial_port(pty)),
+            None => {}
+        }
+        // must hold ptys of additional serial ports while vm is running
+        let _ptys = match config.os {
+            Os::Linux => create_serial_ports(&dir, &config, &mut serial_ports)?,
+            Os::MacOs => vec![],
+        };
 
         let marker = MainThreadMarker::new().unwrap();
         let vm = match config.os {
-            Os::Linux => linux::create_vm(&dir, &config, self.gui, self.mount.as_ref())?,
+            Os::Linux => linux::create_vm(&dir, &config, self.gui, &self.mount, serial_ports)?,
             Os::MacOs => mac_os::create_vm(&dir, &config, marker)?,
         };
         let proto: Retained<ProtocolObject<dyn VZVirtualMachineDelegate>> = ProtocolObject::from_retained(VmDelegate::new());
         unsafe {
             vm.setDelegate(Some(&proto));
         }
+        let _listeners = config
+            .vsock_exposes
+            .iter()
+            .map(|expose| vsock::expose(&vm, expose))
+            .collect::<Result<Vec<_>, _>>()?;
         let vm = Arc::new(MainThreadBound::new(vm, marker));
-        vm::start_vm(Arc::clone(&vm));
+        if self.recovery {
+            vm::start_vm_from_recovery(Arc::clone(&vm));
+        } else if dir.state_path.exists() {
+            vm::restore_vm(Arc::clone(&vm), dir.state_path.clone());
+        } else {
+            vm::start_vm(Arc::clone(&vm));
+        }
+        if let Some(percent) = config.cpu_limit_percent {
+            cpu_limit::limit(percent);
+        }
 
         handle_signal(Arc::clone(&vm))?;
+        control::listen(&dir, Arc::clone(&vm))?;
+
+        for forward in &config.vsock_forwards {
+            vsock::forward(Arc::clone(&vm), forward)?;
+        }
+        if let (Os::MacOs, Some(true)) = (&config.os, config.clipboard) {
+            clipboard::connect(Arc::clone(&vm));
+        }
+
+        if config.network != Some(false) {
+            watch_ip(&dir, &config.mac_address);
+        }
+        if config.readiness_probe.is_some() || config.start_timeout.is_some() || config.heartbeat_timeout.is_some() {
+            // marker of previous runner
+            if dir.unresponsive_path.exists() {
+                fs::remove_file(&dir.unresponsive_path)?;
+            }
+            watch_start(&dir, &config, Arc::clone(&vm));
+        }
+        if !config.auto_forward_ports.is_empty() {
+            auto_forward::start(name.to_string(), config.auto_forward_ports.clone(), READY_TIMEOUT);
+        }
+
+        // foreground runner started from terminal, e.g. not by run_in_background
+        if !self.gui && console.is_some() && io::stdin().is_terminal() {
+            console::attach_stdio(&dir.console_path)?;
+        }
 
         if self.gui {
-            let auto_reconfig_display = matches!(&config.os, Os::MacOs);
-            run_gui(name, marker, vm, auto_reconfig_display);
+            run_gui(name, config.window_size(), marker, vm);
         } else {
             unsafe {
                 dispatch_main();
@@ -105,59 +358,398 @@ impl Run {
         Ok(())
     }
 
+    fn overrides(&self) -> Overrides {
+        Overrides {
+            no_network: self.no_network,
+            cpu: self.cpu,
+            memory: self.memory,
+        }
+    }
+
+    // log file if this process runs vm, instead of launching runner in background or console
+    pub fn log_path(&self) -> Option<PathBuf> {
+        let dir = vm_dir::vm_dir(self.name.as_deref()?);
+        (!self.console && !self.rm && !self.detached && dir.initialized()).then_some(dir.log_path)
+    }
+
     fn validate(&self) -> Result<(), Exception> {
-        if let Some(path) = &self.mount {
+        if let Some(path) = self.mount.iter().find(|path| !path.exists()) {
             return Err(Exception::ValidationError(format!(
                 "mount does not exist, path={}",
                 path.to_string_lossy()
             )));
         }
 
-        if self.detached && (self.gui || self.mount.is_some()) {
+        if self.detached && (self.gui || !self.mount.is_empty()) {
             return Err(Exception::ValidationError("-d must not be used with --gui and --mount".to_string()));
         }
 
+        if self.console && (self.gui || self.detached || !self.mount.is_empty()) {
+            return Err(Exception::ValidationError(
+                "--console must not be used with --gui, -d and --mount".to_string(),
+            ));
+        }
+
+        if self.recovery && (self.detached || self.console || self.rm) {
+            return Err(Exception::ValidationError(
+                "--recovery must not be used with -d, --console and --rm".to_string(),
+            ));
+        }
+
+        if self.rm && (self.gui || self.detached || self.console) {
+            return Err(Exception::ValidationError(
+                "--rm must not be used with --gui, -d and --console".to_string(),
+            ));
+        }
+
+        if self.rm && self.command.is_empty() {
+            return Err(Exception::ValidationError(
+                "--rm requires command, e.g. vz run --rm debian -- uname -a".to_string(),
+            ));
+        }
+
+        if !self.rm && !self.command.is_empty() {
+            return Err(Exception::ValidationError(
+                "command requires --rm, e.g. vz run --rm debian -- uname -a".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn run_ephemeral(&self, source: &VmDir) -> Result<(), Exception> {
+        let mut config = source.load_config()?;
+        if !matches!(config.os, Os::Linux) {
+            return Err(Exception::ValidationError("--rm requires linux guest".to_string()));
+        }
+        // disk must not be cloned while written by running vm
+        if source.pid().is_some() {
+            return Err(Exception::ValidationError(format!("vm is running, name={}", source.name())));
+        }
+        if source.state_path.exists() {
+            return Err(Exception::ValidationError(format!("vm is suspended, name={}", source.name())));
+        }
+
+        let dir = vm_dir::create_ephemeral_vm_dir(source)?;
+        vm::set_ephemeral_dir(dir.dir.clone());
+        // clone runs next to source with own mac addresses, including extra networks
+        create::regenerate_identity(&mut config);
+        self.run_in_ephemeral_vm(&source.name(), dir, config)
+    }
+
+    // throwaway vm of default linux config, with clone of cached image as boot disk
+    fn run_ephemeral_image(&self, image: &str) -> Result<(), Exception> {
+        let image_path = image_cache::resolve(image)?;
+        let dir = vm_dir::create_empty_ephemeral_vm_dir()?;
+        vm::set_ephemeral_dir(dir.dir.clone());
+        info!(
+            "clone image, image={}, disk={}",
+            image_path.to_string_lossy(),
+            dir.disk_path.to_string_lossy()
+        );
+        path::clone_file(&image_path, &dir.disk_path)?;
+        create::create_linux(&dir)?;
+        let config = dir.load_config()?;
+        self.run_in_ephemeral_vm(image, dir, config)
+    }
+
+    fn run_in_ephemeral_vm(&self, name: &str, dir: VmDir, mut config: VmConfig) -> Result<(), Exception> {
+        otlp::set_vm_name(name);
+        if let Some(true) = config.os_log {
+            os_log::enable(name);
+        }
+        if let Some(true) = config.notify {
+            notification::enable(name);
+        }
+        self.overrides().apply(&mut config)?;
+        validate_cpu_limit(config.cpu_limit_percent)?;
+        settings::check_running_limits(&dir.name(), &config)?;
+        dir.save_config(&config)?;
+        let user_data = format!("{}{}", cloud_init::run_command(&self.command)?, cloud_init::guest_config(&config)?);
+        cloud_init::create_seed(&dir, &user_data)?;
+        // lock marks clone as running, so it's counted by running limits and not removed by gc
+        let _lock = dir.lock("run")?;
+        settings::record_resources(&dir, &config)?;
+
+        let (serial_port, output) = console::output_serial_port()?;
+        let marker = MainThreadMarker::new().unwrap();
+        let vm = linux::create_vm(&dir, &config, false, &self.mount, vec![serial_port])?;
+        let proto: Retained<ProtocolObject<dyn VZVirtualMachineDelegate>> = ProtocolObject::from_retained(VmDelegate::new());
+        unsafe {
+            vm.setDelegate(Some(&proto));
+        }
+        let vm = Arc::new(MainThreadBound::new(vm, marker));
+        vm::start_vm(Arc::clone(&vm));
+        if let Some(percent) = config.cpu_limit_percent {
+            cpu_limit::limit(percent);
+        }
+        handle_signal(Arc::clone(&vm))?;
+
+        let (sender, receiver) = channel();
+        vm::set_guest_exit_code(receiver);
+        thread::spawn(move || {
+            let exit_code = forward_output(output);
+            let _ = sender.send(exit_code);
+            vm::terminate(exit_code);
+        });
+
+        unsafe {
+            dispatch_main();
+        }
         Ok(())
     }
 }
 
-fn run_in_background(name: &str) -> Result<(), Exception> {
-    let log_path = PathBuf::from("~/Library/Logs/vz.log").to_absolute_path();
+// with start timeout, vm is stopped and runner exits with TIMEOUT_EXIT_CODE if vm is not started and ready in time
+fn watch_start(dir: &VmDir, config: &VmConfig, vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>) {
+    let name = dir.name();
+    let timeout = config.start_timeout.map(Duration::from_secs);
+    let heartbeat_timeout = config.heartbeat_timeout.map(Duration::from_secs);
+    thread::spawn(move || {
+        let dir = vm_dir::vm_dir(&name);
+        let start = Instant::now();
+        let deadline = timeout.unwrap_or(READY_TIMEOUT);
+        while !vm::started() && start.elapsed() < deadline {
+            sleep(Duration::from_millis(100));
+        }
+        let result = if vm::started() {
+            // runner exits when vm stops, so vm is always running while probing
+            dir.load_config().and_then(|config| {
+                let ip = wait::wait_until_ready(&dir, &config, deadline.saturating_sub(start.elapsed()), &|| true)?;
+                Ok((config, ip))
+            })
+        } else {
+            Err(Exception::ValidationError(format!("timeout waiting for vm start, name={name}")))
+        };
+        match result {
+            Ok((config, ip)) => {
+                info!("vm is ready, name={name}, ip={ip}");
+                if let Some(heartbeat_timeout) = heartbeat_timeout {
+                    watch_heartbeat(&dir, &config, &ip, vm, heartbeat_timeout);
+                }
+            }
+            Err(err) if timeout.is_some() => {
+                let message = format!("vm did not start in time, stop vm, error={err}");
+                error!("{message}");
+                os_log::error(&message);
+                notification::notify(&message);
+                let _ = fs::remove_file(&dir.console_path);
+                vm::abort(vm, TIMEOUT_EXIT_CODE);
+            }
+            Err(err) => warn!("vm is not ready, name={name}, error={err}"),
+        }
+    });
+}
+
+// guest may get other ip after lease expires, e.g. after host reboot, last one is kept in vm dir for list and net reserve
+fn watch_ip(dir: &VmDir, mac_address: &str) {
+    let name = dir.name();
+    let mac_address = mac_address.to_string();
+    thread::spawn(move || {
+        let dir = vm_dir::vm_dir(&name);
+        let mut last_ip = dir.last_ip();
+        loop {
+            match dhcp_lease::find_ip(&mac_address) {
+                Ok(Some(ip)) if last_ip.as_ref() != Some(&ip) => {
+                    info!("vm got ip, name={name}, ip={ip}");
+                    if let Err(err) = fs::write(&dir.ip_path, format!("{ip}\n")) {
+                        warn!("failed to write ip, path={}, error={err}", dir.ip_path.to_string_lossy());
+                    }
+                    last_ip = Some(ip);
+                    hosts_sync::sync(false);
+                }
+                Ok(_) => {}
+                Err(err) => warn!("failed to find ip, name={name}, error={err}"),
+            }
+            sleep(IP_INTERVAL);
+        }
+    });
+}
 
-    if let Ok(metadata) = log_path.metadata() {
-        if !metadata.is_file() || metadata.permissions().readonly() {
-            return Err(Exception::ValidationError(format!(
-                "log file is not writable, path={}",
-                log_path.to_string_lossy()
-            )));
+// guest hang is not visible by pid, mark vm unresponsive if it fails liveness check longer than timeout
+fn watch_heartbeat(dir: &VmDir, config: &VmConfig, ip: &str, vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>, timeout: Duration) {
+    let name = dir.name();
+    let mut last_alive = Instant::now();
+    loop {
+        sleep(HEARTBEAT_INTERVAL);
+        let unresponsive = dir.unresponsive_path.exists();
+        if wait::alive(dir, config, ip) {
+            last_alive = Instant::now();
+            if unresponsive {
+                info!("vm is responsive again, name={name}");
+                os_log::info("vm is responsive again");
+                let _ = fs::remove_file(&dir.unresponsive_path);
+            }
+        } else if !unresponsive && last_alive.elapsed() > timeout {
+            let message = format!("vm is unresponsive, no heartbeat in {}s", last_alive.elapsed().as_secs());
+            error!("{message}, name={name}");
+            os_log::error(&message);
+            notification::notify(&message);
+            otlp::count("vz.vm.unresponsive");
+            let _ = fs::write(&dir.unresponsive_path, "");
+            if matches!(config.restart, Some(RestartPolicy::OnFailure | RestartPolicy::Always)) {
+                let _ = fs::remove_file(&dir.unresponsive_path);
+                vm::restart(vm);
+                return;
+            }
         }
     }
+}
 
-    let mut command = Command::new(current_exe()?);
-    command.args(["run", name]);
-    command.stdout(Stdio::from(File::options().create(true).append(true).open(&log_path)?));
-    command.stderr(Stdio::from(File::options().create(true).append(true).open(&log_path)?));
-    command.spawn()?;
-    info!("vm launched in background, check log in {}", log_path.to_string_lossy());
+fn validate_cpu_limit(percent: Option<u8>) -> Result<(), Exception> {
+    if let Some(percent) = percent.filter(|percent| !(1..=99).contains(percent)) {
+        return Err(Exception::ValidationError(format!(
+            "cpu limit must be 1-99 percent, cpu_limit_percent={percent}"
+        )));
+    }
     Ok(())
 }
 
+// print guest output until exit code is reported, pipe is kept open by vm, so it only ends by read error
+fn forward_output(output: File) -> i32 {
+    for line in BufReader::new(output).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let line = line.trim_end_matches('\r');
+        if let Some(exit_code) = line.strip_prefix(cloud_init::EXIT_CODE_PREFIX) {
+            return exit_code.trim().parse().unwrap_or(1);
+        }
+        os_log::info(line);
+        println!("{line}");
+    }
+    1
+}
+
+pub fn run_in_background(name: &str, overrides: &Overrides) -> Result<(), Exception> {
+    let dir = vm_dir::vm_dir(name);
+    // fail early, instead of in log of background runner
+    let mut config = dir.load_config()?;
+    overrides.apply(&mut config)?;
+    settings::check_running_limits(name, &config)?;
+    start(&Virtualization, &dir, overrides)
+}
+
+fn start(platform: &dyn Platform, dir: &VmDir, overrides: &Overrides) -> Result<(), Exception> {
+    if let Some(pid) = platform.runner_pid(dir) {
+        return Err(Exception::ValidationError(format!(
+            "vm is already running, name={}, pid={pid}",
+            dir.name()
+        )));
+    }
+    let mut args = vec!["run".to_string(), dir.name()];
+    args.extend(overrides.args());
+    if !vm_config::strict() {
+        args.push("--strict=false".to_string());
+    }
+    platform.start(dir, &args)
+}
+
+fn run_with_console(dir: &VmDir, overrides: &Overrides) -> Result<(), Exception> {
+    let name = dir.name();
+    let config = dir.load_config()?;
+    if !matches!(config.os, Os::Linux) {
+        return Err(Exception::ValidationError("console requires linux guest".to_string()));
+    }
+    if dir.pid().is_some() {
+        return Err(Exception::ValidationError(format!("vm is already running, name={name}")));
+    }
+    // remove console link left by previous run, it may point to pty used by other process
+    if dir.console_path.symlink_metadata().is_ok() {
+        fs::remove_file(&dir.console_path)?;
+    }
+
+    run_in_background(&name, overrides)?;
+
+    let mut attempts = 0;
+    while attempts < 20 {
+        if dir.console_path.exists() {
+            return console::attach(&dir.console_path);
+        }
+        sleep(Duration::from_millis(500));
+        attempts += 1;
+    }
+    Err(Exception::ValidationError(format!("console is not available, name={name}")))
+}
+
+fn create_console(dir: &VmDir) -> Result<Pty, Exception> {
+    let pty = console::open_pty()?;
+    if dir.console_path.symlink_metadata().is_ok() {
+        fs::remove_file(&dir.console_path)?;
+    }
+    symlink(&pty.path, &dir.console_path)?;
+    info!(
+        "console created, path={}, pty={}",
+        dir.console_path.to_string_lossy(),
+        pty.path.to_string_lossy()
+    );
+    Ok(pty)
+}
+
+// guest device is /dev/hvc<N>, pty is linked as serial<N> in vm dir
+fn create_serial_ports(dir: &VmDir, config: &VmConfig, serial_ports: &mut Vec<Retained<VZSerialPortConfiguration>>) -> Result<Vec<Pty>, Exception> {
+    let mut ptys = vec![];
+    for (index, port) in config.serial_ports.iter().enumerate() {
+        let field = format!("serial_ports[{index}]");
+        let device = serial_ports.len();
+        match port.backend {
+            SerialBackend::Pty => {
+                let pty = console::open_pty()?;
+                let link = dir.dir.join(format!("serial{device}"));
+                if link.symlink_metadata().is_ok() {
+                    fs::remove_file(&link)?;
+                }
+                symlink(&pty.path, &link)?;
+                info!("serial port created, device=hvc{device}, path={}", link.to_string_lossy());
+                serial_ports.push(console::serial_port(&pty));
+                ptys.push(pty);
+            }
+            SerialBackend::File => {
+                let path = port.path(&field)?;
+                info!("serial port created, device=hvc{device}, file={}", path.to_string_lossy());
+                serial_ports.push(console::file_serial_port(&path)?);
+            }
+            SerialBackend::Socket => {
+                let path = port.path(&field)?;
+                info!("serial port created, device=hvc{device}, socket={}", path.to_string_lossy());
+                serial_ports.push(console::socket_serial_port(&path)?);
+            }
+        }
+    }
+    Ok(ptys)
+}
+
 fn handle_signal(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>) -> Result<(), Exception> {
     let mut signals = Signals::new([SIGTERM, SIGINT, SIGQUIT])?;
+    // ctrl-c of foreground runner, background runner writes to log file
+    let foreground = io::stderr().is_terminal();
     thread::spawn(move || {
-        let signal = signals.forever().next().unwrap();
-        info!("recived signal, signal={signal}");
-        match signal {
-            SIGTERM | SIGINT | SIGQUIT => {
-                vm::stop_vm(vm);
+        let mut stopping = false;
+        for signal in signals.forever() {
+            info!("recived signal, signal={signal}");
+            cpu_limit::release();
+            match signal {
+                // second ctrl-c doesn't wait for guest
+                SIGINT if stopping && foreground => {
+                    eprintln!("force stop vm");
+                    vm::force_stop_vm(Arc::clone(&vm));
+                }
+                SIGTERM | SIGINT | SIGQUIT if !stopping => {
+                    stopping = true;
+                    if signal == SIGINT && foreground {
+                        eprintln!("stopping vm, press ctrl-c again to force");
+                    }
+                    vm::stop_vm(Arc::clone(&vm), vm::STOP_TIMEOUT);
+                }
+                SIGTERM | SIGINT | SIGQUIT => {}
+                _ => unreachable!(),
             }
-            _ => unreachable!(),
         }
     });
     Ok(())
 }
 
-fn run_gui(name: &str, marker: MainThreadMarker, vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>, auto_reconfig_display: bool) {
+fn run_gui(name: &str, (width, height): (f64, f64), marker: MainThreadMarker, vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>) {
     let app = NSApplication::sharedApplication(marker);
     app.setActivationPolicy(NSApplicationActivationPolicy::Regular);
 
@@ -166,7 +758,7 @@ fn run_gui(name: &str, marker: MainThreadMarker, vm: Arc<MainThreadBound<Retaine
             marker.alloc(),
             NSRect {
                 origin: CGPoint::new(0.0, 0.0),
-                size: CGSize::new(1024.0, 768.0),
+                size: CGSize::new(width, height),
             },
             NSWindowStyleMask::Titled | NSWindowStyleMask::Resizable | NSWindowStyleMask::Closable,
             NSBackingStoreType::NSBackingStoreBuffered,
@@ -187,7 +779,8 @@ fn run_gui(name: &str, marker: MainThreadMarker, vm: Arc<MainThreadBound<Retaine
     unsafe {
         let machine_view = VZVirtualMachineView::initWithFrame(marker.alloc(), window.contentLayoutRect());
         machine_view.setCapturesSystemKeys(true);
-        machine_view.setAutomaticallyReconfiguresDisplay(auto_reconfig_display);
+        // resize guest display with window, virtio-gpu scanout of linux guest requires guest driver support
+        machine_view.setAutomaticallyReconfiguresDisplay(true);
         machine_view.setVirtualMachine(Some(vm.get(marker)));
         machine_view.setAutoresizingMask(NSAutoresizingMaskOptions::NSViewWidthSizable | NSAutoresizingMaskOptions::NSViewHeightSizable);
         window.contentView().unwrap().addSubview(&machine_view);
@@ -199,3 +792,32 @@ fn run_gui(name: &str, marker: MainThreadMarker, vm: Arc<MainThreadBound<Retaine
     window.makeKeyAndOrderFront(Option::None);
     unsafe { app.run() };
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::Overrides;
+    use crate::config::vm_dir::VmDir;
+    use crate::vm::platform::mock::MockPlatform;
+
+    #[test]
+    fn start() {
+        let dir = VmDir::new(PathBuf::from("/tmp/vz-test/debian"));
+        let overrides = Overrides {
+            no_network: true,
+            cpu: Some(2),
+            memory: Some(4 * 1024 * 1024 * 1024),
+        };
+        let platform = MockPlatform::default();
+        assert!(super::start(&platform, &dir, &overrides).is_ok());
+        assert_eq!(
+            vec!["start debian: run debian --no-network --cpu 2 --memory 4G"],
+            *platform.calls.borrow()
+        );
+
+        let platform = MockPlatform::running(&[("debian", "running")]);
+        assert!(super::start(&platform, &dir, &overrides).is_err());
+        assert!(platform.calls.borrow().is_empty());
+    }
+}
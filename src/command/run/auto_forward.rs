@@ -0,0 +1,161 @@
+use std::collections::HashSet;
+use std::io;
+use std::io::ErrorKind;
+use std::net::Shutdown;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::thread;
+use std::thread::sleep;
+use std::time::Duration;
+
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+use crate::command::ssh;
+use crate::command::wait;
+use crate::config::vm_config::VmConfig;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+
+const DISCOVER_INTERVAL: Duration = Duration::from_secs(10);
+
+// linux guest has ss, macOS guest has netstat
+const LISTENING_PORTS_COMMAND: &str = "ss -Htln 2>/dev/null || netstat -anp tcp";
+
+// forward allowed guest port from same port on host loopback, or from random port if it's taken on host
+pub fn start(name: String, ports: Vec<u16>, ready_timeout: Duration) {
+    thread::spawn(move || {
+        let dir = vm_dir::vm_dir(&name);
+        let result = dir.load_config().and_then(|config| {
+            let ip = wait::wait_until_ready(&dir, &config, ready_timeout, &|| true)?;
+            Ok((config, ip))
+        });
+        let (config, ip) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                warn!("failed to discover guest ports, name={name}, error={err}");
+                return;
+            }
+        };
+
+        let mut forwarded = HashSet::new();
+        loop {
+            match listening_ports(&dir, &config, &ip) {
+                Ok(listening) => {
+                    let pending: Vec<u16> = ports
+                        .iter()
+                        .copied()
+                        .filter(|port| listening.contains(port) && !forwarded.contains(port))
+                        .collect();
+                    for port in pending {
+                        match forward(&ip, port) {
+                            Ok(()) => {
+                                forwarded.insert(port);
+                            }
+                            Err(err) => error!("failed to forward guest port, port={port}, error={err}"),
+                        }
+                    }
+                }
+                Err(err) => warn!("failed to discover guest ports, name={name}, error={err}"),
+            }
+            if forwarded.len() == ports.len() {
+                return;
+            }
+            sleep(DISCOVER_INTERVAL);
+        }
+    });
+}
+
+fn listening_ports(dir: &VmDir, config: &VmConfig, ip: &str) -> Result<Vec<u16>, Exception> {
+    let output = ssh::ssh_command(dir, config)
+        .args(["-o", "BatchMode=yes", "-o", "ConnectTimeout=5"])
+        .arg(ssh::destination(config, ip, None))
+        .arg(LISTENING_PORTS_COMMAND)
+        .output()?;
+    if !output.status.success() {
+        return Err(Exception::ValidationError(format!(
+            "failed to list listening ports, status={}, error={}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(parse_listening_ports(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn forward(ip: &str, port: u16) -> Result<(), Exception> {
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Err(err) if err.kind() == ErrorKind::AddrInUse => TcpListener::bind(("127.0.0.1", 0))?,
+        result => result?,
+    };
+    let host_port = listener.local_addr()?.port();
+    info!("forward guest port, guest={ip}:{port}, host=127.0.0.1:{host_port}");
+    eprintln!("forward 127.0.0.1:{host_port} -> {ip}:{port}");
+    let ip = ip.to_string();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|host| {
+                let guest = TcpStream::connect((ip.as_str(), port))?;
+                proxy(Arc::new(host), Arc::new(guest));
+                Ok(())
+            });
+            if let Err(err) = result {
+                error!("failed to forward connection, guest={ip}:{port}, error={err}");
+            }
+        }
+    });
+    Ok(())
+}
+
+fn proxy(host: Arc<TcpStream>, guest: Arc<TcpStream>) {
+    copy(Arc::clone(&host), Arc::clone(&guest));
+    copy(guest, host);
+}
+
+fn copy(from: Arc<TcpStream>, to: Arc<TcpStream>) {
+    thread::spawn(move || {
+        let _ = io::copy(&mut from.as_ref(), &mut to.as_ref());
+        let _ = to.shutdown(Shutdown::Write);
+    });
+}
+
+// local address is 4th column of both formats, e.g. "LISTEN 0 4096 0.0.0.0:22 0.0.0.0:*" by ss, "tcp4 0 0 *.22 *.* LISTEN" by netstat,
+// ports only bound to guest loopback are not reachable from host
+fn parse_listening_ports(output: &str) -> Vec<u16> {
+    let mut ports = vec![];
+    for line in output.lines().filter(|line| line.contains("LISTEN")) {
+        let port = line
+            .split_whitespace()
+            .nth(3)
+            .filter(|address| !address.starts_with("127.") && !address.starts_with("[::1]") && !address.starts_with("::1."))
+            .and_then(|address| address.rsplit([':', '.']).next())
+            .and_then(|port| port.parse().ok());
+        if let Some(port) = port.filter(|port| !ports.contains(port)) {
+            ports.push(port);
+        }
+    }
+    ports
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parse_listening_ports() {
+        let ss = "LISTEN 0      4096   127.0.0.53%lo:53        0.0.0.0:*\n\
+                  LISTEN 0      128          0.0.0.0:22        0.0.0.0:*\n\
+                  LISTEN 0      128             [::]:22           [::]:*\n\
+                  LISTEN 0      244        127.0.0.1:5432      0.0.0.0:*\n\
+                  LISTEN 0      511                *:80              *:*\n";
+        assert_eq!(super::parse_listening_ports(ss), vec![22, 80]);
+
+        let netstat = "Active Internet connections (including servers)\n\
+                       Proto Recv-Q Send-Q  Local Address          Foreign Address        (state)\n\
+                       tcp4       0      0  192.168.64.2.22        192.168.64.1.52100     ESTABLISHED\n\
+                       tcp4       0      0  127.0.0.1.5432         *.*                    LISTEN\n\
+                       tcp6       0      0  *.80                   *.*                    LISTEN\n\
+                       tcp4       0      0  *.80                   *.*                    LISTEN\n";
+        assert_eq!(super::parse_listening_ports(netstat), vec![80]);
+    }
+}
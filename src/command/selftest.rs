@@ -0,0 +1,144 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::Instant;
+
+use clap::Args;
+use objc2_virtualization::VZVirtualMachine;
+
+use crate::command::create;
+use crate::command::run;
+use crate::command::run::Overrides;
+use crate::config::image_cache;
+use crate::config::vm_config::Share;
+use crate::config::vm_config::ShareOptions;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::dhcp_lease;
+use crate::util::exception::Exception;
+use crate::util::path;
+use crate::vm::exec;
+use crate::vm::runner;
+
+const BOOT_TIMEOUT: Duration = Duration::from_secs(120);
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+const SHARE_TAG: &str = "selftest";
+const MARKER_FILE: &str = "marker";
+const ECHO: &str = "vz selftest echo";
+
+#[derive(Args)]
+pub struct Selftest {
+    #[arg(
+        long,
+        help = "image pulled by vz pull, linux guest with cloud-init and exec agent on vsock port 7071, e.g. vz pull selftest <url>",
+        default_value = "selftest"
+    )]
+    image: String,
+}
+
+impl Selftest {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let supported = unsafe { VZVirtualMachine::isSupported() };
+        if !report("host supports virtualization", supported) {
+            return Err(Exception::ValidationError("selftest failed".to_string()));
+        }
+        let image = image_cache::resolve(&self.image)?;
+
+        let dir = vm_dir::create_temp_vm_dir()?;
+        let result = run_checks(&dir, &image);
+        if dir.pid().is_some() {
+            runner::request_stop(&dir, false, STOP_TIMEOUT);
+            report(
+                "stop vm",
+                runner::wait_until_stopped(&dir, STOP_TIMEOUT.as_secs() as u32 + runner::FORCE_STOP_TIMEOUT),
+            );
+        }
+        if let Err(err) = fs::remove_dir_all(&dir.dir) {
+            println!("failed to remove temp vm dir, dir={}, error={err}", dir.dir.to_string_lossy());
+        }
+        if !result? {
+            return Err(Exception::ValidationError("selftest failed".to_string()));
+        }
+        Ok(())
+    }
+}
+
+// boot image in background runner, then probe network, virtiofs and vsock from guest by exec agent
+fn run_checks(dir: &VmDir, image: &Path) -> Result<bool, Exception> {
+    path::clone_file(image, &dir.disk_path)?;
+    create::create_linux(dir)?;
+    let share_path = dir.dir.join("share");
+    fs::create_dir(&share_path)?;
+    fs::write(share_path.join(MARKER_FILE), dir.name())?;
+    let mut config = dir.load_config()?;
+    config.sharing.insert(
+        SHARE_TAG.to_string(),
+        Share::Options(ShareOptions {
+            path: share_path.to_string_lossy().to_string(),
+            read_only: true,
+            tag: Some(SHARE_TAG.to_string()),
+        }),
+    );
+    dir.save_config(&config)?;
+    report("create vm", true);
+
+    run::run_in_background(&dir.name(), &Overrides::default())?;
+    if !report("start vm", runner::wait_until_started(dir, Duration::from_secs(10)).is_some()) {
+        return Ok(false);
+    }
+
+    let start = Instant::now();
+    let mut success = report("guest gets ip from NAT network", wait_for_ip(dir, &config.mac_address, start)?);
+    if !report("guest connects to exec agent by vsock", wait_for_agent(dir, start)) {
+        return Ok(false);
+    }
+    let ping = guest_output(dir, "ping -c 1 -W 2 $(ip route | awk '/^default/ {print $3}')", None);
+    success &= report("guest pings host", ping.is_some());
+    let mount = guest_output(
+        dir,
+        &format!("mkdir -p /mnt/{SHARE_TAG} && mount -t virtiofs {SHARE_TAG} /mnt/{SHARE_TAG} && cat /mnt/{SHARE_TAG}/{MARKER_FILE}"),
+        None,
+    );
+    success &= report("guest mounts virtiofs share", mount.as_deref() == Some(dir.name().as_bytes()));
+    let echo = guest_output(dir, "cat", Some(ECHO.as_bytes().to_vec()));
+    success &= report("guest echoes stdin over vsock", echo.as_deref() == Some(ECHO.as_bytes()));
+    Ok(success)
+}
+
+fn wait_for_ip(dir: &VmDir, mac_address: &str, start: Instant) -> Result<bool, Exception> {
+    while start.elapsed() < BOOT_TIMEOUT && dir.pid().is_some() {
+        if dhcp_lease::find_ip(mac_address)?.is_some() {
+            return Ok(true);
+        }
+        sleep(Duration::from_secs(1));
+    }
+    Ok(false)
+}
+
+// agent starts late in boot, e.g. by systemd unit
+fn wait_for_agent(dir: &VmDir, start: Instant) -> bool {
+    while start.elapsed() < BOOT_TIMEOUT && dir.pid().is_some() {
+        if guest_output(dir, "true", None).is_some() {
+            return true;
+        }
+        sleep(Duration::from_secs(1));
+    }
+    false
+}
+
+// stdout of shell script run by exec agent, none if it fails
+fn guest_output(dir: &VmDir, script: &str, stdin: Option<Vec<u8>>) -> Option<Vec<u8>> {
+    let command = ["sh".to_string(), "-c".to_string(), script.to_string()];
+    let mut output = vec![];
+    match exec::run(dir, &command, stdin.map(Cursor::new), &mut output) {
+        Ok(0) => Some(output),
+        _ => None,
+    }
+}
+
+fn report(check: &str, success: bool) -> bool {
+    println!("{:<8}{}", if success { "pass" } else { "fail" }, check);
+    success
+}
@@ -0,0 +1,395 @@
+use clap::Args;
+use clap::ValueEnum;
+use tracing::info;
+
+use crate::command::create;
+use crate::config::vm_config::Graphics;
+use crate::config::vm_config::MacGraphics;
+use crate::config::vm_config::Os;
+use crate::config::vm_config::VirtioGraphics;
+use crate::config::vm_config::VmConfig;
+use crate::config::vm_config::PIXELS_PER_INCH_1X;
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+use crate::vm::linux;
+
+#[derive(Args)]
+pub struct Set {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(long, help = "cpu count")]
+    cpu: Option<usize>,
+
+    #[arg(long, help = "memory with unit, e.g. 16G or 512M, gb if without unit", value_parser = parse_memory)]
+    memory: Option<u64>,
+
+    #[arg(long, help = "share rosetta with linux guest")]
+    rosetta: Option<Switch>,
+
+    #[arg(long, help = "expose virtualization extensions to linux guest, e.g. for kvm")]
+    nested: Option<Switch>,
+
+    #[arg(long, help = "attach network devices")]
+    network: Option<Switch>,
+
+    #[arg(long, help = "sync clipboard with macOS guest")]
+    clipboard: Option<Switch>,
+
+    #[arg(
+        long,
+        help = "mac address of NAT network, locally administered unicast, or random to regenerate, e.g. 02:00:00:00:00:01",
+        value_parser = parse_mac_address
+    )]
+    mac: Option<MacAddress>,
+
+    #[arg(
+        long,
+        help = "set label, empty value removes it, can be repeated, e.g. --label=team=ci --label=owner=",
+        value_parser = parse_label
+    )]
+    label: Vec<(String, String)>,
+
+    #[arg(
+        long,
+        help = "display resolution in pixels, with ui scale for macOS guest, e.g. 2560x1440 or 2560x1440@2x",
+        value_parser = parse_display
+    )]
+    display: Option<DisplaySize>,
+
+    #[arg(long, help = "change config of running vm, it takes effect on next start", default_value_t = false)]
+    next_boot: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DisplaySize {
+    width: isize,
+    height: isize,
+    scale: Option<isize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum MacAddress {
+    Random,
+    Address(String),
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Switch {
+    On,
+    Off,
+}
+
+impl Switch {
+    fn enabled(self) -> bool {
+        matches!(self, Switch::On)
+    }
+}
+
+impl Set {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        if self.hardware_changed() {
+            dir.check_not_suspended()?;
+        }
+        let running = dir.pid().is_some();
+        if running && !self.next_boot {
+            return Err(Exception::ValidationError(format!(
+                "vm is running, stop it first or use --next-boot, name={name}"
+            )));
+        }
+
+        let mut config = dir.load_config()?;
+        let changes = self.apply(&mut config)?;
+        if changes.is_empty() {
+            return Err(Exception::ValidationError(
+                "nothing to set, specify --cpu, --memory, --rosetta, --nested, --network, --clipboard, --mac, --label or --display".to_string(),
+            ));
+        }
+        config.validate_host_limits()?;
+        if self.mac.is_some() {
+            create::check_mac_addresses(name, &config)?;
+        }
+        dir.save_config(&config)?;
+        info!("vm config changed, name={name}, {}", changes.join(", "));
+        if running {
+            info!("vm is running, changes take effect on next start, name={name}");
+        }
+        Ok(())
+    }
+
+    // labels are not part of vm, they can change while it's suspended
+    fn hardware_changed(&self) -> bool {
+        self.cpu.is_some()
+            || self.memory.is_some()
+            || self.rosetta.is_some()
+            || self.nested.is_some()
+            || self.network.is_some()
+            || self.clipboard.is_some()
+            || self.mac.is_some()
+            || self.display.is_some()
+    }
+
+    fn apply(&self, config: &mut VmConfig) -> Result<Vec<String>, Exception> {
+        let mut changes = vec![];
+        if let Some(cpu) = self.cpu {
+            changes.push(format!("cpu={}->{cpu}", config.cpu));
+            config.cpu = cpu;
+        }
+        if let Some(memory) = self.memory {
+            changes.push(format!("memory={}->{}", format_memory(config.memory), format_memory(memory)));
+            config.memory = memory;
+        }
+        if let Some(rosetta) = self.rosetta {
+            if !matches!(config.os, Os::Linux) {
+                return Err(Exception::ValidationError("rosetta is only supported for linux vm".to_string()));
+            }
+            if rosetta.enabled() && !linux::rosetta_installed() {
+                return Err(Exception::ValidationError(
+                    "rosetta is not installed on host, install with vz rosetta install".to_string(),
+                ));
+            }
+            changes.push(format!("rosetta={}", rosetta.enabled()));
+            config.rosetta = Some(rosetta.enabled());
+        }
+        if let Some(nested) = self.nested {
+            if !matches!(config.os, Os::Linux) {
+                return Err(Exception::ValidationError(
+                    "nested virtualization is only supported for linux vm".to_string(),
+                ));
+            }
+            if nested.enabled() && !linux::nested_virtualization_supported() {
+                return Err(Exception::ValidationError(
+                    "nested virtualization is not supported on host, it requires M3 or later and macOS 15".to_string(),
+                ));
+            }
+            changes.push(format!("nested={}", nested.enabled()));
+            config.nested = Some(nested.enabled());
+        }
+        if let Some(network) = self.network {
+            changes.push(format!("network={}", network.enabled()));
+            // default is enabled
+            config.network = if network.enabled() { None } else { Some(false) };
+        }
+        if let Some(clipboard) = self.clipboard {
+            if !matches!(config.os, Os::MacOs) {
+                return Err(Exception::ValidationError("clipboard is only supported for macOS vm".to_string()));
+            }
+            changes.push(format!("clipboard={}", clipboard.enabled()));
+            config.clipboard = Some(clipboard.enabled());
+        }
+        if let Some(mac) = &self.mac {
+            let address = match mac {
+                MacAddress::Random => create::random_mac_address(),
+                MacAddress::Address(address) => address.clone(),
+            };
+            changes.push(format!("mac_address={}->{address}", config.mac_address));
+            config.mac_address = address;
+        }
+        for (key, value) in &self.label {
+            if value.is_empty() {
+                config.labels.remove(key);
+                changes.push(format!("label.{key} removed"));
+            } else {
+                config.labels.insert(key.clone(), value.clone());
+                changes.push(format!("label.{key}={value}"));
+            }
+        }
+        if let Some(display) = self.display {
+            config.graphics = Some(graphics(config, display)?);
+            let scale = display.scale.map(|scale| format!("@{scale}x")).unwrap_or_default();
+            changes.push(format!("display={}x{}{scale}", display.width, display.height));
+        }
+        Ok(changes)
+    }
+}
+
+// keep count of displays
+fn graphics(config: &VmConfig, display: DisplaySize) -> Result<Graphics, Exception> {
+    let DisplaySize { width, height, scale } = display;
+    match config.os {
+        Os::Linux => {
+            if scale.is_some() {
+                return Err(Exception::ValidationError("display scale is only supported for macOS vm".to_string()));
+            }
+            let scanouts = match &config.graphics {
+                Some(Graphics::Virtio(graphics)) => graphics.scanouts,
+                _ => 1,
+            };
+            Ok(Graphics::Virtio(VirtioGraphics { scanouts, width, height }))
+        }
+        Os::MacOs => {
+            let displays = match &config.graphics {
+                Some(Graphics::Mac(graphics)) => graphics.displays,
+                _ => 1,
+            };
+            Ok(Graphics::Mac(MacGraphics {
+                displays,
+                width,
+                height,
+                pixels_per_inch: Some(scale.unwrap_or(1) * PIXELS_PER_INCH_1X),
+            }))
+        }
+    }
+}
+
+// locally administered, so it doesn't collide with address of real device, and unicast, as guest nic can't use multicast address
+fn parse_mac_address(value: &str) -> Result<MacAddress, String> {
+    if value == "random" {
+        return Ok(MacAddress::Random);
+    }
+    let invalid = |reason: &str| format!("invalid mac address, {reason}, mac={value}");
+    let octets = value
+        .split(':')
+        .map(|octet| {
+            let hex = octet.len() == 2 && octet.chars().all(|c| c.is_ascii_hexdigit());
+            hex.then(|| u8::from_str_radix(octet, 16).ok()).flatten()
+        })
+        .collect::<Option<Vec<u8>>>()
+        .filter(|octets| octets.len() == 6)
+        .ok_or_else(|| invalid("it must be 6 hex octets separated by colon"))?;
+    if octets[0] & 0x01 != 0 {
+        return Err(invalid("it must be unicast"));
+    }
+    if octets[0] & 0x02 == 0 {
+        return Err(invalid("it must be locally administered, e.g. 02:xx:xx:xx:xx:xx"));
+    }
+    Ok(MacAddress::Address(value.to_lowercase()))
+}
+
+// e.g. team=ci, vz list shows labels joined by ","
+fn parse_label(value: &str) -> Result<(String, String), String> {
+    let (key, label) = value
+        .split_once('=')
+        .ok_or_else(|| format!("invalid label, it must be key=value, label={value}"))?;
+    let valid_key = !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'));
+    if !valid_key {
+        return Err(format!("invalid label key, it must be letters, digits, -, _, . or /, label={value}"));
+    }
+    if label.contains(',') {
+        return Err(format!("invalid label value, it must not contain \",\", label={value}"));
+    }
+    Ok((key.to_string(), label.to_string()))
+}
+
+// e.g. 2560x1440, 2560x1440@2x
+fn parse_display(value: &str) -> Result<DisplaySize, String> {
+    let invalid = || format!("invalid display, use <width>x<height> or <width>x<height>@<scale>x, display={value}");
+    let (size, scale) = match value.split_once('@') {
+        Some((size, scale)) => (
+            size,
+            Some(
+                scale
+                    .strip_suffix('x')
+                    .and_then(|scale| scale.parse().ok())
+                    .filter(|scale| (1..=3).contains(scale))
+                    .ok_or_else(invalid)?,
+            ),
+        ),
+        None => (value, None),
+    };
+    let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+    let width: isize = width.parse().map_err(|_| invalid())?;
+    let height: isize = height.parse().map_err(|_| invalid())?;
+    if width <= 0 || height <= 0 {
+        return Err(invalid());
+    }
+    Ok(DisplaySize { width, height, scale })
+}
+
+// bytes of memory, e.g. 16G, 512M, 16 is 16G
+pub fn parse_memory(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, unit) = match value.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((index, _)) => value.split_at(index),
+        None => (value, "G"),
+    };
+    let number: u64 = number.parse().map_err(|_| format!("invalid memory, memory={value}"))?;
+    let unit = match unit.to_ascii_uppercase().trim_end_matches('B') {
+        "G" => 1024 * 1024 * 1024,
+        "M" => 1024 * 1024,
+        _ => return Err(format!("invalid memory unit, use G or M, memory={value}")),
+    };
+    Ok(number * unit)
+}
+
+pub fn format_memory(memory: u64) -> String {
+    if memory.is_multiple_of(1024 * 1024 * 1024) {
+        format!("{}G", memory / (1024 * 1024 * 1024))
+    } else {
+        format!("{}M", memory / (1024 * 1024))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parse_memory() {
+        assert_eq!(super::parse_memory("16G"), Ok(16 * 1024 * 1024 * 1024));
+        assert_eq!(super::parse_memory("16gb"), Ok(16 * 1024 * 1024 * 1024));
+        assert_eq!(super::parse_memory("512M"), Ok(512 * 1024 * 1024));
+        assert_eq!(super::parse_memory("8"), Ok(8 * 1024 * 1024 * 1024));
+        assert!(super::parse_memory("8T").is_err());
+        assert!(super::parse_memory("G").is_err());
+    }
+
+    #[test]
+    fn parse_mac_address() {
+        use super::MacAddress;
+        assert_eq!(super::parse_mac_address("random"), Ok(MacAddress::Random));
+        assert_eq!(
+            super::parse_mac_address("0A:1b:2c:3d:4e:5f"),
+            Ok(MacAddress::Address("0a:1b:2c:3d:4e:5f".to_string()))
+        );
+        assert!(super::parse_mac_address("0a:1b:2c:3d:4e").is_err());
+        assert!(super::parse_mac_address("0a:1b:2c:3d:4e:+f").is_err());
+        // multicast
+        assert!(super::parse_mac_address("03:1b:2c:3d:4e:5f").is_err());
+        // universally administered
+        assert!(super::parse_mac_address("a8:1b:2c:3d:4e:5f").is_err());
+    }
+
+    #[test]
+    fn parse_label() {
+        assert_eq!(super::parse_label("team=ci"), Ok(("team".to_string(), "ci".to_string())));
+        assert_eq!(super::parse_label("owner="), Ok(("owner".to_string(), String::new())));
+        assert_eq!(super::parse_label("url=a=b"), Ok(("url".to_string(), "a=b".to_string())));
+        assert!(super::parse_label("team").is_err());
+        assert!(super::parse_label("=ci").is_err());
+        assert!(super::parse_label("te am=ci").is_err());
+    }
+
+    #[test]
+    fn parse_display() {
+        use super::DisplaySize;
+        assert_eq!(
+            super::parse_display("2560x1440"),
+            Ok(DisplaySize {
+                width: 2560,
+                height: 1440,
+                scale: None
+            })
+        );
+        assert_eq!(
+            super::parse_display("2560x1440@2x"),
+            Ok(DisplaySize {
+                width: 2560,
+                height: 1440,
+                scale: Some(2)
+            })
+        );
+        assert!(super::parse_display("2560x1440@2").is_err());
+        assert!(super::parse_display("2560x").is_err());
+        assert!(super::parse_display("0x1440").is_err());
+    }
+
+    #[test]
+    fn format_memory() {
+        assert_eq!(super::format_memory(16 * 1024 * 1024 * 1024), "16G");
+        assert_eq!(super::format_memory(1536 * 1024 * 1024), "1536M");
+    }
+}
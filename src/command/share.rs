@@ -0,0 +1,144 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use clap::Args;
+use clap::Subcommand;
+use clap::ValueHint;
+use tracing::info;
+
+use crate::config::vm_config;
+use crate::config::vm_config::Share;
+use crate::config::vm_config::ShareOptions;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+use crate::util::path::PathExtension;
+
+#[derive(Args)]
+pub struct ShareDir {
+    #[command(subcommand)]
+    command: ShareCommand,
+}
+
+#[derive(Subcommand)]
+enum ShareCommand {
+    #[command(about = "share host dir with vm, or replace share of same name")]
+    Add {
+        #[arg(help = "vm name")]
+        name: String,
+        #[arg(help = "share name, dir name under automount tag in guest")]
+        share: String,
+        #[arg(help = "host dir", value_hint = ValueHint::DirPath)]
+        path: PathBuf,
+        #[arg(long, help = "guest can't write to share", default_value_t = false)]
+        read_only: bool,
+        #[arg(
+            long,
+            help = "mount share by its own virtiofs tag instead of automount tag, e.g. mount -t virtiofs <tag> /mnt"
+        )]
+        tag: Option<String>,
+    },
+    #[command(about = "stop sharing host dir with vm")]
+    Remove {
+        #[arg(help = "vm name")]
+        name: String,
+        #[arg(help = "share name")]
+        share: String,
+    },
+    #[command(about = "list shares of vm")]
+    List {
+        #[arg(help = "vm name")]
+        name: String,
+    },
+}
+
+impl ShareDir {
+    pub fn execute(&self) -> Result<(), Exception> {
+        match &self.command {
+            ShareCommand::Add {
+                name,
+                share,
+                path,
+                read_only,
+                tag,
+            } => add(&initialized_vm_dir(name)?, share, path, *read_only, tag.clone()),
+            ShareCommand::Remove { name, share } => remove(&initialized_vm_dir(name)?, share),
+            ShareCommand::List { name } => list(&initialized_vm_dir(name)?),
+        }
+    }
+}
+
+fn initialized_vm_dir(name: &str) -> Result<VmDir, Exception> {
+    let dir = vm_dir::vm_dir(name);
+    if !dir.initialized() {
+        return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+    }
+    Ok(dir)
+}
+
+fn add(dir: &VmDir, share: &str, path: &Path, read_only: bool, tag: Option<String>) -> Result<(), Exception> {
+    let path = path.to_absolute_path();
+    if !path.is_dir() {
+        return Err(Exception::ValidationError(format!("dir does not exist, path={}", path.to_string_lossy())));
+    }
+    if let Some(tag) = &tag {
+        vm_config::validate_sharing_tag(share, tag)?;
+    }
+    let mut config = dir.load_config()?;
+    if let Some((other, _)) = tag
+        .as_ref()
+        .and_then(|tag| config.sharing.iter().find(|(name, value)| *name != share && value.tag() == Some(tag)))
+    {
+        return Err(Exception::ValidationError(format!(
+            "sharing tag is used by other share, share={other}, tag={}",
+            tag.as_deref().unwrap_or_default()
+        )));
+    }
+    let path = path.to_string_lossy().to_string();
+    let value = if read_only || tag.is_some() {
+        Share::Options(ShareOptions { path, read_only, tag })
+    } else {
+        Share::Path(path)
+    };
+    info!(
+        "add share, name={}, share={share}, path={}, read_only={read_only}",
+        dir.name(),
+        value.path()
+    );
+    config.sharing.insert(share.to_string(), value);
+    dir.save_config(&config)?;
+    if dir.pid().is_some() {
+        info!("vm is running, restart vm to apply");
+    }
+    Ok(())
+}
+
+fn remove(dir: &VmDir, share: &str) -> Result<(), Exception> {
+    let mut config = dir.load_config()?;
+    if config.sharing.remove(share).is_none() {
+        return Err(Exception::ValidationError(format!("share not found, share={share}")));
+    }
+    info!("remove share, name={}, share={share}", dir.name());
+    dir.save_config(&config)?;
+    if dir.pid().is_some() {
+        info!("vm is running, restart vm to apply");
+    }
+    Ok(())
+}
+
+fn list(dir: &VmDir) -> Result<(), Exception> {
+    let config = dir.load_config()?;
+    let mut shares: Vec<_> = config.sharing.iter().collect();
+    shares.sort_by_key(|(name, _)| name.as_str());
+    println!("{:<16}{:<16}{:<6}path", "share", "tag", "mode");
+    for (name, share) in shares {
+        println!(
+            "{:<16}{:<16}{:<6}{}",
+            name,
+            share.tag().unwrap_or("-"),
+            if share.read_only() { "ro" } else { "rw" },
+            share.path()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use std::os::unix::process::CommandExt;
+
+use clap::Args;
+use tracing::info;
+
+use crate::command::ssh;
+use crate::config::vm_config::Os;
+use crate::config::vm_dir;
+use crate::util::dhcp_lease;
+use crate::util::exception::Exception;
+use crate::util::tcp;
+use crate::vm::console;
+
+#[derive(Args)]
+pub struct Shell {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(long, short, help = "guest user, default to ssh_user in config")]
+    user: Option<String>,
+}
+
+impl Shell {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        if dir.pid().is_none() {
+            return Err(Exception::ValidationError(format!("vm not running, name={name}")));
+        }
+
+        let config = dir.load_config()?;
+        if let Some(ip) = dhcp_lease::find_ip(&config.mac_address)?.filter(|ip| tcp::port_open(ip, tcp::SSH_PORT)) {
+            let mut command = ssh::ssh_command(&dir, &config);
+            command.arg(ssh::destination(&config, &ip, self.user.as_ref()));
+            return Err(command.exec().into());
+        }
+
+        // serial console is only created for linux guest, see run
+        if matches!(config.os, Os::Linux) && dir.console_path.exists() {
+            info!("ssh is not reachable, attach serial console, press ctrl-] to detach, name={name}");
+            return console::attach(&dir.console_path);
+        }
+        Err(Exception::ValidationError(format!("neither ssh nor console is available, name={name}")))
+    }
+}
@@ -0,0 +1,414 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use clap::Args;
+use clap::Subcommand;
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::info;
+
+use crate::config::vm_config::VmConfig;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::disk_image;
+use crate::util::exception::Exception;
+use crate::util::json;
+use crate::util::path;
+
+const METADATA_FILE: &str = "snapshot.json";
+// snapshot current disk is based on, last created or restored one
+const CURRENT_FILE: &str = ".current";
+
+#[derive(Args)]
+pub struct Snapshot {
+    #[command(subcommand)]
+    command: SnapshotCommand,
+}
+
+#[derive(Subcommand)]
+enum SnapshotCommand {
+    #[command(about = "snapshot disks, nvram and config of stopped vm")]
+    Create {
+        #[arg(help = "vm name")]
+        name: String,
+        #[arg(help = "snapshot name")]
+        snapshot: String,
+    },
+    #[command(about = "list snapshots of vm with parent and size of blocks changed since parent")]
+    List {
+        #[arg(help = "vm name")]
+        name: String,
+    },
+    #[command(about = "show snapshots of vm as tree of parents")]
+    Tree {
+        #[arg(help = "vm name")]
+        name: String,
+    },
+    #[command(about = "roll back stopped vm to snapshot, current disks are replaced")]
+    Restore {
+        #[arg(help = "vm name")]
+        name: String,
+        #[arg(help = "snapshot name")]
+        snapshot: String,
+    },
+    #[command(about = "delete snapshot, children are attached to its parent")]
+    Delete {
+        #[arg(help = "vm name")]
+        name: String,
+        #[arg(help = "snapshot name")]
+        snapshot: String,
+        #[arg(long, help = "delete snapshot current disk is based on", default_value_t = false)]
+        force: bool,
+    },
+    #[command(about = "delete oldest snapshots, snapshot current disk is based on is kept")]
+    Prune {
+        #[arg(help = "vm name")]
+        name: String,
+        #[arg(long, help = "number of newest snapshots to keep")]
+        keep: usize,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Metadata {
+    // unix seconds
+    created: u64,
+    // snapshot current disk was based on when it was taken
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<String>,
+}
+
+impl Snapshot {
+    pub fn execute(&self) -> Result<(), Exception> {
+        match &self.command {
+            SnapshotCommand::Create { name, snapshot } => create(&initialized_vm_dir(name)?, snapshot),
+            SnapshotCommand::List { name } => list(&initialized_vm_dir(name)?),
+            SnapshotCommand::Tree { name } => tree(&initialized_vm_dir(name)?),
+            SnapshotCommand::Restore { name, snapshot } => restore(&initialized_vm_dir(name)?, snapshot),
+            SnapshotCommand::Delete { name, snapshot, force } => delete(&initialized_vm_dir(name)?, snapshot, *force),
+            SnapshotCommand::Prune { name, keep } => prune(&initialized_vm_dir(name)?, *keep),
+        }
+    }
+}
+
+fn initialized_vm_dir(name: &str) -> Result<VmDir, Exception> {
+    let dir = vm_dir::vm_dir(name);
+    if !dir.initialized() {
+        return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+    }
+    Ok(dir)
+}
+
+// files captured by snapshot, disks added later are not part of older snapshots
+fn vm_files(dir: &VmDir, config: &VmConfig) -> Vec<PathBuf> {
+    let mut files = vec![dir.disk_path.clone(), dir.nvram_path.clone(), dir.config_path.clone()];
+    files.extend(config.disks.iter().map(|disk| dir.extra_disk_path(disk)));
+    files
+}
+
+fn create(dir: &VmDir, snapshot: &str) -> Result<(), Exception> {
+    validate_snapshot_name(snapshot)?;
+    let snapshot_dir = dir.snapshot_dir(snapshot);
+    if snapshot_dir.exists() {
+        return Err(Exception::ValidationError(format!("snapshot already exists, snapshot={snapshot}")));
+    }
+    // guest must not write disk while copying, and vm can't start until snapshot is taken
+    let _lock = dir.lock("snapshot")?;
+    let config = dir.load_config()?;
+
+    info!("create snapshot, name={}, snapshot={snapshot}", dir.name());
+    let temp_dir = dir.snapshots_dir().join(format!(".{snapshot}.tmp"));
+    if temp_dir.exists() {
+        fs::remove_dir_all(&temp_dir)?;
+    }
+    fs::create_dir_all(&temp_dir)?;
+    let result = (|| {
+        for path in vm_files(dir, &config) {
+            path::clone_file(&path, &temp_dir.join(path.file_name().unwrap()))?;
+        }
+        let created = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+        let parent = current(dir);
+        fs::write(temp_dir.join(METADATA_FILE), json::to_json_pretty(&Metadata { created, parent })?)?;
+        Ok::<(), Exception>(())
+    })();
+    if let Err(err) = result {
+        fs::remove_dir_all(&temp_dir)?;
+        return Err(err);
+    }
+    fs::rename(&temp_dir, &snapshot_dir)?;
+    set_current(dir, Some(snapshot))?;
+    info!("snapshot created, path={}", snapshot_dir.to_string_lossy());
+    Ok(())
+}
+
+fn list(dir: &VmDir) -> Result<(), Exception> {
+    let snapshots = snapshots(dir)?;
+    let current = current(dir);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    println!("{:<24}{:<12}{:<24}changed", "snapshot", "age", "parent");
+    for (name, metadata) in &snapshots {
+        let marker = if current.as_deref() == Some(name.as_str()) { "*" } else { "" };
+        // blocks of clones are shared, size is only what changed since parent disk was cloned
+        let changed = changed_size(dir, name, metadata.parent.as_deref())?;
+        println!(
+            "{:<24}{:<12}{:<24}{:.2}G",
+            format!("{name}{marker}"),
+            format_age(now.saturating_sub(metadata.created)),
+            metadata.parent.as_deref().unwrap_or("-"),
+            changed as f32 / 1_000_000_000.0
+        );
+    }
+    if current.is_some() {
+        println!("\n* current disk is based on it");
+    }
+    Ok(())
+}
+
+fn tree(dir: &VmDir) -> Result<(), Exception> {
+    let snapshots: Vec<_> = snapshots(dir)?.into_iter().map(|(name, metadata)| (name, metadata.parent)).collect();
+    for line in format_tree(&snapshots, current(dir).as_deref()) {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+// sorted by created time, then name
+fn snapshots(dir: &VmDir) -> Result<Vec<(String, Metadata)>, Exception> {
+    let snapshots_dir = dir.snapshots_dir();
+    let mut snapshots = vec![];
+    if snapshots_dir.exists() {
+        for entry in fs::read_dir(&snapshots_dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if entry.path().is_dir() && !name.starts_with('.') {
+                let metadata: Metadata = json::from_json(&fs::read_to_string(entry.path().join(METADATA_FILE))?)?;
+                snapshots.push((name, metadata));
+            }
+        }
+    }
+    snapshots.sort_by(|(name, metadata), (other_name, other)| metadata.created.cmp(&other.created).then(name.cmp(other_name)));
+    Ok(snapshots)
+}
+
+// files of parent which are gone, e.g. removed disk, don't share blocks with snapshot
+fn changed_size(dir: &VmDir, snapshot: &str, parent: Option<&str>) -> Result<u64, Exception> {
+    let snapshot_dir = dir.snapshot_dir(snapshot);
+    let parent_dir = parent.map(|parent| dir.snapshot_dir(parent)).filter(|parent_dir| parent_dir.exists());
+    let mut size = 0;
+    for entry in fs::read_dir(&snapshot_dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if name == METADATA_FILE {
+            continue;
+        }
+        let parent_file = parent_dir.as_ref().map(|parent_dir| parent_dir.join(&name)).filter(|path| path.exists());
+        size += match parent_file {
+            Some(parent_file) => disk_image::unshared_size(
+                &disk_image::physical_extents(&entry.path())?,
+                &disk_image::physical_extents(&parent_file)?,
+            ),
+            None => entry.metadata()?.blocks() * 512,
+        };
+    }
+    Ok(size)
+}
+
+fn current(dir: &VmDir) -> Option<String> {
+    fs::read_to_string(dir.snapshots_dir().join(CURRENT_FILE))
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| dir.snapshot_dir(name).join(METADATA_FILE).exists())
+}
+
+fn set_current(dir: &VmDir, snapshot: Option<&str>) -> Result<(), Exception> {
+    let path = dir.snapshots_dir().join(CURRENT_FILE);
+    match snapshot {
+        Some(snapshot) => fs::write(path, snapshot)?,
+        None if path.exists() => fs::remove_file(path)?,
+        None => {}
+    }
+    Ok(())
+}
+
+// each file is replaced by rename, snapshot is cloned first so it's kept for next restore
+fn restore(dir: &VmDir, snapshot: &str) -> Result<(), Exception> {
+    let snapshot_dir = dir.snapshot_dir(snapshot);
+    if !snapshot_dir.join(METADATA_FILE).exists() {
+        return Err(Exception::ValidationError(format!("snapshot not found, snapshot={snapshot}")));
+    }
+    let _lock = dir.lock("snapshot")?;
+
+    info!("restore snapshot, name={}, snapshot={snapshot}", dir.name());
+    let temp_dir = dir.snapshots_dir().join(format!(".{snapshot}.restore"));
+    if temp_dir.exists() {
+        fs::remove_dir_all(&temp_dir)?;
+    }
+    fs::create_dir_all(&temp_dir)?;
+    let mut files = vec![];
+    for entry in fs::read_dir(&snapshot_dir)? {
+        let name = entry?.file_name();
+        if name != METADATA_FILE {
+            fs::copy(snapshot_dir.join(&name), temp_dir.join(&name))?;
+            files.push(name);
+        }
+    }
+    for name in &files {
+        fs::rename(temp_dir.join(name), dir.dir.join(name))?;
+    }
+    fs::remove_dir_all(&temp_dir)?;
+    // saved state doesn't match restored disk
+    if dir.state_path.exists() {
+        fs::remove_file(&dir.state_path)?;
+    }
+    set_current(dir, Some(snapshot))?;
+    info!("snapshot restored, name={}, snapshot={snapshot}", dir.name());
+    Ok(())
+}
+
+fn delete(dir: &VmDir, snapshot: &str, force: bool) -> Result<(), Exception> {
+    let snapshot_dir = dir.snapshot_dir(snapshot);
+    if !snapshot_dir.join(METADATA_FILE).exists() {
+        return Err(Exception::ValidationError(format!("snapshot not found, snapshot={snapshot}")));
+    }
+    if !force && current(dir).as_deref() == Some(snapshot) {
+        return Err(Exception::ValidationError(format!(
+            "current disk is based on snapshot, use --force to delete it, snapshot={snapshot}"
+        )));
+    }
+    remove(dir, snapshot)
+}
+
+fn prune(dir: &VmDir, keep: usize) -> Result<(), Exception> {
+    let snapshots: Vec<_> = snapshots(dir)?.into_iter().map(|(name, metadata)| (name, metadata.created)).collect();
+    let pruned = pruned_snapshots(&snapshots, keep, current(dir).as_deref());
+    if pruned.is_empty() {
+        info!("nothing to prune, name={}", dir.name());
+    }
+    for snapshot in pruned {
+        remove(dir, &snapshot)?;
+    }
+    Ok(())
+}
+
+// children are attached to parent of removed snapshot, so tree stays connected
+fn remove(dir: &VmDir, snapshot: &str) -> Result<(), Exception> {
+    let snapshot_dir = dir.snapshot_dir(snapshot);
+    let metadata: Metadata = json::from_json(&fs::read_to_string(snapshot_dir.join(METADATA_FILE))?)?;
+    for (name, mut child) in snapshots(dir)? {
+        if child.parent.as_deref() == Some(snapshot) {
+            child.parent.clone_from(&metadata.parent);
+            fs::write(dir.snapshot_dir(&name).join(METADATA_FILE), json::to_json_pretty(&child)?)?;
+        }
+    }
+    if current(dir).as_deref() == Some(snapshot) {
+        set_current(dir, metadata.parent.as_deref())?;
+    }
+    info!("delete snapshot, path={}", snapshot_dir.to_string_lossy());
+    fs::remove_dir_all(snapshot_dir)?;
+    Ok(())
+}
+
+// snapshots are (name, created), newest ones are kept, current one is always kept
+fn pruned_snapshots(snapshots: &[(String, u64)], keep: usize, current: Option<&str>) -> Vec<String> {
+    let mut snapshots = snapshots.to_vec();
+    snapshots.sort_by(|(name, created), (other_name, other)| other.cmp(created).then(other_name.cmp(name)));
+    snapshots
+        .into_iter()
+        .skip(keep)
+        .filter(|(name, _)| Some(name.as_str()) != current)
+        .map(|(name, _)| name)
+        .collect()
+}
+
+// snapshots are (name, parent) in created order, children are indented under parent
+fn format_tree(snapshots: &[(String, Option<String>)], current: Option<&str>) -> Vec<String> {
+    // snapshot is root if its parent is deleted
+    fn parent_of<'a>(parent: &'a Option<String>, snapshots: &[(String, Option<String>)]) -> Option<&'a str> {
+        parent.as_deref().filter(|parent| snapshots.iter().any(|(name, _)| name == parent))
+    }
+    let mut lines = vec![];
+    let mut stack: Vec<(&str, usize)> = snapshots
+        .iter()
+        .rev()
+        .filter(|(_, parent)| parent_of(parent, snapshots).is_none())
+        .map(|(name, _)| (name.as_str(), 0))
+        .collect();
+    while let Some((name, depth)) = stack.pop() {
+        let marker = if Some(name) == current { " *" } else { "" };
+        lines.push(format!("{}{name}{marker}", "  ".repeat(depth)));
+        stack.extend(
+            snapshots
+                .iter()
+                .rev()
+                .filter(|(_, parent)| parent_of(parent, snapshots) == Some(name))
+                .map(|(child, _)| (child.as_str(), depth + 1)),
+        );
+    }
+    lines
+}
+
+fn validate_snapshot_name(snapshot: &str) -> Result<(), Exception> {
+    let valid =
+        !snapshot.is_empty() && !snapshot.starts_with('.') && snapshot.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if !valid {
+        return Err(Exception::ValidationError(format!(
+            "invalid snapshot name, it must be letters, digits, -, _ or ., snapshot={snapshot}"
+        )));
+    }
+    Ok(())
+}
+
+fn format_age(seconds: u64) -> String {
+    match seconds {
+        0..60 => format!("{seconds}s"),
+        60..3600 => format!("{}m", seconds / 60),
+        3600..86400 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn validate_snapshot_name() {
+        assert!(super::validate_snapshot_name("clean-install").is_ok());
+        assert!(super::validate_snapshot_name("v1.2").is_ok());
+        assert!(super::validate_snapshot_name(".hidden").is_err());
+        assert!(super::validate_snapshot_name("../vm").is_err());
+        assert!(super::validate_snapshot_name("").is_err());
+    }
+
+    #[test]
+    fn format_age() {
+        assert_eq!(super::format_age(5), "5s");
+        assert_eq!(super::format_age(125), "2m");
+        assert_eq!(super::format_age(7200), "2h");
+        assert_eq!(super::format_age(3 * 86400 + 5), "3d");
+    }
+
+    #[test]
+    fn pruned_snapshots() {
+        let snapshots = [("a".to_string(), 1), ("b".to_string(), 2), ("c".to_string(), 3), ("d".to_string(), 4)];
+        assert_eq!(super::pruned_snapshots(&snapshots, 2, None), vec!["b", "a"]);
+        assert_eq!(super::pruned_snapshots(&snapshots, 2, Some("a")), vec!["b"]);
+        assert_eq!(super::pruned_snapshots(&snapshots, 5, None), Vec::<String>::new());
+    }
+
+    #[test]
+    fn format_tree() {
+        let snapshots = [
+            ("base".to_string(), None),
+            ("a".to_string(), Some("base".to_string())),
+            ("b".to_string(), Some("base".to_string())),
+            ("a1".to_string(), Some("a".to_string())),
+            ("orphan".to_string(), Some("deleted".to_string())),
+        ];
+        assert_eq!(
+            super::format_tree(&snapshots, Some("a1")),
+            vec!["base", "  a", "    a1 *", "  b", "orphan"]
+        );
+    }
+}
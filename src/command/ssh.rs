@@ -0,0 +1,117 @@
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::Instant;
+
+use clap::Args;
+use tracing::info;
+
+use crate::config::vm_config::VmConfig;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::dhcp_lease;
+use crate::util::exception::Exception;
+use crate::util::path;
+use crate::util::tcp;
+
+#[derive(Args)]
+pub struct Ssh {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(long, short, help = "guest user, default to ssh_user in config")]
+    user: Option<String>,
+
+    #[arg(long, help = "forget learned guest host key, e.g. after guest reinstalled", default_value_t = false)]
+    reset_host_key: bool,
+
+    #[arg(
+        long,
+        help = "seconds to wait for guest ip and ssh port, e.g. right after vz run -d",
+        default_value_t = 60
+    )]
+    timeout: u64,
+
+    #[arg(last = true, help = "arguments passed to ssh, e.g. vz ssh debian -- uname -a")]
+    args: Vec<String>,
+}
+
+impl Ssh {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        if dir.pid().is_none() {
+            return Err(Exception::ValidationError(format!("vm not running, name={name}")));
+        }
+
+        if self.reset_host_key && dir.known_hosts_path.exists() {
+            info!("remove known hosts, path={}", dir.known_hosts_path.to_string_lossy());
+            fs::remove_file(&dir.known_hosts_path)?;
+        }
+
+        let config = dir.load_config()?;
+        let ip = wait_for_ssh(&dir, &config, Duration::from_secs(self.timeout))?;
+        let mut command = ssh_command(&dir, &config);
+        command
+            .args(&config.ssh_args)
+            .arg(destination(&config, &ip, self.user.as_ref()))
+            .args(&self.args);
+        Err(command.exec().into())
+    }
+}
+
+pub fn wait_for_ssh(dir: &VmDir, config: &VmConfig, timeout: Duration) -> Result<String, Exception> {
+    let name = dir.name();
+    let start = Instant::now();
+    let mut ip = None;
+    loop {
+        if ip.is_none() {
+            ip = dhcp_lease::find_ip(&config.mac_address)?;
+        }
+        if let Some(ip) = ip.as_ref().filter(|ip| tcp::port_open(ip, tcp::SSH_PORT)) {
+            return Ok(ip.clone());
+        }
+        if start.elapsed() > timeout || dir.pid().is_none() {
+            return Err(match ip {
+                Some(ip) => Exception::ValidationError(format!("ssh port is not open, name={name}, ip={ip}")),
+                None => Exception::ValidationError(format!("vm ip not found, name={name}, mac_address={}", config.mac_address)),
+            });
+        }
+        sleep(Duration::from_secs(1));
+    }
+}
+
+pub fn ssh_command(dir: &VmDir, config: &VmConfig) -> Command {
+    client_command("ssh", dir, config)
+}
+
+// scp takes same options as ssh, ssh_args are left out as they may be ssh only flags, e.g. -t
+pub fn scp_command(dir: &VmDir, config: &VmConfig) -> Command {
+    client_command("scp", dir, config)
+}
+
+// guest ip changes with dhcp and cloned guests regenerate host keys, so learn host key per vm instead of ~/.ssh/known_hosts
+fn client_command(program: &str, dir: &VmDir, config: &VmConfig) -> Command {
+    let mut command = Command::new(program);
+    command
+        .arg("-o")
+        .arg(format!("UserKnownHostsFile={}", dir.known_hosts_path.to_string_lossy()))
+        .args(["-o", "StrictHostKeyChecking=accept-new", "-o", "CheckHostIP=no"]);
+    // path is validated by load_config
+    if let Some(key) = config.ssh_key.as_ref().and_then(|key| path::expand(key).ok()) {
+        command.arg("-i").arg(key);
+    }
+    command
+}
+
+pub fn destination(config: &VmConfig, ip: &str, user: Option<&String>) -> String {
+    match user.or(config.ssh_user.as_ref()) {
+        Some(user) => format!("{user}@{ip}"),
+        None => ip.to_string(),
+    }
+}
@@ -0,0 +1,284 @@
+use std::fs;
+use std::mem;
+use std::os::unix::fs::MetadataExt;
+use std::process::Command;
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use clap::Args;
+use libc::pid_t;
+use serde::Serialize;
+
+use crate::config::vm_config::Os;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::dhcp_lease;
+use crate::util::exception::Exception;
+use crate::util::json;
+use crate::vm::cpu_limit;
+
+#[derive(Args)]
+pub struct Stats {
+    #[arg(long, help = "print as json, e.g. for monitoring scripts", default_value_t = false)]
+    json: bool,
+
+    #[arg(
+        long,
+        short,
+        help = "refresh until ctrl-c, disk io is shown as rate, json prints one snapshot per line",
+        default_value_t = false
+    )]
+    watch: bool,
+
+    #[arg(long, help = "seconds between refreshes, with --watch", default_value_t = 2)]
+    interval: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Snapshot {
+    timestamp: u64,
+    vms: Vec<VmStats>,
+}
+
+#[derive(Serialize, Debug)]
+struct VmStats {
+    name: String,
+    os: Os,
+    // stopped, running or unresponsive
+    status: &'static str,
+    cpu: usize,
+    memory: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cpu_limit_percent: Option<u8>,
+    disk_allocated: u64,
+    disk_size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<pid_t>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    process: Option<ProcessStats>,
+}
+
+// usage of vm process on host, which runs vcpu threads of guest
+#[derive(Serialize, Debug)]
+struct ProcessStats {
+    pid: pid_t,
+    cpu_percent: f64,
+    rss: u64,
+    uptime: u64,
+    // bytes since start, disk image io of guest is done by vm process
+    disk_read: u64,
+    disk_written: u64,
+}
+
+impl Stats {
+    pub fn execute(&self) -> Result<(), Exception> {
+        if !self.watch {
+            let snapshot = snapshot()?;
+            if self.json {
+                println!("{}", json::to_json_pretty(&snapshot)?);
+            } else {
+                print_table(&snapshot, None);
+            }
+            return Ok(());
+        }
+        let mut previous = None;
+        loop {
+            let snapshot = snapshot()?;
+            if self.json {
+                println!("{}", json::to_json(&snapshot)?);
+            } else {
+                // clear screen and move cursor to top left
+                print!("\x1B[2J\x1B[H");
+                print_table(&snapshot, previous.as_ref());
+            }
+            previous = Some(snapshot);
+            sleep(Duration::from_secs(self.interval.max(1)));
+        }
+    }
+}
+
+// cpu% and rss are shown against configured cpu and memory, disk io is rate since previous snapshot if watching
+fn print_table(snapshot: &Snapshot, previous: Option<&Snapshot>) {
+    println!(
+        "{:<16}{:<14}{:<8}{:<10}{:<16}{:<12}{:<16}{:<16}{:<12}{:<12}",
+        "name", "status", "cpu%", "cpu", "rss/memory", "uptime", "ip", "disk", "disk read", "disk write"
+    );
+    let gb = |bytes: u64| format!("{:.2}G", bytes as f64 / (1024.0 * 1024.0 * 1024.0));
+    let seconds = previous.map_or(1, |previous| snapshot.timestamp.saturating_sub(previous.timestamp).max(1));
+    for vm in &snapshot.vms {
+        // same vm process in previous snapshot, counters restart with new one
+        let previous = previous
+            .and_then(|previous| previous.vms.iter().find(|previous| previous.name == vm.name))
+            .and_then(|previous| previous.process.as_ref())
+            .filter(|previous| Some(previous.pid) == vm.process.as_ref().map(|process| process.pid));
+        let (cpu_percent, rss, uptime, disk_read, disk_written) = match &vm.process {
+            Some(process) => {
+                let (disk_read, disk_written) = match previous {
+                    Some(previous) => (
+                        io_rate(process.disk_read, previous.disk_read, seconds),
+                        io_rate(process.disk_written, previous.disk_written, seconds),
+                    ),
+                    None => (gb(process.disk_read), gb(process.disk_written)),
+                };
+                (
+                    format!("{:.1}", process.cpu_percent),
+                    format!("{}/{}", gb(process.rss), gb(vm.memory)),
+                    format!("{}s", process.uptime),
+                    disk_read,
+                    disk_written,
+                )
+            }
+            None => (
+                "-".to_string(),
+                format!("-/{}", gb(vm.memory)),
+                "-".to_string(),
+                "-".to_string(),
+                "-".to_string(),
+            ),
+        };
+        let cpu = match vm.cpu_limit_percent {
+            Some(limit) => format!("{}@{limit}%", vm.cpu),
+            None => vm.cpu.to_string(),
+        };
+        let disk = format!(
+            "{:.2}G/{:.2}G",
+            vm.disk_allocated as f64 / 1_000_000_000.0,
+            vm.disk_size as f64 / 1_000_000_000.0
+        );
+        println!(
+            "{:<16}{:<14}{:<8}{:<10}{:<16}{:<12}{:<16}{:<16}{:<12}{:<12}",
+            vm.name,
+            vm.status,
+            cpu_percent,
+            cpu,
+            rss,
+            uptime,
+            vm.ip.as_deref().unwrap_or("-"),
+            disk,
+            disk_read,
+            disk_written
+        );
+    }
+}
+
+fn io_rate(current: u64, previous: u64, seconds: u64) -> String {
+    format!("{:.2}M/s", current.saturating_sub(previous) as f64 / seconds.max(1) as f64 / 1_000_000.0)
+}
+
+pub fn snapshot() -> Result<Snapshot, Exception> {
+    let home_dir = vm_dir::home_dir();
+    if !home_dir.exists() {
+        return Err(Exception::ValidationError(format!("{} does not exist", home_dir.to_string_lossy())));
+    }
+    let mut vms = vec![];
+    for entry in fs::read_dir(home_dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            let dir = vm_dir::vm_dir(&path.file_name().unwrap().to_string_lossy());
+            if dir.initialized() {
+                vms.push(vm_stats(&dir)?);
+            }
+        }
+    }
+    vms.sort_by(|a, b| a.name.cmp(&b.name));
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    Ok(Snapshot { timestamp, vms })
+}
+
+fn vm_stats(dir: &VmDir) -> Result<VmStats, Exception> {
+    let config = dir.load_config()?;
+    let metadata = dir.disk_path.metadata()?;
+    let pid = dir.pid();
+    let status = match pid {
+        None => "stopped",
+        Some(_) if dir.unresponsive_path.exists() => "unresponsive",
+        Some(_) => "running",
+    };
+    let ip = match pid {
+        Some(_) => dhcp_lease::find_ip(&config.mac_address)?,
+        None => None,
+    };
+    Ok(VmStats {
+        name: dir.name(),
+        os: config.os,
+        status,
+        cpu: config.cpu,
+        memory: config.memory,
+        cpu_limit_percent: config.cpu_limit_percent,
+        disk_allocated: metadata.blocks() * 512,
+        disk_size: metadata.len(),
+        pid,
+        ip,
+        // runner only supervises, guest runs in xpc process of Virtualization.framework
+        process: pid.and_then(cpu_limit::find_vm_process).map(process_stats).transpose()?.flatten(),
+    })
+}
+
+// process may exit between lock check and ps, then it has no stats
+fn process_stats(pid: pid_t) -> Result<Option<ProcessStats>, Exception> {
+    let output = Command::new("ps").args(["-o", "%cpu=,rss=,etime=", "-p", &pid.to_string()]).output()?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut columns = output.split_whitespace();
+    let (Some(cpu), Some(rss), Some(elapsed)) = (columns.next(), columns.next(), columns.next()) else {
+        return Ok(None);
+    };
+    let (disk_read, disk_written) = disk_io(pid).unwrap_or((0, 0));
+    Ok(Some(ProcessStats {
+        pid,
+        cpu_percent: cpu.parse().unwrap_or(0.0),
+        // ps reports rss in kb
+        rss: rss.parse::<u64>().unwrap_or(0) * 1024,
+        uptime: elapsed_seconds(elapsed).unwrap_or(0),
+        disk_read,
+        disk_written,
+    }))
+}
+
+// read and written bytes of process, like ri_diskio of top
+fn disk_io(pid: pid_t) -> Option<(u64, u64)> {
+    unsafe {
+        let mut info: libc::rusage_info_v2 = mem::zeroed();
+        let buffer = &mut info as *mut libc::rusage_info_v2 as *mut libc::rusage_info_t;
+        if libc::proc_pid_rusage(pid, libc::RUSAGE_INFO_V2, buffer) != 0 {
+            return None;
+        }
+        Some((info.ri_diskio_bytesread, info.ri_diskio_byteswritten))
+    }
+}
+
+// ps etime is [[dd-]hh:]mm:ss
+fn elapsed_seconds(elapsed: &str) -> Option<u64> {
+    let (days, time) = match elapsed.split_once('-') {
+        Some((days, time)) => (days.parse::<u64>().ok()?, time),
+        None => (0, elapsed),
+    };
+    let mut seconds = 0;
+    for part in time.split(':') {
+        seconds = seconds * 60 + part.parse::<u64>().ok()?;
+    }
+    Some(days * 86400 + seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn elapsed_seconds() {
+        assert_eq!(Some(5), super::elapsed_seconds("00:05"));
+        assert_eq!(Some(3723), super::elapsed_seconds("01:02:03"));
+        assert_eq!(Some(90061), super::elapsed_seconds("1-01:01:01"));
+        assert_eq!(None, super::elapsed_seconds("invalid"));
+    }
+
+    #[test]
+    fn io_rate() {
+        assert_eq!("1.50M/s", super::io_rate(4_000_000, 1_000_000, 2));
+        assert_eq!("0.00M/s", super::io_rate(0, 1_000_000, 2));
+    }
+}
@@ -0,0 +1,126 @@
+use clap::Args;
+use serde::Serialize;
+
+use crate::config::vm_dir;
+use crate::util::dhcp_lease;
+use crate::util::exception::Exception;
+use crate::util::json;
+use crate::vm::control;
+
+#[derive(Args)]
+pub struct Status {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(long, short, help = "output format, json prints status for scripts", default_value = "text")]
+    output: Output,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum Output {
+    Text,
+    Json,
+}
+
+// state is from runner, uptime is in seconds, devices are only known by runner of running vm
+#[derive(Serialize, Debug)]
+pub struct Record {
+    name: String,
+    state: String,
+    pid: Option<i32>,
+    uptime: Option<u64>,
+    ip: Option<String>,
+    disks: Vec<String>,
+    devices: Vec<String>,
+    shares: Vec<String>,
+}
+
+impl Status {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let record = status(&self.name)?;
+        if self.output == Output::Json {
+            println!("{}", json::to_json_pretty(&record)?);
+            return Ok(());
+        }
+        println!("{:<10}{}", "name", record.name);
+        println!("{:<10}{}", "state", record.state);
+        println!("{:<10}{}", "pid", record.pid.map_or("-".to_string(), |pid| pid.to_string()));
+        println!("{:<10}{}", "uptime", record.uptime.map_or("-".to_string(), format_uptime));
+        println!("{:<10}{}", "ip", record.ip.as_deref().unwrap_or("-"));
+        print_list("disks", &record.disks);
+        print_list("devices", &record.devices);
+        print_list("shares", &record.shares);
+        Ok(())
+    }
+}
+
+pub fn status(name: &str) -> Result<Record, Exception> {
+    let dir = vm_dir::vm_dir(name);
+    if !dir.initialized() {
+        return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+    }
+    let config = dir.load_config()?;
+    let pid = dir.pid();
+    let status = pid.and_then(|_| control::status(&dir));
+    let state = match (&status, pid) {
+        (Some(status), _) => status.state.clone().unwrap_or_default(),
+        // runner holds pid file but doesn't answer on control socket
+        (None, Some(_)) => "unresponsive".to_string(),
+        (None, None) if dir.state_path.exists() => "suspended".to_string(),
+        (None, None) => "stopped".to_string(),
+    };
+    let ip = match pid {
+        Some(_) => dhcp_lease::find_ip(&config.mac_address)?,
+        None => None,
+    };
+    let mut disks = vec![dir.disk_path.to_string_lossy().to_string()];
+    disks.extend(config.disks.iter().map(|disk| dir.extra_disk_path(disk).to_string_lossy().to_string()));
+    let mut shares: Vec<_> = config
+        .sharing
+        .iter()
+        .map(|(share, value)| format!("{share}={}{}", value.path(), if value.read_only() { " (ro)" } else { "" }))
+        .collect();
+    shares.sort();
+    let (uptime, devices) = match status {
+        Some(status) => (status.uptime, status.devices.unwrap_or_default()),
+        None => (None, vec![]),
+    };
+    Ok(Record {
+        name: name.to_string(),
+        state,
+        pid,
+        uptime,
+        ip,
+        disks,
+        devices,
+        shares,
+    })
+}
+
+fn print_list(label: &str, values: &[String]) {
+    if values.is_empty() {
+        println!("{label:<10}-");
+    }
+    for (index, value) in values.iter().enumerate() {
+        println!("{:<10}{value}", if index == 0 { label } else { "" });
+    }
+}
+
+pub fn format_uptime(seconds: u64) -> String {
+    let (days, hours, minutes) = (seconds / 86400, seconds % 86400 / 3600, seconds % 3600 / 60);
+    match (days, hours) {
+        (0, 0) => format!("{minutes}m {}s", seconds % 60),
+        (0, _) => format!("{hours}h {minutes}m"),
+        _ => format!("{days}d {hours}h {minutes}m"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn format_uptime() {
+        assert_eq!(super::format_uptime(59), "0m 59s");
+        assert_eq!(super::format_uptime(3 * 3600 + 120), "3h 2m");
+        assert_eq!(super::format_uptime(2 * 86400 + 3600 + 60), "2d 1h 1m");
+    }
+}
@@ -1,5 +1,4 @@
 use std::process;
-use std::thread::sleep;
 use std::time::Duration;
 
 use clap::Args;
@@ -9,48 +8,111 @@ use tracing::info;
 use crate::config::vm_dir;
 use crate::config::vm_dir::VmDir;
 use crate::util::exception::Exception;
+use crate::vm;
+use crate::vm::platform::Platform;
+use crate::vm::platform::Virtualization;
+use crate::vm::runner;
 
 #[derive(Args)]
 pub struct Stop {
-    #[arg(help = "vm name")]
-    name: String,
+    #[arg(help = "vm name", required_unless_present = "all")]
+    name: Option<String>,
+
+    #[arg(long, help = "stop all running vms", default_value_t = false, conflicts_with = "name")]
+    all: bool,
+
+    #[arg(long, help = "seconds for guest to shut down, then vm is force stopped", default_value_t = vm::STOP_TIMEOUT.as_secs() as u32)]
+    timeout: u32,
+
+    #[arg(long, help = "stop vm without waiting for guest to shut down", default_value_t = false)]
+    force: bool,
 }
 
 impl Stop {
     pub fn execute(&self) -> Result<(), Exception> {
-        let name = &self.name;
-        let dir = vm_dir::vm_dir(name);
-        if !dir.initialized() {
-            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        let platform = &Virtualization;
+        let dirs = match &self.name {
+            Some(name) => {
+                let dir = vm_dir::vm_dir(name);
+                if !dir.initialized() {
+                    return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+                }
+                vec![dir]
+            }
+            None => vm_dir::vm_dirs()?,
+        };
+        let dirs = running_vm_dirs(platform, dirs, self.name.as_deref())?;
+        if dirs.is_empty() {
+            info!("no vm is running");
+            return Ok(());
         }
+        let success = self.stop(platform, &dirs);
+        process::exit(if success { 0 } else { 1 });
+    }
 
-        let pid = dir
-            .pid()
-            .ok_or_else(|| Exception::ValidationError(format!("vm not running, name={name}")))?;
-        info!("stop vm, name={name}, pid={pid}");
-        unsafe {
-            libc::kill(pid, libc::SIGINT);
+    // false if any vm didn't stop in time
+    fn stop(&self, platform: &dyn Platform, dirs: &[VmDir]) -> bool {
+        // request all first, so guests shut down at same time
+        for dir in dirs {
+            platform.request_stop(dir, self.force, Duration::from_secs(self.timeout.into()));
         }
-
-        let success = wait_until_stopped(dir);
-        if success {
-            info!("vm stopped");
-            process::exit(0);
-        } else {
-            error!("failed to stop vm");
-            process::exit(1);
+        let timeout = if self.force { 0 } else { self.timeout } + runner::FORCE_STOP_TIMEOUT;
+        let mut success = true;
+        for dir in dirs {
+            if platform.wait_until_stopped(dir, timeout) {
+                info!("vm stopped, name={}", dir.name());
+            } else {
+                error!("failed to stop vm, name={}", dir.name());
+                success = false;
+            }
         }
+        success
     }
 }
 
-fn wait_until_stopped(dir: VmDir) -> bool {
-    let mut attempts = 0;
-    while attempts < 20 {
-        sleep(Duration::from_secs(1));
-        if dir.pid().is_none() {
-            return true;
-        }
-        attempts += 1;
+// vm given by name must be running, --all skips stopped ones
+fn running_vm_dirs(platform: &dyn Platform, dirs: Vec<VmDir>, name: Option<&str>) -> Result<Vec<VmDir>, Exception> {
+    let dirs: Vec<VmDir> = dirs.into_iter().filter(|dir| platform.runner_pid(dir).is_some()).collect();
+    if let (Some(name), true) = (name, dirs.is_empty()) {
+        return Err(Exception::ValidationError(format!("vm not running, name={name}")));
+    }
+    Ok(dirs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::Stop;
+    use crate::config::vm_dir::VmDir;
+    use crate::vm::platform::mock::MockPlatform;
+
+    fn dirs(names: &[&str]) -> Vec<VmDir> {
+        names.iter().map(|name| VmDir::new(PathBuf::from("/tmp/vz-test").join(name))).collect()
+    }
+
+    #[test]
+    fn running_vm_dirs() {
+        let platform = MockPlatform::running(&[("debian", "running")]);
+        let running = super::running_vm_dirs(&platform, dirs(&["debian", "ubuntu"]), None).unwrap();
+        assert_eq!(vec!["debian".to_string()], running.iter().map(VmDir::name).collect::<Vec<_>>());
+        assert!(super::running_vm_dirs(&platform, dirs(&["ubuntu"]), Some("ubuntu")).is_err());
+        assert!(super::running_vm_dirs(&platform, dirs(&["ubuntu"]), None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn stop() {
+        let platform = MockPlatform {
+            stuck: vec!["ubuntu".to_string()],
+            ..MockPlatform::running(&[("debian", "running"), ("ubuntu", "running")])
+        };
+        let stop = Stop {
+            name: None,
+            all: true,
+            timeout: 15,
+            force: false,
+        };
+        assert!(!stop.stop(&platform, &dirs(&["debian", "ubuntu"])));
+        assert_eq!(vec!["stop debian", "stop ubuntu", "wait debian", "wait ubuntu"], *platform.calls.borrow());
     }
-    false
 }
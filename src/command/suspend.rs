@@ -0,0 +1,35 @@
+use clap::Args;
+use tracing::info;
+
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+use crate::vm::control;
+use crate::vm::control::Request;
+use crate::vm::runner;
+
+#[derive(Args)]
+pub struct Suspend {
+    #[arg(help = "vm name")]
+    name: String,
+}
+
+impl Suspend {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        if dir.pid().is_none() {
+            return Err(Exception::ValidationError(format!("vm is not running, name={name}")));
+        }
+
+        info!("suspend vm, name={name}");
+        control::send(&dir, &Request::Suspend)?;
+        if !runner::wait_until_stopped(&dir, 30) {
+            return Err(Exception::ValidationError(format!("timeout waiting for vm to stop, name={name}")));
+        }
+        info!("vm suspended, resume by vz run, name={name}, state={}", dir.state_path.to_string_lossy());
+        Ok(())
+    }
+}
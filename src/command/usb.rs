@@ -0,0 +1,63 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use clap::Args;
+use clap::Subcommand;
+use tracing::info;
+
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+use crate::vm::control;
+use crate::vm::control::Request;
+
+#[derive(Args)]
+pub struct Usb {
+    #[command(subcommand)]
+    command: UsbCommand,
+}
+
+#[derive(Subcommand)]
+enum UsbCommand {
+    #[command(about = "attach disk image as usb mass storage to running vm, requires macOS 15")]
+    Attach {
+        #[arg(help = "vm name")]
+        name: String,
+
+        #[arg(help = "disk image, e.g. firmware.img")]
+        image: PathBuf,
+
+        #[arg(long, help = "attach read only", default_value_t = false)]
+        read_only: bool,
+    },
+}
+
+impl Usb {
+    pub fn execute(&self) -> Result<(), Exception> {
+        match &self.command {
+            UsbCommand::Attach { name, image, read_only } => attach(name, image, *read_only),
+        }
+    }
+}
+
+fn attach(name: &str, image: &Path, read_only: bool) -> Result<(), Exception> {
+    let dir = vm_dir::vm_dir(name);
+    if !dir.initialized() {
+        return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+    }
+    if dir.pid().is_none() {
+        return Err(Exception::ValidationError(format!("vm is not running, name={name}")));
+    }
+    // runner resolves path in its own working dir
+    let path = image
+        .canonicalize()
+        .map_err(|err| Exception::ValidationError(format!("image not found, image={}, error={err}", image.to_string_lossy())))?;
+    control::send(
+        &dir,
+        &Request::UsbAttach {
+            path: path.to_string_lossy().to_string(),
+            read_only,
+        },
+    )?;
+    info!("usb device attached, name={name}, image={}", path.to_string_lossy());
+    Ok(())
+}
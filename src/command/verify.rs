@@ -0,0 +1,211 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use clap::Args;
+use tracing::info;
+
+use crate::config::vm_config::Os;
+use crate::config::vm_config::VmConfig;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+use crate::vm::linux;
+use crate::vm::mac_os;
+
+#[derive(Args)]
+pub struct Verify {
+    #[arg(help = "vm name")]
+    name: String,
+}
+
+pub struct Issue {
+    pub problem: String,
+    pub fix: String,
+}
+
+impl Verify {
+    pub fn execute(&self) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+
+        let issues = check(&dir);
+        if issues.is_empty() {
+            info!("vm is consistent, name={name}");
+            return Ok(());
+        }
+        for issue in &issues {
+            println!("{}\n  fix: {}", issue.problem, issue.fix);
+        }
+        Err(Exception::ValidationError(format!("vm has {} issues, name={name}", issues.len())))
+    }
+}
+
+// issues of initialized vm dir, e.g. missing files and invalid config
+pub fn check(dir: &VmDir) -> Vec<Issue> {
+    let mut issues = vec![];
+    check_files(dir, &mut issues);
+    match dir.load_config() {
+        Ok(config) => check_config(dir, &config, &mut issues),
+        Err(err) => issues.push(Issue {
+            problem: format!("invalid config, error={err}"),
+            fix: format!("correct config with vz edit {}, or restore config.json from backup", dir.name()),
+        }),
+    }
+    issues
+}
+
+fn check_files(dir: &VmDir, issues: &mut Vec<Issue>) {
+    for (path, file) in [(&dir.disk_path, "disk.img"), (&dir.nvram_path, "nvram.bin")] {
+        match path.metadata() {
+            Ok(metadata) if metadata.is_file() && metadata.len() > 0 => {}
+            Ok(_) => issues.push(Issue {
+                problem: format!("{file} is empty or not a file, path={}", path.to_string_lossy()),
+                fix: format!("restore {file} from backup"),
+            }),
+            Err(_) => issues.push(Issue {
+                problem: format!("{file} not found, path={}", path.to_string_lossy()),
+                fix: format!("restore {file} from backup"),
+            }),
+        }
+    }
+
+    let current_uid = unsafe { libc::getuid() };
+    let paths = [
+        &dir.dir,
+        &dir.config_path,
+        &dir.disk_path,
+        &dir.nvram_path,
+        &dir.seed_path,
+        &dir.kernel_path,
+        &dir.initrd_path,
+    ];
+    for path in paths {
+        if let Ok(metadata) = path.metadata() {
+            issues.extend(permission_issues(path, metadata.uid(), metadata.mode(), current_uid));
+        }
+    }
+}
+
+// runner must read and write all files as current user, e.g. copied from other machine by root
+fn permission_issues(path: &Path, uid: u32, mode: u32, current_uid: u32) -> Vec<Issue> {
+    let path = path.to_string_lossy();
+    let mut issues = vec![];
+    if uid != current_uid {
+        issues.push(Issue {
+            problem: format!("not owned by current user, path={path}, uid={uid}"),
+            fix: format!("sudo chown $(whoami) {path}"),
+        });
+    }
+    if mode & 0o600 != 0o600 {
+        issues.push(Issue {
+            problem: format!("not readable and writable by owner, path={path}, mode={:o}", mode & 0o777),
+            fix: format!("chmod u+rw {path}"),
+        });
+    }
+    if mode & 0o002 != 0 {
+        issues.push(Issue {
+            problem: format!("writable by other users, path={path}, mode={:o}", mode & 0o777),
+            fix: format!("chmod o-w {path}"),
+        });
+    }
+    issues
+}
+
+fn check_config(dir: &VmDir, config: &VmConfig, issues: &mut Vec<Issue>) {
+    let name = dir.name();
+    if let Err(err) = config.validate_paths() {
+        issues.push(Issue {
+            problem: err.to_string(),
+            fix: format!("correct paths with vz edit {name}"),
+        });
+    }
+    if let Err(err) = config.validate_host_limits() {
+        issues.push(Issue {
+            problem: err.to_string(),
+            fix: format!("change cpu or memory with vz resize {name} --cpu=N --memory=N"),
+        });
+    }
+    for disk in &config.disks {
+        let path = dir.extra_disk_path(disk);
+        if !path.is_file() {
+            issues.push(Issue {
+                problem: format!("disk not found, disk={disk}, path={}", path.to_string_lossy()),
+                fix: format!("restore {disk}.img from backup, or remove it with vz disk remove {name} {disk}"),
+            });
+        }
+    }
+    match config.os {
+        Os::MacOs => check_mac_os(config, issues),
+        Os::Linux => {
+            let kernel = linux::boot_files(dir, config).map(|(kernel, _)| kernel);
+            if let (Some(_), Ok(kernel)) = (&config.kernel_command_line, kernel) {
+                if !kernel.exists() {
+                    issues.push(Issue {
+                        problem: format!("kernel_command_line is set but kernel not found, path={}", kernel.to_string_lossy()),
+                        fix: "restore vmlinuz from backup, correct kernel in config, or remove kernel_command_line to boot by EFI".to_string(),
+                    });
+                }
+            }
+            if config.kernel_command_line.is_none() && (config.kernel.is_some() || config.initrd.is_some()) {
+                issues.push(Issue {
+                    problem: "kernel or initrd is set without kernel_command_line, vm boots by EFI".to_string(),
+                    fix: format!("set kernel_command_line with vz edit {name}, e.g. \"console=hvc0 root=/dev/vda1\""),
+                });
+            }
+        }
+    }
+}
+
+// nvram.bin is bound to hardware model and machine identifier, they can't be regenerated
+fn check_mac_os(config: &VmConfig, issues: &mut Vec<Issue>) {
+    let restore = "restore config.json from backup of same vm, it must match nvram.bin";
+    match config.hardware_model.as_deref().map(mac_os::parse_hardware_model) {
+        None => issues.push(Issue {
+            problem: "hardware_model is missing".to_string(),
+            fix: restore.to_string(),
+        }),
+        Some(None) => issues.push(Issue {
+            problem: "hardware_model is invalid".to_string(),
+            fix: restore.to_string(),
+        }),
+        Some(Some(model)) => {
+            if !unsafe { model.isSupported() } {
+                issues.push(Issue {
+                    problem: "hardware_model is not supported by current host".to_string(),
+                    fix: "run vm on apple silicon host with same or newer macOS than vm was created on".to_string(),
+                });
+            }
+        }
+    }
+    match config.machine_identifier.as_deref().map(mac_os::parse_machine_identifier) {
+        None => issues.push(Issue {
+            problem: "machine_identifier is missing".to_string(),
+            fix: restore.to_string(),
+        }),
+        Some(None) => issues.push(Issue {
+            problem: "machine_identifier is invalid".to_string(),
+            fix: restore.to_string(),
+        }),
+        Some(Some(_)) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    #[test]
+    fn permission_issues() {
+        let path = Path::new("/vm/disk.img");
+        assert!(super::permission_issues(path, 501, 0o644, 501).is_empty());
+
+        let issues = super::permission_issues(path, 0, 0o446, 501);
+        assert_eq!(issues.len(), 3);
+        assert_eq!(issues[0].fix, "sudo chown $(whoami) /vm/disk.img");
+        assert_eq!(issues[1].fix, "chmod u+rw /vm/disk.img");
+        assert_eq!(issues[2].fix, "chmod o-w /vm/disk.img");
+    }
+}
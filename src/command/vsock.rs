@@ -0,0 +1,121 @@
+use std::io;
+use std::net::Shutdown;
+use std::path;
+use std::path::PathBuf;
+use std::thread;
+
+use clap::Args;
+use clap::Subcommand;
+use clap::ValueHint;
+use tracing::info;
+
+use crate::config::vm_config::VmConfig;
+use crate::config::vm_config::VsockSocket;
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+use crate::util::path::PathExtension;
+use crate::vm::control;
+use crate::vm::control::Request;
+
+#[derive(Args)]
+pub struct Vsock {
+    #[command(subcommand)]
+    command: VsockCommand,
+}
+
+#[derive(Subcommand)]
+enum VsockCommand {
+    #[command(about = "make guest vsock port reachable via host unix socket")]
+    Forward(VsockArgs),
+    #[command(about = "make host unix socket reachable via guest vsock port")]
+    Expose(VsockArgs),
+    #[command(about = "connect stdio to guest vsock port of running vm")]
+    Connect(BridgeArgs),
+    #[command(about = "wait for guest to connect vsock port of running vm, and bridge connection to stdio")]
+    Listen(BridgeArgs),
+}
+
+#[derive(Args)]
+struct BridgeArgs {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(help = "guest vsock port")]
+    port: u32,
+}
+
+#[derive(Args)]
+struct VsockArgs {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(help = "guest vsock port")]
+    port: u32,
+
+    #[arg(help = "host unix socket path", value_hint = ValueHint::FilePath)]
+    socket: PathBuf,
+
+    #[arg(long, help = "remove socket from vm", default_value_t = false)]
+    remove: bool,
+}
+
+impl Vsock {
+    pub fn execute(&self) -> Result<(), Exception> {
+        match &self.command {
+            VsockCommand::Forward(args) => args.update(|config| &mut config.vsock_forwards),
+            VsockCommand::Expose(args) => args.update(|config| &mut config.vsock_exposes),
+            VsockCommand::Connect(args) => args.bridge(Request::VsockConnect { port: args.port }),
+            VsockCommand::Listen(args) => args.bridge(Request::VsockListen { port: args.port }),
+        }
+    }
+}
+
+impl BridgeArgs {
+    // runner owns vsock device, so connection is relayed by control socket, e.g. vz vsock connect dev 1024 <<< ping
+    fn bridge(&self, request: Request) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+        if dir.pid().is_none() {
+            return Err(Exception::ValidationError(format!("vm is not running, name={name}")));
+        }
+        let stream = control::open(&dir, &request)?;
+        info!("vsock bridged to stdio, name={name}, port={}", self.port);
+        let writer = stream.try_clone()?;
+        thread::spawn(move || {
+            let _ = io::copy(&mut io::stdin(), &mut &writer);
+            let _ = writer.shutdown(Shutdown::Write);
+        });
+        io::copy(&mut &stream, &mut io::stdout())?;
+        Ok(())
+    }
+}
+
+impl VsockArgs {
+    fn update(&self, sockets: impl Fn(&mut VmConfig) -> &mut Vec<VsockSocket>) -> Result<(), Exception> {
+        let name = &self.name;
+        let dir = vm_dir::vm_dir(name);
+        if !dir.initialized() {
+            return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+        }
+
+        let socket = path::absolute(self.socket.to_absolute_path())?.to_string_lossy().to_string();
+        let mut config = dir.load_config()?;
+        let sockets = sockets(&mut config);
+        sockets.retain(|value| value.socket != socket);
+        if self.remove {
+            info!("remove vsock socket, name={name}, socket={socket}");
+        } else {
+            info!("add vsock socket, name={name}, port={}, socket={socket}", self.port);
+            sockets.push(VsockSocket { port: self.port, socket });
+        }
+        dir.save_config(&config)?;
+
+        if dir.pid().is_some() {
+            info!("vm is running, restart vm to apply");
+        }
+        Ok(())
+    }
+}
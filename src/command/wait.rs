@@ -0,0 +1,140 @@
+use std::process::Stdio;
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::Instant;
+
+use clap::Args;
+use tracing::info;
+
+use crate::command::ssh;
+use crate::config::vm_config::ReadinessProbe;
+use crate::config::vm_config::VmConfig;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::dhcp_lease;
+use crate::util::exception::Exception;
+use crate::util::tcp;
+
+#[derive(Args)]
+pub struct Wait {
+    #[arg(help = "vm name")]
+    name: String,
+
+    #[arg(
+        long = "for",
+        help = "condition instead of readiness probe of config, ip, ssh or port:<port>, e.g. --for=port:8080",
+        default_value = "ready",
+        value_parser = parse_condition
+    )]
+    condition: Condition,
+
+    #[arg(long, help = "seconds to wait for readiness probe", default_value_t = DEFAULT_TIMEOUT)]
+    timeout: u64,
+}
+
+pub const DEFAULT_TIMEOUT: u64 = 300;
+
+// ready is readiness probe of config, ip is dhcp lease, ssh and port are checked like probes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    Ready,
+    Ip,
+    Ssh,
+    Port(u16),
+}
+
+impl Wait {
+    pub fn execute(&self) -> Result<(), Exception> {
+        wait(&self.name, self.condition, Duration::from_secs(self.timeout))
+    }
+}
+
+pub fn wait(name: &str, condition: Condition, timeout: Duration) -> Result<(), Exception> {
+    let dir = vm_dir::vm_dir(name);
+    if !dir.initialized() {
+        return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+    }
+    let mut config = dir.load_config()?;
+    match condition {
+        Condition::Ready => {}
+        Condition::Ip => config.readiness_probe = None,
+        Condition::Ssh => config.readiness_probe = Some(ReadinessProbe::Ssh),
+        Condition::Port(port) => config.readiness_probe = Some(ReadinessProbe::Tcp(port)),
+    }
+    let ip = wait_until_ready(&dir, &config, timeout, &|| dir.pid().is_some())?;
+    info!("vm is ready, name={name}, ip={ip}");
+    Ok(())
+}
+
+pub fn parse_condition(value: &str) -> Result<Condition, String> {
+    match value {
+        "ready" => Ok(Condition::Ready),
+        "ip" => Ok(Condition::Ip),
+        "ssh" => Ok(Condition::Ssh),
+        _ => value
+            .strip_prefix("port:")
+            .and_then(|port| port.parse().ok())
+            .map(Condition::Port)
+            .ok_or_else(|| format!("invalid condition, it must be ready, ip, ssh or port:<port>, condition={value}")),
+    }
+}
+
+// without probe, vm is ready once it got dhcp lease
+pub fn wait_until_ready(dir: &VmDir, config: &VmConfig, timeout: Duration, running: &dyn Fn() -> bool) -> Result<String, Exception> {
+    let start = Instant::now();
+    let mut ip = None;
+    while start.elapsed() < timeout {
+        if !running() {
+            return Err(Exception::ValidationError(format!("vm not running, name={}", dir.name())));
+        }
+        if ip.is_none() {
+            ip = dhcp_lease::find_ip(&config.mac_address)?;
+        }
+        if let Some(ip) = &ip {
+            if probe(dir, config, ip) {
+                return Ok(ip.clone());
+            }
+        }
+        sleep(Duration::from_secs(1));
+    }
+    Err(Exception::ValidationError(format!("timeout waiting for vm ready, name={}", dir.name())))
+}
+
+// liveness of ready vm, without probe guest is alive while it accepts connection on ssh port
+pub fn alive(dir: &VmDir, config: &VmConfig, ip: &str) -> bool {
+    match config.readiness_probe {
+        Some(_) => probe(dir, config, ip),
+        None => tcp::port_open(ip, tcp::SSH_PORT),
+    }
+}
+
+fn probe(dir: &VmDir, config: &VmConfig, ip: &str) -> bool {
+    let command = match &config.readiness_probe {
+        None => return true,
+        Some(ReadinessProbe::Tcp(port)) => return tcp::port_open(ip, *port),
+        Some(ReadinessProbe::Ssh) => "true",
+        Some(ReadinessProbe::Command(command)) => command,
+    };
+    ssh::ssh_command(dir, config)
+        .args(["-o", "BatchMode=yes", "-o", "ConnectTimeout=5"])
+        .arg(ssh::destination(config, ip, None))
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Condition;
+
+    #[test]
+    fn parse_condition() {
+        assert_eq!(super::parse_condition("ssh"), Ok(Condition::Ssh));
+        assert_eq!(super::parse_condition("port:8080"), Ok(Condition::Port(8080)));
+        assert!(super::parse_condition("port:").is_err());
+        assert!(super::parse_condition("tcp").is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::str;
+use std::thread;
+
+use clap::Args;
+use tracing::error;
+use tracing::info;
+
+use crate::command::run;
+use crate::command::run::Overrides;
+use crate::command::stats;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+use crate::util::json;
+use crate::vm;
+use crate::vm::runner;
+
+// browser sends custom header only for same origin request, so other sites can't start or stop vm
+const ACTION_HEADER: &str = "x-vz-action";
+
+const PAGE: &str = r#"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>vz</title>
+<style>
+body { font-family: -apple-system, sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { padding: 4px 12px; text-align: left; border-bottom: 1px solid #ddd; }
+</style>
+</head>
+<body>
+<h1>vz</h1>
+<table>
+<thead><tr><th>name</th><th>os</th><th>status</th><th>ip</th><th>cpu%</th><th>rss</th><th>uptime</th><th></th></tr></thead>
+<tbody id="vms"></tbody>
+</table>
+<script>
+const gb = bytes => (bytes / 1024 / 1024 / 1024).toFixed(2) + "G";
+async function action(name, action) {
+  await fetch(`/api/vms/${encodeURIComponent(name)}/${action}`, {method: "POST", headers: {"X-Vz-Action": "1"}});
+  refresh();
+}
+async function refresh() {
+  const snapshot = await (await fetch("/api/vms")).json();
+  const rows = document.getElementById("vms");
+  rows.replaceChildren(...snapshot.vms.map(vm => {
+    const row = document.createElement("tr");
+    const process = vm.process || {};
+    for (const value of [vm.name, vm.os, vm.status, vm.ip || "-", process.cpu_percent ?? "-", process.rss ? gb(process.rss) : "-", process.uptime ? process.uptime + "s" : "-"]) {
+      const cell = document.createElement("td");
+      cell.textContent = value;
+      row.appendChild(cell);
+    }
+    const button = document.createElement("button");
+    const running = vm.status !== "stopped";
+    button.textContent = running ? "stop" : "start";
+    button.onclick = () => action(vm.name, running ? "stop" : "start");
+    const cell = document.createElement("td");
+    cell.appendChild(button);
+    row.appendChild(cell);
+    return row;
+  }));
+}
+refresh();
+setInterval(refresh, 2000);
+</script>
+</body>
+</html>
+"#;
+
+#[derive(Args)]
+pub struct Web {
+    #[arg(
+        long,
+        help = "listen address, must be loopback as web ui has no authentication, share it by ssh port forwarding",
+        default_value = "127.0.0.1:8040"
+    )]
+    listen: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum Route {
+    Page,
+    Vms,
+    Start(String),
+    Stop(String),
+    NotFound,
+}
+
+impl Web {
+    pub fn execute(&self) -> Result<(), Exception> {
+        validate_listen(&self.listen)?;
+        let listener = TcpListener::bind(&self.listen)?;
+        info!("web ui started, url=http://{}", self.listen);
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let listen = self.listen.clone();
+            thread::spawn(move || {
+                if let Err(err) = handle(stream, &listen) {
+                    error!("failed to handle request, error={err}");
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+fn handle(mut stream: TcpStream, listen: &str) -> Result<(), Exception> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut action_header = false;
+    let mut host = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            action_header |= name.trim().eq_ignore_ascii_case(ACTION_HEADER);
+            if name.trim().eq_ignore_ascii_case("host") {
+                host = Some(value.trim().to_string());
+            }
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    let route = route(parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+
+    let (status, content_type, body) = match route {
+        _ if !host.as_deref().is_some_and(|host| allowed_host(host, listen)) => ("403 Forbidden", "text/plain", "invalid host header".to_string()),
+        Route::Page => ("200 OK", "text/html", PAGE.to_string()),
+        Route::Vms => match stats::snapshot().and_then(|snapshot| json::to_json(&snapshot)) {
+            Ok(body) => ("200 OK", "application/json", body),
+            Err(err) => ("500 Internal Server Error", "text/plain", err.to_string()),
+        },
+        Route::Start(_) | Route::Stop(_) if !action_header => ("403 Forbidden", "text/plain", format!("missing {ACTION_HEADER} header")),
+        Route::Start(name) => match start(&name) {
+            Ok(_) => ("200 OK", "text/plain", "started".to_string()),
+            Err(err) => ("400 Bad Request", "text/plain", err.to_string()),
+        },
+        Route::Stop(name) => match stop(&name) {
+            Ok(_) => ("200 OK", "text/plain", "stopping".to_string()),
+            Err(err) => ("400 Bad Request", "text/plain", err.to_string()),
+        },
+        Route::NotFound => ("404 Not Found", "text/plain", "not found".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(())
+}
+
+// anyone reaching listen address can start and stop vms, e.g. ssh -L 8040:127.0.0.1:8040 <host> to share with other hosts
+fn validate_listen(listen: &str) -> Result<(), Exception> {
+    let addresses: Vec<_> = listen
+        .to_socket_addrs()
+        .map_err(|err| Exception::ValidationError(format!("invalid listen address, listen={listen}, error={err}")))?
+        .collect();
+    if addresses.is_empty() || addresses.iter().any(|address| !address.ip().is_loopback()) {
+        return Err(Exception::ValidationError(format!(
+            "web ui has no authentication and only listens on loopback, forward port by ssh to share it, listen={listen}"
+        )));
+    }
+    Ok(())
+}
+
+// dns rebinding resolves name of other site to listen address, browser sends that name as host, so only ip, localhost or host of listen address is allowed
+fn allowed_host(host: &str, listen: &str) -> bool {
+    let name = host_name(host);
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok() || name.eq_ignore_ascii_case(host_name(listen))
+}
+
+// without port, e.g. [::1]:8040 is ::1
+fn host_name(host: &str) -> &str {
+    match host.strip_prefix('[') {
+        Some(host) => host.split_once(']').map_or(host, |(name, _)| name),
+        None => host.split_once(':').map_or(host, |(name, _)| name),
+    }
+}
+
+fn route(method: &str, path: &str) -> Route {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("GET", [""]) => Route::Page,
+        ("GET", ["api", "vms"]) => Route::Vms,
+        ("POST", ["api", "vms", name, "start"]) => Route::Start(decode(name)),
+        ("POST", ["api", "vms", name, "stop"]) => Route::Stop(decode(name)),
+        _ => Route::NotFound,
+    }
+}
+
+// percent decoding of path segment
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = vec![];
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| u8::from_str_radix(str::from_utf8(hex).ok()?, 16).ok());
+        match hex {
+            Some(byte) if bytes[index] == b'%' => {
+                decoded.push(byte);
+                index += 3;
+            }
+            _ => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn start(name: &str) -> Result<(), Exception> {
+    let dir = existing_vm(name)?;
+    if dir.pid().is_some() {
+        return Err(Exception::ValidationError(format!("vm is already running, name={name}")));
+    }
+    run::run_in_background(name, &Overrides::default())
+}
+
+fn stop(name: &str) -> Result<(), Exception> {
+    let dir = existing_vm(name)?;
+    if dir.pid().is_none() {
+        return Err(Exception::ValidationError(format!("vm not running, name={name}")));
+    }
+    // same as vz stop without waiting, guest is force stopped by runner after timeout
+    runner::request_stop(&dir, false, vm::STOP_TIMEOUT);
+    Ok(())
+}
+
+fn existing_vm(name: &str) -> Result<VmDir, Exception> {
+    let dir = vm_dir::vm_dir(name);
+    if name.contains('/') || name.starts_with('.') || !dir.initialized() {
+        return Err(Exception::ValidationError(format!("vm not initialized, name={name}")));
+    }
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Route;
+
+    #[test]
+    fn route() {
+        assert_eq!(Route::Page, super::route("GET", "/"));
+        assert_eq!(Route::Vms, super::route("GET", "/api/vms"));
+        assert_eq!(Route::Start("my vm".to_string()), super::route("POST", "/api/vms/my%20vm/start"));
+        assert_eq!(Route::Stop("debian".to_string()), super::route("POST", "/api/vms/debian/stop"));
+        assert_eq!(Route::NotFound, super::route("GET", "/api/vms/debian/stop"));
+    }
+
+    #[test]
+    fn validate_listen() {
+        assert!(super::validate_listen("127.0.0.1:8040").is_ok());
+        assert!(super::validate_listen("[::1]:8040").is_ok());
+        assert!(super::validate_listen("0.0.0.0:8040").is_err());
+        assert!(super::validate_listen("192.168.1.10:8040").is_err());
+    }
+
+    #[test]
+    fn allowed_host() {
+        assert!(super::allowed_host("127.0.0.1:8040", "127.0.0.1:8040"));
+        assert!(super::allowed_host("localhost:8040", "127.0.0.1:8040"));
+        assert!(super::allowed_host("[::1]:8040", "[::1]:8040"));
+        assert!(super::allowed_host("192.168.1.10:8040", "0.0.0.0:8040"));
+        assert!(super::allowed_host("mac.local:8040", "mac.local:8040"));
+        assert!(!super::allowed_host("attacker.example:8040", "127.0.0.1:8040"));
+        assert!(!super::allowed_host("localhost.attacker.example", "0.0.0.0:8040"));
+    }
+}
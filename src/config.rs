@@ -1,2 +1,8 @@
+pub mod cloud_init;
+pub mod image_cache;
+pub mod ipsw_cache;
+pub mod profile;
+pub mod settings;
+pub mod vm_archive;
 pub mod vm_config;
 pub mod vm_dir;
@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::fs;
+use std::process::Command;
+
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::config::vm_config::VmConfig;
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+use crate::util::json;
+
+pub const EXIT_CODE_PREFIX: &str = "vz-exit-code=";
+const SERIAL_CONSOLE: &str = "/dev/hvc0";
+const GUEST_ENV_PATH: &str = "/etc/vz/env";
+
+pub const GROW_PARTITION: &str = r#"growpart:
+  mode: auto
+  devices: ["/"]
+resize_rootfs: true
+"#;
+
+// run command on boot, write output and exit code to serial console, then power off
+pub fn run_command(command: &[String]) -> Result<String, Exception> {
+    let command: Vec<String> = command.iter().map(|arg| format!("'{}'", arg.replace('\'', r"'\''"))).collect();
+    let script = format!(
+        "{} > {SERIAL_CONSOLE} 2>&1; echo \"{EXIT_CODE_PREFIX}$?\" > {SERIAL_CONSOLE}; poweroff",
+        command.join(" ")
+    );
+    Ok(format!("runcmd:\n  - [sh, -c, {}]\n", json::to_json(&script)?))
+}
+
+// KEY='value' lines work as sh script and systemd EnvironmentFile, login shells export them by profile.d
+pub fn guest_env(env: &HashMap<String, String>) -> Result<String, Exception> {
+    if env.is_empty() {
+        return Ok(String::new());
+    }
+    let mut keys: Vec<&String> = env.keys().collect();
+    keys.sort();
+    let mut content = String::new();
+    for key in keys {
+        let valid = key.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(Exception::ValidationError(format!("invalid guest env name, name={key}")));
+        }
+        content.push_str(&format!("{key}='{}'\n", env[key].replace('\'', r"'\''")));
+    }
+    Ok(format!(
+        "write_files:\n  - path: {GUEST_ENV_PATH}\n    content: {}\n  - path: /etc/profile.d/vz-env.sh\n    content: {}\n",
+        json::to_json(&content)?,
+        json::to_json(&format!("set -a; . {GUEST_ENV_PATH}; set +a\n"))?
+    ))
+}
+
+// user data of seed.iso in vm dir, written next to it as seed.json
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct Seed {
+    // e.g. grow partition of resize, kept when guest config changes, cloud-init modules of it are no-op once applied
+    pending: String,
+    guest_config: String,
+}
+
+// seed is only created again if guest config changed or pending user data is added, as each seed gets new instance id
+pub fn update_seed(dir: &VmDir, config: &VmConfig, pending: Option<&str>) -> Result<(), Exception> {
+    let current: Option<Seed> = fs::read_to_string(&dir.seed_config_path)
+        .ok()
+        .and_then(|json| json::from_json(&json).ok())
+        .filter(|_| dir.seed_path.exists());
+    let Some(seed) = next_seed(current, pending, guest_config(config)?) else {
+        return Ok(());
+    };
+    create_seed(dir, &format!("{}{}", seed.pending, seed.guest_config))?;
+    fs::write(&dir.seed_config_path, json::to_json_pretty(&seed)?)?;
+    Ok(())
+}
+
+// none if current seed is up to date, seed without seed.json, e.g. created by older version, is treated as without pending
+fn next_seed(current: Option<Seed>, pending: Option<&str>, guest_config: String) -> Option<Seed> {
+    match (current, pending) {
+        (_, Some(pending)) => Some(Seed {
+            pending: pending.to_string(),
+            guest_config,
+        }),
+        (Some(current), None) if current.guest_config == guest_config => None,
+        (Some(current), None) => Some(Seed {
+            pending: current.pending,
+            guest_config,
+        }),
+        (None, None) if guest_config.is_empty() => None,
+        (None, None) => Some(Seed {
+            pending: String::new(),
+            guest_config,
+        }),
+    }
+}
+
+// user data applied on every seed of vm, e.g. seed of resize also keeps guest env
+pub fn guest_config(config: &VmConfig) -> Result<String, Exception> {
+    Ok(format!(
+        "{}{}",
+        guest_env(&config.guest_env)?,
+        locale(config.timezone.as_deref(), config.locale.as_deref(), config.keyboard.as_deref())?
+    ))
+}
+
+pub fn locale(timezone: Option<&str>, locale: Option<&str>, keyboard: Option<&str>) -> Result<String, Exception> {
+    let mut user_data = String::new();
+    if let Some(timezone) = timezone {
+        user_data.push_str(&format!("timezone: {}\n", json::to_json(&timezone)?));
+    }
+    if let Some(locale) = locale {
+        user_data.push_str(&format!("locale: {}\n", json::to_json(&locale)?));
+    }
+    if let Some(keyboard) = keyboard {
+        user_data.push_str(&format!("keyboard:\n  layout: {}\n", json::to_json(&keyboard)?));
+    }
+    Ok(user_data)
+}
+
+// create NoCloud seed iso, attached to linux vm on next run, requires cloud-init in guest
+pub fn create_seed(dir: &VmDir, user_data: &str) -> Result<(), Exception> {
+    let seed_dir = dir.dir.join("cidata");
+    fs::create_dir_all(&seed_dir)?;
+    // new instance id, to let cloud-init apply user data again
+    fs::write(
+        seed_dir.join("meta-data"),
+        format!("instance-id: {}\nlocal-hostname: {}\n", Uuid::new_v4(), dir.name()),
+    )?;
+    fs::write(seed_dir.join("user-data"), format!("#cloud-config\n{user_data}"))?;
+
+    if dir.seed_path.exists() {
+        fs::remove_file(&dir.seed_path)?;
+    }
+    info!("create cloud-init seed, path={}", dir.seed_path.to_string_lossy());
+    let status = Command::new("hdiutil")
+        .args(["makehybrid", "-quiet", "-iso", "-joliet", "-default-volume-name", "cidata", "-o"])
+        .arg(&dir.seed_path)
+        .arg(&seed_dir)
+        .status()?;
+    fs::remove_dir_all(&seed_dir)?;
+    if !status.success() {
+        return Err(Exception::ValidationError(format!("failed to create cloud-init seed, status={status}")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::config::cloud_init;
+
+    #[test]
+    fn run_command() {
+        let user_data = cloud_init::run_command(&["echo".to_string(), "it's".to_string()]).unwrap();
+        assert_eq!(
+            user_data,
+            r#"runcmd:
+  - [sh, -c, "'echo' 'it'\\''s' > /dev/hvc0 2>&1; echo \"vz-exit-code=$?\" > /dev/hvc0; poweroff"]
+"#
+        );
+    }
+
+    #[test]
+    fn guest_env() {
+        let env = HashMap::from([("ROLE".to_string(), "web".to_string()), ("NAME".to_string(), "it's".to_string())]);
+        assert_eq!(
+            cloud_init::guest_env(&env).unwrap(),
+            r#"write_files:
+  - path: /etc/vz/env
+    content: "NAME='it'\\''s'\nROLE='web'\n"
+  - path: /etc/profile.d/vz-env.sh
+    content: "set -a; . /etc/vz/env; set +a\n"
+"#
+        );
+        assert!(cloud_init::guest_env(&HashMap::from([("1A".to_string(), String::new())])).is_err());
+        assert_eq!("", cloud_init::guest_env(&HashMap::new()).unwrap());
+    }
+
+    #[test]
+    fn next_seed() {
+        let seed = |pending: &str, guest_config: &str| cloud_init::Seed {
+            pending: pending.to_string(),
+            guest_config: guest_config.to_string(),
+        };
+        let grow = cloud_init::GROW_PARTITION;
+        assert_eq!(
+            None,
+            cloud_init::next_seed(Some(seed(grow, "locale: C\n")), None, "locale: C\n".to_string())
+        );
+        assert_eq!(
+            Some(seed(grow, "timezone: UTC\n")),
+            cloud_init::next_seed(Some(seed(grow, "locale: C\n")), None, "timezone: UTC\n".to_string())
+        );
+        assert_eq!(
+            Some(seed(grow, "locale: C\n")),
+            cloud_init::next_seed(Some(seed("", "locale: C\n")), Some(grow), "locale: C\n".to_string())
+        );
+        assert_eq!(None, cloud_init::next_seed(None, None, String::new()));
+        assert_eq!(
+            Some(seed("", "locale: C\n")),
+            cloud_init::next_seed(None, None, "locale: C\n".to_string())
+        );
+    }
+
+    #[test]
+    fn locale() {
+        assert_eq!(
+            cloud_init::locale(Some("Europe/Berlin"), Some("de_DE.UTF-8"), Some("de")).unwrap(),
+            r#"timezone: "Europe/Berlin"
+locale: "de_DE.UTF-8"
+keyboard:
+  layout: "de"
+"#
+        );
+        assert_eq!("", cloud_init::locale(None, None, None).unwrap());
+    }
+}
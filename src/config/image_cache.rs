@@ -0,0 +1,111 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::info;
+
+use crate::util::digest;
+use crate::util::disk_image;
+use crate::util::exception::Exception;
+use crate::util::json;
+use crate::util::path::PathExtension;
+use crate::util::terminal;
+
+// metadata is stored next to image, e.g. ubuntu-24.04.img.json
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Metadata {
+    pub url: String,
+    pub sha256: String,
+}
+
+pub fn cache_dir() -> PathBuf {
+    PathBuf::from("~/Library/Caches/vz/images").to_absolute_path()
+}
+
+fn image_path(name: &str) -> PathBuf {
+    cache_dir().join(format!("{name}.img"))
+}
+
+// download image into cache as raw sparse disk, qcow2, vmdk and vhdx are converted, e.g. ubuntu cloud image
+pub fn pull(name: &str, url: &str, sha256: Option<&str>) -> Result<PathBuf, Exception> {
+    validate_name(name)?;
+    let path = image_path(name);
+    if path.exists() {
+        info!("image found in cache, name={name}, path={}", path.to_string_lossy());
+        return Ok(path);
+    }
+
+    let dir = cache_dir();
+    fs::create_dir_all(&dir)?;
+    let temp_path = dir.join(format!("{name}.download"));
+    info!("download image, url={url}, path={}", temp_path.to_string_lossy());
+    let status = Command::new("curl")
+        .args(["--fail", "--location", terminal::curl_progress_arg(), "--continue-at", "-", "--output"])
+        .arg(&temp_path)
+        .arg(url)
+        .status()?;
+    if !status.success() {
+        return Err(Exception::ValidationError(format!(
+            "failed to download image, url={url}, status={status}"
+        )));
+    }
+
+    let actual = digest::file_sha256_hex(&temp_path)?;
+    if let Some(expected) = sha256.filter(|expected| !expected.eq_ignore_ascii_case(&actual)) {
+        fs::remove_file(&temp_path)?;
+        return Err(Exception::ValidationError(format!(
+            "image checksum mismatch, downloaded file is removed, url={url}, expected={expected}, actual={actual}"
+        )));
+    }
+
+    let converted_path = dir.join(format!("{name}.img.tmp"));
+    disk_image::convert_to_raw(&temp_path, &converted_path)?;
+    fs::rename(&converted_path, &path)?;
+    fs::remove_file(&temp_path)?;
+    let metadata = Metadata {
+        url: url.to_string(),
+        sha256: actual,
+    };
+    fs::write(metadata_path(name), json::to_json_pretty(&metadata)?)?;
+    info!("image pulled, name={name}, sha256={}, path={}", metadata.sha256, path.to_string_lossy());
+    Ok(path)
+}
+
+pub fn resolve(name: &str) -> Result<PathBuf, Exception> {
+    validate_name(name)?;
+    let path = image_path(name);
+    if !path.exists() {
+        return Err(Exception::ValidationError(format!(
+            "image not found in cache, pull it by vz pull {name} <url>, name={name}"
+        )));
+    }
+    info!("image found in cache, name={name}, path={}", path.to_string_lossy());
+    Ok(path)
+}
+
+fn metadata_path(name: &str) -> PathBuf {
+    cache_dir().join(format!("{name}.img.json"))
+}
+
+fn validate_name(name: &str) -> Result<(), Exception> {
+    let valid = !name.is_empty() && !name.starts_with('.') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if !valid {
+        return Err(Exception::ValidationError(format!(
+            "invalid image name, it must be letters, digits, -, _ or ., name={name}"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn validate_name() {
+        assert!(super::validate_name("ubuntu-24.04").is_ok());
+        assert!(super::validate_name("debian_12").is_ok());
+        assert!(super::validate_name("../ubuntu").is_err());
+        assert!(super::validate_name("").is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+use objc2_foundation::NSProcessInfo;
+
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::info;
+use tracing::warn;
+
+use crate::util::digest;
+use crate::util::exception::Exception;
+use crate::util::json;
+use crate::util::path::PathExtension;
+use crate::util::terminal;
+use crate::vm::mac_os;
+
+// restore images for virtual mac published by apple, virtualization framework only provides latest supported one
+const CATALOG_URL: &str = "https://mesu.apple.com/assets/macos/com_apple_macOSIPSW/com_apple_macOSIPSW.xml";
+
+// metadata is stored next to ipsw, e.g. UniversalMac_14.5_23F79_Restore.ipsw.json
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Metadata {
+    pub version: String,
+    pub build: String,
+    // only known for ipsw downloaded by vz, ipsw copied into cache is not hashed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+pub struct Entry {
+    pub path: PathBuf,
+    pub metadata: Metadata,
+}
+
+pub fn cache_dir() -> PathBuf {
+    PathBuf::from("~/Library/Caches/vz/ipsw").to_absolute_path()
+}
+
+// download ipsw into cache dir, skip if already downloaded, partial download will be resumed by http range request
+pub fn download(url: &str) -> Result<PathBuf, Exception> {
+    let file_name = url
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| Exception::ValidationError(format!("invalid ipsw url, url={url}")))?;
+    let dir = cache_dir();
+    let path = dir.join(file_name);
+    if path.exists() {
+        info!("ipsw found in cache, path={}", path.to_string_lossy());
+        return Ok(path);
+    }
+
+    fs::create_dir_all(&dir)?;
+    let temp_path = dir.join(format!("{file_name}.download"));
+    info!("download ipsw, url={url}, path={}", temp_path.to_string_lossy());
+    let status = Command::new("curl")
+        .args(["--fail", "--location", terminal::curl_progress_arg(), "--continue-at", "-", "--output"])
+        .arg(&temp_path)
+        .arg(url)
+        .status()?;
+    if !status.success() {
+        return Err(Exception::ValidationError(format!("failed to download ipsw, url={url}, status={status}")));
+    }
+    fs::rename(&temp_path, &path)?;
+    info!("ipsw downloaded, path={}", path.to_string_lossy());
+    // loading restore image verifies downloaded file, corrupted file must not stay in cache
+    let mut metadata = match create_metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            fs::remove_file(&path)?;
+            return Err(Exception::ValidationError(format!(
+                "downloaded ipsw is invalid, removed from cache, url={url}, error={err}"
+            )));
+        }
+    };
+    metadata.sha256 = Some(digest::file_sha256_hex(&path)?);
+    fs::write(metadata_path(&path), json::to_json_pretty(&metadata)?)?;
+    Ok(path)
+}
+
+// ipsw can be referred by file path, or by version or build of cached ipsw, e.g. 14.5 or 23F79
+pub fn resolve(ipsw: &Path) -> Result<PathBuf, Exception> {
+    if ipsw.exists() {
+        return Ok(ipsw.to_absolute_path());
+    }
+    let reference = ipsw.to_string_lossy();
+    if !reference.contains('/') {
+        if let Some(entry) = entries()?.into_iter().find(|entry| matches(&entry.metadata, &reference)) {
+            info!(
+                "ipsw found in cache, version={}, path={}",
+                entry.metadata.version,
+                entry.path.to_string_lossy()
+            );
+            return Ok(entry.path);
+        }
+    }
+    Err(Exception::ValidationError(format!(
+        "ipsw does not exist, use file path, or version or build of cached ipsw, ipsw={reference}"
+    )))
+}
+
+// find cached ipsw of macOS version, or download it by apple catalog
+pub fn version(version: &str) -> Result<PathBuf, Exception> {
+    if let Some(entry) = entries()?.into_iter().find(|entry| entry.metadata.version == version) {
+        info!("ipsw found in cache, version={version}, path={}", entry.path.to_string_lossy());
+        return Ok(entry.path);
+    }
+    // host can only run guest with same or older major version
+    let host_version = NSProcessInfo::processInfo().operatingSystemVersion();
+    let major: isize = version.split('.').next().and_then(|major| major.parse().ok()).unwrap_or_default();
+    if major == 0 || major > host_version.majorVersion {
+        return Err(Exception::ValidationError(format!(
+            "macOS version is not supported by current host, version={version}, host_version={}",
+            host_version.majorVersion
+        )));
+    }
+
+    info!("fetch restore image catalog, url={CATALOG_URL}");
+    let output = Command::new("curl").args(["--fail", "--silent", "--location", CATALOG_URL]).output()?;
+    if !output.status.success() {
+        return Err(Exception::ValidationError(format!(
+            "failed to fetch restore image catalog, url={CATALOG_URL}, status={}",
+            output.status
+        )));
+    }
+    let url = find_url(&String::from_utf8_lossy(&output.stdout), version)
+        .ok_or_else(|| Exception::ValidationError(format!("macOS version not found in restore image catalog, version={version}")))?;
+    let path = download(&url)?;
+    let metadata = metadata(&path)?;
+    if metadata.version != version {
+        return Err(Exception::ValidationError(format!(
+            "ipsw does not match version, version={version}, ipsw_version={}, path={}",
+            metadata.version,
+            path.to_string_lossy()
+        )));
+    }
+    Ok(path)
+}
+
+// cached ipsw sorted by file name, metadata is created for ipsw copied into cache or downloaded by previous version, invalid ipsw is skipped
+pub fn entries() -> Result<Vec<Entry>, Exception> {
+    let dir = cache_dir();
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|extension| extension == "ipsw") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    let mut entries = vec![];
+    for path in paths {
+        match metadata(&path) {
+            Ok(metadata) => entries.push(Entry { path, metadata }),
+            Err(err) => warn!("skip invalid ipsw in cache, path={}, error={err}", path.to_string_lossy()),
+        }
+    }
+    Ok(entries)
+}
+
+pub fn remove(reference: &str) -> Result<(), Exception> {
+    let entries = entries()?;
+    let Some(entry) = entries
+        .iter()
+        .find(|entry| matches(&entry.metadata, reference) || entry.path.file_name().is_some_and(|name| name.to_string_lossy() == reference))
+    else {
+        return Err(Exception::ValidationError(format!("ipsw not found in cache, ipsw={reference}")));
+    };
+    info!("remove ipsw, version={}, path={}", entry.metadata.version, entry.path.to_string_lossy());
+    fs::remove_file(&entry.path)?;
+    let metadata_path = metadata_path(&entry.path);
+    if metadata_path.exists() {
+        fs::remove_file(metadata_path)?;
+    }
+    Ok(())
+}
+
+pub fn metadata_path(ipsw: &Path) -> PathBuf {
+    let mut name = ipsw.file_name().unwrap().to_os_string();
+    name.push(".json");
+    ipsw.with_file_name(name)
+}
+
+fn metadata(ipsw: &Path) -> Result<Metadata, Exception> {
+    let path = metadata_path(ipsw);
+    if path.exists() {
+        return json::from_json(&fs::read_to_string(path)?);
+    }
+    let metadata = create_metadata(ipsw)?;
+    fs::write(path, json::to_json_pretty(&metadata)?)?;
+    Ok(metadata)
+}
+
+// loading restore image only reads its manifest, hashing ipsw of many gb is left to download
+fn create_metadata(ipsw: &Path) -> Result<Metadata, Exception> {
+    info!("create ipsw metadata, path={}", ipsw.to_string_lossy());
+    let image = mac_os::load_restore_image(ipsw)?;
+    let (version, build) = mac_os::restore_image_version(&image);
+    Ok(Metadata {
+        version,
+        build,
+        sha256: None,
+    })
+}
+
+fn matches(metadata: &Metadata, reference: &str) -> bool {
+    metadata.version == reference || metadata.build == reference
+}
+
+// catalog is plist, ipsw file name contains version, e.g. <string>https://.../UniversalMac_14.6.1_23G93_Restore.ipsw</string>
+fn find_url(catalog: &str, version: &str) -> Option<String> {
+    let prefix = format!("UniversalMac_{version}_");
+    catalog
+        .split("<string>")
+        .filter_map(|value| value.split_once("</string>").map(|(value, _)| value.trim()))
+        .filter(|value| value.starts_with("https://") && value.ends_with(".ipsw"))
+        .find(|url| url.rsplit('/').next().is_some_and(|name| name.starts_with(&prefix)))
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn find_url() {
+        let catalog = r#"<dict>
+            <key>FirmwareURL</key>
+            <string>https://updates.cdn-apple.com/2024SummerFCS/fullrestores/062-52859/UniversalMac_14.6_23G80_Restore.ipsw</string>
+            <key>FirmwareURL</key>
+            <string>https://updates.cdn-apple.com/2024SummerFCS/fullrestores/062-59061/UniversalMac_14.6.1_23G93_Restore.ipsw</string>
+            <key>ProductVersion</key>
+            <string>14.6.1</string>
+        </dict>"#;
+        assert_eq!(
+            super::find_url(catalog, "14.6").as_deref(),
+            Some("https://updates.cdn-apple.com/2024SummerFCS/fullrestores/062-52859/UniversalMac_14.6_23G80_Restore.ipsw")
+        );
+        assert_eq!(
+            super::find_url(catalog, "14.6.1").as_deref(),
+            Some("https://updates.cdn-apple.com/2024SummerFCS/fullrestores/062-59061/UniversalMac_14.6.1_23G93_Restore.ipsw")
+        );
+        assert_eq!(super::find_url(catalog, "14.5"), None);
+    }
+}
@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::PathBuf;
+
+use serde_json::Map;
+use serde_json::Value;
+
+use crate::config::vm_dir;
+use crate::util::exception::Exception;
+use crate::util::json;
+
+// profile may extend another profile, limit depth to catch cycle
+const MAX_DEPTH: usize = 8;
+
+type Fields = Map<String, Value>;
+
+// profiles are partial vm configs in ~/.vm/profiles/<name>.json, e.g. {"cpu": 4, "memory": 8589934592, "ssh_user": "dev"}
+pub fn profile_path(name: &str) -> PathBuf {
+    vm_dir::home_dir().join("profiles").join(format!("{name}.json"))
+}
+
+pub fn merge(config: Fields) -> Result<Fields, Exception> {
+    let Some(name) = extends(&config) else {
+        return Ok(config);
+    };
+    Ok(overlay(load(&name, 0)?, config))
+}
+
+// fields same as profile are left out, so later changes of profile apply to vm
+pub fn remove_inherited(config: &mut Fields) -> Result<(), Exception> {
+    let Some(name) = extends(config) else {
+        return Ok(());
+    };
+    let profile = load(&name, 0)?;
+    config.retain(|key, value| profile.get(key) != Some(value));
+    Ok(())
+}
+
+fn extends(config: &Fields) -> Option<String> {
+    config.get("extends").and_then(Value::as_str).map(|name| name.to_string())
+}
+
+fn load(name: &str, depth: usize) -> Result<Fields, Exception> {
+    if depth >= MAX_DEPTH {
+        return Err(Exception::ValidationError(format!(
+            "profile extends too deep, maybe cycle, profile={name}"
+        )));
+    }
+    let path = profile_path(name);
+    if !path.exists() {
+        return Err(Exception::ValidationError(format!(
+            "profile not found, profile={name}, path={}",
+            path.to_string_lossy()
+        )));
+    }
+    let profile: Fields = json::from_json(&fs::read_to_string(&path)?)?;
+    match extends(&profile) {
+        Some(parent) => Ok(overlay(load(&parent, depth + 1)?, profile)),
+        None => Ok(profile),
+    }
+}
+
+// top level fields override base, e.g. sharing of vm replaces sharing of profile, extends of base is replaced too
+fn overlay(mut base: Fields, fields: Fields) -> Fields {
+    base.extend(fields);
+    base
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn fields(value: Value) -> Fields {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn overlay() {
+        let profile = fields(json!({"cpu": 4, "memory": 8, "sharing": {"code": "~/code"}}));
+        let config = fields(json!({"extends": "dev", "cpu": 2, "sharing": {}}));
+        assert_eq!(
+            fields(json!({"extends": "dev", "cpu": 2, "memory": 8, "sharing": {}})),
+            super::overlay(profile, config)
+        );
+    }
+}
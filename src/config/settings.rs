@@ -0,0 +1,183 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::io;
+
+use serde::Deserialize;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::config::vm_config::VmConfig;
+use crate::config::vm_dir;
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+use crate::util::json;
+use crate::util::terminal;
+
+// global settings in ~/.vm/settings.json, e.g. {"maxStorage": 500}
+#[derive(Deserialize, Debug, Default)]
+pub struct Settings {
+    // max total disk size of all vms in gb
+    #[serde(rename = "maxStorage")]
+    pub max_storage: Option<u64>,
+    #[serde(rename = "maxRunningVms")]
+    pub max_running_vms: Option<usize>,
+    // max total cpu and memory in gb of running vms
+    #[serde(rename = "maxRunningCpu")]
+    pub max_running_cpu: Option<usize>,
+    #[serde(rename = "maxRunningMemory")]
+    pub max_running_memory: Option<u64>,
+    // runner updates /etc/hosts when vm gets ip and when it stops
+    #[serde(rename = "syncHosts")]
+    pub sync_hosts: Option<bool>,
+}
+
+// effective resources of running vm, config may differ by overrides, e.g. vz run --cpu
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Resources {
+    cpu: usize,
+    memory: u64,
+}
+
+pub fn load() -> Result<Settings, Exception> {
+    let path = vm_dir::home_dir().join("settings.json");
+    if !path.exists() {
+        return Ok(Settings::default());
+    }
+    json::from_json(&fs::read_to_string(path)?)
+}
+
+// disk of vm counts as its provisioned size, sparse disk can grow up to it
+pub fn check_storage_quota(requested: u64) -> Result<(), Exception> {
+    let Some(max_storage) = load()?.max_storage else {
+        return Ok(());
+    };
+    let mut usages = vec![];
+    let home_dir = vm_dir::home_dir();
+    if home_dir.exists() {
+        for entry in fs::read_dir(home_dir)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            // temp dir of create or import in progress
+            if Uuid::parse_str(&name).is_ok() {
+                continue;
+            }
+            let dir = vm_dir::vm_dir(&name);
+            if dir.initialized() {
+                usages.push((name, dir.disk_path.metadata()?.len()));
+            }
+        }
+    }
+    let used: u64 = usages.iter().map(|(_, size)| size).sum();
+    if used + requested > max_storage * 1_000_000_000 {
+        usages.sort_by_key(|(_, size)| Reverse(*size));
+        let vms: Vec<String> = usages
+            .iter()
+            .map(|(name, size)| format!("{name}:{}", terminal::format_size(*size)))
+            .collect();
+        return Err(Exception::ValidationError(format!(
+            "storage quota exceeded, max={max_storage}G, used={}, requested={}, vms={}",
+            terminal::format_size(used),
+            terminal::format_size(requested),
+            vms.join(",")
+        )));
+    }
+    Ok(())
+}
+
+// runner records them once it holds vm lock, so they are counted by running limits
+pub fn record_resources(dir: &VmDir, config: &VmConfig) -> Result<(), Exception> {
+    let resources = Resources {
+        cpu: config.cpu,
+        memory: config.memory,
+    };
+    fs::write(&dir.resources_path, json::to_json(&resources)?)?;
+    Ok(())
+}
+
+// vm started by older version has no recorded resources
+fn running_resources(dir: &VmDir) -> Result<Resources, Exception> {
+    match fs::read_to_string(&dir.resources_path) {
+        Ok(content) => json::from_json(&content),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let config = dir.load_config()?;
+            Ok(Resources {
+                cpu: config.cpu,
+                memory: config.memory,
+            })
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+// checked before vm starts, not atomic with start, vm started at same time by other runner may not be counted
+pub fn check_running_limits(name: &str, config: &VmConfig) -> Result<(), Exception> {
+    let settings = load()?;
+    if settings.max_running_vms.is_none() && settings.max_running_cpu.is_none() && settings.max_running_memory.is_none() {
+        return Ok(());
+    }
+    let mut running = vec![];
+    let home_dir = vm_dir::home_dir();
+    if home_dir.exists() {
+        for entry in fs::read_dir(home_dir)? {
+            let dir = vm_dir::vm_dir(&entry?.file_name().to_string_lossy());
+            if dir.initialized() && dir.name() != name && dir.pid().is_some() {
+                running.push(dir);
+            }
+        }
+    }
+    // throwaway clones of vz run --rm
+    running.extend(vm_dir::ephemeral_vm_dirs()?.into_iter().filter(|dir| dir.pid().is_some()));
+
+    let mut vms = vec![];
+    let (mut cpu, mut memory) = (config.cpu, config.memory);
+    for dir in running {
+        let resources = running_resources(&dir)?;
+        cpu += resources.cpu;
+        memory += resources.memory;
+        vms.push(dir.name());
+    }
+    let count = vms.len() + 1;
+    let vms = vms.join(",");
+    if let Some(max) = settings.max_running_vms.filter(|max| count > *max) {
+        return Err(Exception::ValidationError(format!("max running vms exceeded, max={max}, running={vms}")));
+    }
+    if let Some(max) = settings.max_running_cpu.filter(|max| cpu > *max) {
+        return Err(Exception::ValidationError(format!(
+            "max running cpu exceeded, max={max}, requested={cpu}, running={vms}"
+        )));
+    }
+    if let Some(max) = settings.max_running_memory.filter(|max| memory > max * 1024 * 1024 * 1024) {
+        return Err(Exception::ValidationError(format!(
+            "max running memory exceeded, max={max}G, requested={:.2}G, running={vms}",
+            memory as f32 / (1024.0 * 1024.0 * 1024.0)
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use uuid::Uuid;
+
+    use super::Resources;
+    use crate::config::vm_dir::VmDir;
+
+    #[test]
+    fn running_resources() {
+        let dir = VmDir::new(env::temp_dir().join(format!("vz-test-{}", Uuid::new_v4())));
+        fs::create_dir_all(&dir.dir).unwrap();
+        // started with vz run --cpu=4 --memory=8G
+        fs::write(&dir.resources_path, r#"{"cpu":4,"memory":8589934592}"#).unwrap();
+        let resources = super::running_resources(&dir);
+        fs::remove_dir_all(&dir.dir).unwrap();
+        assert_eq!(
+            resources.unwrap(),
+            Resources {
+                cpu: 4,
+                memory: 8 * 1024 * 1024 * 1024
+            }
+        );
+    }
+}
@@ -0,0 +1,351 @@
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Child;
+use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Stdio;
+use std::thread;
+
+use tracing::info;
+
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+use crate::util::json;
+
+mod extents;
+
+const AGE_HEADER: &[u8] = b"age-encryption.org/";
+const ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Compression {
+    #[cfg_attr(feature = "cli", clap(name = "zstd"))]
+    Zstd,
+    #[cfg_attr(feature = "cli", clap(name = "gzip"))]
+    Gzip,
+    #[cfg_attr(feature = "cli", clap(name = "none"))]
+    None,
+}
+
+pub struct Options {
+    pub compression: Compression,
+    pub level: Option<u32>,
+    pub threads: u32,
+    pub encrypt: bool,
+    // incremental export only contains disk extents changed since base
+    pub base: Option<PathBuf>,
+}
+
+// archive is tar of vm dir files, then compressed, then optionally encrypted by age with passphrase, age prompts passphrase from tty
+// extent manifest of incremental export is stored in archive and next to it, so next incremental export doesn't need to read previous archive
+// full export doesn't hash disk, its manifest is calculated from archive once it's used as base
+pub fn export(dir: &VmDir, file: &Path, options: &Options) -> Result<(), Exception> {
+    info!(
+        "export vm, name={}, file={}, compression={:?}, encrypt={}",
+        dir.name(),
+        file.to_string_lossy(),
+        options.compression,
+        options.encrypt
+    );
+    let manifest_path = dir.dir.join(extents::MANIFEST_FILE);
+    let delta_path = dir.dir.join(extents::DELTA_FILE);
+    let mut manifest_json = None;
+    let mut disk_path = &dir.disk_path;
+    if let Some(base) = &options.base {
+        let base_manifest = base_manifest(base, &dir.dir.join("base"))?;
+        info!("calculate disk extents, disk={}", dir.disk_path.to_string_lossy());
+        let mut manifest = extents::manifest(&dir.disk_path)?;
+        let changed = manifest.changed(&base_manifest)?;
+        info!(
+            "export changed extents, base={}, changed={}, total={}",
+            base.to_string_lossy(),
+            changed.len(),
+            manifest.extents.len()
+        );
+        manifest.base = Some(extents::Base {
+            file: base.file_name().unwrap().to_string_lossy().to_string(),
+            digest: base_manifest.digest(),
+        });
+        let mut writer = BufWriter::new(File::create(&delta_path)?);
+        extents::write_delta(&mut File::open(&dir.disk_path)?, &manifest, &changed, &mut writer)?;
+        writer.flush()?;
+        disk_path = &delta_path;
+        let json = json::to_json(&manifest)?;
+        fs::write(&manifest_path, &json)?;
+        manifest_json = Some(json);
+    }
+
+    // added disks are always archived in full, only main disk has extent manifest
+    let disks: Vec<PathBuf> = dir.load_config()?.disks.iter().map(|name| dir.extra_disk_path(name)).collect();
+    let files: Vec<PathBuf> = [
+        &dir.config_path,
+        &dir.nvram_path,
+        disk_path,
+        &dir.kernel_path,
+        &dir.initrd_path,
+        &manifest_path,
+    ]
+    .into_iter()
+    .chain(&disks)
+    .filter(|path| path.exists())
+    .map(|path| PathBuf::from(path.file_name().unwrap()))
+    .collect();
+    let mut commands = vec![];
+    let mut tar = Command::new("tar");
+    // pax format keeps holes of sparse disk image, bsdtar finds them by SEEK_HOLE, so unused space is neither read nor stored
+    tar.args(["-c", "--format", "pax", "-f", "-", "-C"]).arg(&dir.dir).args(&files);
+    commands.push(tar);
+    if let Some(compress) = compress_command(options)? {
+        commands.push(compress);
+    }
+    if options.encrypt {
+        let mut age = Command::new("age");
+        age.arg("--passphrase");
+        commands.push(age);
+    }
+    let result = pipeline(&mut commands, None, Some(File::create_new(file)?));
+
+    for path in [&manifest_path, &delta_path] {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+    match (&result, manifest_json) {
+        (Ok(_), Some(manifest_json)) => fs::write(manifest_file(file), manifest_json)?,
+        // left by removed archive of same name, it doesn't describe this one
+        (Ok(_), None) if manifest_file(file).exists() => fs::remove_file(manifest_file(file))?,
+        (Ok(_), None) => {}
+        (Err(_), _) => fs::remove_file(file)?,
+    }
+    result
+}
+
+// manifest next to incremental archive, or calculated from disk of full archive, extracted into temp dir
+fn base_manifest(base: &Path, temp_dir: &Path) -> Result<extents::Manifest, Exception> {
+    let path = manifest_file(base);
+    if path.exists() {
+        return load_manifest(&path);
+    }
+    if !base.exists() {
+        return Err(Exception::ValidationError(format!(
+            "base archive not found, path={}",
+            base.to_string_lossy()
+        )));
+    }
+    fs::create_dir(temp_dir)?;
+    let result = extract_archive(base, temp_dir).and_then(|_| disk_manifest(temp_dir));
+    fs::remove_dir_all(temp_dir)?;
+    result
+}
+
+// full archive has no manifest
+fn disk_manifest(dir: &Path) -> Result<extents::Manifest, Exception> {
+    let path = dir.join(extents::MANIFEST_FILE);
+    if path.exists() {
+        return load_manifest(&path);
+    }
+    let disk_path = dir.join(extents::DISK_FILE);
+    info!("calculate disk extents, disk={}", disk_path.to_string_lossy());
+    extents::manifest(&disk_path)
+}
+
+pub fn extract(file: &Path, dir: &VmDir) -> Result<(), Exception> {
+    extract_archive(file, &dir.dir)?;
+    // manifest only describes disk at export time
+    let manifest_path = dir.dir.join(extents::MANIFEST_FILE);
+    if manifest_path.exists() {
+        fs::remove_file(manifest_path)?;
+    }
+    Ok(())
+}
+
+// base of incremental archive is extracted into sub dir, then its disk with delta applied replaces delta
+fn extract_archive(file: &Path, dir: &Path) -> Result<(), Exception> {
+    unpack(file, dir)?;
+    let delta_path = dir.join(extents::DELTA_FILE);
+    if !delta_path.exists() {
+        return Ok(());
+    }
+    let manifest = load_manifest(&dir.join(extents::MANIFEST_FILE))?;
+    let Some(base) = &manifest.base else {
+        return Err(Exception::ValidationError(format!(
+            "invalid vm archive, missing base, path={}",
+            file.to_string_lossy()
+        )));
+    };
+    let base_file = file.with_file_name(&base.file);
+    if !base_file.exists() {
+        return Err(Exception::ValidationError(format!(
+            "base archive not found, path={}",
+            base_file.to_string_lossy()
+        )));
+    }
+    let base_dir = dir.join("base");
+    fs::create_dir(&base_dir)?;
+    extract_archive(&base_file, &base_dir)?;
+    let base_manifest = disk_manifest(&base_dir)?;
+    if base_manifest.digest() != base.digest {
+        return Err(Exception::ValidationError(format!(
+            "base archive does not match, path={}",
+            base_file.to_string_lossy()
+        )));
+    }
+
+    info!("apply disk delta, base={}", base_file.to_string_lossy());
+    let disk_path = base_dir.join(extents::DISK_FILE);
+    let mut disk = OpenOptions::new().write(true).open(&disk_path)?;
+    disk.set_len(manifest.size)?;
+    extents::apply_delta(&mut BufReader::new(File::open(&delta_path)?), &manifest, &mut disk)?;
+    fs::rename(&disk_path, dir.join(extents::DISK_FILE))?;
+    fs::remove_file(&delta_path)?;
+    fs::remove_dir_all(&base_dir)?;
+    Ok(())
+}
+
+fn unpack(file: &Path, dir: &Path) -> Result<(), Exception> {
+    info!("extract vm archive, file={}, dir={}", file.to_string_lossy(), dir.to_string_lossy());
+    let mut decrypt = None;
+    let (mut header, mut input) = peek(Box::new(File::open(file)?))?;
+    if header.starts_with(AGE_HEADER) {
+        let mut age = Command::new("age");
+        age.arg("--decrypt").arg(file).stdout(Stdio::piped());
+        let mut child = age.spawn().map_err(|err| program_error(&age, err))?;
+        (header, input) = peek(Box::new(child.stdout.take().unwrap()))?;
+        decrypt = Some((age, child));
+    }
+
+    let mut commands = vec![];
+    if header.starts_with(ZSTD_MAGIC) {
+        let mut zstd = Command::new("zstd");
+        zstd.args(["-d", "-c"]);
+        commands.push(zstd);
+    } else if header.starts_with(GZIP_MAGIC) {
+        let mut gzip = Command::new("gzip");
+        gzip.args(["-d", "-c"]);
+        commands.push(gzip);
+    }
+    let mut tar = Command::new("tar");
+    // -S creates holes for zero blocks, in case archive was created without sparse entries
+    tar.args(["-x", "-S", "-f", "-", "-C"]).arg(dir);
+    commands.push(tar);
+    let result = pipeline(&mut commands, Some(input), None);
+
+    if let Some((age, mut child)) = decrypt {
+        let status = child.wait()?;
+        // wrong passphrase fails age, which is more relevant than tar error of truncated input
+        if !status.success() {
+            return Err(failed(&age, status));
+        }
+    }
+    result
+}
+
+// e.g. debian.tar.zst.extents.json
+fn manifest_file(archive: &Path) -> PathBuf {
+    let mut name = archive.file_name().unwrap().to_os_string();
+    name.push(".extents.json");
+    archive.with_file_name(name)
+}
+
+fn load_manifest(path: &Path) -> Result<extents::Manifest, Exception> {
+    if !path.exists() {
+        return Err(Exception::ValidationError(format!(
+            "disk extent manifest not found, path={}",
+            path.to_string_lossy()
+        )));
+    }
+    json::from_json(&fs::read_to_string(path)?)
+}
+
+fn compress_command(options: &Options) -> Result<Option<Command>, Exception> {
+    let (program, max_level) = match options.compression {
+        Compression::Zstd => ("zstd", 19),
+        Compression::Gzip => ("gzip", 9),
+        Compression::None => return Ok(None),
+    };
+    let mut command = Command::new(program);
+    command.arg("-c");
+    if let Some(level) = options.level {
+        if !(1..=max_level).contains(&level) {
+            return Err(Exception::ValidationError(format!(
+                "invalid compression level, compression={program}, level={level}, range=1-{max_level}"
+            )));
+        }
+        command.arg(format!("-{level}"));
+    }
+    // gzip is single threaded, zstd uses all cores with -T0
+    if let Compression::Zstd = options.compression {
+        command.arg(format!("-T{}", options.threads));
+    }
+    Ok(Some(command))
+}
+
+type Input = Box<dyn Read + Send>;
+
+// return first bytes of input, and input which still starts with them
+fn peek(mut input: Input) -> Result<(Vec<u8>, Input), Exception> {
+    let mut header = vec![];
+    input.by_ref().take(AGE_HEADER.len() as u64).read_to_end(&mut header)?;
+    Ok((header.clone(), Box::new(Cursor::new(header).chain(input))))
+}
+
+// connect commands with pipes, input is written to stdin of first command, stdout of last command goes to output
+fn pipeline(commands: &mut [Command], input: Option<Input>, output: Option<File>) -> Result<(), Exception> {
+    let mut children: Vec<Child> = vec![];
+    let last = commands.len() - 1;
+    for (index, command) in commands.iter_mut().enumerate() {
+        if let Some(previous) = children.last_mut() {
+            command.stdin(previous.stdout.take().unwrap());
+        } else if input.is_some() {
+            command.stdin(Stdio::piped());
+        }
+        if index < last {
+            command.stdout(Stdio::piped());
+        } else if let Some(output) = &output {
+            command.stdout(output.try_clone()?);
+        }
+        children.push(command.spawn().map_err(|err| program_error(command, err))?);
+    }
+
+    let writer = input.map(|mut input| {
+        let mut stdin = children[0].stdin.take().unwrap();
+        thread::spawn(move || io::copy(&mut input, &mut stdin))
+    });
+    let mut result = Ok(());
+    for (command, child) in commands.iter().zip(children.iter_mut()) {
+        let status = child.wait()?;
+        if !status.success() && result.is_ok() {
+            result = Err(failed(command, status));
+        }
+    }
+    if let Some(writer) = writer {
+        let copied = writer.join().unwrap();
+        if result.is_ok() {
+            copied?;
+        }
+    }
+    result
+}
+
+fn failed(command: &Command, status: ExitStatus) -> Exception {
+    Exception::ValidationError(format!("failed to run {}, status={status}", command.get_program().to_string_lossy()))
+}
+
+fn program_error(command: &Command, err: io::Error) -> Exception {
+    let program = command.get_program().to_string_lossy();
+    if program == "age" || program == "zstd" {
+        return Exception::ValidationError(format!("failed to run {program}, install with brew install {program}, error={err}"));
+    }
+    Exception::ValidationError(format!("failed to run {program}, error={err}"))
+}
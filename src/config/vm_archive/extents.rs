@@ -0,0 +1,152 @@
+use std::fs::File;
+use std::io::ErrorKind;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::util::digest;
+use crate::util::exception::Exception;
+
+pub const DISK_FILE: &str = "disk.img";
+pub const MANIFEST_FILE: &str = "disk.extents.json";
+pub const DELTA_FILE: &str = "disk.delta";
+
+const EXTENT_SIZE: u64 = 4 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    pub size: u64,
+    #[serde(rename = "extentSize")]
+    pub extent_size: u64,
+    // sha256 of each extent, empty for all zero extent
+    pub extents: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base: Option<Base>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Base {
+    // file name of base archive, in same dir as incremental archive
+    pub file: String,
+    pub digest: String,
+}
+
+impl Manifest {
+    pub fn digest(&self) -> String {
+        digest::sha256_hex(format!("{},{},{}", self.size, self.extent_size, self.extents.join(",")).as_bytes())
+    }
+
+    // extents beyond base are changed if disk was resized
+    pub fn changed(&self, base: &Manifest) -> Result<Vec<u64>, Exception> {
+        if self.extent_size != base.extent_size {
+            return Err(Exception::ValidationError(format!(
+                "extent size of base does not match, extentSize={}, baseExtentSize={}",
+                self.extent_size, base.extent_size
+            )));
+        }
+        Ok((0..self.extents.len())
+            .filter(|&index| base.extents.get(index) != Some(&self.extents[index]))
+            .map(|index| index as u64)
+            .collect())
+    }
+}
+
+pub fn manifest(disk: &Path) -> Result<Manifest, Exception> {
+    let mut file = File::open(disk)?;
+    let size = file.metadata()?.len();
+    let mut extents = vec![];
+    let mut buffer = vec![0; EXTENT_SIZE as usize];
+    for index in 0..size.div_ceil(EXTENT_SIZE) {
+        let data = &mut buffer[..extent_length(size, EXTENT_SIZE, index) as usize];
+        file.read_exact(data)?;
+        // holes of sparse disk read as zeros
+        if data.iter().all(|&byte| byte == 0) {
+            extents.push(String::new());
+        } else {
+            extents.push(digest::sha256_hex(data));
+        }
+    }
+    Ok(Manifest {
+        size,
+        extent_size: EXTENT_SIZE,
+        extents,
+        base: None,
+    })
+}
+
+// delta is sequence of extent index in u64 little endian, followed by extent data
+pub fn write_delta<R: Read + Seek, W: Write>(disk: &mut R, manifest: &Manifest, extents: &[u64], writer: &mut W) -> Result<(), Exception> {
+    let mut buffer = vec![0; manifest.extent_size as usize];
+    for &index in extents {
+        let data = &mut buffer[..extent_length(manifest.size, manifest.extent_size, index) as usize];
+        disk.seek(SeekFrom::Start(index * manifest.extent_size))?;
+        disk.read_exact(data)?;
+        writer.write_all(&index.to_le_bytes())?;
+        writer.write_all(data)?;
+    }
+    Ok(())
+}
+
+pub fn apply_delta<R: Read, W: Write + Seek>(reader: &mut R, manifest: &Manifest, disk: &mut W) -> Result<(), Exception> {
+    let mut buffer = vec![0; manifest.extent_size as usize];
+    loop {
+        let mut index = [0; 8];
+        match reader.read_exact(&mut index) {
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+        let index = u64::from_le_bytes(index);
+        if index >= manifest.size.div_ceil(manifest.extent_size) {
+            return Err(Exception::ValidationError(format!("invalid extent in delta, index={index}")));
+        }
+        let data = &mut buffer[..extent_length(manifest.size, manifest.extent_size, index) as usize];
+        reader.read_exact(data)?;
+        disk.seek(SeekFrom::Start(index * manifest.extent_size))?;
+        disk.write_all(data)?;
+    }
+}
+
+// last extent is shorter if disk size is not multiple of extent size
+fn extent_length(size: u64, extent_size: u64, index: u64) -> u64 {
+    extent_size.min(size - index * extent_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::Manifest;
+
+    fn manifest(extents: &[&str]) -> Manifest {
+        Manifest {
+            size: 10,
+            extent_size: 4,
+            extents: extents.iter().map(|extent| extent.to_string()).collect(),
+            base: None,
+        }
+    }
+
+    #[test]
+    fn changed() {
+        let base = manifest(&["a", "", "c"]);
+        assert_eq!(manifest(&["a", "b", "c"]).changed(&base).unwrap(), vec![1]);
+        assert_eq!(manifest(&["a", "", "c", "d"]).changed(&base).unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn write_and_apply_delta() {
+        let manifest = manifest(&[]);
+        let mut delta = vec![];
+        super::write_delta(&mut Cursor::new(b"0123456789".to_vec()), &manifest, &[0, 2], &mut delta).unwrap();
+        assert_eq!(delta.len(), 8 + 4 + 8 + 2);
+
+        let mut disk = Cursor::new(b"abcdefghij".to_vec());
+        super::apply_delta(&mut delta.as_slice(), &manifest, &mut disk).unwrap();
+        assert_eq!(disk.into_inner(), b"0123efgh89");
+    }
+}
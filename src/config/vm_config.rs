@@ -1,86 +1,731 @@
 use std::collections::HashMap;
+use std::fs;
+use std::os::fd::IntoRawFd;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 
 use objc2::rc::Id;
 use objc2::rc::Retained;
 use objc2::ClassType;
+use objc2_foundation::NSArray;
 use objc2_foundation::NSDictionary;
+use objc2_foundation::NSFileHandle;
 use objc2_foundation::NSString;
+use objc2_virtualization::VZAudioDeviceConfiguration;
+use objc2_virtualization::VZBridgedNetworkDeviceAttachment;
+use objc2_virtualization::VZBridgedNetworkInterface;
+use objc2_virtualization::VZConsoleDeviceConfiguration;
 use objc2_virtualization::VZDirectorySharingDeviceConfiguration;
+use objc2_virtualization::VZDiskImageCachingMode;
+use objc2_virtualization::VZDiskImageSynchronizationMode;
+use objc2_virtualization::VZEntropyDeviceConfiguration;
+use objc2_virtualization::VZFileHandleNetworkDeviceAttachment;
+use objc2_virtualization::VZHostAudioOutputStreamSink;
 use objc2_virtualization::VZMACAddress;
+use objc2_virtualization::VZMemoryBalloonDeviceConfiguration;
 use objc2_virtualization::VZMultipleDirectoryShare;
 use objc2_virtualization::VZNATNetworkDeviceAttachment;
+use objc2_virtualization::VZNetworkDeviceAttachment;
 use objc2_virtualization::VZNetworkDeviceConfiguration;
 use objc2_virtualization::VZSharedDirectory;
+use objc2_virtualization::VZSingleDirectoryShare;
+use objc2_virtualization::VZSpiceAgentPortAttachment;
+use objc2_virtualization::VZVirtioConsoleDeviceConfiguration;
+use objc2_virtualization::VZVirtioConsolePortConfiguration;
+use objc2_virtualization::VZVirtioEntropyDeviceConfiguration;
 use objc2_virtualization::VZVirtioFileSystemDeviceConfiguration;
 use objc2_virtualization::VZVirtioNetworkDeviceConfiguration;
+use objc2_virtualization::VZVirtioSoundDeviceConfiguration;
+use objc2_virtualization::VZVirtioSoundDeviceOutputStreamConfiguration;
+use objc2_virtualization::VZVirtioTraditionalMemoryBalloonDeviceConfiguration;
 use serde::Deserialize;
 use serde::Serialize;
+use serde_json::Value;
+use tracing::warn;
 
+use crate::config::vm_dir::VmDir;
 use crate::util::exception::Exception;
+use crate::util::path;
 use crate::util::path::PathExtension;
+use crate::vm::platform::Platform;
+use crate::vm::platform::Virtualization;
 
-#[derive(Serialize, Deserialize, Debug, Clone, clap::ValueEnum)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
 pub enum Os {
     #[serde(rename = "linux")]
-    #[clap(name = "linux")]
+    #[cfg_attr(feature = "cli", clap(name = "linux"))]
     Linux,
     #[serde(rename = "macOS")]
-    #[clap(name = "macOS")]
+    #[cfg_attr(feature = "cli", clap(name = "macOS"))]
     MacOs,
 }
 
+// unknown fields are rejected, so typo of field name doesn't fall back to default silently, --strict=false ignores them
+static STRICT: AtomicBool = AtomicBool::new(true);
+
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct VmConfig {
+    // name of profile in ~/.vm/profiles, its fields are defaults of this config
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    // arbitrary key/value pairs to select vms by vz list --filter, e.g. {"team": "ci"}
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub labels: HashMap<String, String>,
     pub os: Os,
     pub cpu: usize,
     pub memory: u64,
     #[serde(rename = "macAddress")]
     pub mac_address: String,
-    pub sharing: HashMap<String, String>,
+    pub sharing: HashMap<String, Share>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<bool>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub networks: Vec<NetworkInterface>,
+    // additional disk images in vm dir, attached after disk.img in order, e.g. ["data"] for data.img
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disks: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vsock_forwards: Vec<VsockSocket>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vsock_exposes: Vec<VsockSocket>,
+    // allowlist of guest tcp ports, forwarded from host loopback once guest listens on them, discovered by ssh
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auto_forward_ports: Vec<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssh_user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssh_key: Option<String>,
+    // extra arguments of vz ssh, e.g. ["-A", "-o", "SetEnv=TERM=xterm-256color"]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ssh_args: Vec<String>,
+    // written to /etc/vz/env of linux guest by cloud-init, e.g. {"ROLE": "web"}
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub guest_env: HashMap<String, String>,
+    // applied by cloud-init in linux guest, e.g. "Europe/Berlin", "de_DE.UTF-8" and "de"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keyboard: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub rosetta: Option<bool>,
+    // expose virtualization extensions to linux guest, e.g. for kvm, requires M3 or later and macOS 15
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nested: Option<bool>,
+    // display of --gui, virtio-gpu with one 1024x768 scanout for linux, 1920x1080 points display fit to host screen for macOS if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub graphics: Option<Graphics>,
+    // boot kernel in vm dir directly instead of EFI, for linux vm without bootloader
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kernel_command_line: Option<String>,
+    // host paths of kernel and initrd booted with kernel_command_line instead of vmlinuz and initrd in vm dir, e.g. build output of kernel tree
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initrd: Option<String>,
+    // mirror serial console output and lifecycle events to unified logging
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os_log: Option<bool>,
+    // serial ports of linux guest in addition to console, become /dev/hvc1, /dev/hvc2... in guest
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub serial_ports: Vec<SerialPort>,
+    // sync host pasteboard with macOS guest, requires vz clipboard-agent running in guest
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clipboard: Option<bool>,
+    // virtual hardware to trim, entropy and memory balloon devices are attached unless false,
+    // spice agent shares clipboard with linux guest running spice-vdagent, sound plays guest audio on host speakers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entropy: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_balloon: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spice_agent: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sound: Option<bool>,
+    // post user notification when vm stops abnormally, e.g. by error or failed command of vz run --rm, not by guest poweroff
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify: Option<bool>,
+    // percent of wall time vm is allowed to run on host, 1-99
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_limit_percent: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disk_caching: Option<DiskCaching>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disk_sync: Option<DiskSync>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub readiness_probe: Option<ReadinessProbe>,
+    // seconds for vm to start and pass readiness probe, otherwise vm is stopped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_timeout: Option<u64>,
+    // seconds vm may fail liveness check before marked unresponsive, readiness probe is used as liveness check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heartbeat_timeout: Option<u64>,
+    // restart after guest stops or vm crashes, unless stopped by host, e.g. vz stop
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub restart: Option<RestartPolicy>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub hardware_model: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub machine_identifier: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum DiskCaching {
+    #[serde(rename = "automatic")]
+    Automatic,
+    #[serde(rename = "cached")]
+    Cached,
+    #[serde(rename = "uncached")]
+    Uncached,
+}
+
+// fsync is default, full also flushes drive cache, none never flushes, e.g. for disposable build vm
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum DiskSync {
+    #[serde(rename = "full")]
+    Full,
+    #[serde(rename = "fsync")]
+    Fsync,
+    #[serde(rename = "none")]
+    None,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum RestartPolicy {
+    #[serde(rename = "never")]
+    Never,
+    #[serde(rename = "on-failure")]
+    OnFailure,
+    #[serde(rename = "always")]
+    Always,
+}
+
+// checked after guest got ip, e.g. {"tcp": 5432}, "ssh" or {"command": "pg_isready"}, command runs in guest over ssh
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ReadinessProbe {
+    #[serde(rename = "tcp")]
+    Tcp(u16),
+    #[serde(rename = "ssh")]
+    Ssh,
+    #[serde(rename = "command")]
+    Command(String),
+}
+
+// e.g. "none" or {"virtio": {"scanouts": 1, "width": 2560, "height": 1440}} for linux, {"mac": {"width": 2560, "height": 1440, "pixels_per_inch": 220}} for macOS,
+// fields of virtio and mac are optional
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Graphics {
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "virtio")]
+    Virtio(VirtioGraphics),
+    #[serde(rename = "mac")]
+    Mac(MacGraphics),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct VirtioGraphics {
+    // Virtualization.framework validates count of scanouts it supports
+    #[serde(default = "default_scanouts")]
+    pub scanouts: usize,
+    #[serde(default = "default_width")]
+    pub width: isize,
+    #[serde(default = "default_height")]
+    pub height: isize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MacGraphics {
+    #[serde(default = "default_scanouts")]
+    pub displays: usize,
+    #[serde(default = "default_width")]
+    pub width: isize,
+    #[serde(default = "default_height")]
+    pub height: isize,
+    // width and height are pixels if set, otherwise points fit to host main screen, e.g. 220 shows retina 2x ui
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixels_per_inch: Option<isize>,
+}
+
+// ui of macOS guest is 1x around 110 pixels per inch and 2x around 220, like mac displays
+pub const PIXELS_PER_INCH_1X: isize = 110;
+
+impl MacGraphics {
+    pub fn scale(&self) -> isize {
+        self.pixels_per_inch.map_or(1, |ppi| (ppi / PIXELS_PER_INCH_1X).max(1))
+    }
+}
+
+fn default_scanouts() -> usize {
+    1
+}
+
+fn default_width() -> isize {
+    1920
+}
+
+fn default_height() -> isize {
+    1080
+}
+
+// additional network interface, e.g. {"attachment": "bridged", "interface": "en0", "mac_address": "..."}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SerialPort {
+    pub backend: SerialBackend,
+    // file to append guest output, or unix socket to listen, pty is linked as serial<N> in vm dir
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum SerialBackend {
+    #[serde(rename = "pty")]
+    Pty,
+    // guest output only
+    #[serde(rename = "file")]
+    File,
+    // one client at a time, guest output is dropped while no client is connected
+    #[serde(rename = "socket")]
+    Socket,
+}
+
+impl SerialPort {
+    pub fn path(&self, field: &str) -> Result<PathBuf, Exception> {
+        let Some(path) = &self.path else {
+            return Err(Exception::ValidationError(format!("serial port requires path, field={field}")));
+        };
+        expand_path(&format!("{field}.path"), path)
+    }
+}
+
+// host dir path, e.g. "~/code", or with options, e.g. {"path": "~/code", "read_only": true, "tag": "code"}
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Share {
+    Path(String),
+    Options(ShareOptions),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ShareOptions {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub read_only: bool,
+    // own virtiofs device mounted by tag, e.g. mount -t virtiofs code /mnt/code, otherwise dir under automount tag
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+impl Share {
+    pub fn path(&self) -> &String {
+        match self {
+            Share::Path(path) => path,
+            Share::Options(options) => &options.path,
+        }
+    }
+
+    pub fn read_only(&self) -> bool {
+        matches!(self, Share::Options(ShareOptions { read_only: true, .. }))
+    }
+
+    pub fn tag(&self) -> Option<&str> {
+        match self {
+            Share::Path(_) => None,
+            Share::Options(options) => options.tag.as_deref(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkInterface {
+    pub attachment: NetworkAttachment,
+    pub mac_address: String,
+    // host interface of bridged attachment, e.g. en0
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    // unix datagram socket of host network stack for file-handle attachment, e.g. socket_vmnet
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub socket: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum NetworkAttachment {
+    #[serde(rename = "nat")]
+    Nat,
+    // requires com.apple.vm.networking entitlement
+    #[serde(rename = "bridged")]
+    Bridged,
+    #[serde(rename = "file-handle")]
+    FileHandle,
+}
+
+impl NetworkInterface {
+    fn attachment(&self, field: &str, local: &Path) -> Result<Retained<VZNetworkDeviceAttachment>, Exception> {
+        match self.attachment {
+            NetworkAttachment::Nat => Ok(unsafe { Id::into_super(VZNATNetworkDeviceAttachment::new()) }),
+            NetworkAttachment::Bridged => {
+                let Some(name) = &self.interface else {
+                    return Err(Exception::ValidationError(format!(
+                        "bridged attachment requires interface, field={field}"
+                    )));
+                };
+                let interfaces = unsafe { VZBridgedNetworkInterface::networkInterfaces() };
+                let interface = interfaces.iter().find(|interface| unsafe { interface.identifier() }.to_string() == *name);
+                let Some(interface) = interface else {
+                    let names: Vec<String> = interfaces.iter().map(|interface| unsafe { interface.identifier() }.to_string()).collect();
+                    return Err(Exception::ValidationError(format!(
+                        "bridged interface not found, field={field}, interface={name}, available={}",
+                        names.join(",")
+                    )));
+                };
+                Ok(unsafe {
+                    Id::into_super(VZBridgedNetworkDeviceAttachment::initWithInterface(
+                        VZBridgedNetworkDeviceAttachment::alloc(),
+                        interface,
+                    ))
+                })
+            }
+            NetworkAttachment::FileHandle => {
+                let Some(socket) = &self.socket else {
+                    return Err(Exception::ValidationError(format!(
+                        "file-handle attachment requires socket, field={field}"
+                    )));
+                };
+                let socket = expand_path(&format!("{field}.socket"), socket)?;
+                // bound to own path, so host network stack can send frames back
+                if local.exists() {
+                    fs::remove_file(local)?;
+                }
+                let datagram = UnixDatagram::bind(local)?;
+                datagram.connect(&socket).map_err(|err| {
+                    Exception::ValidationError(format!(
+                        "failed to connect network socket, field={field}, socket={}, error={err}",
+                        socket.to_string_lossy()
+                    ))
+                })?;
+                unsafe {
+                    let handle = NSFileHandle::initWithFileDescriptor_closeOnDealloc(NSFileHandle::alloc(), datagram.into_raw_fd(), true);
+                    Ok(Id::into_super(VZFileHandleNetworkDeviceAttachment::initWithFileHandle(
+                        VZFileHandleNetworkDeviceAttachment::alloc(),
+                        &handle,
+                    )))
+                }
+            }
+        }
+    }
+}
+
+fn network_device(mac_address: &str, attachment: Retained<VZNetworkDeviceAttachment>) -> Result<Retained<VZNetworkDeviceConfiguration>, Exception> {
+    unsafe {
+        let Some(address) = VZMACAddress::initWithString(VZMACAddress::alloc(), &NSString::from_str(mac_address)) else {
+            return Err(Exception::ValidationError(format!("invalid mac address, mac_address={mac_address}")));
+        };
+        let network = VZVirtioNetworkDeviceConfiguration::new();
+        network.setAttachment(Some(&attachment));
+        network.setMACAddress(&address);
+        Ok(Id::into_super(network))
+    }
+}
+
+// pair of guest vsock port and host unix socket, used by both forward and expose
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VsockSocket {
+    pub port: u32,
+    pub socket: String,
+}
+
+pub fn set_strict(strict: bool) {
+    STRICT.store(strict, Ordering::Relaxed);
+}
+
+pub fn strict() -> bool {
+    STRICT.load(Ordering::Relaxed)
+}
+
+// error of strict parsing contains offending key and its line and column
+pub fn parse(json: &str) -> Result<VmConfig, serde_json::Error> {
+    if strict() {
+        return serde_json::from_str(json);
+    }
+    from_value(serde_json::from_str(json)?)
+}
+
+pub fn from_value(mut value: Value) -> Result<VmConfig, serde_json::Error> {
+    loop {
+        match VmConfig::deserialize(&value) {
+            Err(err) if !strict() => match unknown_field(&err.to_string()) {
+                Some(field) if value.as_object_mut().and_then(|object| object.remove(&field)).is_some() => {
+                    warn!("ignore unknown config field, field={field}");
+                }
+                _ => return Err(err),
+            },
+            result => return result,
+        }
+    }
+}
+
+// serde error is like "unknown field `memroy`, expected one of `os`, `cpu`, ..."
+fn unknown_field(message: &str) -> Option<String> {
+    let field = message.strip_prefix("unknown field `")?;
+    field.split_once('`').map(|(field, _)| field.to_string())
+}
+
+// config paths may start with ~ or contain env vars, so same config works on other hosts
+// rosetta is taken by rosetta share of linux vm
+pub fn validate_sharing_tag(name: &str, tag: &str) -> Result<(), Exception> {
+    if tag == "rosetta" {
+        return Err(Exception::ValidationError(format!("sharing tag is reserved, name={name}, tag={tag}")));
+    }
+    let valid = unsafe { VZVirtioFileSystemDeviceConfiguration::validateTag_error(&NSString::from_str(tag)) };
+    valid.map_err(|err| {
+        Exception::ValidationError(format!(
+            "invalid sharing tag, name={name}, tag={tag}, error={}",
+            err.localizedDescription()
+        ))
+    })
+}
+
+pub fn expand_path(field: &str, path: &str) -> Result<PathBuf, Exception> {
+    path::expand(path).map_err(|err| Exception::ValidationError(format!("invalid path in config, field={field}, error={err}")))
+}
+
+impl VsockSocket {
+    pub fn socket_path(&self) -> Result<PathBuf, Exception> {
+        expand_path(&self.field(), &self.socket)
+    }
+
+    fn field(&self) -> String {
+        format!("vsock.{}.socket", self.port)
+    }
+}
+
 impl VmConfig {
-    pub fn network(&self) -> Retained<VZNetworkDeviceConfiguration> {
+    // first interface is NAT with mac_address, network=false removes all interfaces
+    pub fn network_devices(&self, dir: &VmDir) -> Result<Vec<Retained<VZNetworkDeviceConfiguration>>, Exception> {
+        if let Some(false) = self.network {
+            return Ok(vec![]);
+        }
+        let mut mac_addresses = vec![self.mac_address.to_lowercase()];
+        for (index, network) in self.networks.iter().enumerate() {
+            let mac_address = network.mac_address.to_lowercase();
+            if mac_addresses.contains(&mac_address) {
+                return Err(Exception::ValidationError(format!(
+                    "duplicate mac address, field=networks[{index}], mac_address={mac_address}"
+                )));
+            }
+            mac_addresses.push(mac_address);
+        }
+
+        let mut devices = vec![network_device(&self.mac_address, unsafe {
+            Id::into_super(VZNATNetworkDeviceAttachment::new())
+        })?];
+        for (index, network) in self.networks.iter().enumerate() {
+            let field = format!("networks[{index}]");
+            let attachment = network.attachment(&field, &dir.network_socket_path(index))?;
+            devices.push(network_device(&network.mac_address, attachment)?);
+        }
+        Ok(devices)
+    }
+
+    // guest seeds its rng from it, linux guest without it boots with poor entropy and slow crypto initialization
+    pub fn entropy_devices(&self) -> Vec<Retained<VZEntropyDeviceConfiguration>> {
+        if let Some(false) = self.entropy {
+            return vec![];
+        }
+        vec![unsafe { Id::into_super(VZVirtioEntropyDeviceConfiguration::new()) }]
+    }
+
+    // vz balloon requires traditional balloon device
+    pub fn memory_balloon_devices(&self) -> Vec<Retained<VZMemoryBalloonDeviceConfiguration>> {
+        if let Some(false) = self.memory_balloon {
+            return vec![];
+        }
+        vec![unsafe { Id::into_super(VZVirtioTraditionalMemoryBalloonDeviceConfiguration::new()) }]
+    }
+
+    pub fn console_devices(&self) -> Result<Vec<Retained<VZConsoleDeviceConfiguration>>, Exception> {
+        if !matches!(self.spice_agent, Some(true)) {
+            return Ok(vec![]);
+        }
+        if !matches!(self.os, Os::Linux) {
+            return Err(Exception::ValidationError(
+                "spice agent is only supported for linux vm, use clipboard for macOS vm".to_string(),
+            ));
+        }
+        unsafe {
+            let attachment = VZSpiceAgentPortAttachment::new();
+            attachment.setSharesClipboard(true);
+            let port = VZVirtioConsolePortConfiguration::new();
+            port.setName(Some(&VZSpiceAgentPortAttachment::spiceAgentPortName()));
+            port.setAttachment(Some(&attachment));
+            let device = VZVirtioConsoleDeviceConfiguration::new();
+            device.ports().setObject_atIndexedSubscript(Some(&port), 0);
+            Ok(vec![Id::into_super(device)])
+        }
+    }
+
+    // output only, input would prompt for microphone access
+    pub fn audio_devices(&self) -> Vec<Retained<VZAudioDeviceConfiguration>> {
+        if !matches!(self.sound, Some(true)) {
+            return vec![];
+        }
         unsafe {
-            let network = VZVirtioNetworkDeviceConfiguration::new();
-            network.setAttachment(Some(&VZNATNetworkDeviceAttachment::new()));
-            let mac_address = VZMACAddress::initWithString(VZMACAddress::alloc(), &NSString::from_str(&self.mac_address)).unwrap();
-            network.setMACAddress(&mac_address);
-            Id::into_super(network)
+            let stream = VZVirtioSoundDeviceOutputStreamConfiguration::new();
+            stream.setSink(Some(&VZHostAudioOutputStreamSink::new()));
+            let device = VZVirtioSoundDeviceConfiguration::new();
+            device.setStreams(&NSArray::from_vec(vec![Id::into_super(stream)]));
+            vec![Id::into_super(device)]
+        }
+    }
+
+    pub fn disk_caching_mode(&self) -> VZDiskImageCachingMode {
+        match self.disk_caching {
+            Some(DiskCaching::Cached) => VZDiskImageCachingMode::Cached,
+            Some(DiskCaching::Uncached) => VZDiskImageCachingMode::Uncached,
+            Some(DiskCaching::Automatic) | None => VZDiskImageCachingMode::Automatic,
+        }
+    }
+
+    pub fn disk_synchronization_mode(&self) -> VZDiskImageSynchronizationMode {
+        match self.disk_sync {
+            Some(DiskSync::Full) => VZDiskImageSynchronizationMode::Full,
+            Some(DiskSync::None) => VZDiskImageSynchronizationMode::None,
+            Some(DiskSync::Fsync) | None => VZDiskImageSynchronizationMode::Fsync,
+        }
+    }
+
+    // report all paths can't be expanded when loading, instead of failing at first use
+    pub fn validate_paths(&self) -> Result<(), Exception> {
+        let mut paths: Vec<(String, &String)> = self
+            .sharing
+            .iter()
+            .map(|(name, share)| (format!("sharing.{name}"), share.path()))
+            .collect();
+        if let Some(key) = &self.ssh_key {
+            paths.push(("ssh_key".to_string(), key));
+        }
+        if let Some(kernel) = &self.kernel {
+            paths.push(("kernel".to_string(), kernel));
+        }
+        if let Some(initrd) = &self.initrd {
+            paths.push(("initrd".to_string(), initrd));
+        }
+        for (index, network) in self.networks.iter().enumerate() {
+            if let Some(socket) = &network.socket {
+                paths.push((format!("networks[{index}].socket"), socket));
+            }
+        }
+        for (index, port) in self.serial_ports.iter().enumerate() {
+            if let Some(path) = &port.path {
+                paths.push((format!("serial_ports[{index}].path"), path));
+            }
+        }
+        for socket in self.vsock_forwards.iter().chain(self.vsock_exposes.iter()) {
+            paths.push((socket.field(), &socket.socket));
+        }
+        let errors: Vec<String> = paths
+            .into_iter()
+            .filter_map(|(field, path)| path::expand(path).err().map(|err| format!("field={field}, error={err}")))
+            .collect();
+        if !errors.is_empty() {
+            return Err(Exception::ValidationError(format!("invalid paths in config, {}", errors.join("; "))));
+        }
+        Ok(())
+    }
+
+    pub fn validate_graphics(&self) -> Result<(), Exception> {
+        match (&self.os, &self.graphics) {
+            (Os::Linux, Some(Graphics::Mac(_))) => Err(Exception::ValidationError("linux vm requires virtio graphics, graphics=mac".to_string())),
+            (Os::MacOs, Some(Graphics::Virtio(_))) => Err(Exception::ValidationError("macOS vm requires mac graphics, graphics=virtio".to_string())),
+            (Os::MacOs, Some(Graphics::None)) => Err(Exception::ValidationError("macOS vm requires display, graphics=none".to_string())),
+            _ => Ok(()),
         }
     }
 
-    pub fn sharing_directories(&self) -> Result<Option<Retained<VZDirectorySharingDeviceConfiguration>>, Exception> {
-        if self.sharing.is_empty() {
-            return Ok(None);
+    // initial window content size of --gui in points, guest display follows window after it opens
+    pub fn window_size(&self) -> (f64, f64) {
+        match &self.graphics {
+            Some(Graphics::Virtio(graphics)) => (graphics.width as f64, graphics.height as f64),
+            Some(Graphics::Mac(graphics)) => ((graphics.width / graphics.scale()) as f64, (graphics.height / graphics.scale()) as f64),
+            _ => (1024.0, 768.0),
         }
+    }
+
+    pub fn validate_host_limits(&self) -> Result<(), Exception> {
+        self.validate_limits(&Virtualization)
+    }
+
+    fn validate_limits(&self, platform: &dyn Platform) -> Result<(), Exception> {
+        let (min_cpu, max_cpu) = platform.cpu_range();
+        let (min_memory, max_memory) = platform.memory_range();
+        if !(min_cpu..=max_cpu).contains(&self.cpu) {
+            return Err(Exception::ValidationError(format!(
+                "cpu exceeds host limits, cpu={}, range={min_cpu}-{max_cpu}",
+                self.cpu
+            )));
+        }
+        if !(min_memory..=max_memory).contains(&self.memory) {
+            return Err(Exception::ValidationError(format!(
+                "memory exceeds host limits, memory={}, range={min_memory}-{max_memory}",
+                self.memory
+            )));
+        }
+        Ok(())
+    }
+
+    // shares without tag are dirs of one device with macOS automount tag, each tagged share is its own device
+    pub fn sharing_directories(&self) -> Result<Vec<Retained<VZDirectorySharingDeviceConfiguration>>, Exception> {
+        let mut devices = vec![];
         let mut keys: Vec<Retained<NSString>> = vec![];
         let mut values: Vec<Retained<VZSharedDirectory>> = vec![];
+        let mut tags = vec![];
 
-        for (key, value) in self.sharing.iter() {
-            keys.push(NSString::from_str(key));
-            let path = PathBuf::from(value).to_absolute_path();
+        for (key, share) in self.sharing.iter() {
+            let path = expand_path(&format!("sharing.{key}"), share.path())?;
             if !path.exists() {
                 return Err(Exception::ValidationError(format!(
                     "sharing path does not exist, name={key}, path={}",
                     path.to_string_lossy()
                 )));
             }
-            unsafe {
-                values.push(VZSharedDirectory::initWithURL_readOnly(
-                    VZSharedDirectory::alloc(),
-                    &path.to_ns_url(),
-                    false,
-                ));
+            let directory = unsafe { VZSharedDirectory::initWithURL_readOnly(VZSharedDirectory::alloc(), &path.to_ns_url(), share.read_only()) };
+            match share.tag() {
+                Some(tag) => {
+                    validate_sharing_tag(key, tag)?;
+                    if tags.contains(&tag) {
+                        return Err(Exception::ValidationError(format!("sharing tag is duplicated, name={key}, tag={tag}")));
+                    }
+                    tags.push(tag);
+                    unsafe {
+                        let device = VZVirtioFileSystemDeviceConfiguration::initWithTag(
+                            VZVirtioFileSystemDeviceConfiguration::alloc(),
+                            &NSString::from_str(tag),
+                        );
+                        let share = VZSingleDirectoryShare::initWithDirectory(VZSingleDirectoryShare::alloc(), &directory);
+                        device.setShare(Some(&Id::into_super(share)));
+                        devices.push(Id::into_super(device));
+                    }
+                }
+                None => {
+                    keys.push(NSString::from_str(key));
+                    values.push(directory);
+                }
             }
         }
+        if values.is_empty() {
+            return Ok(devices);
+        }
 
         let keys: Vec<&NSString> = keys.iter().map(|v| v.as_ref()).collect();
         let directories = NSDictionary::from_vec(&keys, values);
@@ -91,7 +736,63 @@ impl VmConfig {
             );
             let sharings = VZMultipleDirectoryShare::initWithDirectories(VZMultipleDirectoryShare::alloc(), &directories);
             device.setShare(Some(&Id::into_super(sharings)));
-            Ok(Some(Id::into_super(device)))
+            devices.insert(0, Id::into_super(device));
         }
+        Ok(devices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::platform::mock::MockPlatform;
+
+    #[test]
+    fn unknown_field() {
+        assert_eq!(
+            Some("memroy".to_string()),
+            super::unknown_field("unknown field `memroy`, expected one of `os`, `cpu` at line 4 column 12")
+        );
+        assert_eq!(None, super::unknown_field("missing field `cpu`"));
+    }
+
+    #[test]
+    fn validate_limits() {
+        let config = |cpu: usize, memory: u64| {
+            super::parse(&format!(
+                r#"{{"os": "linux", "cpu": {cpu}, "memory": {memory}, "macAddress": "", "sharing": {{}}}}"#
+            ))
+            .unwrap()
+        };
+        let platform = MockPlatform::default();
+        assert!(config(4, 1024 * 1024 * 1024).validate_limits(&platform).is_ok());
+        assert!(config(8, 1024 * 1024 * 1024).validate_limits(&platform).is_err());
+        assert!(config(1, 16 * 1024 * 1024 * 1024).validate_limits(&platform).is_err());
+    }
+
+    #[test]
+    fn window_size() {
+        let config = super::parse(
+            r#"{"os": "macOS", "cpu": 1, "memory": 1, "macAddress": "", "sharing": {},
+                "graphics": {"mac": {"width": 2560, "height": 1440, "pixels_per_inch": 220}}}"#,
+        )
+        .unwrap();
+        assert!(config.validate_graphics().is_ok());
+        assert_eq!(config.window_size(), (1280.0, 720.0));
+
+        let config = super::parse(r#"{"os": "linux", "cpu": 1, "memory": 1, "macAddress": "", "sharing": {}, "graphics": {"mac": {}}}"#).unwrap();
+        assert!(config.validate_graphics().is_err());
+    }
+
+    #[test]
+    fn sharing() {
+        let config = super::parse(
+            r#"{"os": "linux", "cpu": 1, "memory": 1, "macAddress": "",
+                "sharing": {"code": "~/code", "docs": {"path": "~/docs", "read_only": true, "tag": "docs"}}}"#,
+        )
+        .unwrap();
+        let code = &config.sharing["code"];
+        assert_eq!((code.path().as_str(), code.read_only(), code.tag()), ("~/code", false, None));
+        let docs = &config.sharing["docs"];
+        assert_eq!((docs.path().as_str(), docs.read_only(), docs.tag()), ("~/docs", true, Some("docs")));
     }
 }
@@ -1,14 +1,21 @@
+use std::env;
 use std::fs;
 use std::path::PathBuf;
 
 use libc::pid_t;
+use serde_json::Map;
+use serde_json::Value;
 use tracing::info;
 use uuid::Uuid;
 
+use super::profile;
+use super::vm_config;
 use super::vm_config::VmConfig;
 use crate::util::exception::Exception;
+use crate::util::file_lock;
 use crate::util::file_lock::FileLock;
 use crate::util::json;
+use crate::util::path;
 use crate::util::path::PathExtension;
 
 pub struct VmDir {
@@ -16,18 +23,88 @@ pub struct VmDir {
     pub nvram_path: PathBuf,
     pub disk_path: PathBuf,
     pub config_path: PathBuf,
+    pub console_path: PathBuf,
+    // unix socket of runner to change running vm, e.g. vz display resize
+    pub control_path: PathBuf,
+    pub seed_path: PathBuf,
+    // user data of seed, pending part is kept when seed is created again for changed guest config
+    pub seed_config_path: PathBuf,
+    pub known_hosts_path: PathBuf,
+    pub kernel_path: PathBuf,
+    pub initrd_path: PathBuf,
+    // exists while runner sees guest failing liveness check
+    pub unresponsive_path: PathBuf,
+    // saved state of suspended vm, restored and removed by next run
+    pub state_path: PathBuf,
+    // output of runner started in background, e.g. vz run -d
+    pub log_path: PathBuf,
+    // exists until macOS is installed into created disk
+    pub uninstalled_path: PathBuf,
+    // flock of process using vm, e.g. runner or resize
+    pub lock_path: PathBuf,
+    // last ip of guest seen in dhcp leases, written by runner, kept after vm stops
+    pub ip_path: PathBuf,
+    // cpu and memory of running vm, including overrides of vz run, written by runner
+    pub resources_path: PathBuf,
+}
+
+// temp dirs of vz run --rm, other temp files of vz use different prefix, e.g. vz-bench-<uuid>
+const EPHEMERAL_PREFIX: &str = "vz-rm-";
+
+// lock owners which boot vm, pid of them is pid of vm
+const RUNNER_COMMANDS: [&str; 2] = ["run", "install"];
+
+// held while vm of name is created, e.g. by create, clone and import, lock file is removed with it
+pub struct NameLock {
+    path: PathBuf,
+    _lock: FileLock,
+}
+
+impl Drop for NameLock {
+    fn drop(&mut self) {
+        // other process locking removed file checks vm dir after, so it sees created vm
+        let _ = fs::remove_file(&self.path);
+    }
 }
 
 impl VmDir {
-    fn new(dir: PathBuf) -> Self {
+    pub fn new(dir: PathBuf) -> Self {
         let nvram_path = dir.as_path().join("nvram.bin");
         let disk_path = dir.as_path().join("disk.img");
         let config_path = dir.as_path().join("config.json");
+        let console_path = dir.as_path().join("console");
+        let control_path = dir.as_path().join("control.sock");
+        let seed_path = dir.as_path().join("seed.iso");
+        let seed_config_path = dir.as_path().join("seed.json");
+        let known_hosts_path = dir.as_path().join("known_hosts");
+        let kernel_path = dir.as_path().join("vmlinuz");
+        let initrd_path = dir.as_path().join("initrd");
+        let unresponsive_path = dir.as_path().join("unresponsive");
+        let state_path = dir.as_path().join("state.vzvmsave");
+        let log_path = dir.as_path().join("vz.log");
+        let uninstalled_path = dir.as_path().join("uninstalled");
+        let lock_path = dir.as_path().join("vz.lock");
+        let ip_path = dir.as_path().join("ip");
+        let resources_path = dir.as_path().join("resources.json");
         VmDir {
             dir,
             nvram_path,
             disk_path,
             config_path,
+            console_path,
+            control_path,
+            seed_path,
+            seed_config_path,
+            known_hosts_path,
+            kernel_path,
+            initrd_path,
+            unresponsive_path,
+            state_path,
+            log_path,
+            uninstalled_path,
+            lock_path,
+            ip_path,
+            resources_path,
         }
     }
 
@@ -39,35 +116,98 @@ impl VmDir {
         self.config_path.exists() && self.disk_path.exists() && self.nvram_path.exists()
     }
 
+    // saved state only restores on same hardware, changed hardware discards it and guest loses unsaved work
+    pub fn check_not_suspended(&self) -> Result<(), Exception> {
+        if self.state_path.exists() {
+            return Err(Exception::ValidationError(format!(
+                "vm is suspended, hardware can't change until it's resumed, resume it by vz run and stop it first, name={}",
+                self.name()
+            )));
+        }
+        Ok(())
+    }
+
     pub fn load_config(&self) -> Result<VmConfig, Exception> {
-        let json = fs::read_to_string(&self.config_path)?;
-        json::from_json(&json)
+        self.parse_config(&fs::read_to_string(&self.config_path)?)
+    }
+
+    // parse config json of this vm, e.g. edited copy before it's saved
+    pub fn parse_config(&self, json: &str) -> Result<VmConfig, Exception> {
+        let invalid = |err| Exception::ValidationError(format!("invalid config, path={}, error={err}", self.config_path.to_string_lossy()));
+        let fields: Map<String, Value> = serde_json::from_str(json).map_err(invalid)?;
+        let config = if fields.contains_key("extends") {
+            vm_config::from_value(Value::Object(profile::merge(fields)?)).map_err(invalid)?
+        } else {
+            vm_config::parse(json).map_err(invalid)?
+        };
+        config.validate_paths()?;
+        config.validate_graphics()?;
+        Ok(config)
     }
 
     pub fn save_config(&self, config: &VmConfig) -> Result<(), Exception> {
-        let json = json::to_json_pretty(&config)?;
+        let mut fields: Map<String, Value> = json::from_json(&json::to_json(config)?)?;
+        profile::remove_inherited(&mut fields)?;
+        let json = json::to_json_pretty(&fields)?;
         fs::write(&self.config_path, json)?;
         Ok(())
     }
 
+    // snapshots/<snapshot> holds cloned disks, nvram and config
+    pub fn snapshots_dir(&self) -> PathBuf {
+        self.dir.join("snapshots")
+    }
+
+    pub fn snapshot_dir(&self, snapshot: &str) -> PathBuf {
+        self.snapshots_dir().join(snapshot)
+    }
+
+    // additional disk of config.disks
+    pub fn extra_disk_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}.img"))
+    }
+
+    // local end of file-handle network of config.networks, in vm dir so it's reused by next start and removed with vm
+    pub fn network_socket_path(&self, index: usize) -> PathBuf {
+        self.dir.join(format!("net{index}.sock"))
+    }
+
     pub fn resize(&self, size: u64) -> Result<(), Exception> {
         let file = fs::OpenOptions::new().create(true).append(true).open(&self.disk_path)?;
         file.set_len(size)?;
         Ok(())
     }
 
-    pub fn lock(&self) -> Result<FileLock, Exception> {
-        let lock = FileLock::new(&self.config_path);
-        if lock.lock() {
-            Ok(lock)
-        } else {
-            Err(Exception::ValidationError(format!("vm is already running, name={}", self.name())))
+    // command is shown to other processes, e.g. run or resize
+    pub fn lock(&self, command: &str) -> Result<FileLock, Exception> {
+        if let Some(lock) = FileLock::try_lock(&self.lock_path, command)? {
+            return Ok(lock);
         }
+        let name = self.name();
+        Err(match file_lock::owner(&self.lock_path) {
+            Some(owner) if RUNNER_COMMANDS.contains(&owner.command.as_str()) => {
+                Exception::ValidationError(format!("vm is already running, name={name}, pid={}", owner.pid))
+            }
+            Some(owner) => Exception::ValidationError(format!(
+                "vm is in use by other command, name={name}, command={}, pid={}",
+                owner.command, owner.pid
+            )),
+            None => Exception::ValidationError(format!("vm is in use by other process, name={name}")),
+        })
+    }
+
+    pub fn last_ip(&self) -> Option<String> {
+        fs::read_to_string(&self.ip_path)
+            .ok()
+            .map(|ip| ip.trim().to_string())
+            .filter(|ip| !ip.is_empty())
     }
 
+    // pid of runner, none if vm is stopped or locked by other command, e.g. snapshot
     pub fn pid(&self) -> Option<pid_t> {
-        let lock = FileLock::new(&self.config_path);
-        lock.pid()
+        file_lock::owner(&self.lock_path)
+            .filter(|owner| RUNNER_COMMANDS.contains(&owner.command.as_str()))
+            .map(|owner| owner.pid)
     }
 }
 
@@ -79,6 +219,81 @@ pub fn vm_dir(name: &str) -> VmDir {
     VmDir::new(home_dir().join(name))
 }
 
+// ephemeral vm dir in system temp dir, it's not listed in home dir, and will be cleaned by os if left behind
+pub fn create_empty_ephemeral_vm_dir() -> Result<VmDir, Exception> {
+    let dir = VmDir::new(env::temp_dir().join(format!("{EPHEMERAL_PREFIX}{}", Uuid::new_v4())));
+    info!("create ephemeral vm dir, dir={}", dir.dir.to_string_lossy());
+    fs::create_dir_all(&dir.dir)?;
+    Ok(dir)
+}
+
+// clone vm into ephemeral vm dir
+pub fn create_ephemeral_vm_dir(source: &VmDir) -> Result<VmDir, Exception> {
+    let dir = create_empty_ephemeral_vm_dir()?;
+    info!("clone vm, from={}, dir={}", source.dir.to_string_lossy(), dir.dir.to_string_lossy());
+    // disks added by vz disk add and kernel in vm dir are attached from clone as well
+    let disks = source.load_config()?.disks.into_iter().map(|name| source.extra_disk_path(&name));
+    let paths = [&source.nvram_path, &source.disk_path, &source.kernel_path, &source.initrd_path]
+        .into_iter()
+        .cloned()
+        .chain(disks);
+    for path in paths {
+        if path.exists() {
+            path::clone_file(&path, &dir.dir.join(path.file_name().unwrap()))?;
+        }
+    }
+    // config is copied last, gc skips dir without it
+    fs::copy(&source.config_path, &dir.config_path)?;
+    Ok(dir)
+}
+
+// initialized vm dirs in home dir, temp dirs of create, clone and import are excluded
+pub fn vm_dirs() -> Result<Vec<VmDir>, Exception> {
+    let home_dir = home_dir();
+    if !home_dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut dirs = vec![];
+    for entry in fs::read_dir(home_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.path().is_dir() && Uuid::parse_str(&name).is_err() {
+            let dir = vm_dir(&name);
+            if dir.initialized() {
+                dirs.push(dir);
+            }
+        }
+    }
+    Ok(dirs)
+}
+
+// ephemeral vm dirs left behind, e.g. runner was killed
+pub fn ephemeral_vm_dirs() -> Result<Vec<VmDir>, Exception> {
+    let mut dirs = vec![];
+    for entry in fs::read_dir(env::temp_dir())? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with(EPHEMERAL_PREFIX) && entry.path().is_dir() {
+            // config is copied last, dir without it is still being created
+            let dir = VmDir::new(entry.path());
+            if dir.config_path.exists() {
+                dirs.push(dir);
+            }
+        }
+    }
+    Ok(dirs)
+}
+
+// lock name of new vm, vm dir must be checked after locking
+pub fn lock_name(name: &str) -> Result<NameLock, Exception> {
+    let home_dir = home_dir();
+    fs::create_dir_all(&home_dir)?;
+    let path = home_dir.join(format!(".{name}.lock"));
+    match FileLock::try_lock(&path, "create")? {
+        Some(lock) => Ok(NameLock { path, _lock: lock }),
+        None => Err(Exception::ValidationError(format!("vm is being created by other process, name={name}"))),
+    }
+}
+
 pub fn create_temp_vm_dir() -> Result<VmDir, Exception> {
     let temp_dir = home_dir().join(Uuid::new_v4().to_string());
     info!("create temp vm dir, dir={}", temp_dir.to_string_lossy());
@@ -0,0 +1,15 @@
+// vm management as library, e.g. config, vm dir, runner and control of running vm, main.rs is cli on top of it
+// Vm, VmHandle and VmConfig don't depend on clap, commands are only built with cli feature
+mod api;
+#[cfg(feature = "cli")]
+pub mod command;
+pub mod config;
+pub mod util;
+pub mod vm;
+
+pub use api::StartOptions;
+pub use api::Vm;
+pub use api::VmHandle;
+pub use api::VmState;
+pub use config::vm_config::VmConfig;
+pub use util::exception::Exception;
@@ -1,24 +1,93 @@
+use std::time::SystemTime;
+
+use clap::ArgAction;
+use clap::CommandFactory;
+use clap::FromArgMatches;
 use clap::Parser;
 use clap::Subcommand;
-use command::create::Create;
-use command::generate_zsh_completion::GenerateZshCompletion;
-use command::install::Install;
-use command::ipsw::Ipsw;
-use command::list::List;
-use command::resize::Resize;
-use command::run::Run;
-use command::stop::Stop;
-use util::exception::Exception;
-
-mod command;
-mod config;
-mod util;
-mod vm;
+use vz::command::autostart::Autostart;
+use vz::command::balloon::Balloon;
+use vz::command::bench::Bench;
+use vz::command::build::Build;
+use vz::command::clipboard_agent::ClipboardAgent;
+use vz::command::clone::CloneVm;
+use vz::command::completion::Completion;
+use vz::command::console::Console;
+use vz::command::cp::Cp;
+use vz::command::create::Create;
+use vz::command::daemon::Daemon;
+use vz::command::delete::Delete;
+use vz::command::disk::Disk;
+use vz::command::display::Display;
+use vz::command::doctor::Doctor;
+use vz::command::edit::Edit;
+use vz::command::exec::Exec;
+use vz::command::exec_agent::ExecAgent;
+use vz::command::export::Export;
+use vz::command::gc::Gc;
+use vz::command::generate_man_page::GenerateManPage;
+use vz::command::generate_zsh_completion::GenerateZshCompletion;
+use vz::command::host::Host;
+use vz::command::hosts::Hosts;
+use vz::command::import::Import;
+use vz::command::install::Install;
+use vz::command::ip::Ip;
+use vz::command::ipsw::Ipsw;
+use vz::command::list::List;
+use vz::command::logs::Logs;
+use vz::command::mount::Mount;
+use vz::command::net::Net;
+use vz::command::pause::Pause;
+use vz::command::pause::Resume;
+use vz::command::pull::Pull;
+use vz::command::resize::Resize;
+use vz::command::rosetta::Rosetta;
+use vz::command::run::Run;
+use vz::command::selftest::Selftest;
+use vz::command::set::Set;
+use vz::command::share::ShareDir;
+use vz::command::shell::Shell;
+use vz::command::snapshot::Snapshot;
+use vz::command::ssh::Ssh;
+use vz::command::stats::Stats;
+use vz::command::status::Status;
+use vz::command::stop::Stop;
+use vz::command::suspend::Suspend;
+use vz::command::usb::Usb;
+use vz::command::verify::Verify;
+use vz::command::vsock::Vsock;
+use vz::command::wait::Wait;
+use vz::command::web::Web;
+use vz::config::vm_config;
+use vz::util::exception::Exception;
+use vz::util::log_file;
+use vz::util::otlp;
+use vz::util::terminal;
+use vz::util::terminal::ColorMode;
 
 #[derive(Parser)]
 #[command(author, version)]
 #[command(about = "manage virtual machines")]
 pub struct Cli {
+    #[arg(
+        long,
+        global = true,
+        help = "reject unknown fields in vm config, --strict=false ignores them",
+        default_value_t = true,
+        action = ArgAction::Set,
+        num_args = 0..=1,
+        default_missing_value = "true"
+    )]
+    strict: bool,
+
+    #[arg(
+        long,
+        global = true,
+        help = "colors and progress bars, auto only uses them if stdout is terminal and not under CI",
+        default_value = "auto"
+    )]
+    color: ColorMode,
+
     #[command(subcommand)]
     command: Option<Command>,
 }
@@ -28,37 +97,196 @@ pub struct Cli {
 pub enum Command {
     #[command(name = "ls", about = "list vm status")]
     List(List),
+    #[command(about = "show live state, uptime, ip, devices and shares of vm")]
+    Status(Status),
     #[command(about = "create vm")]
     Create(Create),
+    #[command(about = "clone stopped vm, disk is copy on write, mac address and machine identifier are regenerated")]
+    Clone(CloneVm),
+    #[command(about = "delete stopped vm with its disks and snapshots, and disable its autostart")]
+    Delete(Delete),
     #[command(about = "run vm")]
     Run(Run),
     #[command(about = "stop vm")]
     Stop(Stop),
+    #[command(about = "save state of running vm and stop it, next run resumes it")]
+    Suspend(Suspend),
+    #[command(about = "pause running vm, it keeps memory but stops cpu")]
+    Pause(Pause),
+    #[command(about = "resume paused vm")]
+    Resume(Resume),
+    #[command(about = "print log of vm runner, e.g. lifecycle events and output of background vm")]
+    Logs(Logs),
+    #[command(about = "attach terminal to serial console of running linux vm")]
+    Console(Console),
+    #[command(about = "start vm at login by launchd")]
+    Autostart(Autostart),
+    #[command(about = "show state and host resource usage of all vms")]
+    Stats(Stats),
+    #[command(about = "serve web ui to view, start and stop vms")]
+    Web(Web),
+    #[command(about = "serve json-rpc api on unix socket to manage vms, e.g. for editor plugins")]
+    Daemon(Daemon),
+    #[command(about = "edit vm config in $EDITOR, it's only saved if valid")]
+    Edit(Edit),
+    #[command(about = "wait until vm passes readiness probe")]
+    Wait(Wait),
     #[command(
-        about = "get macOS restore image ipsw url",
-        long_about = "get macOS restore image ipsw url, download ipsw file manually, then use in create command with --ipsw"
+        about = "get macOS restore image ipsw url, or manage cached ipsw",
+        long_about = "get macOS restore image ipsw url, download ipsw file manually, then use in create command with --ipsw, cached ipsw can be referred by version, e.g. --ipsw=14.5"
     )]
     Ipsw(Ipsw),
-    #[command(about = "increase disk image size")]
+    #[command(about = "download prebuilt linux disk image into cache, for create --image")]
+    Pull(Pull),
+    #[command(about = "change cpu, memory or increase disk size of vm")]
     Resize(Resize),
+    #[command(about = "shrink or grow memory of running vm by memory balloon")]
+    Balloon(Balloon),
+    #[command(about = "change hardware config of vm, e.g. cpu, memory and rosetta")]
+    Set(Set),
+    #[command(about = "manage disk image")]
+    Disk(Disk),
+    #[command(about = "change display of running vm")]
+    Display(Display),
+    #[command(about = "attach usb mass storage to running vm")]
+    Usb(Usb),
     #[command(about = "install macOS")]
     Install(Install),
-    #[command(about = "generate zsh completion")]
+    #[command(about = "import vm from vagrant box, UTM bundle or archive")]
+    Import(Import),
+    #[command(about = "export vm as archive")]
+    Export(Export),
+    #[command(about = "remove unused cached images and leftover vm dirs")]
+    Gc(Gc),
+    #[command(about = "manage named snapshots of vm")]
+    Snapshot(Snapshot),
+    #[command(about = "check vm dir is consistent, e.g. after restoring from backup")]
+    Verify(Verify),
+    #[command(about = "check all vms and vm home dir, e.g. stale files of crashed runner and left temp dirs")]
+    Doctor(Doctor),
+    #[command(about = "print ip of running vm")]
+    Ip(Ip),
+    #[command(about = "ssh into vm")]
+    Ssh(Ssh),
+    #[command(about = "ssh into vm, or attach serial console if ssh is not reachable")]
+    Shell(Shell),
+    #[command(about = "run command in vm by exec agent in guest, without ssh")]
+    Exec(Exec),
+    #[command(about = "copy files between host and vm, e.g. vz cp a.txt dev:/tmp/")]
+    Cp(Cp),
+    #[command(about = "mount guest files on host, by sshfs if vm is running, or attach disk of stopped macOS vm read only")]
+    Mount(Mount),
+    #[command(about = "manage host dirs shared with vm")]
+    Share(ShareDir),
+    #[command(about = "show host capabilities")]
+    Host(Host),
+    #[command(about = "check or install rosetta for linux vms")]
+    Rosetta(Rosetta),
+    #[command(about = "manage /etc/hosts entries of vms")]
+    Hosts(Hosts),
+    #[command(about = "manage fixed ip reservations of vms")]
+    Net(Net),
+    #[command(about = "manage vsock forwarding, or bridge guest vsock port to stdio")]
+    Vsock(Vsock),
+    #[command(about = "create, boot, provision and export vm in one step, e.g. for packer")]
+    Build(Build),
+    #[command(about = "benchmark disk, virtiofs and network of linux vm across disk caching and sync modes")]
+    Bench(Bench),
+    #[command(about = "boot throwaway vm to verify host and binary")]
+    Selftest(Selftest),
+    #[command(about = "generate shell completion, with names of vms")]
+    Completion(Completion),
+    #[command(about = "generate zsh completion, same as completion zsh", hide = true)]
     GenerateZshCompletion(GenerateZshCompletion),
+    #[command(about = "generate man pages", hide = true)]
+    GenerateManPage(GenerateManPage),
+    #[command(about = "sync clipboard with host, run inside macOS guest", hide = true)]
+    ClipboardAgent(ClipboardAgent),
+    #[command(about = "run commands of vz exec, run inside macOS guest", hide = true)]
+    ExecAgent(ExecAgent),
 }
 
 fn main() -> Result<(), Exception> {
-    tracing_subscriber::fmt().with_thread_ids(true).init();
-    let cli = Cli::parse();
-    match cli.command {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    // subcommand name of cli, not first arg, which may be global option, e.g. vz --strict=false run debian
+    let name = format!("vz {}", matches.subcommand_name().unwrap_or_default());
+    terminal::init(cli.color);
+    let ansi = terminal::interactive();
+    let log_path = match &cli.command {
+        Some(Command::Run(run)) => run.log_path(),
+        _ => None,
+    };
+    // machine readable output owns stdout
+    if matches!(&cli.command, Some(Command::Build(build)) if build.machine_readable) {
+        tracing_subscriber::fmt()
+            .with_thread_ids(true)
+            .with_ansi(ansi)
+            .with_writer(std::io::stderr)
+            .init();
+    } else if let Some(path) = log_path {
+        log_file::init_tracing(&path, ansi);
+    } else {
+        tracing_subscriber::fmt().with_thread_ids(true).with_ansi(ansi).init();
+    }
+    vm_config::set_strict(cli.strict);
+    let start = SystemTime::now();
+    let result = match cli.command {
         Some(Command::List(command)) => command.execute(),
         Some(Command::Create(command)) => command.execute(),
+        Some(Command::Clone(command)) => command.execute(),
+        Some(Command::Delete(command)) => command.execute(),
         Some(Command::Run(command)) => command.execute(),
         Some(Command::Stop(command)) => command.execute(),
+        Some(Command::Suspend(command)) => command.execute(),
+        Some(Command::Pause(command)) => command.execute(),
+        Some(Command::Resume(command)) => command.execute(),
+        Some(Command::Logs(command)) => command.execute(),
+        Some(Command::Console(command)) => command.execute(),
+        Some(Command::Autostart(command)) => command.execute(),
+        Some(Command::Stats(command)) => command.execute(),
+        Some(Command::Status(command)) => command.execute(),
+        Some(Command::Web(command)) => command.execute(),
+        Some(Command::Daemon(command)) => command.execute(),
+        Some(Command::Edit(command)) => command.execute(),
+        Some(Command::Wait(command)) => command.execute(),
         Some(Command::Ipsw(command)) => command.execute(),
+        Some(Command::Pull(command)) => command.execute(),
         Some(Command::Resize(command)) => command.execute(),
+        Some(Command::Balloon(command)) => command.execute(),
+        Some(Command::Set(command)) => command.execute(),
+        Some(Command::Disk(command)) => command.execute(),
+        Some(Command::Display(command)) => command.execute(),
+        Some(Command::Usb(command)) => command.execute(),
         Some(Command::Install(command)) => command.execute(),
-        Some(Command::GenerateZshCompletion(command)) => command.execute(),
+        Some(Command::Import(command)) => command.execute(),
+        Some(Command::Export(command)) => command.execute(),
+        Some(Command::Gc(command)) => command.execute(),
+        Some(Command::Snapshot(command)) => command.execute(),
+        Some(Command::Verify(command)) => command.execute(),
+        Some(Command::Doctor(command)) => command.execute(),
+        Some(Command::Ip(command)) => command.execute(),
+        Some(Command::Ssh(command)) => command.execute(),
+        Some(Command::Shell(command)) => command.execute(),
+        Some(Command::Exec(command)) => command.execute(),
+        Some(Command::Cp(command)) => command.execute(),
+        Some(Command::Mount(command)) => command.execute(),
+        Some(Command::Share(command)) => command.execute(),
+        Some(Command::Host(command)) => command.execute(),
+        Some(Command::Rosetta(command)) => command.execute(),
+        Some(Command::Hosts(command)) => command.execute(),
+        Some(Command::Net(command)) => command.execute(),
+        Some(Command::Vsock(command)) => command.execute(),
+        Some(Command::Build(command)) => command.execute(),
+        Some(Command::Bench(command)) => command.execute(),
+        Some(Command::Selftest(command)) => command.execute(),
+        Some(Command::Completion(command)) => command.execute(Cli::command()),
+        Some(Command::GenerateZshCompletion(command)) => command.execute(Cli::command()),
+        Some(Command::GenerateManPage(command)) => command.execute(Cli::command()),
+        Some(Command::ClipboardAgent(command)) => command.execute(),
+        Some(Command::ExecAgent(command)) => command.execute(),
         None => panic!("not implemented"),
-    }
+    };
+    otlp::span(&name, start, result.as_ref().err().map(|err| err.to_string()));
+    result
 }
@@ -1,4 +1,16 @@
+pub mod dhcp_lease;
+pub mod digest;
+pub mod disk_image;
 pub mod exception;
 pub mod file_lock;
+pub mod hosts_sync;
 pub mod json;
+pub mod log_file;
+pub mod notification;
+pub mod oci_image;
+pub mod os_log;
+pub mod otlp;
 pub mod path;
+pub mod tar;
+pub mod tcp;
+pub mod terminal;
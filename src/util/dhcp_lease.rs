@@ -0,0 +1,189 @@
+use std::fs;
+use std::io;
+use std::process::Command;
+
+use crate::util::exception::Exception;
+
+const LEASES_PATH: &str = "/var/db/dhcpd_leases";
+// static bindings of bootpd, entries after "%%" line are "<name> <hwtype> <hwaddr> <ipaddr>"
+pub const BOOTPTAB_PATH: &str = "/etc/bootptab";
+
+// find ip assigned by macOS NAT dhcp server, mac address is like "aa:bb:cc:dd:ee:ff"
+// live lease is what guest has, reservation only until guest renews, e.g. reserved while vm is running
+pub fn find_ip(mac_address: &str) -> Result<Option<String>, Exception> {
+    let leases = match fs::read_to_string(LEASES_PATH) {
+        Ok(leases) => leases,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+    match parse_ip(&leases, mac_address) {
+        Some(ip) => Ok(Some(ip)),
+        None => find_reservation(mac_address),
+    }
+}
+
+// bootpd writes hw_address as "1,a:b:c:d:e:f", without leading zeros, newest lease first
+fn parse_ip(leases: &str, mac_address: &str) -> Option<String> {
+    let mac_address = normalize_mac_address(mac_address);
+    let mut ip = None;
+    for line in leases.lines() {
+        let line = line.trim();
+        if line == "{" {
+            ip = None;
+        } else if let Some(value) = line.strip_prefix("ip_address=") {
+            ip = Some(value.to_string());
+        } else if let Some(value) = line.strip_prefix("hw_address=") {
+            let hw_address = value.split_once(',').map_or(value, |(_, address)| address);
+            if normalize_mac_address(hw_address) == mac_address && ip.is_some() {
+                return ip;
+            }
+        }
+    }
+    None
+}
+
+// guest with static ip or lease expired from file still answers arp, entry is like "? (192.168.64.3) at e:1:2:a0:b:c on bridge100 ifscope [bridge]"
+pub fn find_arp_ip(mac_address: &str) -> Result<Option<String>, Exception> {
+    let output = Command::new("arp").arg("-an").output()?;
+    if !output.status.success() {
+        return Err(Exception::ValidationError(format!("failed to read arp table, status={}", output.status)));
+    }
+    Ok(parse_arp_ip(&String::from_utf8_lossy(&output.stdout), mac_address))
+}
+
+fn parse_arp_ip(arp: &str, mac_address: &str) -> Option<String> {
+    let mac_address = normalize_mac_address(mac_address);
+    arp.lines().find_map(|line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+        [_, ip, "at", hw_address, ..] if normalize_mac_address(hw_address) == mac_address => {
+            Some(ip.trim_start_matches('(').trim_end_matches(')').to_string())
+        }
+        _ => None,
+    })
+}
+
+pub fn find_reservation(mac_address: &str) -> Result<Option<String>, Exception> {
+    let bootptab = read_bootptab()?;
+    let mac_address = normalize_mac_address(mac_address);
+    Ok(reservations(&bootptab)
+        .into_iter()
+        .find(|reservation| normalize_mac_address(reservation.mac_address) == mac_address)
+        .map(|reservation| reservation.ip.to_string()))
+}
+
+pub fn read_bootptab() -> Result<String, Exception> {
+    match fs::read_to_string(BOOTPTAB_PATH) {
+        Ok(bootptab) => Ok(bootptab),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub struct Reservation<'a> {
+    pub name: &'a str,
+    pub mac_address: &'a str,
+    pub ip: &'a str,
+}
+
+pub fn reservations(bootptab: &str) -> Vec<Reservation<'_>> {
+    bootptab
+        .lines()
+        .skip_while(|line| line.trim() != "%%")
+        .skip(1)
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [name, _, mac_address, ip, ..] => Some(Reservation { name, mac_address, ip }),
+            _ => None,
+        })
+        .collect()
+}
+
+// replace entry of vm, matched by name or mac address, remove entry if ip is none
+pub fn update_reservation(bootptab: &str, name: &str, mac_address: &str, ip: Option<&str>) -> String {
+    let mac_address = normalize_mac_address(mac_address);
+    let mut lines: Vec<String> = vec![];
+    let mut in_entries = false;
+    for line in bootptab.lines() {
+        if in_entries && !line.trim_start().starts_with('#') {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.first() == Some(&name) || fields.get(2).is_some_and(|address| normalize_mac_address(address) == mac_address) {
+                continue;
+            }
+        }
+        in_entries |= line.trim() == "%%";
+        lines.push(line.to_string());
+    }
+    if !in_entries {
+        lines.push("%%".to_string());
+    }
+    if let Some(ip) = ip {
+        lines.push(format!("{name}\t1\t{mac_address}\t{ip}"));
+    }
+    let mut result = lines.join("\n");
+    result.push('\n');
+    result
+}
+
+fn normalize_mac_address(mac_address: &str) -> String {
+    mac_address
+        .split(':')
+        .map(|octet| format!("{:0>2}", octet.to_lowercase()))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn parse_arp_ip() {
+        let arp = "? (192.168.1.1) at 0:11:22:33:44:55 on en0 ifscope [ethernet]\n\
+                   ? (192.168.64.3) at e:1:2:a0:b:c on bridge100 ifscope [bridge]\n\
+                   ? (192.168.64.255) at (incomplete) on bridge100 ifscope [bridge]\n";
+        assert_eq!(super::parse_arp_ip(arp, "0e:01:02:a0:0b:0c").as_deref(), Some("192.168.64.3"));
+        assert_eq!(super::parse_arp_ip(arp, "0e:01:02:a0:0b:0d"), None);
+    }
+
+    #[test]
+    fn parse_ip() {
+        let leases = r#"{
+	name=debian
+	ip_address=192.168.64.3
+	hw_address=1,e:1:2:a0:b:c
+	identifier=1,e:1:2:a0:b:c
+	lease=0x66a1b2c3
+}
+{
+	name=ubuntu
+	ip_address=192.168.64.2
+	hw_address=1,aa:bb:cc:dd:ee:ff
+	identifier=1,aa:bb:cc:dd:ee:ff
+	lease=0x66a1b2c0
+}
+"#;
+        assert_eq!(super::parse_ip(leases, "0e:01:02:a0:0b:0c"), Some("192.168.64.3".to_string()));
+        assert_eq!(super::parse_ip(leases, "AA:BB:CC:DD:EE:FF"), Some("192.168.64.2".to_string()));
+        assert_eq!(super::parse_ip(leases, "aa:bb:cc:dd:ee:00"), None);
+    }
+
+    #[test]
+    fn update_reservation() {
+        let bootptab = super::update_reservation("", "debian", "E:1:2:a0:b:c", Some("192.168.64.10"));
+        assert_eq!(bootptab, "%%\ndebian\t1\t0e:01:02:a0:0b:0c\t192.168.64.10\n");
+
+        let bootptab = super::update_reservation(&bootptab, "ubuntu", "aa:bb:cc:dd:ee:ff", Some("192.168.64.11"));
+        let bootptab = super::update_reservation(&bootptab, "debian", "0e:01:02:a0:0b:0c", Some("192.168.64.12"));
+        let reservations = super::reservations(&bootptab);
+        assert_eq!(reservations.len(), 2);
+        assert_eq!((reservations[0].name, reservations[0].ip), ("ubuntu", "192.168.64.11"));
+        assert_eq!((reservations[1].name, reservations[1].ip), ("debian", "192.168.64.12"));
+
+        assert_eq!(
+            super::update_reservation(&bootptab, "debian", "0e:01:02:a0:0b:0c", None),
+            "%%\nubuntu\t1\taa:bb:cc:dd:ee:ff\t192.168.64.11\n"
+        );
+    }
+
+    #[test]
+    fn normalize_mac_address() {
+        assert_eq!(super::normalize_mac_address("e:1:2:A0:b:c"), "0e:01:02:a0:0b:0c");
+    }
+}
@@ -0,0 +1,84 @@
+use std::ffi::c_void;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::util::exception::Exception;
+
+// CommonCrypto is part of libSystem
+extern "C" {
+    fn CC_SHA256(data: *const c_void, length: u32, digest: *mut u8) -> *mut u8;
+    fn CC_SHA256_Init(context: *mut Sha256Context) -> i32;
+    fn CC_SHA256_Update(context: *mut Sha256Context, data: *const c_void, length: u32) -> i32;
+    fn CC_SHA256_Final(digest: *mut u8, context: *mut Sha256Context) -> i32;
+}
+
+// CC_SHA256_CTX
+#[repr(C)]
+struct Sha256Context {
+    count: [u32; 2],
+    hash: [u32; 8],
+    wbuf: [u32; 16],
+}
+
+// length of data is CC_LONG, which is u32
+pub fn sha256_hex(data: &[u8]) -> String {
+    let mut digest = [0; 32];
+    unsafe {
+        CC_SHA256(data.as_ptr().cast(), data.len() as u32, digest.as_mut_ptr());
+    }
+    hex(&digest)
+}
+
+// file is read in chunks, e.g. ipsw or downloaded image of many gb
+pub fn file_sha256_hex(path: &Path) -> Result<String, Exception> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; 1024 * 1024];
+    let mut context = Sha256Context {
+        count: [0; 2],
+        hash: [0; 8],
+        wbuf: [0; 16],
+    };
+    let mut digest = [0; 32];
+    unsafe {
+        CC_SHA256_Init(&mut context);
+        loop {
+            let length = file.read(&mut buffer)?;
+            if length == 0 {
+                break;
+            }
+            CC_SHA256_Update(&mut context, buffer.as_ptr().cast(), length as u32);
+        }
+        CC_SHA256_Final(digest.as_mut_ptr(), &mut context);
+    }
+    Ok(hex(&digest))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use uuid::Uuid;
+
+    #[test]
+    fn sha256_hex() {
+        assert_eq!(
+            super::sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn file_sha256_hex() {
+        let path = env::temp_dir().join(format!("vz-test-{}", Uuid::new_v4()));
+        fs::write(&path, b"abc").unwrap();
+        let digest = super::file_sha256_hex(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(digest, super::sha256_hex(b"abc"));
+    }
+}
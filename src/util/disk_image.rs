@@ -0,0 +1,203 @@
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::process::Command;
+
+use tracing::info;
+
+use crate::util::exception::Exception;
+use crate::util::path;
+
+mod inflate;
+mod qcow2;
+mod vhdx;
+mod vmdk;
+
+// convert disk image to raw sparse image, return virtual size
+pub fn convert_to_raw(source: &Path, target: &Path) -> Result<u64, Exception> {
+    let mut reader = File::open(source)?;
+    let mut magic = [0; 8];
+    let length = reader.read(&mut magic)?;
+    let magic = &magic[..length];
+    if magic.starts_with(qcow2::MAGIC) {
+        return convert(source, target, "qcow2", qcow2::size, qcow2::convert);
+    }
+    if magic.starts_with(vmdk::MAGIC) {
+        return convert(source, target, "vmdk", vmdk::size, vmdk::convert);
+    }
+    if magic.starts_with(vhdx::SIGNATURE) {
+        return convert(source, target, "vhdx", vhdx::size, vhdx::convert);
+    }
+
+    info!("copy raw image, from={}, to={}", source.to_string_lossy(), target.to_string_lossy());
+    path::clone_file(source, target)?;
+    Ok(target.metadata()?.len())
+}
+
+// format disk as ext4 with content of tar, requires e2fsprogs 1.47.1+, e.g. brew install e2fsprogs
+pub fn create_ext4(tar: &Path, disk: &Path) -> Result<(), Exception> {
+    // homebrew e2fsprogs is keg-only, not in PATH by default
+    let mkfs = ["/opt/homebrew/opt/e2fsprogs/sbin/mkfs.ext4", "/usr/local/opt/e2fsprogs/sbin/mkfs.ext4"]
+        .into_iter()
+        .find(|path| Path::new(path).exists())
+        .unwrap_or("mkfs.ext4");
+    info!("create ext4 image, from={}, to={}", tar.to_string_lossy(), disk.to_string_lossy());
+    let status = Command::new(mkfs)
+        .args(["-q", "-F", "-L", "rootfs", "-E", "root_owner=0:0,lazy_itable_init=1", "-d"])
+        .arg(tar)
+        .arg(disk)
+        .status()
+        .map_err(|err| Exception::ValidationError(format!("failed to run mkfs.ext4, install e2fsprogs with brew, error={err}")))?;
+    if !status.success() {
+        return Err(Exception::ValidationError(format!("failed to create ext4 image, status={status}")));
+    }
+    Ok(())
+}
+
+// multiple of APFS block size, as required by F_PUNCHHOLE
+const PUNCH_CHUNK_SIZE: u64 = 1024 * 1024;
+
+// deallocate all zero chunks of sparse disk image, e.g. blocks freed by guest without discard
+pub fn punch_zero_chunks(path: &Path) -> Result<(), Exception> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let size = file.metadata()?.len();
+    let fd = file.as_raw_fd();
+    let mut buffer = vec![0; PUNCH_CHUNK_SIZE as usize];
+    let mut offset = 0;
+    // partial chunk at end is not aligned to block size
+    while offset + PUNCH_CHUNK_SIZE <= size {
+        // skip holes
+        let data = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+        if data < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ENXIO) {
+                break;
+            }
+            return Err(err.into());
+        }
+        offset = data as u64 / PUNCH_CHUNK_SIZE * PUNCH_CHUNK_SIZE;
+        if offset + PUNCH_CHUNK_SIZE > size {
+            break;
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut buffer)?;
+        if buffer.iter().all(|&byte| byte == 0) {
+            let hole = libc::fpunchhole_t {
+                fp_flags: 0,
+                reserved: 0,
+                fp_offset: offset as libc::off_t,
+                fp_length: PUNCH_CHUNK_SIZE as libc::off_t,
+            };
+            if unsafe { libc::fcntl(fd, libc::F_PUNCHHOLE, &hole) } != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+        }
+        offset += PUNCH_CHUNK_SIZE;
+    }
+    Ok(())
+}
+
+// physical ranges of allocated data on device, (offset, length), clones share them until either writes
+pub fn physical_extents(path: &Path) -> Result<Vec<(u64, u64)>, Exception> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let fd = file.as_raw_fd();
+    let mut extents = vec![];
+    let mut offset = 0;
+    while offset < size {
+        let data = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+        if data < 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(libc::ENXIO) {
+                break;
+            }
+            return Err(err.into());
+        }
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let (mut position, end) = (data as u64, hole as u64);
+        while position < end {
+            // takes file offset and length to map, returns device offset and contiguous length
+            let mut map = libc::log2phys {
+                l2p_flags: 0,
+                l2p_contigbytes: (end - position) as libc::off_t,
+                l2p_devoffset: position as libc::off_t,
+            };
+            if unsafe { libc::fcntl(fd, libc::F_LOG2PHYS_EXT, &mut map) } != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            let length = (map.l2p_contigbytes as u64).min(end - position);
+            if length == 0 {
+                break;
+            }
+            extents.push((map.l2p_devoffset as u64, length));
+            position += length;
+        }
+        offset = end;
+    }
+    Ok(extents)
+}
+
+// bytes of extents which are not in other, e.g. blocks written since clone was taken
+pub fn unshared_size(extents: &[(u64, u64)], other: &[(u64, u64)]) -> u64 {
+    let mut other = other.to_vec();
+    other.sort();
+    let mut size = 0;
+    for &(start, length) in extents {
+        let end = start + length;
+        let mut shared = 0;
+        let first = other.partition_point(|&(offset, length)| offset + length <= start);
+        for &(offset, length) in other[first..].iter().take_while(|(offset, _)| *offset < end) {
+            shared += end.min(offset + length) - start.max(offset);
+        }
+        size += length - shared.min(length);
+    }
+    size
+}
+
+type SizeFn = fn(&mut File) -> Result<u64, Exception>;
+type ConvertFn = fn(&mut File, &mut File) -> Result<(), Exception>;
+
+fn convert(source: &Path, target: &Path, format: &str, size: SizeFn, convert: ConvertFn) -> Result<u64, Exception> {
+    info!(
+        "convert {format} image, from={}, to={}",
+        source.to_string_lossy(),
+        target.to_string_lossy()
+    );
+    let mut reader = File::open(source)?;
+    let size = size(&mut reader)?;
+    let mut writer = File::create(target)?;
+    writer.set_len(size)?;
+    convert(&mut reader, &mut writer)?;
+    Ok(size)
+}
+
+fn le_u16(bytes: &[u8]) -> u16 {
+    u16::from_le_bytes(bytes.try_into().unwrap())
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes.try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes.try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn unshared_size() {
+        assert_eq!(super::unshared_size(&[(0, 100)], &[]), 100);
+        assert_eq!(super::unshared_size(&[(0, 100)], &[(0, 100)]), 0);
+        assert_eq!(super::unshared_size(&[(0, 100), (200, 50)], &[(50, 20), (90, 120)]), 70 + 40);
+        assert_eq!(super::unshared_size(&[(100, 10)], &[(0, 50), (200, 10)]), 10);
+    }
+}
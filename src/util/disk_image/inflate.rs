@@ -0,0 +1,273 @@
+use crate::util::exception::Exception;
+
+// minimal inflate of zlib stream (RFC 1950/1951), to read compressed vmdk grains without extra dependency,
+// output is bounded by max_size, e.g. grain size, so crafted stream can't expand without limit
+pub fn zlib_decompress(data: &[u8], max_size: usize) -> Result<Vec<u8>, Exception> {
+    // CMF must be deflate, and CMF/FLG must be multiple of 31
+    if data.len() < 2 || data[0] & 0x0f != 8 || (u16::from(data[0]) << 8 | u16::from(data[1])) % 31 != 0 {
+        return Err(invalid_data());
+    }
+    inflate(&data[2..], max_size)
+}
+
+// raw deflate stream without zlib header, e.g. compressed qcow2 cluster
+pub fn deflate_decompress(data: &[u8], max_size: usize) -> Result<Vec<u8>, Exception> {
+    inflate(data, max_size)
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn inflate(data: &[u8], max_size: usize) -> Result<Vec<u8>, Exception> {
+    let mut reader = BitReader {
+        data,
+        position: 0,
+        buffer: 0,
+        count: 0,
+    };
+    let mut output = vec![];
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => stored_block(&mut reader, &mut output, max_size)?,
+            1 => {
+                let (literal, distance) = fixed_codes();
+                compressed_block(&mut reader, &mut output, max_size, &literal, &distance)?
+            }
+            2 => {
+                let (literal, distance) = dynamic_codes(&mut reader)?;
+                compressed_block(&mut reader, &mut output, max_size, &literal, &distance)?
+            }
+            _ => return Err(invalid_data()),
+        }
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+fn stored_block(reader: &mut BitReader, output: &mut Vec<u8>, max_size: usize) -> Result<(), Exception> {
+    reader.align();
+    let length = reader.bits(16)?;
+    if length != !reader.bits(16)? & 0xffff {
+        return Err(invalid_data());
+    }
+    check_size(output.len() + length as usize, max_size)?;
+    for _ in 0..length {
+        output.push(reader.bits(8)? as u8);
+    }
+    Ok(())
+}
+
+fn compressed_block(reader: &mut BitReader, output: &mut Vec<u8>, max_size: usize, literal: &Huffman, distance: &Huffman) -> Result<(), Exception> {
+    loop {
+        let symbol = literal.decode(reader)? as usize;
+        if symbol < 256 {
+            check_size(output.len() + 1, max_size)?;
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(invalid_data());
+        }
+        let length = LENGTH_BASE[symbol] as usize + reader.bits(LENGTH_EXTRA[symbol].into())? as usize;
+        let symbol = distance.decode(reader)? as usize;
+        if symbol >= DISTANCE_BASE.len() {
+            return Err(invalid_data());
+        }
+        let distance = DISTANCE_BASE[symbol] as usize + reader.bits(DISTANCE_EXTRA[symbol].into())? as usize;
+        if distance > output.len() {
+            return Err(invalid_data());
+        }
+        check_size(output.len() + length, max_size)?;
+        // copy byte by byte, source and destination may overlap
+        let start = output.len() - distance;
+        for index in start..start + length {
+            output.push(output[index]);
+        }
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+    lengths[0..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..288].fill(8);
+    (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), Exception> {
+    let literals = reader.bits(5)? as usize + 257;
+    let distances = reader.bits(5)? as usize + 1;
+    let code_lengths = reader.bits(4)? as usize + 4;
+
+    let mut lengths = [0; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length = Huffman::new(&lengths);
+
+    let mut lengths = vec![];
+    while lengths.len() < literals + distances {
+        let symbol = code_length.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or_else(invalid_data)?, 3 + reader.bits(2)?),
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() != literals + distances {
+        return Err(invalid_data());
+    }
+    Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> Result<u32, Exception> {
+        while self.count < count {
+            let byte = *self.data.get(self.position).ok_or_else(invalid_data)?;
+            self.buffer |= u32::from(byte) << self.count;
+            self.position += 1;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1 << count) - 1);
+        self.buffer >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    // discard remaining bits of current byte
+    fn align(&mut self) {
+        let count = self.count % 8;
+        self.buffer >>= count;
+        self.count -= count;
+    }
+}
+
+// canonical huffman code, symbols are ordered by code length then by symbol value
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols = vec![];
+        for length in 1..16 {
+            for (symbol, _) in lengths.iter().enumerate().filter(|(_, &value)| value == length) {
+                symbols.push(symbol as u16);
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    // codes are packed starting from most significant bit
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, Exception> {
+        let mut code = 0;
+        let mut first = 0;
+        let mut index = 0;
+        for &count in &self.counts[1..] {
+            code |= reader.bits(1)? as usize;
+            let count = count as usize;
+            if code < first + count {
+                return self.symbols.get(index + code - first).copied().ok_or_else(invalid_data);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid_data())
+    }
+}
+
+fn check_size(size: usize, max_size: usize) -> Result<(), Exception> {
+    if size > max_size {
+        return Err(Exception::ValidationError(format!(
+            "decompressed data exceeds max size, max_size={max_size}"
+        )));
+    }
+    Ok(())
+}
+
+fn invalid_data() -> Exception {
+    Exception::ValidationError("invalid compressed data".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    fn decode_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn stored_block() {
+        let data = super::zlib_decompress(&decode_hex("7801010500faff68656c6c6f062c0215"), 5).unwrap();
+        assert_eq!(data, b"hello");
+    }
+
+    #[test]
+    fn fixed_codes() {
+        let data = super::zlib_decompress(&decode_hex("789ccb48cdc9c957c84090003a2e067d"), 17).unwrap();
+        assert_eq!(data, b"hello hello hello");
+    }
+
+    #[test]
+    fn dynamic_codes() {
+        let data = super::zlib_decompress(
+            &decode_hex(
+                "78da2d8ec911c0300c025b516b1cfdd7102f9397415c969b48726ceb21eb0a8df278e042e7b5c461560d20f67c2652e44bfc9cbd59cef5235a1579a303b7e64dadb75cac7fe33a48f8f006478fe8fb29cdd5fc34d0f60177ce48e1",
+            ),
+            4096,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            "abdccaaabcbbbacaaba daabccacbaccaba aaaabba abaacabacbbbaaabacba aad b baabcdaaca ccb abd baaab bdbabdabaccaabaab ccbcaabbbacabdaabcabaacdbbbabaabaacab dbaabadbab abbbcaacdd cbcabcbaaabdabbababdaaaabb"
+        );
+    }
+
+    #[test]
+    fn max_size() {
+        assert!(super::zlib_decompress(&decode_hex("7801010500faff68656c6c6f062c0215"), 4).is_err());
+        // back reference expands 17 bytes from 7 literals
+        assert!(super::zlib_decompress(&decode_hex("789ccb48cdc9c957c84090003a2e067d"), 16).is_err());
+    }
+
+    #[test]
+    fn invalid_header() {
+        assert!(super::zlib_decompress(b"\x78\x00", 0).is_err());
+    }
+}
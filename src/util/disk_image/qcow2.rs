@@ -0,0 +1,201 @@
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+
+use super::inflate;
+use crate::util::exception::Exception;
+
+pub const MAGIC: &[u8; 4] = b"QFI\xfb";
+
+struct Qcow2Header {
+    cluster_bits: u32,
+    size: u64,
+    l1_size: u32,
+    l1_table_offset: u64,
+}
+
+fn read_qcow2_header<R: Read + Seek>(reader: &mut R) -> Result<Qcow2Header, Exception> {
+    let mut header = [0; 104];
+    reader.seek(SeekFrom::Start(0))?;
+    let length = reader.read(&mut header)?;
+    if length < 72 || &header[0..4] != MAGIC {
+        return Err(Exception::ValidationError("invalid qcow2 image".to_string()));
+    }
+    let version = be_u32(&header[4..8]);
+    if be_u64(&header[8..16]) != 0 {
+        return Err(Exception::ValidationError("qcow2 image with backing file is not supported".to_string()));
+    }
+    if be_u32(&header[32..36]) != 0 {
+        return Err(Exception::ValidationError("encrypted qcow2 image is not supported".to_string()));
+    }
+    // bit 4: extended l2 entries
+    if version >= 3 && length >= 80 && be_u64(&header[72..80]) & (1 << 4) != 0 {
+        return Err(Exception::ValidationError(
+            "qcow2 image with extended l2 entries is not supported".to_string(),
+        ));
+    }
+    let header = Qcow2Header {
+        cluster_bits: be_u32(&header[20..24]),
+        size: be_u64(&header[24..32]),
+        l1_size: be_u32(&header[36..40]),
+        l1_table_offset: be_u64(&header[40..48]),
+    };
+    // qemu limits cluster size to 512 bytes - 2M
+    if !(9..=21).contains(&header.cluster_bits) {
+        return Err(Exception::ValidationError(format!(
+            "invalid qcow2 cluster size, cluster_bits={}",
+            header.cluster_bits
+        )));
+    }
+    // each l1 entry maps one l2 table of cluster_size / 8 clusters
+    let required_l1_size = header.size.div_ceil(1 << (2 * header.cluster_bits - 3));
+    if u64::from(header.l1_size) > required_l1_size {
+        return Err(Exception::ValidationError(format!(
+            "invalid qcow2 l1 table size, l1_size={}, required={required_l1_size}",
+            header.l1_size
+        )));
+    }
+    Ok(header)
+}
+
+pub fn size<R: Read + Seek>(reader: &mut R) -> Result<u64, Exception> {
+    Ok(read_qcow2_header(reader)?.size)
+}
+
+// only write allocated non-zero clusters, unallocated clusters remain as holes in target
+pub fn convert<R: Read + Seek, W: Write + Seek>(reader: &mut R, writer: &mut W) -> Result<(), Exception> {
+    const OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+    const COMPRESSED: u64 = 1 << 62;
+    const ZERO: u64 = 1;
+
+    let header = read_qcow2_header(reader)?;
+    let cluster_size = 1u64 << header.cluster_bits;
+    let l2_entries = cluster_size / 8;
+
+    let l1_table = read_table(reader, header.l1_table_offset, header.l1_size as usize)?;
+    let mut cluster = vec![0; cluster_size as usize];
+    for (l1_index, l1_entry) in l1_table.iter().enumerate() {
+        let l2_offset = l1_entry & OFFSET_MASK;
+        if l2_offset == 0 {
+            continue;
+        }
+        let l2_table = read_table(reader, l2_offset, l2_entries as usize)?;
+        for (l2_index, l2_entry) in l2_table.iter().enumerate() {
+            let guest_offset = (l1_index as u64 * l2_entries + l2_index as u64) * cluster_size;
+            if guest_offset >= header.size {
+                return Ok(());
+            }
+            let length = cluster_size.min(header.size - guest_offset) as usize;
+            if l2_entry & COMPRESSED != 0 {
+                let data = read_compressed_cluster(reader, *l2_entry, header.cluster_bits)?;
+                let data = &data[..data.len().min(length)];
+                if data.iter().any(|&byte| byte != 0) {
+                    writer.seek(SeekFrom::Start(guest_offset))?;
+                    writer.write_all(data)?;
+                }
+                continue;
+            }
+            let host_offset = l2_entry & OFFSET_MASK;
+            if host_offset == 0 || l2_entry & ZERO != 0 {
+                continue;
+            }
+            reader.seek(SeekFrom::Start(host_offset))?;
+            reader.read_exact(&mut cluster[..length])?;
+            if cluster[..length].iter().all(|&byte| byte == 0) {
+                continue;
+            }
+            writer.seek(SeekFrom::Start(guest_offset))?;
+            writer.write_all(&cluster[..length])?;
+        }
+    }
+    Ok(())
+}
+
+// compressed l2 entry: host offset in low 62 - (cluster_bits - 8) bits, then number of additional 512 bytes sectors
+fn read_compressed_cluster<R: Read + Seek>(reader: &mut R, l2_entry: u64, cluster_bits: u32) -> Result<Vec<u8>, Exception> {
+    let offset_bits = 62 - (cluster_bits - 8);
+    let host_offset = l2_entry & ((1 << offset_bits) - 1);
+    let sectors = (l2_entry & ((1 << 62) - 1)) >> offset_bits;
+    let length = (sectors + 1) * 512 - (host_offset & 511);
+    // last compressed cluster may end before its last sector
+    let mut compressed = vec![];
+    reader.seek(SeekFrom::Start(host_offset))?;
+    reader.take(length).read_to_end(&mut compressed)?;
+    inflate::deflate_decompress(&compressed, 1 << cluster_bits)
+}
+
+fn read_table<R: Read + Seek>(reader: &mut R, offset: u64, entries: usize) -> Result<Vec<u64>, Exception> {
+    let mut table = vec![0; entries * 8];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut table)?;
+    Ok(table.chunks_exact(8).map(be_u64).collect())
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes.try_into().unwrap())
+}
+
+fn be_u64(bytes: &[u8]) -> u64 {
+    u64::from_be_bytes(bytes.try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    // 2 clusters of 512 bytes, l1 at 512, l2 at 1024, first data cluster at 1536, second cluster unallocated
+    fn qcow2_image() -> Vec<u8> {
+        let mut image = vec![0; 2048];
+        image[0..4].copy_from_slice(super::MAGIC);
+        image[4..8].copy_from_slice(&3u32.to_be_bytes());
+        image[20..24].copy_from_slice(&9u32.to_be_bytes());
+        image[24..32].copy_from_slice(&1024u64.to_be_bytes());
+        image[36..40].copy_from_slice(&1u32.to_be_bytes());
+        image[40..48].copy_from_slice(&512u64.to_be_bytes());
+        image[512..520].copy_from_slice(&(1024u64 | 1 << 63).to_be_bytes());
+        image[1024..1032].copy_from_slice(&(1536u64 | 1 << 63).to_be_bytes());
+        image[1536..2048].fill(0xab);
+        image
+    }
+
+    #[test]
+    fn convert_qcow2() {
+        let mut reader = Cursor::new(qcow2_image());
+        assert_eq!(super::size(&mut reader).unwrap(), 1024);
+
+        let mut writer = Cursor::new(vec![0; 1024]);
+        super::convert(&mut reader, &mut writer).unwrap();
+        let raw = writer.into_inner();
+        assert!(raw[..512].iter().all(|&byte| byte == 0xab));
+        assert!(raw[512..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn convert_compressed_qcow2() {
+        // first cluster stored as uncompressed deflate block at 2048, spans 2 sectors
+        let mut image = qcow2_image();
+        image.resize(2560, 0);
+        image[1024..1032].copy_from_slice(&(2048u64 | 1 << 61 | 1 << 62).to_be_bytes());
+        image[2048..2053].copy_from_slice(&[0x01, 0x00, 0x02, 0xff, 0xfd]);
+        image[2053..2560].fill(0xcd);
+        image.extend_from_slice(&[0xcd; 5]);
+
+        let mut writer = Cursor::new(vec![0; 1024]);
+        super::convert(&mut Cursor::new(image), &mut writer).unwrap();
+        let raw = writer.into_inner();
+        assert!(raw[..512].iter().all(|&byte| byte == 0xcd));
+        assert!(raw[512..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn invalid_qcow2_header() {
+        let mut image = qcow2_image();
+        image[20..24].copy_from_slice(&22u32.to_be_bytes());
+        assert!(super::size(&mut Cursor::new(image)).is_err());
+
+        let mut image = qcow2_image();
+        image[36..40].copy_from_slice(&2u32.to_be_bytes());
+        assert!(super::size(&mut Cursor::new(image)).is_err());
+    }
+}
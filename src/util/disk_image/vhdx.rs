@@ -0,0 +1,242 @@
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+
+use super::le_u16;
+use super::le_u32;
+use super::le_u64;
+use crate::util::exception::Exception;
+
+pub const SIGNATURE: &[u8; 8] = b"vhdxfile";
+const MB: u64 = 1024 * 1024;
+
+// refer to https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-vhdx
+const BAT_REGION: &str = "2DC27766-F623-4200-9D64-115E9BFD4A08";
+const METADATA_REGION: &str = "8B7CA206-4790-4B9A-B8FE-575F050F886E";
+const FILE_PARAMETERS: &str = "CAA16737-FA36-4D43-B3B6-33F0AA44E76B";
+const VIRTUAL_DISK_SIZE: &str = "2FA54224-CD1B-4876-B211-5DBED83BF4B8";
+const LOGICAL_SECTOR_SIZE: &str = "8141BF1D-A96F-4709-BA47-F233A8FAAB5F";
+
+struct Header {
+    block_size: u64,
+    size: u64,
+    logical_sector_size: u64,
+    bat_offset: u64,
+}
+
+fn read_header<R: Read + Seek>(reader: &mut R) -> Result<Header, Exception> {
+    const HAS_PARENT: u32 = 1 << 1;
+
+    let mut signature = [0; 8];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut signature)?;
+    if &signature != SIGNATURE {
+        return Err(Exception::ValidationError("invalid vhdx image".to_string()));
+    }
+
+    // use header with larger sequence number, log must be empty, otherwise metadata may be stale
+    let mut current: Option<[u8; 64]> = None;
+    for offset in [64 * 1024, 128 * 1024] {
+        let mut header = [0; 64];
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut header)?;
+        if &header[0..4] == b"head" && current.is_none_or(|current| le_u64(&header[8..16]) > le_u64(&current[8..16])) {
+            current = Some(header);
+        }
+    }
+    let header = current.ok_or_else(|| Exception::ValidationError("invalid vhdx image, header not found".to_string()))?;
+    if header[48..64].iter().any(|&byte| byte != 0) {
+        return Err(Exception::ValidationError(
+            "vhdx image has pending log, mount it once with hyper-v to replay log".to_string(),
+        ));
+    }
+
+    let regions = read_table_block(reader, 192 * 1024, b"regi")?;
+    let regions = entries(&regions[16..], le_u32(&regions[8..12]) as usize);
+    let bat_offset = le_u64(&find_entry(&regions, BAT_REGION)?[16..24]);
+    let metadata_offset = le_u64(&find_entry(&regions, METADATA_REGION)?[16..24]);
+
+    let metadata = read_table_block(reader, metadata_offset, b"metadata")?;
+    let metadata = entries(&metadata[32..], le_u16(&metadata[10..12]) as usize);
+    let mut metadata_item = |id: &str, length: usize| -> Result<Vec<u8>, Exception> {
+        let offset = le_u32(&find_entry(&metadata, id)?[16..20]) as u64;
+        let mut item = vec![0; length];
+        reader.seek(SeekFrom::Start(metadata_offset + offset))?;
+        reader.read_exact(&mut item)?;
+        Ok(item)
+    };
+    let file_parameters = metadata_item(FILE_PARAMETERS, 8)?;
+    if le_u32(&file_parameters[4..8]) & HAS_PARENT != 0 {
+        return Err(Exception::ValidationError("differencing vhdx image is not supported".to_string()));
+    }
+    let block_size = le_u32(&file_parameters[0..4]) as u64;
+    let size = le_u64(&metadata_item(VIRTUAL_DISK_SIZE, 8)?);
+    let logical_sector_size = le_u32(&metadata_item(LOGICAL_SECTOR_SIZE, 4)?) as u64;
+    if block_size < MB || !block_size.is_multiple_of(MB) || logical_sector_size == 0 {
+        return Err(Exception::ValidationError(format!("invalid vhdx image, block_size={block_size}")));
+    }
+    Ok(Header {
+        block_size,
+        size,
+        logical_sector_size,
+        bat_offset,
+    })
+}
+
+pub fn size<R: Read + Seek>(reader: &mut R) -> Result<u64, Exception> {
+    Ok(read_header(reader)?.size)
+}
+
+// only write fully present blocks, other blocks are zero or unallocated and remain as holes in target
+pub fn convert<R: Read + Seek, W: Write + Seek>(reader: &mut R, writer: &mut W) -> Result<(), Exception> {
+    const STATE_MASK: u64 = 0b111;
+    const FULLY_PRESENT: u64 = 6;
+    const PARTIALLY_PRESENT: u64 = 7;
+
+    let header = read_header(reader)?;
+    // bat interleaves one sector bitmap entry after every chunk ratio payload entries
+    let chunk_ratio = (1 << 23) * header.logical_sector_size / header.block_size;
+    let blocks = header.size.div_ceil(header.block_size);
+    if blocks == 0 {
+        return Ok(());
+    }
+    let bat = read_table(reader, header.bat_offset, (blocks - 1 + (blocks - 1) / chunk_ratio + 1) as usize)?;
+
+    let mut chunk = vec![0; MB as usize];
+    for block in 0..blocks {
+        let entry = bat[(block + block / chunk_ratio) as usize];
+        match entry & STATE_MASK {
+            FULLY_PRESENT => {}
+            PARTIALLY_PRESENT => return Err(Exception::ValidationError("differencing vhdx image is not supported".to_string())),
+            _ => continue,
+        }
+        let host_offset = (entry >> 20) * MB;
+        let guest_offset = block * header.block_size;
+        let length = header.block_size.min(header.size - guest_offset);
+        for chunk_offset in (0..length).step_by(MB as usize) {
+            let chunk = &mut chunk[..MB.min(length - chunk_offset) as usize];
+            reader.seek(SeekFrom::Start(host_offset + chunk_offset))?;
+            reader.read_exact(chunk)?;
+            if chunk.iter().all(|&byte| byte == 0) {
+                continue;
+            }
+            writer.seek(SeekFrom::Start(guest_offset + chunk_offset))?;
+            writer.write_all(chunk)?;
+        }
+    }
+    Ok(())
+}
+
+// region table and metadata table are 64KB, start with signature, entries are 32 bytes and start with guid
+fn read_table_block<R: Read + Seek>(reader: &mut R, offset: u64, signature: &[u8]) -> Result<Vec<u8>, Exception> {
+    let mut table = vec![0; 64 * 1024];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut table)?;
+    if !table.starts_with(signature) {
+        return Err(Exception::ValidationError(format!(
+            "invalid vhdx image, table not found, signature={}",
+            String::from_utf8_lossy(signature)
+        )));
+    }
+    Ok(table)
+}
+
+fn entries(table: &[u8], count: usize) -> Vec<&[u8]> {
+    table.chunks_exact(32).take(count).collect()
+}
+
+fn find_entry<'a>(entries: &[&'a [u8]], id: &str) -> Result<&'a [u8], Exception> {
+    let id_bytes = guid(id);
+    entries
+        .iter()
+        .find(|entry| entry[0..16] == id_bytes)
+        .copied()
+        .ok_or_else(|| Exception::ValidationError(format!("invalid vhdx image, entry not found, id={id}")))
+}
+
+fn read_table<R: Read + Seek>(reader: &mut R, offset: u64, entries: usize) -> Result<Vec<u64>, Exception> {
+    let mut table = vec![0; entries * 8];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut table)?;
+    Ok(table.chunks_exact(8).map(le_u64).collect())
+}
+
+// guid is stored with first 3 fields in little endian
+fn guid(value: &str) -> [u8; 16] {
+    let hex: Vec<u8> = value.bytes().filter(|&byte| byte != b'-').collect();
+    let mut bytes = [0; 16];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(std::str::from_utf8(&hex[index * 2..index * 2 + 2]).unwrap(), 16).unwrap();
+    }
+    bytes[0..4].reverse();
+    bytes[4..6].reverse();
+    bytes[6..8].reverse();
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::guid;
+    use super::MB;
+
+    // 2 blocks of 1MB, bat at 1MB, metadata at 2MB, first block at 3MB, second block not present
+    fn vhdx_image() -> Vec<u8> {
+        let mut image = vec![0; 4 * MB as usize];
+        image[0..8].copy_from_slice(super::SIGNATURE);
+        image[0x10000..0x10004].copy_from_slice(b"head");
+        image[0x10008..0x10010].copy_from_slice(&1u64.to_le_bytes());
+
+        let regions = 0x30000;
+        image[regions..regions + 4].copy_from_slice(b"regi");
+        image[regions + 8..regions + 12].copy_from_slice(&2u32.to_le_bytes());
+        for (index, (id, offset)) in [(super::BAT_REGION, MB), (super::METADATA_REGION, 2 * MB)].iter().enumerate() {
+            let entry = regions + 16 + index * 32;
+            image[entry..entry + 16].copy_from_slice(&guid(id));
+            image[entry + 16..entry + 24].copy_from_slice(&offset.to_le_bytes());
+        }
+
+        let metadata = 2 * MB as usize;
+        image[metadata..metadata + 8].copy_from_slice(b"metadata");
+        image[metadata + 10..metadata + 12].copy_from_slice(&3u16.to_le_bytes());
+        let items: [(&str, u64); 3] = [
+            (super::FILE_PARAMETERS, MB),
+            (super::VIRTUAL_DISK_SIZE, 2 * MB),
+            (super::LOGICAL_SECTOR_SIZE, 512),
+        ];
+        for (index, (id, value)) in items.iter().enumerate() {
+            let entry = metadata + 32 + index * 32;
+            let offset = 0x10000 + index * 8;
+            image[entry..entry + 16].copy_from_slice(&guid(id));
+            image[entry + 16..entry + 20].copy_from_slice(&(offset as u32).to_le_bytes());
+            image[metadata + offset..metadata + offset + 8].copy_from_slice(&value.to_le_bytes());
+        }
+
+        let bat = MB as usize;
+        image[bat..bat + 8].copy_from_slice(&(3 << 20 | 6u64).to_le_bytes());
+        image[3 * MB as usize..].fill(0xab);
+        image
+    }
+
+    #[test]
+    fn convert_vhdx() {
+        let mut reader = Cursor::new(vhdx_image());
+        assert_eq!(super::size(&mut reader).unwrap(), 2 * MB);
+
+        let mut writer = Cursor::new(vec![0; 2 * MB as usize]);
+        super::convert(&mut reader, &mut writer).unwrap();
+        let raw = writer.into_inner();
+        assert!(raw[..MB as usize].iter().all(|&byte| byte == 0xab));
+        assert!(raw[MB as usize..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn guid_bytes() {
+        assert_eq!(
+            guid("2DC27766-F623-4200-9D64-115E9BFD4A08"),
+            [0x66, 0x77, 0xc2, 0x2d, 0x23, 0xf6, 0x00, 0x42, 0x9d, 0x64, 0x11, 0x5e, 0x9b, 0xfd, 0x4a, 0x08]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+
+use super::inflate;
+use super::le_u16;
+use super::le_u32;
+use super::le_u64;
+use crate::util::exception::Exception;
+
+pub const MAGIC: &[u8; 4] = b"KDMV";
+const SECTOR_SIZE: u64 = 512;
+
+// refer to https://github.com/libyal/libvmdk/blob/main/documentation/VMware%20Virtual%20Disk%20Format%20(VMDK).asciidoc
+struct Header {
+    capacity: u64,
+    grain_size: u64,
+    over_head: u64,
+}
+
+fn read_header<R: Read + Seek>(reader: &mut R) -> Result<Header, Exception> {
+    const COMPRESSED: u32 = 1 << 16;
+    const MARKERS: u32 = 1 << 17;
+    const DEFLATE: u16 = 1;
+
+    let mut header = [0; 512];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut header)?;
+    if &header[0..4] != MAGIC {
+        return Err(Exception::ValidationError("invalid vmdk image".to_string()));
+    }
+    let flags = le_u32(&header[8..12]);
+    if flags & COMPRESSED == 0 || flags & MARKERS == 0 || le_u16(&header[77..79]) != DEFLATE {
+        return Err(Exception::ValidationError("only streamOptimized vmdk image is supported".to_string()));
+    }
+    let header = Header {
+        capacity: le_u64(&header[12..20]),
+        grain_size: le_u64(&header[20..28]),
+        over_head: le_u64(&header[64..72]),
+    };
+    // in sectors, qemu limits it to 1G
+    if !header.grain_size.is_power_of_two() || header.grain_size > 0x200000 {
+        return Err(Exception::ValidationError(format!(
+            "invalid vmdk grain size, grain_size={}",
+            header.grain_size
+        )));
+    }
+    Ok(header)
+}
+
+pub fn size<R: Read + Seek>(reader: &mut R) -> Result<u64, Exception> {
+    Ok(read_header(reader)?.capacity * SECTOR_SIZE)
+}
+
+// streamOptimized image is sequence of sector aligned markers after overhead,
+// grain marker is followed by deflated grain, metadata marker is followed by grain table, grain directory or footer
+pub fn convert<R: Read + Seek, W: Write + Seek>(reader: &mut R, writer: &mut W) -> Result<(), Exception> {
+    const END_OF_STREAM: u32 = 0;
+
+    let header = read_header(reader)?;
+    let size = header.capacity * SECTOR_SIZE;
+    let grain_size = header.grain_size * SECTOR_SIZE;
+    let mut offset = header.over_head * SECTOR_SIZE;
+    loop {
+        let mut marker = [0; 16];
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut marker)?;
+        let value = le_u64(&marker[0..8]);
+        let length = le_u32(&marker[8..12]) as u64;
+        if length == 0 {
+            if le_u32(&marker[12..16]) == END_OF_STREAM {
+                return Ok(());
+            }
+            // value is number of metadata sectors after marker sector
+            offset += (value + 1) * SECTOR_SIZE;
+            continue;
+        }
+
+        // value is guest sector of grain
+        let guest_offset = value * SECTOR_SIZE;
+        if guest_offset >= size {
+            return Err(Exception::ValidationError(format!("invalid vmdk grain, sector={value}")));
+        }
+        let mut compressed = vec![0; length as usize];
+        reader.seek(SeekFrom::Start(offset + 12))?;
+        reader.read_exact(&mut compressed)?;
+        let grain = inflate::zlib_decompress(&compressed, grain_size as usize)?;
+        let grain = &grain[..grain.len().min((size - guest_offset) as usize)];
+        if grain.iter().any(|&byte| byte != 0) {
+            writer.seek(SeekFrom::Start(guest_offset))?;
+            writer.write_all(grain)?;
+        }
+        offset = (offset + 12 + length).next_multiple_of(SECTOR_SIZE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    // 2 grains of 512 bytes, first grain stored as uncompressed deflate block, second grain absent
+    fn vmdk_image() -> Vec<u8> {
+        let mut image = vec![0; 512 * 4];
+        image[0..4].copy_from_slice(super::MAGIC);
+        image[8..12].copy_from_slice(&(1u32 << 16 | 1 << 17).to_le_bytes());
+        image[12..20].copy_from_slice(&2u64.to_le_bytes());
+        image[20..28].copy_from_slice(&1u64.to_le_bytes());
+        image[64..72].copy_from_slice(&1u64.to_le_bytes());
+        image[77..79].copy_from_slice(&1u16.to_le_bytes());
+
+        let mut grain = vec![0x78, 0x01, 0x01, 0x00, 0x02, 0xff, 0xfd];
+        grain.extend_from_slice(&[0xab; 512]);
+        grain.extend_from_slice(&[0; 4]);
+        image[512..520].copy_from_slice(&0u64.to_le_bytes());
+        image[520..524].copy_from_slice(&(grain.len() as u32).to_le_bytes());
+        image[524..524 + grain.len()].copy_from_slice(&grain);
+        // end of stream marker at sector 3 is all zero
+        image
+    }
+
+    #[test]
+    fn convert_vmdk() {
+        let mut reader = Cursor::new(vmdk_image());
+        assert_eq!(super::size(&mut reader).unwrap(), 1024);
+
+        let mut writer = Cursor::new(vec![0; 1024]);
+        super::convert(&mut reader, &mut writer).unwrap();
+        let raw = writer.into_inner();
+        assert!(raw[..512].iter().all(|&byte| byte == 0xab));
+        assert!(raw[512..].iter().all(|&byte| byte == 0));
+    }
+}
@@ -35,8 +35,8 @@ impl Exception {
         }
     }
 
-    pub fn from_ns_error(err: *mut NSError) -> Self {
-        Self::ObjcError(unsafe { (*err).localizedDescription().to_string() })
+    pub(crate) fn from_ns_error(err: *mut NSError) -> Self {
+        Self::ObjcError(ns_error_message(unsafe { &*err }))
     }
 }
 
@@ -72,7 +72,7 @@ impl From<RecvError> for Exception {
 
 impl From<Retained<NSError>> for Exception {
     fn from(err: Retained<NSError>) -> Self {
-        Exception::ObjcError(err.localizedDescription().to_string())
+        Exception::ObjcError(ns_error_message(&err))
     }
 }
 
@@ -92,3 +92,55 @@ impl From<ParseIntError> for Exception {
         Exception::ValidationError(err.to_string())
     }
 }
+
+fn ns_error_message(err: &NSError) -> String {
+    let description = err.localizedDescription().to_string();
+    let domain = err.domain().to_string();
+    let code = err.code();
+    match hint(&domain, code, &description) {
+        Some(hint) => format!("{description}, domain={domain}, code={code}, hint={hint}"),
+        None => format!("{description}, domain={domain}, code={code}"),
+    }
+}
+
+// codes of VZErrorCode, installation errors start from 10001
+fn hint(domain: &str, code: isize, description: &str) -> Option<&'static str> {
+    if description.contains("entitlement") {
+        return Some("sign binary with com.apple.security.virtualization entitlement, bridged network also requires com.apple.vm.networking");
+    }
+    if domain != "VZErrorDomain" {
+        return None;
+    }
+    match code {
+        2 => Some("check cpu, memory and devices in config.json against vz host info"),
+        3 | 4 => Some("vm is not in state for this operation, check status with vz ls"),
+        5 => Some(
+            "disk image is invalid or is used by other process, create vm from it with vz create --disk-image to convert it, or check with vz ls",
+        ),
+        6 => Some("too many vms are running, macOS allows only 2 macOS vms at same time"),
+        7 => Some("check network interfaces in config.json, bridged network requires com.apple.vm.networking entitlement"),
+        8 => Some("host is out of disk space, free space or run vz gc"),
+        10 => Some("not supported by this host or macOS version, check with vz host info"),
+        10001..=10003 => Some("failed to fetch restore image catalog, check network or download ipsw manually with vz ipsw"),
+        10004 | 10005 => Some("ipsw is invalid or incomplete, download it again"),
+        10006 => Some("install latest macOS updates on host, restore image requires newer Virtualization.framework"),
+        10007 => Some("installation failed, retry with vz install, or use other restore image"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn hint() {
+        assert!(super::hint("VZErrorDomain", 8, "disk full").unwrap().contains("disk space"));
+        assert!(super::hint("VZErrorDomain", 2, "process is missing entitlement")
+            .unwrap()
+            .contains("com.apple.security.virtualization"));
+        assert_eq!(None, super::hint("NSPOSIXErrorDomain", 8, "exec format error"));
+        assert_eq!(None, super::hint("VZErrorDomain", 1, "internal error"));
+        assert!(super::hint("VZErrorDomain", 5, "invalid disk image")
+            .unwrap()
+            .contains("vz create --disk-image"));
+    }
+}
@@ -1,57 +1,95 @@
-use std::ffi::CString;
-use std::os::fd::RawFd;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::os::fd::AsRawFd;
 use std::path::Path;
+use std::process;
+use std::thread::sleep;
+use std::time::Duration;
 
 use libc::pid_t;
 
+// flock is bound to open file, unlike fcntl lock it's not released when process closes other fd of same file,
+// it's released once process exits, so file content is never stale while locked
 pub struct FileLock {
-    fd: RawFd,
+    _file: File,
+}
+
+// pid and command of process holding the lock, written into lock file
+#[derive(Debug, PartialEq)]
+pub struct Owner {
+    pub pid: pid_t,
+    pub command: String,
 }
 
 impl FileLock {
-    // fd returned by File.as_raw_fd() doesn't work with fcntl
-    pub fn new(path: &Path) -> Self {
-        let path = CString::new(path.to_string_lossy().as_bytes()).unwrap();
-        let fd = unsafe { libc::open(path.as_ptr(), libc::O_WRONLY) };
-        Self { fd }
+    // none if other process holds the lock
+    pub fn try_lock(path: &Path, command: &str) -> io::Result<Option<FileLock>> {
+        let mut file = File::options().create(true).truncate(false).read(true).write(true).open(path)?;
+        // owner() takes shared lock for a moment, retry before giving up
+        let mut attempts = 0;
+        while !flock(&file, libc::LOCK_EX)? {
+            attempts += 1;
+            if attempts == 5 {
+                return Ok(None);
+            }
+            sleep(Duration::from_millis(20));
+        }
+        file.set_len(0)?;
+        file.write_all(format_owner(process::id() as pid_t, command).as_bytes())?;
+        Ok(Some(FileLock { _file: file }))
     }
+}
 
-    // refer to "man fcntl", once process obtain the lock, it must not reopen fd and close if,
-    // close fd will release all locks of current process !!! e.g. lock one file, then read the file / close file
-    // https://apenwarr.ca/log/20101213
-    pub fn lock(&self) -> bool {
-        let lock = libc::flock {
-            l_start: 0,
-            l_len: 0,
-            l_pid: -1,
-            l_type: libc::F_WRLCK,
-            l_whence: libc::SEEK_SET as libc::c_short,
-        };
-        let result = unsafe { libc::fcntl(self.fd, libc::F_SETLK, &lock) };
-        result == 0
+// none if lock is not held
+pub fn owner(path: &Path) -> Option<Owner> {
+    let file = File::open(path).ok()?;
+    // got shared lock, so no one holds exclusive one, closing file releases it
+    if flock(&file, libc::LOCK_SH).ok()? {
+        return None;
     }
+    parse_owner(&fs::read_to_string(path).ok()?)
+}
 
-    // return pid of write lock owner
-    pub fn pid(&self) -> Option<pid_t> {
-        let mut lock = libc::flock {
-            l_start: 0,
-            l_len: 0,
-            l_pid: -1,
-            l_type: libc::F_RDLCK,
-            l_whence: libc::SEEK_SET as libc::c_short,
-        };
-        unsafe { libc::fcntl(self.fd, libc::F_GETLK, &mut lock) };
-        if lock.l_type == libc::F_WRLCK {
-            Some(lock.l_pid)
-        } else {
-            None
-        }
+// false if lock is held by other process
+fn flock(file: &File, operation: i32) -> io::Result<bool> {
+    if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+        return Ok(true);
     }
+    let err = io::Error::last_os_error();
+    if err.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        return Ok(false);
+    }
+    Err(err)
+}
+
+fn format_owner(pid: pid_t, command: &str) -> String {
+    format!("{pid} {command}\n")
 }
 
-impl Drop for FileLock {
-    fn drop(&mut self) {
-        // close fd will release all locks
-        unsafe { libc::close(self.fd) };
+fn parse_owner(content: &str) -> Option<Owner> {
+    let (pid, command) = content.trim_end().split_once(' ')?;
+    Some(Owner {
+        pid: pid.parse().ok()?,
+        command: command.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Owner;
+
+    #[test]
+    fn parse_owner() {
+        assert_eq!(
+            super::parse_owner(&super::format_owner(1234, "run")),
+            Some(Owner {
+                pid: 1234,
+                command: "run".to_string()
+            })
+        );
+        assert_eq!(super::parse_owner(""), None);
+        assert_eq!(super::parse_owner("abc run"), None);
     }
 }
@@ -0,0 +1,40 @@
+use std::env::current_exe;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use tracing::info;
+use tracing::warn;
+
+static VM_NAME: OnceLock<String> = OnceLock::new();
+
+// /etc/hosts is only synced by runner once enabled, e.g. by syncHosts of settings.json
+pub fn enable(vm_name: &str) {
+    let _ = VM_NAME.set(vm_name.to_string());
+}
+
+// runner is not root, it runs vz hosts sync by sudo without password prompt, stopped vm is excluded as its runner is still alive
+pub fn sync(stopped: bool) {
+    let Some(name) = VM_NAME.get() else {
+        return;
+    };
+    let exe = match current_exe() {
+        Ok(exe) => exe,
+        Err(err) => {
+            warn!("failed to sync hosts, error={err}");
+            return;
+        }
+    };
+    let mut command = Command::new("sudo");
+    command.arg("-n").arg(exe).args(["hosts", "sync"]);
+    if stopped {
+        command.args(["--exclude", name]);
+    }
+    match command.output() {
+        Ok(output) if output.status.success() => info!("hosts synced, name={name}, stopped={stopped}"),
+        Ok(output) => warn!(
+            "failed to sync hosts, allow vz hosts sync by sudo without password, error={}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(err) => warn!("failed to sync hosts, error={err}"),
+    }
+}
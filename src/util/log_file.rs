@@ -0,0 +1,138 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::mem;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use tracing::level_filters::LevelFilter;
+use tracing::warn;
+use tracing_subscriber::fmt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+const MAX_SIZE: u64 = 10 * 1024 * 1024;
+const MAX_AGE: Duration = Duration::from_secs(7 * 24 * 3600);
+// rotated files are vz.log.1 to vz.log.5, vz.log.1 is newest
+pub const MAX_ROTATED_FILES: u32 = 5;
+
+// log file of runner, rotated once it's larger than MAX_SIZE or older than MAX_AGE
+pub struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    created: SystemTime,
+    // background runner has stdout and stderr on log file, they follow rotation, e.g. for output of guest and panic
+    redirect_stdio: bool,
+}
+
+impl LogFile {
+    pub fn open(path: &Path, redirect_stdio: bool) -> io::Result<LogFile> {
+        let (file, size, created) = open(path)?;
+        Ok(LogFile {
+            path: path.to_path_buf(),
+            file,
+            size,
+            created,
+            redirect_stdio,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..MAX_ROTATED_FILES).rev() {
+            let path = rotated_path(&self.path, index);
+            if path.exists() {
+                fs::rename(&path, rotated_path(&self.path, index + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        (self.file, self.size, self.created) = open(&self.path)?;
+        if self.redirect_stdio {
+            unsafe {
+                libc::dup2(self.file.as_raw_fd(), libc::STDOUT_FILENO);
+                libc::dup2(self.file.as_raw_fd(), libc::STDERR_FILENO);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        let expired = self.created.elapsed().is_ok_and(|age| age > MAX_AGE);
+        if self.size > 0 && (self.size + buffer.len() as u64 > MAX_SIZE || expired) {
+            // keep logging into current file if rotation fails, e.g. vm dir is read only
+            let _ = self.rotate();
+        }
+        let length = self.file.write(buffer)?;
+        self.size += length as u64;
+        Ok(length)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// runner logs into file, foreground runner also prints to terminal
+pub fn init_tracing(path: &Path, ansi: bool) {
+    let background = is_stdout(path);
+    let file = match LogFile::open(path, background) {
+        Ok(file) => file,
+        Err(err) => {
+            tracing_subscriber::fmt().with_thread_ids(true).with_ansi(ansi).init();
+            warn!("failed to open log file, path={}, error={err}", path.to_string_lossy());
+            return;
+        }
+    };
+    let console = (!background).then(|| fmt::layer().with_thread_ids(true).with_ansi(ansi));
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(fmt::layer().with_thread_ids(true).with_ansi(false).with_writer(Mutex::new(file)))
+        .with(console)
+        .init();
+}
+
+pub fn rotated_path(path: &Path, index: u32) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(format!(".{index}"));
+    PathBuf::from(path)
+}
+
+// whether stdout of current process is the file, e.g. runner started by run_in_background
+fn is_stdout(path: &Path) -> bool {
+    let Ok(metadata) = path.metadata() else {
+        return false;
+    };
+    unsafe {
+        let mut stat: libc::stat = mem::zeroed();
+        libc::fstat(libc::STDOUT_FILENO, &mut stat) == 0 && stat.st_dev as u64 == metadata.dev() && stat.st_ino as u64 == metadata.ino()
+    }
+}
+
+fn open(path: &Path) -> io::Result<(File, u64, SystemTime)> {
+    let file = File::options().create(true).append(true).open(path)?;
+    let metadata = file.metadata()?;
+    let created = metadata.created().or_else(|_| metadata.modified())?;
+    Ok((file, metadata.len(), created))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    #[test]
+    fn rotated_path() {
+        assert_eq!(
+            super::rotated_path(Path::new("/Users/dev/.vm/debian/vz.log"), 1),
+            PathBuf::from("/Users/dev/.vm/debian/vz.log.1")
+        );
+    }
+}
@@ -0,0 +1,40 @@
+use std::process::Command;
+use std::sync::OnceLock;
+
+use tracing::warn;
+
+static VM_NAME: OnceLock<String> = OnceLock::new();
+
+// notifications are dropped until enabled
+pub fn enable(vm_name: &str) {
+    let _ = VM_NAME.set(vm_name.to_string());
+}
+
+// UNUserNotificationCenter requires app bundle, cli posts notification via osascript
+pub fn notify(message: &str) {
+    let Some(name) = VM_NAME.get() else {
+        return;
+    };
+    let script = format!(
+        "display notification {} with title \"vz\" subtitle {}",
+        apple_script_string(message),
+        apple_script_string(name)
+    );
+    match Command::new("osascript").args(["-e", &script]).output() {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => warn!("failed to post notification, error={}", String::from_utf8_lossy(&output.stderr).trim()),
+        Err(err) => warn!("failed to post notification, error={err}"),
+    }
+}
+
+fn apple_script_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn apple_script_string() {
+        assert_eq!(super::apple_script_string(r#"error="disk" \ full"#), r#""error=\"disk\" \\ full""#);
+    }
+}
@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde_json::Value;
+use tracing::info;
+
+use crate::util::digest;
+use crate::util::exception::Exception;
+use crate::util::json;
+use crate::util::tar;
+use crate::util::terminal;
+
+pub const INIT_PATH: &str = "vz-init";
+
+const MANIFEST_TYPES: [&str; 4] = [
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.oci.image.manifest.v1+json",
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+    "application/vnd.docker.distribution.manifest.v2+json",
+];
+
+// e.g. docker.io/library/ubuntu:24.04, ubuntu, ghcr.io/org/image@sha256:<digest>
+#[derive(Debug, PartialEq)]
+pub struct Reference {
+    pub registry: String,
+    pub repository: String,
+    // tag or digest
+    pub reference: String,
+}
+
+impl Reference {
+    pub fn parse(image: &str) -> Self {
+        let (name, reference) = match image.split_once('@') {
+            Some((name, digest)) => (name, digest),
+            None => match image.rsplit_once(':').filter(|(_, tag)| !tag.contains('/')) {
+                Some((name, tag)) => (name, tag),
+                None => (image, "latest"),
+            },
+        };
+        let (registry, repository) = match name.split_once('/') {
+            Some((registry, repository)) if registry.contains(['.', ':']) || registry == "localhost" => (registry, repository.to_string()),
+            _ => ("docker.io", name.to_string()),
+        };
+        let repository = if registry == "docker.io" && !repository.contains('/') {
+            format!("library/{repository}")
+        } else {
+            repository
+        };
+        Reference {
+            registry: registry.to_string(),
+            repository,
+            reference: reference.to_string(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        let host = if self.registry == "docker.io" {
+            "registry-1.docker.io"
+        } else {
+            &self.registry
+        };
+        format!("https://{host}/v2/{}/{path}", self.repository)
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let separator = if self.reference.contains(':') { '@' } else { ':' };
+        write!(f, "{}/{}{separator}{}", self.registry, self.repository, self.reference)
+    }
+}
+
+pub struct Image {
+    pub layers: Vec<PathBuf>,
+    pub config: Value,
+}
+
+// pull linux/arm64 image, layers are decompressed into dir as tar files
+pub fn pull(reference: &Reference, dir: &Path) -> Result<Image, Exception> {
+    info!("pull oci image, image={reference}");
+    let token = token(reference)?;
+    let mut manifest = manifest(reference, &reference.reference, token.as_deref())?;
+    if let Some(manifests) = manifest["manifests"].as_array() {
+        let digest = manifests
+            .iter()
+            .find(|manifest| manifest["platform"]["os"] == "linux" && manifest["platform"]["architecture"] == "arm64")
+            .and_then(|manifest| manifest["digest"].as_str())
+            .ok_or_else(|| Exception::ValidationError(format!("linux/arm64 image not found, image={reference}")))?;
+        manifest = self::manifest(reference, digest, token.as_deref())?;
+    }
+
+    let config_digest = manifest["config"]["digest"]
+        .as_str()
+        .ok_or_else(|| Exception::ValidationError(format!("invalid image manifest, image={reference}")))?;
+    let config = fetch(reference, &format!("blobs/{config_digest}"), token.as_deref(), &[])?;
+    let config: Value = json::from_json(&String::from_utf8_lossy(&config))?;
+
+    let mut layers = vec![];
+    for (index, layer) in manifest["layers"].as_array().map(Vec::as_slice).unwrap_or_default().iter().enumerate() {
+        let (Some(digest), Some(media_type)) = (layer["digest"].as_str(), layer["mediaType"].as_str()) else {
+            return Err(Exception::ValidationError(format!("invalid image layer, image={reference}")));
+        };
+        let blob = dir.join(format!("layer-{index}"));
+        download_blob(reference, digest, token.as_deref(), &blob)?;
+        let path = dir.join(format!("layer-{index}.tar"));
+        decompress(media_type, &blob, &path)?;
+        layers.push(path);
+    }
+    Ok(Image { layers, config })
+}
+
+// write flattened layers with init script into single tar, which can be used by mkfs.ext4 -d
+pub fn create_rootfs(image: &Image, hostname: &str, path: &Path) -> Result<(), Exception> {
+    info!("flatten image layers, layers={}, path={}", image.layers.len(), path.to_string_lossy());
+    let mut layers = vec![];
+    for layer in &image.layers {
+        layers.push(tar::entries(&mut BufReader::new(File::open(layer)?))?);
+    }
+    let entries = merge(layers);
+
+    let mut readers = vec![];
+    for layer in &image.layers {
+        readers.push(BufReader::new(File::open(layer)?));
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+    for (layer, entry) in entries.iter().filter(|(_, entry)| entry.path != INIT_PATH) {
+        tar::copy_entry(&mut readers[*layer], entry, &mut writer)?;
+    }
+    tar::write_file(&mut writer, INIT_PATH, 0o755, init_script(hostname, &image.config).as_bytes())?;
+    tar::finish(&mut writer)?;
+    Ok(())
+}
+
+// registry returns bearer challenge if auth is required, anonymous token is enough to pull public image
+fn token(reference: &Reference) -> Result<Option<String>, Exception> {
+    let output = Command::new("curl")
+        .args(["--silent", "--output", "/dev/null", "--dump-header", "-"])
+        .arg(reference.url("tags/list"))
+        .output()?;
+    let headers = String::from_utf8_lossy(&output.stdout);
+    let challenge = headers.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("www-authenticate").then(|| value.trim().to_string())
+    });
+    let Some(params) = challenge.as_deref().and_then(|challenge| challenge.strip_prefix("Bearer ")) else {
+        return Ok(None);
+    };
+    let params = challenge_params(params);
+    let realm = params
+        .get("realm")
+        .ok_or_else(|| Exception::ValidationError(format!("invalid registry auth challenge, challenge={params:?}")))?;
+    let mut url = format!("{realm}?scope=repository:{}:pull", reference.repository);
+    if let Some(service) = params.get("service") {
+        url.push_str(&format!("&service={service}"));
+    }
+    let response = run(curl(None).arg(&url), &url)?;
+    let response: Value = json::from_json(&String::from_utf8_lossy(&response))?;
+    Ok(response["token"].as_str().or(response["access_token"].as_str()).map(str::to_string))
+}
+
+// e.g. realm="https://auth.docker.io/token",service="registry.docker.io"
+fn challenge_params(params: &str) -> HashMap<String, String> {
+    params
+        .split(',')
+        .filter_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            Some((key.trim().to_string(), value.trim().trim_matches('"').to_string()))
+        })
+        .collect()
+}
+
+fn manifest(reference: &Reference, tag: &str, token: Option<&str>) -> Result<Value, Exception> {
+    let manifest = fetch(reference, &format!("manifests/{tag}"), token, &MANIFEST_TYPES)?;
+    json::from_json(&String::from_utf8_lossy(&manifest))
+}
+
+fn fetch(reference: &Reference, path: &str, token: Option<&str>, accept: &[&str]) -> Result<Vec<u8>, Exception> {
+    let url = reference.url(path);
+    let mut command = curl(token);
+    command.arg("--silent");
+    if !accept.is_empty() {
+        command.arg("--header").arg(format!("Accept: {}", accept.join(", ")));
+    }
+    run(command.arg(&url), &url)
+}
+
+fn download_blob(reference: &Reference, digest: &str, token: Option<&str>, path: &Path) -> Result<(), Exception> {
+    let url = reference.url(&format!("blobs/{digest}"));
+    info!("download image layer, digest={digest}");
+    let status = curl(token)
+        .arg(terminal::curl_progress_arg())
+        .arg("--output")
+        .arg(path)
+        .arg(&url)
+        .status()?;
+    if !status.success() {
+        return Err(Exception::ValidationError(format!("failed to download, url={url}, status={status}")));
+    }
+
+    let expected = digest.strip_prefix("sha256:").unwrap_or(digest);
+    if digest::file_sha256_hex(path)? != expected {
+        return Err(Exception::ValidationError(format!("image layer digest mismatch, digest={digest}")));
+    }
+    Ok(())
+}
+
+// custom authorization header is not sent to other host when redirected, e.g. blob storage
+fn curl(token: Option<&str>) -> Command {
+    let mut command = Command::new("curl");
+    command.args(["--fail", "--show-error", "--location"]);
+    if let Some(token) = token {
+        command.arg("--header").arg(format!("Authorization: Bearer {token}"));
+    }
+    command
+}
+
+fn run(command: &mut Command, url: &str) -> Result<Vec<u8>, Exception> {
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(Exception::ValidationError(format!(
+            "failed to fetch, url={url}, error={}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+fn decompress(media_type: &str, blob: &Path, path: &Path) -> Result<(), Exception> {
+    let program = if media_type.ends_with("gzip") {
+        "gzip"
+    } else if media_type.ends_with("zstd") {
+        "zstd"
+    } else {
+        fs::rename(blob, path)?;
+        return Ok(());
+    };
+    let status = Command::new(program).arg("-dc").arg(blob).stdout(File::create(path)?).status()?;
+    if !status.success() {
+        return Err(Exception::ValidationError(format!(
+            "failed to decompress image layer, media_type={media_type}"
+        )));
+    }
+    fs::remove_file(blob)?;
+    Ok(())
+}
+
+// later layer overrides entries of earlier layers, whiteout entry removes path from lower layers,
+// opaque whiteout removes all lower entries under its dir, refer to https://github.com/opencontainers/image-spec/blob/main/layer.md
+fn merge(layers: Vec<Vec<tar::Entry>>) -> Vec<(usize, tar::Entry)> {
+    let mut entries: Vec<Option<(usize, tar::Entry)>> = vec![];
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for (layer, layer_entries) in layers.into_iter().enumerate() {
+        for entry in layer_entries {
+            let (dir, name) = entry.path.rsplit_once('/').unwrap_or(("", &entry.path));
+            if name == ".wh..wh..opq" {
+                let dir = dir.to_string();
+                remove(&mut entries, &mut index, layer, |path| is_under(path, &dir));
+                continue;
+            }
+            if let Some(name) = name.strip_prefix(".wh.") {
+                let target = if dir.is_empty() { name.to_string() } else { format!("{dir}/{name}") };
+                remove(&mut entries, &mut index, layer, |path| path == target || is_under(path, &target));
+                continue;
+            }
+            if entry.path.is_empty() {
+                continue;
+            }
+            if let Some(&position) = index.get(&entry.path) {
+                // keep position of directory, so its children are still after it
+                let existing_directory = entries[position].as_ref().is_some_and(|(_, existing)| existing.kind == tar::DIRECTORY);
+                if existing_directory && entry.kind == tar::DIRECTORY {
+                    entries[position] = Some((layer, entry));
+                    continue;
+                }
+                let path = entry.path.clone();
+                remove(&mut entries, &mut index, layer + 1, |existing| {
+                    existing == path || is_under(existing, &path)
+                });
+            }
+            index.insert(entry.path.clone(), entries.len());
+            entries.push(Some((layer, entry)));
+        }
+    }
+    entries.into_iter().flatten().collect()
+}
+
+fn remove(entries: &mut [Option<(usize, tar::Entry)>], index: &mut HashMap<String, usize>, below_layer: usize, matches: impl Fn(&str) -> bool) {
+    for slot in entries.iter_mut() {
+        if slot.as_ref().is_some_and(|(layer, entry)| *layer < below_layer && matches(&entry.path)) {
+            if let Some((_, entry)) = slot.take() {
+                index.remove(&entry.path);
+            }
+        }
+    }
+}
+
+fn is_under(path: &str, dir: &str) -> bool {
+    if dir.is_empty() {
+        return !path.is_empty();
+    }
+    path.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/'))
+}
+
+// container image has no init system, init mounts kernel filesystems then execs image entrypoint and cmd,
+// vm stops once command exits, as kernel panics on init exit and reboots with panic=-1
+fn init_script(hostname: &str, config: &Value) -> String {
+    let config = &config["config"];
+    let strings = |value: &Value| -> Vec<String> {
+        value
+            .as_array()
+            .map(|values| values.iter().filter_map(|value| value.as_str().map(str::to_string)).collect())
+            .unwrap_or_default()
+    };
+    let mut command = strings(&config["Entrypoint"]);
+    command.extend(strings(&config["Cmd"]));
+    if command.is_empty() {
+        command.push("/bin/sh".to_string());
+    }
+
+    let mut script = String::from("#!/bin/sh\n");
+    script.push_str("mkdir -p /proc /sys /dev /tmp\n");
+    script.push_str("mount -t proc proc /proc\n");
+    script.push_str("mount -t sysfs sysfs /sys\n");
+    script.push_str("mount -t devtmpfs devtmpfs /dev 2>/dev/null\n");
+    script.push_str(&format!("hostname {}\n", quote(hostname)));
+    for env in strings(&config["Env"]) {
+        script.push_str(&format!("export {}\n", quote(&env)));
+    }
+    if let Some(dir) = config["WorkingDir"].as_str().filter(|dir| !dir.is_empty()) {
+        script.push_str(&format!("cd {}\n", quote(dir)));
+    }
+    let command: Vec<String> = command.iter().map(|arg| quote(arg)).collect();
+    script.push_str(&format!("exec {}\n", command.join(" ")));
+    script
+}
+
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Reference;
+    use crate::util::tar;
+
+    #[test]
+    fn parse_reference() {
+        assert_eq!(
+            Reference::parse("ubuntu"),
+            Reference {
+                registry: "docker.io".to_string(),
+                repository: "library/ubuntu".to_string(),
+                reference: "latest".to_string()
+            }
+        );
+        assert_eq!(
+            Reference::parse("docker.io/library/ubuntu:24.04").to_string(),
+            "docker.io/library/ubuntu:24.04"
+        );
+        assert_eq!(Reference::parse("localhost:5000/app").to_string(), "localhost:5000/app:latest");
+        assert_eq!(Reference::parse("ghcr.io/org/app@sha256:abc").to_string(), "ghcr.io/org/app@sha256:abc");
+    }
+
+    #[test]
+    fn challenge_params() {
+        let params = super::challenge_params(r#"realm="https://auth.docker.io/token",service="registry.docker.io""#);
+        assert_eq!(params["realm"], "https://auth.docker.io/token");
+        assert_eq!(params["service"], "registry.docker.io");
+    }
+
+    fn entry(path: &str, kind: u8) -> tar::Entry {
+        tar::Entry {
+            path: path.to_string(),
+            kind,
+            start: 0,
+            end: 0,
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let lower = vec![
+            entry("etc", tar::DIRECTORY),
+            entry("etc/hosts", b'0'),
+            entry("etc/motd", b'0'),
+            entry("var", tar::DIRECTORY),
+            entry("var/cache", tar::DIRECTORY),
+            entry("var/cache/apt", b'0'),
+        ];
+        let upper = vec![
+            entry("etc", tar::DIRECTORY),
+            entry("etc/.wh.motd", b'0'),
+            entry("etc/hosts", b'0'),
+            entry("var/cache/.wh..wh..opq", b'0'),
+            entry("var/cache/new", b'0'),
+        ];
+        let entries: Vec<(usize, String)> = super::merge(vec![lower, upper])
+            .into_iter()
+            .map(|(layer, entry)| (layer, entry.path))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (1, "etc".to_string()),
+                (0, "var".to_string()),
+                (0, "var/cache".to_string()),
+                (1, "etc/hosts".to_string()),
+                (1, "var/cache/new".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn init_script() {
+        let config = json!({"config": {"Env": ["PATH=/usr/bin"], "Cmd": ["nginx", "-g", "daemon off;"], "WorkingDir": "/srv"}});
+        let script = super::init_script("web", &config);
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("hostname 'web'\nexport 'PATH=/usr/bin'\ncd '/srv'\n"));
+        assert!(script.ends_with("exec 'nginx' '-g' 'daemon off;'\n"));
+    }
+}
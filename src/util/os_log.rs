@@ -0,0 +1,65 @@
+use std::ffi::c_char;
+use std::ffi::c_void;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::sync::OnceLock;
+
+// messages are visible by log stream --predicate 'subsystem == "vz"', with vm name as category
+const SUBSYSTEM: &CStr = c"vz";
+const FORMAT: &CStr = c"%{public}s";
+const OS_LOG_TYPE_DEFAULT: u8 = 0x00;
+const OS_LOG_TYPE_ERROR: u8 = 0x10;
+
+extern "C" {
+    // os_log stores format string as offset in image, __dso_handle is start of image
+    static __dso_handle: u8;
+    fn os_log_create(subsystem: *const c_char, category: *const c_char) -> *mut c_void;
+    fn _os_log_impl(dso: *const c_void, log: *mut c_void, kind: u8, format: *const c_char, buffer: *const u8, size: u32);
+}
+
+struct Log(*mut c_void);
+
+// os_log_t is thread safe
+unsafe impl Send for Log {}
+unsafe impl Sync for Log {}
+
+static LOG: OnceLock<Log> = OnceLock::new();
+
+// messages are dropped until enabled
+pub fn enable(vm_name: &str) {
+    let category = CString::new(vm_name).unwrap_or_default();
+    let _ = LOG.set(Log(unsafe { os_log_create(SUBSYSTEM.as_ptr(), category.as_ptr()) }));
+}
+
+pub fn info(message: &str) {
+    log(OS_LOG_TYPE_DEFAULT, message);
+}
+
+pub fn error(message: &str) {
+    log(OS_LOG_TYPE_ERROR, message);
+}
+
+fn log(kind: u8, message: &str) {
+    let Some(log) = LOG.get() else {
+        return;
+    };
+    let message = CString::new(message.replace('\0', "")).unwrap();
+    // argument buffer as encoded by os_log macro, summary (has non scalar), count,
+    // then descriptor (public string), size and value of pointer
+    let mut buffer = [0; 12];
+    buffer[0] = 0x02;
+    buffer[1] = 1;
+    buffer[2] = 0x22;
+    buffer[3] = 8;
+    buffer[4..12].copy_from_slice(&(message.as_ptr() as usize).to_ne_bytes());
+    unsafe {
+        _os_log_impl(
+            &__dso_handle as *const u8 as *const c_void,
+            log.0,
+            kind,
+            FORMAT.as_ptr(),
+            buffer.as_ptr(),
+            buffer.len() as u32,
+        );
+    }
+}
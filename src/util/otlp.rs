@@ -0,0 +1,148 @@
+use std::env;
+use std::io::Write;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use serde_json::json;
+use serde_json::Value;
+use tracing::warn;
+use uuid::Uuid;
+
+// export is enabled by standard otel env, e.g. OTEL_EXPORTER_OTLP_ENDPOINT=http://localhost:4318, sent as OTLP/HTTP json
+const ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+static VM_NAME: OnceLock<String> = OnceLock::new();
+
+// vm runner process reports telemetry of single vm
+pub fn set_vm_name(name: &str) {
+    let _ = VM_NAME.set(name.to_string());
+}
+
+pub fn span(name: &str, start: SystemTime, error: Option<String>) {
+    let Some(endpoint) = endpoint() else {
+        return;
+    };
+    let trace_id = Uuid::new_v4().simple().to_string();
+    let span_id = Uuid::new_v4().simple().to_string()[..16].to_string();
+    // status code 1 is ok, 2 is error
+    let status = match &error {
+        Some(message) => json!({"code": 2, "message": message}),
+        None => json!({"code": 1}),
+    };
+    let body = json!({"resourceSpans": [{
+        "resource": resource(),
+        "scopeSpans": [{
+            "scope": {"name": "vz"},
+            "spans": [{
+                "traceId": trace_id,
+                "spanId": span_id,
+                "name": name,
+                // kind 1 is internal
+                "kind": 1,
+                "startTimeUnixNano": unix_nano(start),
+                "endTimeUnixNano": unix_nano(SystemTime::now()),
+                "status": status,
+            }],
+        }],
+    }]});
+    send(&format!("{endpoint}/v1/traces"), &body);
+}
+
+pub fn gauge(name: &str, unit: &str, value: f64) {
+    metric(name, unit, "gauge", json!({"dataPoints": [data_point(value)]}));
+}
+
+// delta sum, collector aggregates them into count
+pub fn count(name: &str) {
+    metric(
+        name,
+        "1",
+        "sum",
+        json!({"dataPoints": [data_point(1.0)], "aggregationTemporality": 1, "isMonotonic": true}),
+    );
+}
+
+fn metric(name: &str, unit: &str, kind: &str, data: Value) {
+    let Some(endpoint) = endpoint() else {
+        return;
+    };
+    let mut metric = json!({"name": name, "unit": unit});
+    metric[kind] = data;
+    let body = json!({"resourceMetrics": [{
+        "resource": resource(),
+        "scopeMetrics": [{
+            "scope": {"name": "vz"},
+            "metrics": [metric],
+        }],
+    }]});
+    send(&format!("{endpoint}/v1/metrics"), &body);
+}
+
+fn data_point(value: f64) -> Value {
+    json!({"timeUnixNano": unix_nano(SystemTime::now()), "asDouble": value})
+}
+
+fn resource() -> Value {
+    let mut attributes = vec![attribute("service.name", "vz")];
+    if let Some(name) = VM_NAME.get() {
+        attributes.push(attribute("vz.vm.name", name));
+    }
+    json!({"attributes": attributes})
+}
+
+fn attribute(key: &str, value: &str) -> Value {
+    json!({"key": key, "value": {"stringValue": value}})
+}
+
+// 64 bit integers are encoded as string in OTLP json
+fn unix_nano(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_nanos()).to_string()
+}
+
+fn endpoint() -> Option<String> {
+    env::var(ENDPOINT_ENV)
+        .ok()
+        .filter(|endpoint| !endpoint.is_empty())
+        .map(|endpoint| endpoint.trim_end_matches('/').to_string())
+}
+
+// telemetry must not fail or slow down vm operations, curl is not waited for and keeps sending after vz exits
+fn send(url: &str, body: &Value) {
+    let child = Command::new("curl")
+        .args([
+            "--silent",
+            "--fail",
+            "--max-time",
+            "5",
+            "--header",
+            "Content-Type: application/json",
+            "--data-binary",
+            "@-",
+            "--output",
+            "/dev/null",
+        ])
+        .arg(url)
+        .stdin(Stdio::piped())
+        .spawn();
+    // body is small and fits in pipe buffer, stdin is closed when dropped so curl sends it
+    let result = child.and_then(|mut child| {
+        child.stdin.take().unwrap().write_all(body.to_string().as_bytes())?;
+        Ok(child)
+    });
+    match result {
+        // reap curl in background, e.g. runner sends metrics periodically
+        Ok(mut child) => {
+            let url = url.to_string();
+            thread::spawn(move || match child.wait() {
+                Ok(status) if status.success() => {}
+                Ok(status) => warn!("failed to send telemetry, url={url}, status={status}"),
+                Err(err) => warn!("failed to send telemetry, url={url}, error={err}"),
+            });
+        }
+        Err(err) => warn!("failed to send telemetry, url={url}, error={err}"),
+    }
+}
@@ -1,3 +1,6 @@
+use std::env;
+use std::fs;
+use std::io;
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -30,6 +33,44 @@ impl PathExtension for Path {
     }
 }
 
+// fs::copy uses clonefile on APFS, so disk image is cloned at once and shares blocks until either one is written
+pub fn clone_file(source: &Path, target: &Path) -> io::Result<()> {
+    fs::copy(source, target)?;
+    Ok(())
+}
+
+// expand leading ~ and env vars, e.g. ~/code, $HOME/code or ${PROJECTS}/app, $ not followed by var name is kept
+pub fn expand(path: &str) -> Result<PathBuf, String> {
+    let mut result = String::new();
+    let mut rest = path;
+    if let Some(suffix) = path.strip_prefix('~') {
+        if suffix.is_empty() || suffix.starts_with('/') {
+            result.push_str(&var("HOME")?);
+            rest = suffix;
+        }
+    }
+    while let Some(index) = rest.find('$') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+        let (name, remaining) = match rest.strip_prefix('{') {
+            Some(braced) => braced.split_once('}').ok_or_else(|| format!("missing }}, path={path}"))?,
+            None => rest.split_at(rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len())),
+        };
+        if name.is_empty() {
+            result.push('$');
+            continue;
+        }
+        result.push_str(&var(name)?);
+        rest = remaining;
+    }
+    result.push_str(rest);
+    Ok(PathBuf::from(result))
+}
+
+fn var(name: &str) -> Result<String, String> {
+    env::var(name).map_err(|_| format!("env var not defined, var={name}"))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -44,4 +85,19 @@ mod tests {
             PathBuf::from(format!("{}/Desktop", env!("HOME")))
         );
     }
+
+    #[test]
+    fn expand() {
+        let home = env::var("HOME").unwrap();
+        assert_eq!(PathBuf::from("/Users/code"), super::expand("/Users/code").unwrap());
+        assert_eq!(PathBuf::from(format!("{home}/code")), super::expand("~/code").unwrap());
+        assert_eq!(PathBuf::from(format!("{home}/code")), super::expand("$HOME/code").unwrap());
+        assert_eq!(PathBuf::from(format!("{home}_code")), super::expand("${HOME}_code").unwrap());
+        assert_eq!(PathBuf::from("~user/a$/b"), super::expand("~user/a$/b").unwrap());
+        assert_eq!(
+            Err("env var not defined, var=VZ_UNDEFINED".to_string()),
+            super::expand("$VZ_UNDEFINED/code")
+        );
+        assert!(super::expand("${HOME/code").is_err());
+    }
 }
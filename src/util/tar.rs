@@ -0,0 +1,168 @@
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+
+use crate::util::exception::Exception;
+
+const BLOCK_SIZE: u64 = 512;
+
+pub const DIRECTORY: u8 = b'5';
+
+// range covers extended headers (pax or gnu long name) of entry, so entry can be copied as is
+pub struct Entry {
+    pub path: String,
+    pub kind: u8,
+    pub start: u64,
+    pub end: u64,
+}
+
+pub fn entries<R: Read + Seek>(reader: &mut R) -> Result<Vec<Entry>, Exception> {
+    let mut entries = vec![];
+    let mut offset = 0;
+    let mut start = None;
+    let mut long_path = None;
+    loop {
+        let mut header = [0; BLOCK_SIZE as usize];
+        reader.seek(SeekFrom::Start(offset))?;
+        if reader.read(&mut header)? < header.len() || header.iter().all(|&byte| byte == 0) {
+            return Ok(entries);
+        }
+        let size = parse_size(&header[124..136])?;
+        let kind = header[156];
+        let data_offset = offset + BLOCK_SIZE;
+        let end = data_offset + size.next_multiple_of(BLOCK_SIZE);
+        match kind {
+            // pax extended header, or gnu long name, applies to next entry
+            b'x' | b'L' => {
+                let mut data = vec![0; size as usize];
+                reader.read_exact(&mut data)?;
+                let path = if kind == b'x' { pax_path(&data) } else { Some(c_string(&data)) };
+                long_path = path.or(long_path);
+                start = start.or(Some(offset));
+            }
+            b'K' => start = start.or(Some(offset)),
+            // pax global header
+            b'g' => {}
+            _ => {
+                let path = long_path.take().unwrap_or_else(|| header_path(&header));
+                entries.push(Entry {
+                    path: normalize(&path),
+                    kind,
+                    start: start.take().unwrap_or(offset),
+                    end,
+                });
+            }
+        }
+        offset = end;
+    }
+}
+
+pub fn copy_entry<R: Read + Seek, W: Write>(reader: &mut R, entry: &Entry, writer: &mut W) -> Result<(), Exception> {
+    reader.seek(SeekFrom::Start(entry.start))?;
+    std::io::copy(&mut reader.by_ref().take(entry.end - entry.start), writer)?;
+    Ok(())
+}
+
+pub fn write_file<W: Write>(writer: &mut W, path: &str, mode: u32, data: &[u8]) -> Result<(), Exception> {
+    let mut header = [0; BLOCK_SIZE as usize];
+    if path.len() > 100 {
+        return Err(Exception::ValidationError(format!("tar path is too long, path={path}")));
+    }
+    header[0..path.len()].copy_from_slice(path.as_bytes());
+    header[100..108].copy_from_slice(format!("{mode:07o}\0").as_bytes());
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+    header[136..148].copy_from_slice(b"00000000000\0");
+    header[156] = b'0';
+    header[257..265].copy_from_slice(b"ustar\x0000");
+    // checksum is calculated with checksum field filled with spaces
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&byte| byte as u32).sum();
+    header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    writer.write_all(&header)?;
+    writer.write_all(data)?;
+    writer.write_all(&vec![0; (data.len() as u64).next_multiple_of(BLOCK_SIZE) as usize - data.len()])?;
+    Ok(())
+}
+
+// end of archive is 2 zero blocks
+pub fn finish<W: Write>(writer: &mut W) -> Result<(), Exception> {
+    writer.write_all(&[0; 2 * BLOCK_SIZE as usize])?;
+    Ok(())
+}
+
+fn header_path(header: &[u8]) -> String {
+    let name = c_string(&header[0..100]);
+    let prefix = c_string(&header[345..500]);
+    if &header[257..262] == b"ustar" && !prefix.is_empty() {
+        format!("{prefix}/{name}")
+    } else {
+        name
+    }
+}
+
+// pax records are "<length> <key>=<value>\n"
+fn pax_path(data: &[u8]) -> Option<String> {
+    String::from_utf8_lossy(data)
+        .lines()
+        .filter_map(|record| record.split_once(' ')?.1.strip_prefix("path=").map(str::to_string))
+        .next_back()
+}
+
+// size is octal, or base-256 big endian when high bit is set
+fn parse_size(field: &[u8]) -> Result<u64, Exception> {
+    if field[0] & 0x80 != 0 {
+        return Ok(field[1..].iter().fold(0, |size, &byte| size << 8 | byte as u64));
+    }
+    let value = c_string(field);
+    let value = value.trim_matches(|char: char| char == ' ' || char == '\0');
+    if value.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(value, 8).map_err(|_| Exception::ValidationError(format!("invalid tar entry size, size={value}")))
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let length = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..length]).to_string()
+}
+
+fn normalize(path: &str) -> String {
+    let path = path.trim_start_matches("./").trim_start_matches('/').trim_end_matches('/');
+    if path == "." {
+        String::new()
+    } else {
+        path.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    #[test]
+    fn write_and_read_entries() {
+        let mut archive = vec![];
+        super::write_file(&mut archive, "./etc/hostname", 0o644, b"vm\n").unwrap();
+        super::write_file(&mut archive, "bin/init", 0o755, &[1; 600]).unwrap();
+        super::finish(&mut archive).unwrap();
+
+        let entries = super::entries(&mut Cursor::new(&archive)).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, "etc/hostname");
+        assert_eq!((entries[0].start, entries[0].end), (0, 1024));
+        assert_eq!(entries[1].path, "bin/init");
+        assert_eq!((entries[1].start, entries[1].end), (1024, 1024 + 512 + 1024));
+    }
+
+    #[test]
+    fn pax_path() {
+        assert_eq!(
+            super::pax_path(b"30 mtime=1700000000.123456789\n19 path=usr/lib/x\n"),
+            Some("usr/lib/x".to_string())
+        );
+        assert_eq!(super::pax_path(b"20 linkpath=target\n"), None);
+    }
+}
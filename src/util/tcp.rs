@@ -0,0 +1,29 @@
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::net::TcpStream;
+use std::time::Duration;
+
+pub const SSH_PORT: u16 = 22;
+
+// guest on NAT network refuses at once if port is closed, timeout is only hit if guest doesn't answer
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub fn port_open(ip: &str, port: u16) -> bool {
+    let Ok(address) = ip.parse::<IpAddr>() else {
+        return false;
+    };
+    TcpStream::connect_timeout(&SocketAddr::new(address, port), CONNECT_TIMEOUT).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    #[test]
+    fn port_open() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(super::port_open("127.0.0.1", port));
+        assert!(!super::port_open("invalid", port));
+    }
+}
@@ -0,0 +1,94 @@
+use std::env;
+use std::io;
+use std::io::IsTerminal;
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use crate::util::exception::Exception;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+// colors and progress bars only if stdout is terminal, captured output of pipe or CI is plain lines
+static INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+pub fn init(mode: ColorMode) {
+    let ci = env::var_os("CI").is_some_and(|value| !value.is_empty());
+    let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    INTERACTIVE.store(interactive_output(mode, io::stdout().is_terminal(), ci || no_color), Ordering::Relaxed);
+}
+
+pub fn interactive() -> bool {
+    INTERACTIVE.load(Ordering::Relaxed)
+}
+
+// curl prints full progress meter if stderr is not terminal, which is one long line with carriage returns in captured output
+pub fn curl_progress_arg() -> &'static str {
+    if interactive() {
+        "--progress-bar"
+    } else {
+        "--no-progress-meter"
+    }
+}
+
+fn interactive_output(mode: ColorMode, terminal: bool, plain_env: bool) -> bool {
+    match mode {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => terminal && !plain_env,
+    }
+}
+
+// e.g. disk usage, in GB as shown by Finder
+pub fn format_size(size: u64) -> String {
+    format!("{:.2}G", size as f32 / 1_000_000_000.0)
+}
+
+// empty answer takes default, closed stdin is no
+pub fn confirm(prompt: &str, default: bool) -> Result<bool, Exception> {
+    print!("{prompt} {} ", if default { "[Y/n]" } else { "[y/N]" });
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer)? == 0 {
+        return Ok(false);
+    }
+    Ok(parse_answer(&answer, default))
+}
+
+fn parse_answer(answer: &str, default: bool) -> bool {
+    match answer.trim() {
+        "y" | "Y" | "yes" => true,
+        "n" | "N" | "no" => false,
+        "" => default,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ColorMode;
+
+    #[test]
+    fn interactive_output() {
+        assert!(super::interactive_output(ColorMode::Auto, true, false));
+        assert!(!super::interactive_output(ColorMode::Auto, false, false));
+        assert!(!super::interactive_output(ColorMode::Auto, true, true));
+        assert!(super::interactive_output(ColorMode::Always, false, true));
+        assert!(!super::interactive_output(ColorMode::Never, true, false));
+    }
+
+    #[test]
+    fn parse_answer() {
+        assert!(super::parse_answer("y\n", false));
+        assert!(super::parse_answer("\n", true));
+        assert!(!super::parse_answer("\n", false));
+        assert!(!super::parse_answer("no\n", true));
+        assert!(!super::parse_answer("maybe\n", true));
+    }
+}
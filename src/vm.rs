@@ -1,59 +1,354 @@
+use std::env;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
 use std::process;
+use std::process::Command;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::channel;
+use std::sync::mpsc::Receiver;
 use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::thread;
+use std::thread::sleep;
 use std::time::Duration;
+use std::time::Instant;
 
+use block2::Block;
 use block2::StackBlock;
 use dispatch::Queue;
 use objc2::rc::Retained;
 use objc2_foundation::run_on_main;
 use objc2_foundation::MainThreadBound;
+use objc2_foundation::MainThreadMarker;
 use objc2_foundation::NSError;
+use objc2_virtualization::VZMacOSVirtualMachineStartOptions;
 use objc2_virtualization::VZVirtualMachine;
 use tracing::error;
 use tracing::info;
+use tracing::warn;
 
+use crate::config::vm_config::RestartPolicy;
+use crate::util::exception::Exception;
+use crate::util::hosts_sync;
+use crate::util::notification;
+use crate::util::os_log;
+use crate::util::otlp;
+use crate::util::path::PathExtension;
+
+pub mod clipboard;
+pub mod console;
+pub mod control;
+pub mod cpu_limit;
+pub mod exec;
 pub mod gui_delegate;
 pub mod linux;
 pub mod mac_os;
 pub mod mac_os_installer;
+pub mod platform;
+pub mod runner;
+pub mod usb;
 pub mod vm_delegate;
+pub mod vsock;
+
+// time for guest to shut down, e.g. ctrl-c of foreground runner
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(15);
+
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+// when vm started or resumed from saved state in this runner
+static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+static RESTART_POLICY: OnceLock<RestartPolicy> = OnceLock::new();
+static EPHEMERAL_DIR: OnceLock<PathBuf> = OnceLock::new();
+static RESTARTING: AtomicBool = AtomicBool::new(false);
+static GUEST_EXIT_CODE: OnceLock<Mutex<Receiver<i32>>> = OnceLock::new();
+// restarted runner gets number of previous restarts, to back off crash loop
+const RESTART_COUNT_ENV: &str = "VZ_RESTART_COUNT";
+// vm ran long enough before it stopped, next restart starts backoff over
+const STABLE_UPTIME: Duration = Duration::from_secs(600);
+const GUEST_EXIT_CODE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// guest also stops vm after host requested to stop
+pub fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::Relaxed)
+}
+
+pub fn started() -> bool {
+    STARTED_AT.get().is_some()
+}
+
+pub fn uptime() -> Option<Duration> {
+    STARTED_AT.get().map(Instant::elapsed)
+}
+
+pub fn set_restart_policy(policy: RestartPolicy) {
+    let _ = RESTART_POLICY.set(policy);
+}
+
+// vm dir of vz run --rm, removed whichever way runner exits, e.g. guest powers off, signal or stop request
+pub fn set_ephemeral_dir(dir: PathBuf) {
+    let _ = EPHEMERAL_DIR.set(dir);
+}
+
+pub fn remove_ephemeral_dir() {
+    if let Some(dir) = EPHEMERAL_DIR.get() {
+        info!("remove ephemeral vm dir, dir={}", dir.to_string_lossy());
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+// exit code of command run by vz run --rm, parsed from guest output by forwarder
+pub fn set_guest_exit_code(receiver: Receiver<i32>) {
+    let _ = GUEST_EXIT_CODE.set(Mutex::new(receiver));
+}
+
+pub fn terminate(code: i32) -> ! {
+    cpu_limit::release();
+    remove_ephemeral_dir();
+    hosts_sync::sync(true);
+    process::exit(code)
+}
+
+// vm stopped without host request, exit runner, or replace it with new runner of same vm by restart policy after backoff
+pub fn exit(code: i32) {
+    cpu_limit::release();
+    // guest powers off right after it prints exit code, forwarder may not have read it yet, none means command didn't finish
+    if let Some(receiver) = GUEST_EXIT_CODE.get() {
+        let guest_code = receiver.lock().unwrap().recv_timeout(GUEST_EXIT_CODE_TIMEOUT).unwrap_or(1);
+        if code == 0 && guest_code != 0 {
+            notification::notify(&format!("guest command failed, exit_code={guest_code}"));
+        }
+        terminate(if code == 0 { guest_code } else { code });
+    }
+    let restart = match RESTART_POLICY.get() {
+        _ if stop_requested() => false,
+        Some(RestartPolicy::Always) => true,
+        Some(RestartPolicy::OnFailure) => code != 0,
+        Some(RestartPolicy::Never) | None => false,
+    };
+    if !restart {
+        terminate(code);
+    }
+    // e.g. stop completion and its timeout both exit
+    if RESTARTING.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    // backoff runs off main thread, so signals and control requests are still handled while waiting
+    thread::spawn(move || restart_runner(code));
+}
+
+fn restart_runner(code: i32) -> ! {
+    let count = restart_count(env::var(RESTART_COUNT_ENV).ok().as_deref(), uptime());
+    let delay = Duration::from_secs(1 << count.min(6));
+    let message = format!("restart vm by policy, restarts={count}, delay={}s", delay.as_secs());
+    info!("{message}");
+    os_log::info(&message);
+    sleep(delay);
+    // vm stop requested while waiting, e.g. vz stop or ctrl-c
+    if stop_requested() {
+        terminate(code);
+    }
+    console::restore_terminal();
+    // exec keeps pid and log output, file lock is released with closed fd and taken again by new runner
+    let err = match env::current_exe() {
+        Ok(exe) => Command::new(exe)
+            .args(env::args_os().skip(1))
+            .env(RESTART_COUNT_ENV, (count + 1).to_string())
+            .exec(),
+        Err(err) => err,
+    };
+    error!("failed to restart vm, error={err}");
+    terminate(code)
+}
+
+fn restart_count(count: Option<&str>, uptime: Option<Duration>) -> u32 {
+    if uptime.is_some_and(|uptime| uptime >= STABLE_UPTIME) {
+        return 0;
+    }
+    count.and_then(|count| count.parse().ok()).unwrap_or(0)
+}
 
 pub fn start_vm(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>) {
+    start(vm, false);
+}
+
+// boot macOS guest into recoveryOS, e.g. to disable SIP by csrutil
+pub fn start_vm_from_recovery(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>) {
+    start(vm, true);
+}
+
+fn start(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>, recovery: bool) {
     run_on_main(|marker| {
-        info!("start vm");
+        info!("start vm, recovery={recovery}");
         let vm = vm.get(marker);
-        let block = &StackBlock::new(|err: *mut NSError| {
+        let start = Instant::now();
+        let block = &StackBlock::new(move |err: *mut NSError| {
             if err.is_null() {
+                let _ = STARTED_AT.set(Instant::now());
                 info!("vm started");
+                os_log::info("vm started");
+                otlp::gauge("vz.vm.start.duration", "s", start.elapsed().as_secs_f64());
             } else {
-                error!("vm failed to start, error={}", unsafe { (*err).localizedDescription() });
-                process::exit(1);
+                let message = format!("vm failed to start, error={}", unsafe { (*err).localizedDescription() });
+                error!("{message}");
+                os_log::error(&message);
+                notification::notify(&message);
+                exit(1);
             }
         });
         unsafe {
-            vm.startWithCompletionHandler(block);
+            if recovery {
+                let options = VZMacOSVirtualMachineStartOptions::new();
+                options.setStartUpFromMacOSRecovery(true);
+                vm.startWithOptions_completionHandler(&options, block);
+            } else {
+                vm.startWithCompletionHandler(block);
+            }
         }
     });
 }
 
-pub fn stop_vm(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>) {
+// restore saved state of suspended vm and resume it, state file is removed once restored, vm boots instead if state is not restorable
+pub fn restore_vm(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>, state: PathBuf) {
     run_on_main(|marker| {
-        info!("stop vm");
+        info!("restore vm, state={}", state.to_string_lossy());
+        let start = Instant::now();
+        let url = state.to_ns_url();
+        let restored_vm = Arc::clone(&vm);
+        let block = &StackBlock::new(move |err: *mut NSError| {
+            let _ = fs::remove_file(&state);
+            if !err.is_null() {
+                let message = format!("failed to restore vm, boot instead, error={}", unsafe { (*err).localizedDescription() });
+                warn!("{message}");
+                os_log::error(&message);
+                start_vm(Arc::clone(&restored_vm));
+                return;
+            }
+            let block = &StackBlock::new(move |err: *mut NSError| {
+                if err.is_null() {
+                    let _ = STARTED_AT.set(Instant::now());
+                    info!("vm resumed");
+                    os_log::info("vm resumed");
+                    otlp::gauge("vz.vm.start.duration", "s", start.elapsed().as_secs_f64());
+                } else {
+                    let message = format!("vm failed to resume, error={}", unsafe { (*err).localizedDescription() });
+                    error!("{message}");
+                    os_log::error(&message);
+                    notification::notify(&message);
+                    exit(1);
+                }
+            });
+            let marker = MainThreadMarker::new().unwrap();
+            unsafe {
+                restored_vm.get(marker).resumeWithCompletionHandler(block);
+            }
+        });
+        unsafe {
+            vm.get(marker).restoreMachineStateFromURL_completionHandler(&url, block);
+        }
+    });
+}
+
+// pause and resume must not be called on main thread, guest clock is behind after paused
+pub fn pause_vm(vm: &Arc<MainThreadBound<Retained<VZVirtualMachine>>>) -> Result<(), Exception> {
+    wait_completion(vm, |vm, block| unsafe { vm.pauseWithCompletionHandler(block) })?;
+    os_log::info("vm paused");
+    Ok(())
+}
+
+pub fn resume_vm(vm: &Arc<MainThreadBound<Retained<VZVirtualMachine>>>) -> Result<(), Exception> {
+    wait_completion(vm, |vm, block| unsafe { vm.resumeWithCompletionHandler(block) })?;
+    os_log::info("vm resumed");
+    Ok(())
+}
+
+// pause vm and save its state, vm is resumed if state can't be saved, must not be called on main thread
+pub fn suspend_vm(vm: &Arc<MainThreadBound<Retained<VZVirtualMachine>>>, state: PathBuf) -> Result<(), Exception> {
+    info!("pause vm");
+    wait_completion(vm, |vm, block| unsafe { vm.pauseWithCompletionHandler(block) })?;
+    info!("save vm state, state={}", state.to_string_lossy());
+    let url = state.clone();
+    if let Err(err) = wait_completion(vm, move |vm, block| unsafe {
+        vm.saveMachineStateToURL_completionHandler(&url.to_ns_url(), block)
+    }) {
+        let _ = fs::remove_file(&state);
+        wait_completion(vm, |vm, block| unsafe { vm.resumeWithCompletionHandler(block) })?;
+        return Err(err);
+    }
+    os_log::info("vm suspended");
+    Ok(())
+}
+
+// call vm method with completion handler on main thread, and wait until it completes
+fn wait_completion<F>(vm: &Arc<MainThreadBound<Retained<VZVirtualMachine>>>, method: F) -> Result<(), Exception>
+where
+    F: FnOnce(&VZVirtualMachine, &Block<dyn Fn(*mut NSError)>) + Send,
+{
+    let (tx, rx) = channel();
+    run_on_main(|marker| {
+        let block = &StackBlock::new(move |err: *mut NSError| {
+            let result = if err.is_null() { Ok(()) } else { Err(Exception::from_ns_error(err)) };
+            let _ = tx.send(result);
+        });
+        method(vm.get(marker), block);
+    });
+    rx.recv()?
+}
+
+// ask guest to shut down, force stop if it doesn't within timeout
+pub fn stop_vm(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>, timeout: Duration) {
+    run_on_main(|marker| {
+        info!("stop vm, timeout={}s", timeout.as_secs());
+        os_log::info("stop vm");
+        STOP_REQUESTED.store(true, Ordering::Relaxed);
         if request_stop_vm(vm.get(marker)) {
-            Queue::main().exec_after(Duration::from_secs(15), || force_stop_vm(vm));
+            Queue::main().exec_after(timeout, || force_stop_vm(vm));
         } else {
             force_stop_vm(vm);
         }
     });
 }
 
+// stop vm without waiting for guest, also while start is pending, then exit with code, not restarted by policy
+pub fn abort(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>, code: i32) {
+    STOP_REQUESTED.store(true, Ordering::Relaxed);
+    stop_then_exit(vm, code, |code| terminate(code));
+}
+
+// stop vm as failed, e.g. guest hangs, then restart by policy
+pub fn restart(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>) {
+    stop_then_exit(vm, 1, exit);
+}
+
+fn stop_then_exit(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>, code: i32, exit: fn(i32)) {
+    run_on_main(move |marker| {
+        // completion handler may never be called, vm process is terminated with runner anyway
+        Queue::main().exec_after(Duration::from_secs(10), move || exit(code));
+        let vm = vm.get(marker);
+        if unsafe { vm.canStop() } {
+            let block = &StackBlock::new(move |err: *mut NSError| {
+                if !err.is_null() {
+                    error!("vm failed to stop, error={}", unsafe { (*err).localizedDescription() });
+                }
+                exit(code);
+            });
+            unsafe {
+                vm.stopWithCompletionHandler(block);
+            }
+        } else {
+            exit(code);
+        }
+    });
+}
+
 fn request_stop_vm(vm: &Retained<VZVirtualMachine>) -> bool {
     unsafe {
         if vm.canRequestStop() {
             info!("request vm to stop");
             if let Err(err) = vm.requestStopWithError() {
                 error!("failed to request vm to stop, error={}", err.localizedDescription());
-                process::exit(1);
+                terminate(1);
             }
             return true;
         }
@@ -61,7 +356,7 @@ fn request_stop_vm(vm: &Retained<VZVirtualMachine>) -> bool {
     }
 }
 
-fn force_stop_vm(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>) {
+pub fn force_stop_vm(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>) {
     run_on_main(|marker| {
         info!("force to stop vm");
         let vm = vm.get(marker);
@@ -69,17 +364,34 @@ fn force_stop_vm(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>) {
             let block = &StackBlock::new(|err: *mut NSError| {
                 if err.is_null() {
                     info!("vm stopped");
-                    process::exit(0);
+                    os_log::info("vm stopped");
+                    terminate(0);
                 } else {
                     error!("vm failed to stop, error={}", unsafe { (*err).localizedDescription() });
-                    process::exit(1);
+                    terminate(1);
                 }
             });
             unsafe {
                 vm.stopWithCompletionHandler(block);
             }
         } else {
-            process::exit(1);
+            terminate(1);
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[test]
+    fn restart_count() {
+        assert_eq!(super::restart_count(None, None), 0);
+        assert_eq!(super::restart_count(Some("3"), Some(Duration::from_secs(5))), 3);
+        assert_eq!(super::restart_count(Some("invalid"), Some(Duration::from_secs(5))), 0);
+        // vm was stable before it stopped
+        assert_eq!(super::restart_count(Some("3"), Some(super::STABLE_UPTIME)), 0);
+        // vm stopped before it started
+        assert_eq!(super::restart_count(Some("3"), None), 3);
+    }
+}
@@ -0,0 +1,172 @@
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::Shutdown;
+use std::os::fd::FromRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::thread::sleep;
+use std::time::Duration;
+
+use block2::StackBlock;
+use objc2::rc::Id;
+use objc2::rc::Retained;
+use objc2_app_kit::NSPasteboard;
+use objc2_app_kit::NSPasteboardTypeString;
+use objc2_foundation::run_on_main;
+use objc2_foundation::MainThreadBound;
+use objc2_foundation::NSError;
+use objc2_foundation::NSString;
+use objc2_virtualization::VZVirtioSocketConnection;
+use objc2_virtualization::VZVirtioSocketDevice;
+use objc2_virtualization::VZVirtualMachine;
+use tracing::error;
+use tracing::info;
+
+use crate::util::exception::Exception;
+use crate::vm::vsock;
+
+// vsock port of clipboard agent in guest
+pub const PORT: u32 = 7070;
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_TEXT_SIZE: usize = 16 * 1024 * 1024;
+
+// host keeps connecting agent in guest, it starts after guest login
+pub fn connect(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>) {
+    thread::spawn(move || loop {
+        let (tx, rx) = channel();
+        let vm = Arc::clone(&vm);
+        run_on_main(move |marker| {
+            let Some(device) = (unsafe { vm.get(marker).socketDevices() }).get_retained(0) else {
+                let _ = tx.send(None);
+                return;
+            };
+            let device: Retained<VZVirtioSocketDevice> = unsafe { Id::cast(device) };
+            let block = &StackBlock::new(move |connection: *mut VZVirtioSocketConnection, err: *mut NSError| {
+                // connection closes its fd once released
+                let stream = (err.is_null()).then(|| unsafe { UnixStream::from_raw_fd(libc::dup((*connection).fileDescriptor())) });
+                let _ = tx.send(stream);
+            });
+            unsafe {
+                device.connectToPort_completionHandler(PORT, block);
+            }
+        });
+        if let Ok(Some(stream)) = rx.recv() {
+            info!("clipboard agent connected, port={PORT}");
+            sync(stream);
+            info!("clipboard agent disconnected, port={PORT}");
+        }
+        sleep(RETRY_INTERVAL);
+    });
+}
+
+// run in macOS guest, accept connection of host on vsock port
+pub fn serve() -> Result<(), Exception> {
+    let listener = vsock::listen_in_guest(PORT)?;
+    info!("clipboard agent listening, port={PORT}");
+    loop {
+        let stream = vsock::accept_in_guest(listener)?;
+        info!("host connected");
+        sync(stream);
+        info!("host disconnected");
+    }
+}
+
+// both sides send text when own pasteboard changes, received text is remembered so it's not sent back
+fn sync(stream: UnixStream) {
+    let last = Arc::new(Mutex::new(pasteboard_text()));
+    let Ok(mut reader) = stream.try_clone() else {
+        return;
+    };
+    let received = Arc::clone(&last);
+    let reading = thread::spawn(move || {
+        while let Ok(Some(text)) = read_frame(&mut reader) {
+            set_pasteboard_text(&text);
+            *received.lock().unwrap() = Some(text);
+        }
+    });
+
+    let mut writer = stream;
+    let mut count = change_count();
+    while !reading.is_finished() {
+        sleep(POLL_INTERVAL);
+        let current = change_count();
+        if current == count {
+            continue;
+        }
+        count = current;
+        let Some(text) = pasteboard_text() else {
+            continue;
+        };
+        let mut last = last.lock().unwrap();
+        if last.as_ref() == Some(&text) {
+            continue;
+        }
+        if let Err(err) = writer.write_all(&encode(&text)) {
+            error!("failed to send clipboard, error={err}");
+            break;
+        }
+        *last = Some(text);
+    }
+    let _ = writer.shutdown(Shutdown::Both);
+}
+
+fn change_count() -> isize {
+    unsafe { NSPasteboard::generalPasteboard().changeCount() }
+}
+
+fn pasteboard_text() -> Option<String> {
+    unsafe { NSPasteboard::generalPasteboard().stringForType(NSPasteboardTypeString) }.map(|text| text.to_string())
+}
+
+fn set_pasteboard_text(text: &str) {
+    unsafe {
+        let pasteboard = NSPasteboard::generalPasteboard();
+        pasteboard.clearContents();
+        pasteboard.setString_forType(&NSString::from_str(text), NSPasteboardTypeString);
+    }
+}
+
+// frame is 4 bytes big endian length then utf8 text
+fn encode(text: &str) -> Vec<u8> {
+    let mut frame = (text.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(text.as_bytes());
+    frame
+}
+
+fn read_frame(reader: &mut impl Read) -> io::Result<Option<String>> {
+    let mut length = [0; 4];
+    match reader.read_exact(&mut length) {
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_TEXT_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("clipboard text too large, size={length}"),
+        ));
+    }
+    let mut text = vec![0; length];
+    reader.read_exact(&mut text)?;
+    Ok(Some(String::from_utf8_lossy(&text).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    #[test]
+    fn frame() {
+        let mut input = super::encode("token");
+        input.extend(super::encode(""));
+        let mut reader = Cursor::new(input);
+        assert_eq!(Some("token".to_string()), super::read_frame(&mut reader).unwrap());
+        assert_eq!(Some("".to_string()), super::read_frame(&mut reader).unwrap());
+        assert_eq!(None, super::read_frame(&mut reader).unwrap());
+    }
+}
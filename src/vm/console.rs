@@ -0,0 +1,368 @@
+use std::ffi::CStr;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::mem::MaybeUninit;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::IntoRawFd;
+use std::os::fd::RawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::OnceLock;
+use std::thread;
+
+use objc2::rc::Id;
+use objc2::rc::Retained;
+use objc2::ClassType;
+use objc2_foundation::NSFileHandle;
+use objc2_virtualization::VZFileHandleSerialPortAttachment;
+use objc2_virtualization::VZSerialPortConfiguration;
+use objc2_virtualization::VZVirtioConsoleDeviceSerialPortConfiguration;
+use tracing::error;
+
+use crate::util::exception::Exception;
+
+// ctrl-]
+const DETACH_KEY: u8 = 0x1d;
+
+// terminal of foreground runner, restored when process exits or restarts
+static ORIGINAL_TERMINAL: OnceLock<libc::termios> = OnceLock::new();
+
+pub struct Pty {
+    master: RawFd,
+    // keep slave open, otherwise reading master returns EIO when no console is attached
+    _slave: RawFd,
+    pub path: PathBuf,
+}
+
+pub fn open_pty() -> Result<Pty, Exception> {
+    unsafe {
+        let master = libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY);
+        if master < 0 || libc::grantpt(master) != 0 || libc::unlockpt(master) != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let path = PathBuf::from(CStr::from_ptr(libc::ptsname(master)).to_string_lossy().to_string());
+        let slave = libc::open(libc::ptsname(master), libc::O_RDWR | libc::O_NOCTTY);
+        if slave < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        make_raw(slave)?;
+        Ok(Pty { master, _slave: slave, path })
+    }
+}
+
+pub fn serial_port(pty: &Pty) -> Retained<VZSerialPortConfiguration> {
+    unsafe {
+        let file_handle = NSFileHandle::initWithFileDescriptor(NSFileHandle::alloc(), pty.master);
+        file_handle_serial_port(Some(&file_handle), Some(&file_handle))
+    }
+}
+
+// guest output is written to pty and passed to mirror line by line
+pub fn mirrored_serial_port(pty: &Pty, mirror: fn(&str)) -> Result<Retained<VZSerialPortConfiguration>, Exception> {
+    let (reading, writing) = pipe()?;
+    let mut master = unsafe { File::from_raw_fd(libc::dup(pty.master)) };
+    thread::spawn(move || {
+        let mut reader = unsafe { File::from_raw_fd(reading) };
+        let mut buffer = [0; 4096];
+        let mut line = vec![];
+        while let Ok(length) = reader.read(&mut buffer) {
+            if length == 0 || master.write_all(&buffer[..length]).is_err() {
+                break;
+            }
+            for &byte in &buffer[..length] {
+                if byte == b'\n' {
+                    mirror(String::from_utf8_lossy(&line).trim_end_matches('\r'));
+                    line.clear();
+                } else {
+                    line.push(byte);
+                }
+            }
+        }
+    });
+    unsafe {
+        let input = NSFileHandle::initWithFileDescriptor(NSFileHandle::alloc(), pty.master);
+        let output = NSFileHandle::initWithFileDescriptor(NSFileHandle::alloc(), writing);
+        Ok(file_handle_serial_port(Some(&input), Some(&output)))
+    }
+}
+
+// guest output only, return reader of guest output
+pub fn output_serial_port() -> Result<(Retained<VZSerialPortConfiguration>, File), Exception> {
+    let (reader, writer) = pipe()?;
+    let port = unsafe {
+        let file_handle = NSFileHandle::initWithFileDescriptor(NSFileHandle::alloc(), writer);
+        file_handle_serial_port(None, Some(&file_handle))
+    };
+    Ok((port, unsafe { File::from_raw_fd(reader) }))
+}
+
+// append guest output to file, e.g. kernel log consumed by log shipper
+pub fn file_serial_port(path: &Path) -> Result<Retained<VZSerialPortConfiguration>, Exception> {
+    let file = File::options().create(true).append(true).open(path)?;
+    unsafe {
+        let file_handle = NSFileHandle::initWithFileDescriptor_closeOnDealloc(NSFileHandle::alloc(), file.into_raw_fd(), true);
+        Ok(file_handle_serial_port(None, Some(&file_handle)))
+    }
+}
+
+// listen on unix socket, client is connected to serial port until it disconnects, then next client is accepted
+pub fn socket_serial_port(path: &Path) -> Result<Retained<VZSerialPortConfiguration>, Exception> {
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let (output_reading, output_writing) = pipe()?;
+    let (input_reading, input_writing) = pipe()?;
+    let client: Arc<Mutex<Option<UnixStream>>> = Arc::new(Mutex::new(None));
+
+    // guest output must be drained, otherwise guest blocks when no client is connected
+    let output_client = Arc::clone(&client);
+    thread::spawn(move || {
+        let mut reader = unsafe { File::from_raw_fd(output_reading) };
+        let mut buffer = [0; 4096];
+        while let Ok(length) = reader.read(&mut buffer) {
+            if length == 0 {
+                break;
+            }
+            let mut client = output_client.lock().unwrap();
+            if client.as_mut().is_some_and(|stream| stream.write_all(&buffer[..length]).is_err()) {
+                *client = None;
+            }
+        }
+    });
+    thread::spawn(move || {
+        let mut input = unsafe { File::from_raw_fd(input_writing) };
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            let Ok(writer) = stream.try_clone() else {
+                continue;
+            };
+            *client.lock().unwrap() = Some(writer);
+            let _ = io::copy(&mut stream, &mut input);
+            *client.lock().unwrap() = None;
+        }
+    });
+    unsafe {
+        let input = NSFileHandle::initWithFileDescriptor(NSFileHandle::alloc(), input_reading);
+        let output = NSFileHandle::initWithFileDescriptor(NSFileHandle::alloc(), output_writing);
+        Ok(file_handle_serial_port(Some(&input), Some(&output)))
+    }
+}
+
+fn pipe() -> Result<(RawFd, RawFd), Exception> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok((fds[0], fds[1]))
+}
+
+fn file_handle_serial_port(reading: Option<&NSFileHandle>, writing: Option<&NSFileHandle>) -> Retained<VZSerialPortConfiguration> {
+    unsafe {
+        let attachment = VZFileHandleSerialPortAttachment::initWithFileHandleForReading_fileHandleForWriting(
+            VZFileHandleSerialPortAttachment::alloc(),
+            reading,
+            writing,
+        );
+        let port = VZVirtioConsoleDeviceSerialPortConfiguration::new();
+        port.setAttachment(Some(&Id::into_super(attachment)));
+        Id::into_super(port)
+    }
+}
+
+// forward stdin/stdout to console until detach key is pressed, vm keeps running after detached
+pub fn attach(path: &Path) -> Result<(), Exception> {
+    let console = File::options().read(true).write(true).custom_flags(libc::O_NOCTTY).open(path)?;
+    forward_output(console.try_clone()?);
+
+    eprintln!("console attached, press ctrl-] to detach");
+    let terminal = RawTerminal::new()?;
+    let result = forward_stdin(console);
+    drop(terminal);
+    eprintln!("\nconsole detached");
+    result
+}
+
+// terminal of foreground runner becomes console of vm in same process, like qemu -nographic,
+// ctrl-] followed by q stops vm, d detaches terminal and vm keeps running
+pub fn attach_stdio(path: &Path) -> Result<(), Exception> {
+    let console = File::options().read(true).write(true).custom_flags(libc::O_NOCTTY).open(path)?;
+    forward_output(console.try_clone()?);
+    // keep output processing, so log lines of runner are not staircased
+    let original = set_raw(io::stdin().as_raw_fd(), true)?;
+    if ORIGINAL_TERMINAL.set(original).is_ok() {
+        unsafe { libc::atexit(restore_terminal_at_exit) };
+    }
+    eprintln!("console attached, press ctrl-] h for help");
+    thread::spawn(move || {
+        if let Err(err) = forward_stdio(console) {
+            error!("failed to forward terminal to console, error={err}");
+        }
+        restore_terminal();
+    });
+    Ok(())
+}
+
+pub fn restore_terminal() {
+    if let Some(original) = ORIGINAL_TERMINAL.get() {
+        unsafe { libc::tcsetattr(io::stdin().as_raw_fd(), libc::TCSANOW, original) };
+    }
+}
+
+extern "C" fn restore_terminal_at_exit() {
+    restore_terminal();
+}
+
+#[derive(Debug, PartialEq)]
+enum Escape {
+    Send(u8),
+    Stop,
+    Detach,
+    Help,
+}
+
+// key pressed after ctrl-], ctrl-] twice sends it to guest
+fn escape(key: u8) -> Escape {
+    match key {
+        DETACH_KEY => Escape::Send(DETACH_KEY),
+        b'q' => Escape::Stop,
+        b'd' => Escape::Detach,
+        _ => Escape::Help,
+    }
+}
+
+fn forward_stdio(mut console: File) -> Result<(), Exception> {
+    let mut stdin = io::stdin().lock();
+    let mut buffer = [0; 1024];
+    let mut escaped = false;
+    loop {
+        let length = stdin.read(&mut buffer)?;
+        if length == 0 {
+            return Ok(());
+        }
+        let mut input = Vec::with_capacity(length);
+        for &key in &buffer[..length] {
+            if !escaped {
+                if key == DETACH_KEY {
+                    escaped = true;
+                } else {
+                    input.push(key);
+                }
+                continue;
+            }
+            escaped = false;
+            match escape(key) {
+                Escape::Send(key) => input.push(key),
+                Escape::Stop => {
+                    console.write_all(&input)?;
+                    eprintln!("\nstop vm");
+                    // same as ctrl-c, handled by signal handler of runner
+                    unsafe { libc::kill(libc::getpid(), libc::SIGINT) };
+                    return Ok(());
+                }
+                Escape::Detach => {
+                    console.write_all(&input)?;
+                    eprintln!("\nconsole detached, vm keeps running, stop with vz stop");
+                    return Ok(());
+                }
+                Escape::Help => eprintln!("\nctrl-] q: stop vm, ctrl-] d: detach console, ctrl-] ctrl-]: send ctrl-]"),
+            }
+        }
+        console.write_all(&input)?;
+    }
+}
+
+fn forward_output(mut reader: File) {
+    thread::spawn(move || {
+        let mut stdout = io::stdout();
+        let mut buffer = [0; 4096];
+        while let Ok(length) = reader.read(&mut buffer) {
+            if length == 0 || stdout.write_all(&buffer[..length]).and_then(|_| stdout.flush()).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn forward_stdin(mut console: File) -> Result<(), Exception> {
+    let mut stdin = io::stdin().lock();
+    let mut buffer = [0; 1024];
+    loop {
+        let length = stdin.read(&mut buffer)?;
+        if length == 0 {
+            return Ok(());
+        }
+        let input = &buffer[..length];
+        if let Some(index) = input.iter().position(|&key| key == DETACH_KEY) {
+            console.write_all(&input[..index])?;
+            return Ok(());
+        }
+        console.write_all(input)?;
+    }
+}
+
+fn make_raw(fd: RawFd) -> Result<libc::termios, Exception> {
+    set_raw(fd, false)
+}
+
+fn set_raw(fd: RawFd, output_processing: bool) -> Result<libc::termios, Exception> {
+    unsafe {
+        let mut termios = MaybeUninit::<libc::termios>::uninit();
+        if libc::tcgetattr(fd, termios.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let original = termios.assume_init();
+        let mut raw = original;
+        libc::cfmakeraw(&mut raw);
+        if output_processing {
+            raw.c_oflag |= libc::OPOST | libc::ONLCR;
+        }
+        if libc::tcsetattr(fd, libc::TCSANOW, &raw) != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(original)
+    }
+}
+
+// restore terminal mode on drop
+struct RawTerminal {
+    original: libc::termios,
+}
+
+impl RawTerminal {
+    fn new() -> Result<Self, Exception> {
+        let original = make_raw(io::stdin().as_raw_fd())?;
+        Ok(Self { original })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(io::stdin().as_raw_fd(), libc::TCSANOW, &self.original) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Escape;
+    use super::DETACH_KEY;
+
+    #[test]
+    fn escape() {
+        assert_eq!(Escape::Send(DETACH_KEY), super::escape(DETACH_KEY));
+        assert_eq!(Escape::Stop, super::escape(b'q'));
+        assert_eq!(Escape::Detach, super::escape(b'd'));
+        assert_eq!(Escape::Help, super::escape(b'x'));
+    }
+}
@@ -0,0 +1,379 @@
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use objc2::rc::Retained;
+use objc2::runtime::NSObjectProtocol;
+use objc2::ClassType;
+use objc2_foundation::run_on_main;
+use objc2_foundation::CGSize;
+use objc2_foundation::MainThreadBound;
+use objc2_foundation::NSObject;
+use objc2_virtualization::VZVirtioTraditionalMemoryBalloonDevice;
+use objc2_virtualization::VZVirtualMachine;
+use objc2_virtualization::VZVirtualMachineState;
+use serde::Deserialize;
+use serde::Serialize;
+use tracing::error;
+use tracing::info;
+
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+use crate::util::json;
+use crate::vm;
+use crate::vm::usb;
+use crate::vm::vsock;
+
+// runner listens on control socket in vm dir, request and response are single lines of json,
+// e.g. {"command": "status"} and {"ok": true, "state": "running", "uptime": 60, "devices": [...]}, error response is {"ok": false, "error": "<message>"},
+// after ok of vsock requests, rest of connection is bridged to guest vsock
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "command", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Request {
+    Status,
+    // ask guest to stop, force stop if it doesn't within timeout seconds, default to vm::STOP_TIMEOUT, then exit runner
+    Stop { timeout: Option<u64> },
+    ForceStop,
+    Pause,
+    Resume,
+    ResizeDisplay { width: u32, height: u32 },
+    // target memory of balloon device in bytes, guest returns memory above target to host
+    Balloon { target: u64 },
+    VsockConnect { port: u32 },
+    VsockListen { port: u32 },
+    // save state into vm dir, then stop vm and exit runner
+    Suspend,
+    // attach disk image as usb mass storage, path is absolute, requires macOS 15
+    UsbAttach { path: String, read_only: bool },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // state of vm for status, e.g. running, paused
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    // seconds since vm started, for status
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime: Option<u64>,
+    // class names of devices attached to vm, for status, e.g. VZVirtioNetworkDevice
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub devices: Option<Vec<String>>,
+}
+
+impl Response {
+    fn ok() -> Self {
+        Response {
+            ok: true,
+            error: None,
+            state: None,
+            uptime: None,
+            devices: None,
+        }
+    }
+
+    fn error(message: String) -> Self {
+        Response {
+            ok: false,
+            error: Some(message),
+            ..Response::ok()
+        }
+    }
+
+    fn status(vm: &VZVirtualMachine) -> Self {
+        Response {
+            state: Some(vm_state(vm).to_string()),
+            uptime: vm::uptime().map(|uptime| uptime.as_secs()),
+            devices: Some(devices(vm)),
+            ..Response::ok()
+        }
+    }
+}
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn listen(dir: &VmDir, vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>) -> Result<(), Exception> {
+    let path = &dir.control_path;
+    if path.symlink_metadata().is_ok() {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    info!("listen on control socket, path={}", path.to_string_lossy());
+    let state = dir.state_path.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                // each connection has own thread, e.g. vsock bridge or stop waiting for guest doesn't block status of vz ls
+                Ok(stream) => {
+                    let state = state.clone();
+                    let vm = Arc::clone(&vm);
+                    thread::spawn(move || {
+                        if let Err(err) = serve(stream, &state, vm) {
+                            error!("failed to serve control request, error={err}");
+                        }
+                    });
+                }
+                Err(err) => error!("failed to accept control connection, error={err}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+// send request to runner of vm
+pub fn send(dir: &VmDir, request: &Request) -> Result<Response, Exception> {
+    request_with_timeout(dir, request, None)
+}
+
+// status of vm from runner, none if runner doesn't respond in time, e.g. older runner without json protocol
+pub fn status(dir: &VmDir) -> Option<Response> {
+    request_with_timeout(dir, &Request::Status, Some(Duration::from_secs(1))).ok()
+}
+
+fn request_with_timeout(dir: &VmDir, request: &Request, timeout: Option<Duration>) -> Result<Response, Exception> {
+    let mut stream = connect(dir)?;
+    stream.set_read_timeout(timeout)?;
+    writeln!(stream, "{}", json::to_json(request)?)?;
+    let mut response = String::new();
+    BufReader::new(stream).read_line(&mut response)?;
+    check_response(&response)
+}
+
+// send vsock request and return connection bridged to guest vsock
+pub fn open(dir: &VmDir, request: &Request) -> Result<UnixStream, Exception> {
+    let mut stream = connect(dir)?;
+    writeln!(stream, "{}", json::to_json(request)?)?;
+    // read response byte by byte, data after it belongs to guest
+    let mut response = vec![];
+    let mut byte = [0];
+    while stream.read(&mut byte)? == 1 && byte[0] != b'\n' {
+        response.push(byte[0]);
+    }
+    check_response(&String::from_utf8_lossy(&response))?;
+    Ok(stream)
+}
+
+fn connect(dir: &VmDir) -> Result<UnixStream, Exception> {
+    UnixStream::connect(&dir.control_path)
+        .map_err(|err| Exception::ValidationError(format!("failed to connect control socket of vm, name={}, error={err}", dir.name())))
+}
+
+fn check_response(line: &str) -> Result<Response, Exception> {
+    let response: Response = json::from_json(line.trim_end())?;
+    if !response.ok {
+        return Err(Exception::ValidationError(response.error.unwrap_or_default()));
+    }
+    Ok(response)
+}
+
+fn respond(stream: &UnixStream, response: &Response) -> io::Result<()> {
+    let line = serde_json::to_string(response).map_err(io::Error::other)?;
+    writeln!(&*stream, "{line}")
+}
+
+fn serve(stream: UnixStream, state: &Path, vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>) -> Result<(), Exception> {
+    // client must send request line in time, connection of vsock bridge is blocking after it
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    stream.set_read_timeout(None)?;
+    let request = parse(line.trim_end());
+    match request {
+        Ok(Request::VsockConnect { port }) => {
+            respond(&stream, &Response::ok())?;
+            vsock::connect(vm, port, Arc::new(stream));
+            return Ok(());
+        }
+        Ok(Request::VsockListen { port }) => {
+            let writer = stream.try_clone()?;
+            if let Err(message) = vsock::listen(vm, port, stream, |stream| respond(stream, &Response::ok())) {
+                respond(&writer, &Response::error(message))?;
+            }
+            return Ok(());
+        }
+        Ok(Request::Status) => {
+            respond(&stream, &run_on_main(move |marker| Response::status(vm.get(marker))))?;
+            return Ok(());
+        }
+        _ => {}
+    }
+    let result = request
+        .as_ref()
+        .map_err(String::clone)
+        .and_then(|request| handle(request, state, Arc::clone(&vm)));
+    match &result {
+        Ok(()) => respond(&stream, &Response::ok())?,
+        Err(message) => respond(&stream, &Response::error(message.clone()))?,
+    }
+    // runner exits after response is sent
+    match (request, result) {
+        (Ok(Request::Suspend), Ok(_)) => vm::abort(vm, 0),
+        (Ok(Request::Stop { timeout }), Ok(_)) => vm::stop_vm(vm, timeout.map_or(vm::STOP_TIMEOUT, Duration::from_secs)),
+        (Ok(Request::ForceStop), Ok(_)) => vm::force_stop_vm(vm),
+        _ => {}
+    }
+    Ok(())
+}
+
+fn handle(request: &Request, state: &Path, vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>) -> Result<(), String> {
+    match *request {
+        // stop is started after response is sent, as runner exits once vm stopped
+        Request::Stop { .. } | Request::ForceStop => Ok(()),
+        Request::Pause => {
+            info!("pause vm");
+            vm::pause_vm(&vm).map(|_| ()).map_err(|err| format!("failed to pause vm, error={err}"))
+        }
+        Request::Resume => {
+            info!("resume vm");
+            vm::resume_vm(&vm).map(|_| ()).map_err(|err| format!("failed to resume vm, error={err}"))
+        }
+        Request::Suspend => {
+            info!("suspend vm, state={}", state.to_string_lossy());
+            vm::suspend_vm(&vm, state.to_path_buf())
+                .map(|_| ())
+                .map_err(|err| format!("failed to suspend vm, error={err}"))
+        }
+        Request::UsbAttach { ref path, read_only } => {
+            usb::attach(&vm, PathBuf::from(path), read_only).map_err(|err| format!("failed to attach usb device, error={err}"))
+        }
+        Request::ResizeDisplay { width, height } => {
+            info!("resize display, width={width}, height={height}");
+            run_on_main(move |marker| {
+                let vm = vm.get(marker);
+                let display = unsafe { vm.graphicsDevices() }
+                    .get_retained(0)
+                    .and_then(|device| unsafe { device.displays() }.get_retained(0));
+                let Some(display) = display else {
+                    return Err("vm has no display".to_string());
+                };
+                unsafe { display.reconfigureWithSizeInPixels_error(CGSize::new(width as f64, height as f64)) }
+                    .map_err(|err| format!("failed to resize display, error={}", err.localizedDescription()))
+            })
+        }
+        Request::Status | Request::VsockConnect { .. } | Request::VsockListen { .. } => {
+            unreachable!("status and vsock requests are handled by serve")
+        }
+        Request::Balloon { target } => {
+            info!("set memory balloon target, target={target}");
+            run_on_main(move |marker| {
+                let vm = vm.get(marker);
+                // only traditional balloon device is attached, see create_vm_config
+                let device = unsafe { vm.memoryBalloonDevices() }
+                    .get_retained(0)
+                    .filter(|device| device.isKindOfClass(VZVirtioTraditionalMemoryBalloonDevice::class()));
+                let Some(device) = device else {
+                    return Err("vm has no memory balloon device".to_string());
+                };
+                unsafe {
+                    let device = Retained::cast::<VZVirtioTraditionalMemoryBalloonDevice>(device);
+                    device.setTargetVirtualMachineMemorySize(target);
+                }
+                Ok(())
+            })
+        }
+    }
+}
+
+fn vm_state(vm: &VZVirtualMachine) -> &'static str {
+    match unsafe { vm.state() } {
+        VZVirtualMachineState::Stopped => "stopped",
+        VZVirtualMachineState::Running => "running",
+        VZVirtualMachineState::Paused => "paused",
+        VZVirtualMachineState::Starting => "starting",
+        VZVirtualMachineState::Pausing => "pausing",
+        VZVirtualMachineState::Resuming => "resuming",
+        VZVirtualMachineState::Stopping => "stopping",
+        VZVirtualMachineState::Saving => "saving",
+        VZVirtualMachineState::Restoring => "restoring",
+        _ => "error",
+    }
+}
+
+fn devices(vm: &VZVirtualMachine) -> Vec<String> {
+    let name = |device: &NSObject| device.class().name().to_string();
+    let mut devices = vec![];
+    unsafe {
+        devices.extend(vm.graphicsDevices().iter().map(|device| name(device)));
+        devices.extend(vm.networkDevices().iter().map(|device| name(device)));
+        devices.extend(vm.directorySharingDevices().iter().map(|device| name(device)));
+        devices.extend(vm.consoleDevices().iter().map(|device| name(device)));
+        devices.extend(vm.socketDevices().iter().map(|device| name(device)));
+        devices.extend(vm.memoryBalloonDevices().iter().map(|device| name(device)));
+    }
+    devices
+}
+
+fn parse(line: &str) -> Result<Request, String> {
+    serde_json::from_str(line).map_err(|err| format!("invalid request, request={line}, error={err}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Request;
+    use super::Response;
+    use crate::util::json;
+
+    #[test]
+    fn parse() {
+        let request = Request::ResizeDisplay { width: 1920, height: 1080 };
+        assert_eq!(
+            json::to_json(&request).unwrap(),
+            r#"{"command":"resize_display","width":1920,"height":1080}"#
+        );
+        assert_eq!(super::parse(&json::to_json(&request).unwrap()), Ok(request));
+        assert_eq!(super::parse(r#"{"command": "suspend"}"#), Ok(Request::Suspend));
+        assert_eq!(super::parse(r#"{"command": "force_stop"}"#), Ok(Request::ForceStop));
+        assert_eq!(super::parse(r#"{"command": "stop"}"#), Ok(Request::Stop { timeout: None }));
+        assert_eq!(
+            super::parse(r#"{"command": "stop", "timeout": 60}"#),
+            Ok(Request::Stop { timeout: Some(60) })
+        );
+        assert_eq!(
+            super::parse(r#"{"command": "balloon", "target": 4294967296}"#),
+            Ok(Request::Balloon { target: 4294967296 })
+        );
+        assert_eq!(
+            super::parse(r#"{"command": "usb_attach", "path": "/tmp/firmware.img", "read_only": false}"#),
+            Ok(Request::UsbAttach {
+                path: "/tmp/firmware.img".to_string(),
+                read_only: false
+            })
+        );
+        assert!(super::parse(r#"{"command": "balloon", "target": "4G"}"#).is_err());
+        assert_eq!(
+            super::parse(r#"{"command": "vsock_connect", "port": 1024}"#),
+            Ok(Request::VsockConnect { port: 1024 })
+        );
+        assert!(super::parse(r#"{"command": "resize_display", "width": 1920}"#).is_err());
+        assert!(super::parse(r#"{"command": "reboot"}"#).is_err());
+        assert!(super::parse("suspend").is_err());
+    }
+
+    #[test]
+    fn check_response() {
+        assert_eq!(
+            super::check_response("{\"ok\":true,\"state\":\"paused\"}\n").unwrap().state,
+            Some("paused".to_string())
+        );
+        assert!(super::check_response(r#"{"ok": false, "error": "vm has no display"}"#).is_err());
+        assert_eq!(
+            super::check_response(r#"{"ok": true, "state": "running", "uptime": 60, "devices": ["VZVirtioSocketDevice"]}"#)
+                .unwrap()
+                .devices,
+            Some(vec!["VZVirtioSocketDevice".to_string()])
+        );
+        assert_eq!(json::to_json(&Response::ok()).unwrap(), r#"{"ok":true}"#);
+    }
+}
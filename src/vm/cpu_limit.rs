@@ -0,0 +1,137 @@
+use std::ffi::c_int;
+use std::mem;
+use std::process;
+use std::ptr;
+use std::sync::Mutex;
+use std::thread;
+use std::thread::sleep;
+use std::time::Duration;
+
+use libc::pid_t;
+use tracing::info;
+use tracing::warn;
+
+use crate::vm;
+
+const PERIOD: Duration = Duration::from_millis(100);
+// vcpus run in xpc service process launched by Virtualization.framework, not in vz process
+const VM_PROCESS_NAME: &str = "com.apple.Virtualization.VirtualMachine";
+// vm process being duty cycled, 0 if none or released, lock is held while sending signals so release can't interleave
+static LIMITED_PID: Mutex<pid_t> = Mutex::new(0);
+
+extern "C" {
+    // xpc service is child of launchd, vz process is responsible for it
+    fn responsibility_get_pid_responsible_for_pid(pid: pid_t) -> pid_t;
+}
+
+// duty cycle vm process with SIGSTOP/SIGCONT, so it runs at most percent of wall time
+pub fn limit(percent: u8) {
+    thread::spawn(move || {
+        let own = process::id() as pid_t;
+        let Some(pid) = wait_for_vm_process(own) else {
+            warn!("vm process not found, cpu limit is not applied");
+            return;
+        };
+        info!("limit vm cpu, pid={pid}, percent={percent}");
+        *LIMITED_PID.lock().unwrap() = pid;
+        let running = PERIOD * percent as u32 / 100;
+        loop {
+            sleep(running);
+            {
+                let limited = LIMITED_PID.lock().unwrap();
+                // vm process must run to handle stop request
+                if vm::stop_requested() || *limited == 0 {
+                    return;
+                }
+                // pid may be reused by other process after vm process exits
+                if unsafe { responsibility_get_pid_responsible_for_pid(pid) } != own {
+                    info!("vm process exited, stop cpu limit, pid={pid}");
+                    return;
+                }
+                unsafe {
+                    libc::kill(pid, libc::SIGSTOP);
+                }
+            }
+            sleep(PERIOD - running);
+            // released in between already continued vm process
+            if *LIMITED_PID.lock().unwrap() == pid {
+                unsafe {
+                    libc::kill(pid, libc::SIGCONT);
+                }
+            }
+        }
+    });
+}
+
+// stop duty cycle and continue vm process, e.g. on signal or exit of runner, otherwise it's left stopped
+pub fn release() {
+    let mut limited = LIMITED_PID.lock().unwrap();
+    let pid = mem::take(&mut *limited);
+    if pid != 0 {
+        unsafe {
+            libc::kill(pid, libc::SIGCONT);
+        }
+    }
+}
+
+fn wait_for_vm_process(own: pid_t) -> Option<pid_t> {
+    for _ in 0..30 {
+        if let Some(pid) = find_vm_process(own) {
+            return Some(pid);
+        }
+        sleep(Duration::from_secs(1));
+    }
+    None
+}
+
+// vm process of runner, e.g. to sample cpu and disk io of guest, none if vm is not started yet
+pub fn find_vm_process(runner: pid_t) -> Option<pid_t> {
+    let count = unsafe { libc::proc_listallpids(ptr::null_mut(), 0) };
+    // reserve room for processes started in between
+    let mut pids: Vec<pid_t> = vec![0; count.max(0) as usize + 64];
+    let count = unsafe { libc::proc_listallpids(pids.as_mut_ptr().cast(), (pids.len() * mem::size_of::<pid_t>()) as c_int) };
+    pids.truncate(count.max(0) as usize);
+    select_vm_process(
+        runner,
+        &pids,
+        |pid| unsafe { responsibility_get_pid_responsible_for_pid(pid) },
+        process_path,
+    )
+}
+
+// path is only resolved for processes runner is responsible for, proc_pidpath of every process is slow
+fn select_vm_process(runner: pid_t, pids: &[pid_t], responsible: impl Fn(pid_t) -> pid_t, path: impl Fn(pid_t) -> String) -> Option<pid_t> {
+    pids.iter()
+        .copied()
+        .find(|&pid| pid != runner && responsible(pid) == runner && path(pid).ends_with(VM_PROCESS_NAME))
+}
+
+fn process_path(pid: pid_t) -> String {
+    let mut buffer = vec![0_u8; libc::PROC_PIDPATHINFO_MAXSIZE as usize];
+    let length = unsafe { libc::proc_pidpath(pid, buffer.as_mut_ptr().cast(), buffer.len() as u32) };
+    String::from_utf8_lossy(&buffer[..length.max(0) as usize]).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn select_vm_process() {
+        // 10 is vm process of runner 1, 20 is vm process of other runner 2, 30 is other xpc service of runner 1
+        let responsible = |pid| match pid {
+            10 | 30 => 1,
+            20 => 2,
+            pid => pid,
+        };
+        let path = |pid| match pid {
+            10 | 20 => format!(
+                "/System/Library/Frameworks/Virtualization.framework/XPCServices/{}",
+                super::VM_PROCESS_NAME
+            ),
+            _ => "/usr/libexec/other".to_string(),
+        };
+        let pids = [1, 2, 30, 20, 10];
+        assert_eq!(super::select_vm_process(1, &pids, responsible, path), Some(10));
+        assert_eq!(super::select_vm_process(2, &pids, responsible, path), Some(20));
+        assert_eq!(super::select_vm_process(3, &pids, responsible, path), None);
+    }
+}
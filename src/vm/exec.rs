@@ -0,0 +1,209 @@
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+
+use tracing::error;
+use tracing::info;
+
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+use crate::util::json;
+use crate::vm::control;
+use crate::vm::control::Request;
+use crate::vm::vsock;
+
+// vsock port of exec agent in guest
+pub const PORT: u32 = 7071;
+const MAX_PAYLOAD_SIZE: usize = 16 * 1024 * 1024;
+
+// frame is 1 byte kind, 4 bytes big endian length then payload,
+// host sends spawn with json array of command and args, then stdin, empty stdin is eof,
+// agent sends stdout and stderr, then exit with 4 bytes big endian exit code, 128 + signal if killed by signal
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Kind {
+    Spawn = 1,
+    Stdin = 2,
+    Stdout = 3,
+    Stderr = 4,
+    Exit = 5,
+}
+
+impl Kind {
+    fn from_byte(value: u8) -> Option<Kind> {
+        match value {
+            1 => Some(Kind::Spawn),
+            2 => Some(Kind::Stdin),
+            3 => Some(Kind::Stdout),
+            4 => Some(Kind::Stderr),
+            5 => Some(Kind::Exit),
+            _ => None,
+        }
+    }
+}
+
+pub fn encode(kind: Kind, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![kind as u8];
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Option<(Kind, Vec<u8>)>> {
+    let mut header = [0; 5];
+    match reader.read_exact(&mut header) {
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let Some(kind) = Kind::from_byte(header[0]) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown frame kind, kind={}", header[0]),
+        ));
+    };
+    let length = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if length > MAX_PAYLOAD_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame too large, size={length}")));
+    }
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+    Ok(Some((kind, payload)))
+}
+
+// run command in guest by agent, through control socket of runner, returns exit code of command
+pub fn run(dir: &VmDir, command: &[String], stdin: Option<impl Read + Send + 'static>, stdout: &mut impl Write) -> Result<i32, Exception> {
+    let stream = control::open(dir, &Request::VsockConnect { port: PORT })?;
+    let mut writer = stream.try_clone()?;
+    writer.write_all(&encode(Kind::Spawn, json::to_json(&command)?.as_bytes()))?;
+    thread::spawn(move || {
+        if let Some(mut stdin) = stdin {
+            let mut buffer = [0; 64 * 1024];
+            while let Ok(length @ 1..) = stdin.read(&mut buffer) {
+                if writer.write_all(&encode(Kind::Stdin, &buffer[..length])).is_err() {
+                    return;
+                }
+            }
+        }
+        let _ = writer.write_all(&encode(Kind::Stdin, &[]));
+    });
+
+    let mut reader = stream;
+    while let Some((kind, payload)) = read_frame(&mut reader)? {
+        match kind {
+            Kind::Stdout => {
+                stdout.write_all(&payload)?;
+                stdout.flush()?;
+            }
+            Kind::Stderr => io::stderr().write_all(&payload)?,
+            Kind::Exit => return Ok(payload.try_into().map_or(1, i32::from_be_bytes)),
+            Kind::Spawn | Kind::Stdin => {}
+        }
+    }
+    Err(Exception::ValidationError(format!(
+        "exec agent disconnected, run vz exec-agent in guest, or agent of same protocol on vsock port {PORT}, name={}",
+        dir.name()
+    )))
+}
+
+// run in macOS guest, linux guest needs own agent speaking same protocol on PORT
+pub fn serve() -> Result<(), Exception> {
+    let listener = vsock::listen_in_guest(PORT)?;
+    info!("exec agent listening, port={PORT}");
+    loop {
+        let stream = vsock::accept_in_guest(listener)?;
+        thread::spawn(move || {
+            if let Err(err) = execute(stream) {
+                error!("failed to exec command, error={err}");
+            }
+        });
+    }
+}
+
+fn execute(stream: UnixStream) -> Result<(), Exception> {
+    let mut reader = stream.try_clone()?;
+    let writer = Arc::new(Mutex::new(stream));
+    let command: Vec<String> = match read_frame(&mut reader)? {
+        Some((Kind::Spawn, payload)) => json::from_json(&String::from_utf8_lossy(&payload))?,
+        _ => return Err(Exception::ValidationError("first frame must be spawn".to_string())),
+    };
+    let Some((program, args)) = command.split_first() else {
+        return Err(Exception::ValidationError("command is empty".to_string()));
+    };
+    info!("exec command, command={}", command.join(" "));
+    let mut child = match Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            // same as shell, command not found
+            let mut writer = writer.lock().unwrap();
+            writer.write_all(&encode(
+                Kind::Stderr,
+                format!("failed to exec command, program={program}, error={err}\n").as_bytes(),
+            ))?;
+            writer.write_all(&encode(Kind::Exit, &127_i32.to_be_bytes()))?;
+            return Ok(());
+        }
+    };
+
+    let mut stdin = child.stdin.take();
+    thread::spawn(move || {
+        while let Ok(Some((Kind::Stdin, payload))) = read_frame(&mut reader) {
+            if payload.is_empty() {
+                break;
+            }
+            if stdin.as_mut().is_some_and(|stdin| stdin.write_all(&payload).is_err()) {
+                break;
+            }
+        }
+        drop(stdin.take());
+    });
+    let stdout = child.stdout.take().map(|output| forward(output, Kind::Stdout, Arc::clone(&writer)));
+    let stderr = child.stderr.take().map(|output| forward(output, Kind::Stderr, Arc::clone(&writer)));
+    for output in [stdout, stderr].into_iter().flatten() {
+        let _ = output.join();
+    }
+    let status = child.wait()?;
+    let code = status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0));
+    writer.lock().unwrap().write_all(&encode(Kind::Exit, &code.to_be_bytes()))?;
+    Ok(())
+}
+
+fn forward(mut output: impl Read + Send + 'static, kind: Kind, writer: Arc<Mutex<UnixStream>>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buffer = [0; 64 * 1024];
+        while let Ok(length) = output.read(&mut buffer) {
+            if length == 0 || writer.lock().unwrap().write_all(&encode(kind, &buffer[..length])).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::Kind;
+
+    #[test]
+    fn frame() {
+        let mut input = super::encode(Kind::Stdout, b"hello");
+        input.extend(super::encode(Kind::Exit, &3_i32.to_be_bytes()));
+        let mut reader = Cursor::new(input);
+        assert_eq!(Some((Kind::Stdout, b"hello".to_vec())), super::read_frame(&mut reader).unwrap());
+        assert_eq!(Some((Kind::Exit, vec![0, 0, 0, 3])), super::read_frame(&mut reader).unwrap());
+        assert_eq!(None, super::read_frame(&mut reader).unwrap());
+        assert!(super::read_frame(&mut Cursor::new(vec![9, 0, 0, 0, 0])).is_err());
+    }
+}
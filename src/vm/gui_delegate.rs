@@ -38,7 +38,7 @@ declare_class!(
     unsafe impl NSWindowDelegate for GuiDelegate {
         #[method(windowWillClose:)]
         fn window_will_close(&self, _: &NSNotification) {
-             vm::stop_vm(Arc::clone(&self.ivars().vm));
+             vm::stop_vm(Arc::clone(&self.ivars().vm), vm::STOP_TIMEOUT);
         }
     }
 );
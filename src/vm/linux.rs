@@ -2,44 +2,63 @@ use std::path::Path;
 use std::path::PathBuf;
 
 use objc2::exception::catch;
+use objc2::msg_send;
 use objc2::rc::Id;
 use objc2::rc::Retained;
+use objc2::sel;
 use objc2::ClassType;
 use objc2_foundation::ns_string;
 use objc2_foundation::NSArray;
 use objc2_foundation::NSString;
 use objc2_foundation::NSURL;
+use objc2_virtualization::VZBootLoader;
 use objc2_virtualization::VZDirectorySharingDeviceConfiguration;
-use objc2_virtualization::VZDiskImageCachingMode;
 use objc2_virtualization::VZDiskImageStorageDeviceAttachment;
-use objc2_virtualization::VZDiskImageSynchronizationMode;
 use objc2_virtualization::VZEFIBootLoader;
 use objc2_virtualization::VZEFIVariableStore;
 use objc2_virtualization::VZGenericPlatformConfiguration;
 use objc2_virtualization::VZGraphicsDeviceConfiguration;
+use objc2_virtualization::VZLinuxBootLoader;
+use objc2_virtualization::VZLinuxRosettaAvailability;
 use objc2_virtualization::VZLinuxRosettaDirectoryShare;
+use objc2_virtualization::VZSerialPortConfiguration;
 use objc2_virtualization::VZStorageDeviceConfiguration;
 use objc2_virtualization::VZUSBKeyboardConfiguration;
 use objc2_virtualization::VZUSBMassStorageDeviceConfiguration;
 use objc2_virtualization::VZUSBScreenCoordinatePointingDeviceConfiguration;
 use objc2_virtualization::VZVirtioBlockDeviceConfiguration;
-use objc2_virtualization::VZVirtioEntropyDeviceConfiguration;
 use objc2_virtualization::VZVirtioFileSystemDeviceConfiguration;
 use objc2_virtualization::VZVirtioGraphicsDeviceConfiguration;
 use objc2_virtualization::VZVirtioGraphicsScanoutConfiguration;
-use objc2_virtualization::VZVirtioTraditionalMemoryBalloonDeviceConfiguration;
 use objc2_virtualization::VZVirtualMachine;
 use objc2_virtualization::VZVirtualMachineConfiguration;
 use tracing::info;
 
+use crate::config::vm_config;
+use crate::config::vm_config::Graphics;
 use crate::config::vm_config::VmConfig;
 use crate::config::vm_dir::VmDir;
 use crate::util::exception::Exception;
 use crate::util::path::PathExtension;
+use crate::vm::usb;
+use crate::vm::vsock;
 
-pub fn create_vm(dir: &VmDir, config: &VmConfig, gui: bool, mount: Option<&PathBuf>) -> Result<Retained<VZVirtualMachine>, Exception> {
+pub fn rosetta_installed() -> bool {
+    matches!(
+        unsafe { VZLinuxRosettaDirectoryShare::availability() },
+        VZLinuxRosettaAvailability::Installed
+    )
+}
+
+pub fn create_vm(
+    dir: &VmDir,
+    config: &VmConfig,
+    gui: bool,
+    mounts: &[PathBuf],
+    serial_ports: Vec<Retained<VZSerialPortConfiguration>>,
+) -> Result<Retained<VZVirtualMachine>, Exception> {
     info!("create linux vm, name={}", dir.name());
-    let vz_config = create_vm_config(dir, config, gui, mount)?;
+    let vz_config = create_vm_config(dir, config, gui, mounts, serial_ports)?;
     unsafe {
         vz_config.validateWithError()?;
         Ok(VZVirtualMachine::initWithConfiguration(VZVirtualMachine::alloc(), &vz_config))
@@ -50,37 +69,55 @@ fn create_vm_config(
     dir: &VmDir,
     config: &VmConfig,
     gui: bool,
-    mount: Option<&PathBuf>,
+    mounts: &[PathBuf],
+    serial_ports: Vec<Retained<VZSerialPortConfiguration>>,
 ) -> Result<Retained<VZVirtualMachineConfiguration>, Exception> {
     unsafe {
         let vz_config = VZVirtualMachineConfiguration::new();
         vz_config.setCPUCount(config.cpu);
         vz_config.setMemorySize(config.memory);
 
-        vz_config.setBootLoader(Option::Some(&boot_loader(dir)));
-        vz_config.setPlatform(&VZGenericPlatformConfiguration::new());
+        let boot_loader = boot_loader(dir, config)?;
+        vz_config.setBootLoader(Option::Some(&boot_loader));
+        let platform = platform(dir, config)?;
+        vz_config.setPlatform(&platform);
 
         if gui {
-            vz_config.setGraphicsDevices(&NSArray::from_vec(vec![display(1024, 768)]));
+            let display = match &config.graphics {
+                Some(Graphics::None) => return Err(Exception::ValidationError("--gui requires graphics, graphics=none".to_string())),
+                Some(Graphics::Virtio(graphics)) => display(graphics.scanouts, graphics.width, graphics.height),
+                Some(Graphics::Mac(_)) | None => display(1, 1024, 768),
+            };
+            vz_config.setGraphicsDevices(&NSArray::from_vec(vec![display]));
             vz_config.setKeyboards(&NSArray::from_vec(vec![Id::into_super(VZUSBKeyboardConfiguration::new())]));
             vz_config.setPointingDevices(&NSArray::from_vec(vec![Id::into_super(
                 VZUSBScreenCoordinatePointingDeviceConfiguration::new(),
             )]));
         }
 
-        vz_config.setNetworkDevices(&NSArray::from_vec(vec![config.network()]));
-        vz_config.setStorageDevices(&NSArray::from_vec(storage(dir, mount)?));
+        if !serial_ports.is_empty() {
+            vz_config.setSerialPorts(&NSArray::from_vec(serial_ports));
+        }
 
-        vz_config.setMemoryBalloonDevices(&NSArray::from_vec(vec![Id::into_super(
-            VZVirtioTraditionalMemoryBalloonDeviceConfiguration::new(),
-        )]));
-        vz_config.setEntropyDevices(&NSArray::from_vec(vec![Id::into_super(VZVirtioEntropyDeviceConfiguration::new())]));
+        vz_config.setNetworkDevices(&NSArray::from_vec(config.network_devices(dir)?));
+        vz_config.setStorageDevices(&NSArray::from_vec(storage(dir, config, mounts)?));
 
-        let mut sharings: Vec<Retained<VZDirectorySharingDeviceConfiguration>> = vec![];
-        if let Some(sharing) = config.sharing_directories()? {
-            sharings.push(sharing);
-        }
+        vz_config.setMemoryBalloonDevices(&NSArray::from_vec(config.memory_balloon_devices()));
+        vz_config.setEntropyDevices(&NSArray::from_vec(config.entropy_devices()));
+        vz_config.setConsoleDevices(&NSArray::from_vec(config.console_devices()?));
+        vz_config.setAudioDevices(&NSArray::from_vec(config.audio_devices()));
+        vz_config.setSocketDevices(&NSArray::from_vec(vec![vsock::socket_device()]));
+        usb::add_controller(&vz_config);
+
+        let mut sharings: Vec<Retained<VZDirectorySharingDeviceConfiguration>> = config.sharing_directories()?;
         if let Some(true) = config.rosetta {
+            // share fails at start with vague error if rosetta is missing, e.g. on new host
+            if !rosetta_installed() {
+                return Err(Exception::ValidationError(format!(
+                    "rosetta is enabled but not installed on host, install with vz rosetta install, or disable with vz set {} --rosetta=off",
+                    dir.name()
+                )));
+            }
             let device = VZVirtioFileSystemDeviceConfiguration::initWithTag(VZVirtioFileSystemDeviceConfiguration::alloc(), ns_string!("rosetta"));
             device.setShare(Some(&Id::into_super(VZLinuxRosettaDirectoryShare::new())));
             sharings.push(Id::into_super(device));
@@ -91,26 +128,65 @@ fn create_vm_config(
     }
 }
 
-fn boot_loader(dir: &VmDir) -> Retained<VZEFIBootLoader> {
+// kernel and initrd of config are read on every start, so kernel can be swapped without touching disk
+pub fn boot_files(dir: &VmDir, config: &VmConfig) -> Result<(PathBuf, Option<PathBuf>), Exception> {
+    let kernel = match &config.kernel {
+        Some(kernel) => vm_config::expand_path("kernel", kernel)?,
+        None => dir.kernel_path.clone(),
+    };
+    let initrd = match &config.initrd {
+        Some(initrd) => Some(vm_config::expand_path("initrd", initrd)?),
+        None => Some(dir.initrd_path.clone()).filter(|path| path.exists()),
+    };
+    Ok((kernel, initrd))
+}
+
+fn boot_loader(dir: &VmDir, config: &VmConfig) -> Result<Retained<VZBootLoader>, Exception> {
     unsafe {
+        if let Some(command_line) = &config.kernel_command_line {
+            let (kernel, initrd) = boot_files(dir, config)?;
+            for path in [Some(&kernel), initrd.as_ref()].into_iter().flatten() {
+                if !path.is_file() {
+                    return Err(Exception::ValidationError(format!(
+                        "boot file not found, path={}",
+                        path.to_string_lossy()
+                    )));
+                }
+            }
+            info!("boot kernel, path={}, command_line={command_line}", kernel.to_string_lossy());
+            let loader = VZLinuxBootLoader::initWithKernelURL(VZLinuxBootLoader::alloc(), &kernel.to_ns_url());
+            loader.setCommandLine(&NSString::from_str(command_line));
+            if let Some(initrd) = initrd {
+                info!("load initrd, path={}", initrd.to_string_lossy());
+                loader.setInitialRamdiskURL(Some(&initrd.to_ns_url()));
+            }
+            return Ok(Id::into_super(loader));
+        }
         let store = VZEFIVariableStore::initWithURL(VZEFIVariableStore::alloc(), &dir.nvram_path.to_ns_url());
         let loader = VZEFIBootLoader::new();
         loader.setVariableStore(Option::Some(&store));
-        loader
+        Ok(Id::into_super(loader))
     }
 }
 
-fn storage(dir: &VmDir, mount: Option<&PathBuf>) -> Result<Vec<Retained<VZStorageDeviceConfiguration>>, Exception> {
-    let disk = disk(&dir.disk_path)?;
-    let mut storage = vec![disk];
-    if let Option::Some(mount) = mount {
-        let disk = mount_disk(mount)?;
-        storage.push(disk)
+fn storage(dir: &VmDir, config: &VmConfig, mounts: &[PathBuf]) -> Result<Vec<Retained<VZStorageDeviceConfiguration>>, Exception> {
+    let mut storage = vec![disk(&dir.disk_path, config)?];
+    for name in &config.disks {
+        storage.push(disk(&dir.extra_disk_path(name), config)?);
+    }
+    if dir.seed_path.exists() {
+        info!("attach cloud-init seed, path={}", dir.seed_path.to_string_lossy());
+        storage.push(mount_disk(&dir.seed_path)?);
+    }
+    // EFI boots from usb storage if disk is not bootable yet, e.g. installer iso
+    for mount in mounts {
+        info!("attach disk image, path={}", mount.to_string_lossy());
+        storage.push(mount_disk(mount)?);
     }
     Ok(storage)
 }
 
-fn disk(disk: &Path) -> Result<Retained<VZStorageDeviceConfiguration>, Exception> {
+fn disk(disk: &Path, config: &VmConfig) -> Result<Retained<VZStorageDeviceConfiguration>, Exception> {
     unsafe {
         let attachment = catch(|| {
             let url = NSURL::initFileURLWithPath(NSURL::alloc(), &NSString::from_str(&disk.to_string_lossy()));
@@ -118,8 +194,8 @@ fn disk(disk: &Path) -> Result<Retained<VZStorageDeviceConfiguration>, Exception
                 VZDiskImageStorageDeviceAttachment::alloc(),
                 &url,
                 false,
-                VZDiskImageCachingMode::Automatic,
-                VZDiskImageSynchronizationMode::Fsync,
+                config.disk_caching_mode(),
+                config.disk_synchronization_mode(),
             )
         })??;
         let disk = VZVirtioBlockDeviceConfiguration::initWithAttachment(VZVirtioBlockDeviceConfiguration::alloc(), &attachment);
@@ -137,12 +213,39 @@ fn mount_disk(mount: &Path) -> Result<Retained<VZStorageDeviceConfiguration>, Ex
     }
 }
 
-fn display(width: isize, height: isize) -> Retained<VZGraphicsDeviceConfiguration> {
+fn platform(dir: &VmDir, config: &VmConfig) -> Result<Retained<VZGenericPlatformConfiguration>, Exception> {
+    let platform = unsafe { VZGenericPlatformConfiguration::new() };
+    if let Some(true) = config.nested {
+        if !nested_virtualization_supported() {
+            return Err(Exception::ValidationError(format!(
+                "nested virtualization is not supported on host, it requires M3 or later and macOS 15, disable with vz set {} --nested=off",
+                dir.name()
+            )));
+        }
+        let _: () = unsafe { msg_send![&platform, setNestedVirtualizationEnabled: true] };
+    }
+    Ok(platform)
+}
+
+// api is added in macOS 15 and not in bindings, detected by selector so same binary runs on older macOS
+pub fn nested_virtualization_supported() -> bool {
+    let class = VZGenericPlatformConfiguration::class();
+    class.metaclass().responds_to(sel!(isNestedVirtualizationSupported)) && unsafe { msg_send![class, isNestedVirtualizationSupported] }
+}
+
+fn display(count: usize, width: isize, height: isize) -> Retained<VZGraphicsDeviceConfiguration> {
     unsafe {
         let display = VZVirtioGraphicsDeviceConfiguration::new();
-        let scanout =
-            VZVirtioGraphicsScanoutConfiguration::initWithWidthInPixels_heightInPixels(VZVirtioGraphicsScanoutConfiguration::alloc(), width, height);
-        let scanouts = &NSArray::from_vec(vec![scanout]);
+        let scanouts = (0..count)
+            .map(|_| {
+                VZVirtioGraphicsScanoutConfiguration::initWithWidthInPixels_heightInPixels(
+                    VZVirtioGraphicsScanoutConfiguration::alloc(),
+                    width,
+                    height,
+                )
+            })
+            .collect();
+        let scanouts = &NSArray::from_vec(scanouts);
         display.setScanouts(scanouts);
         Id::into_super(display)
     }
@@ -1,5 +1,7 @@
 use std::path::Path;
+use std::sync::mpsc::channel;
 
+use block2::StackBlock;
 use objc2::exception::catch;
 use objc2::rc::Id;
 use objc2::rc::Retained;
@@ -10,11 +12,10 @@ use objc2_foundation::MainThreadMarker;
 use objc2_foundation::NSArray;
 use objc2_foundation::NSData;
 use objc2_foundation::NSDataBase64DecodingOptions;
+use objc2_foundation::NSError;
 use objc2_foundation::NSSize;
 use objc2_foundation::NSString;
-use objc2_virtualization::VZDiskImageCachingMode;
 use objc2_virtualization::VZDiskImageStorageDeviceAttachment;
-use objc2_virtualization::VZDiskImageSynchronizationMode;
 use objc2_virtualization::VZGraphicsDeviceConfiguration;
 use objc2_virtualization::VZMacAuxiliaryStorage;
 use objc2_virtualization::VZMacGraphicsDeviceConfiguration;
@@ -23,21 +24,24 @@ use objc2_virtualization::VZMacHardwareModel;
 use objc2_virtualization::VZMacKeyboardConfiguration;
 use objc2_virtualization::VZMacMachineIdentifier;
 use objc2_virtualization::VZMacOSBootLoader;
+use objc2_virtualization::VZMacOSRestoreImage;
 use objc2_virtualization::VZMacPlatformConfiguration;
 use objc2_virtualization::VZMacTrackpadConfiguration;
 use objc2_virtualization::VZPlatformConfiguration;
 use objc2_virtualization::VZStorageDeviceConfiguration;
 use objc2_virtualization::VZVirtioBlockDeviceConfiguration;
-use objc2_virtualization::VZVirtioEntropyDeviceConfiguration;
-use objc2_virtualization::VZVirtioTraditionalMemoryBalloonDeviceConfiguration;
 use objc2_virtualization::VZVirtualMachine;
 use objc2_virtualization::VZVirtualMachineConfiguration;
 use tracing::info;
 
+use crate::config::vm_config::Graphics;
+use crate::config::vm_config::MacGraphics;
 use crate::config::vm_config::VmConfig;
 use crate::config::vm_dir::VmDir;
 use crate::util::exception::Exception;
 use crate::util::path::PathExtension;
+use crate::vm::usb;
+use crate::vm::vsock;
 
 pub fn create_vm(dir: &VmDir, config: &VmConfig, marker: MainThreadMarker) -> Result<Retained<VZVirtualMachine>, Exception> {
     info!("create macOS vm, name={}", dir.name());
@@ -49,12 +53,65 @@ pub fn create_vm(dir: &VmDir, config: &VmConfig, marker: MainThreadMarker) -> Re
 }
 
 pub fn hardware_model(base64_string: &str) -> Retained<VZMacHardwareModel> {
+    parse_hardware_model(base64_string).unwrap()
+}
+
+pub fn parse_hardware_model(base64_string: &str) -> Option<Retained<VZMacHardwareModel>> {
     unsafe {
         let data_representation =
-            NSData::initWithBase64EncodedString_options(NSData::alloc(), &NSString::from_str(base64_string), NSDataBase64DecodingOptions::empty())
-                .unwrap();
+            NSData::initWithBase64EncodedString_options(NSData::alloc(), &NSString::from_str(base64_string), NSDataBase64DecodingOptions::empty())?;
+        VZMacHardwareModel::initWithDataRepresentation(VZMacHardwareModel::alloc(), &data_representation)
+    }
+}
+
+pub fn latest_restore_image_url() -> Result<String, Exception> {
+    let (tx, rx) = channel();
+    let block = StackBlock::new(move |image: *mut VZMacOSRestoreImage, err: *mut NSError| {
+        if !err.is_null() {
+            tx.send(Err(Exception::from_ns_error(err))).unwrap();
+        } else {
+            let url = unsafe { (*image).URL().absoluteString().unwrap() };
+            tx.send(Ok(url.to_string())).unwrap();
+        }
+    });
+    unsafe {
+        VZMacOSRestoreImage::fetchLatestSupportedWithCompletionHandler(&block);
+    };
+    rx.recv()?
+}
+
+pub fn load_restore_image(ipsw: &Path) -> Result<Retained<VZMacOSRestoreImage>, Exception> {
+    let (tx, rx) = channel();
+    unsafe {
+        let block = StackBlock::new(move |image: *mut VZMacOSRestoreImage, err: *mut NSError| {
+            if !err.is_null() {
+                tx.send(Err(Exception::from_ns_error(err))).unwrap();
+            } else {
+                let image = Id::from_raw(image).unwrap();
+                tx.send(Ok(image)).unwrap();
+            }
+        });
+        VZMacOSRestoreImage::loadFileURL_completionHandler(&ipsw.to_ns_url(), &block);
+    };
+    let image = rx.recv()??;
+    Ok(image)
+}
 
-        VZMacHardwareModel::initWithDataRepresentation(VZMacHardwareModel::alloc(), &data_representation).unwrap()
+// e.g. (14.5, 23F79)
+pub fn restore_image_version(image: &VZMacOSRestoreImage) -> (String, String) {
+    unsafe {
+        let version = image.operatingSystemVersion();
+        let version = format_version(version.majorVersion, version.minorVersion, version.patchVersion);
+        (version, image.buildVersion().to_string())
+    }
+}
+
+// patch version is omitted if zero, same as ipsw file name, e.g. 14.5, 14.6.1
+fn format_version(major: isize, minor: isize, patch: isize) -> String {
+    if patch == 0 {
+        format!("{major}.{minor}")
+    } else {
+        format!("{major}.{minor}.{patch}")
     }
 }
 
@@ -67,20 +124,39 @@ fn create_vm_config(dir: &VmDir, config: &VmConfig, marker: MainThreadMarker) ->
         vz_config.setBootLoader(Some(&VZMacOSBootLoader::new()));
         vz_config.setPlatform(&platform(dir, config));
 
-        vz_config.setGraphicsDevices(&NSArray::from_vec(vec![display(1920, 1080, marker)]));
+        let display = match &config.graphics {
+            Some(Graphics::Mac(graphics)) => display(graphics, marker),
+            _ => display(
+                &MacGraphics {
+                    displays: 1,
+                    width: 1920,
+                    height: 1080,
+                    pixels_per_inch: None,
+                },
+                marker,
+            ),
+        };
+        vz_config.setGraphicsDevices(&NSArray::from_vec(vec![display]));
         vz_config.setKeyboards(&NSArray::from_vec(vec![Id::into_super(VZMacKeyboardConfiguration::new())]));
         vz_config.setPointingDevices(&NSArray::from_vec(vec![Id::into_super(VZMacTrackpadConfiguration::new())]));
 
-        vz_config.setNetworkDevices(&NSArray::from_vec(vec![config.network()]));
-        vz_config.setStorageDevices(&NSArray::from_vec(vec![disk(&dir.disk_path)?]));
+        vz_config.setNetworkDevices(&NSArray::from_vec(config.network_devices(dir)?));
+        let mut storage = vec![disk(&dir.disk_path, config)?];
+        for name in &config.disks {
+            storage.push(disk(&dir.extra_disk_path(name), config)?);
+        }
+        vz_config.setStorageDevices(&NSArray::from_vec(storage));
 
-        vz_config.setMemoryBalloonDevices(&NSArray::from_vec(vec![Id::into_super(
-            VZVirtioTraditionalMemoryBalloonDeviceConfiguration::new(),
-        )]));
-        vz_config.setEntropyDevices(&NSArray::from_vec(vec![Id::into_super(VZVirtioEntropyDeviceConfiguration::new())]));
+        vz_config.setMemoryBalloonDevices(&NSArray::from_vec(config.memory_balloon_devices()));
+        vz_config.setEntropyDevices(&NSArray::from_vec(config.entropy_devices()));
+        vz_config.setConsoleDevices(&NSArray::from_vec(config.console_devices()?));
+        vz_config.setAudioDevices(&NSArray::from_vec(config.audio_devices()));
+        vz_config.setSocketDevices(&NSArray::from_vec(vec![vsock::socket_device()]));
+        usb::add_controller(&vz_config);
 
-        if let Some(sharing) = config.sharing_directories()? {
-            vz_config.setDirectorySharingDevices(&NSArray::from_vec(vec![sharing]));
+        let sharings = config.sharing_directories()?;
+        if !sharings.is_empty() {
+            vz_config.setDirectorySharingDevices(&NSArray::from_vec(sharings));
         }
         Ok(vz_config)
     }
@@ -99,15 +175,15 @@ fn platform(dir: &VmDir, config: &VmConfig) -> Retained<VZPlatformConfiguration>
     }
 }
 
-fn disk(disk: &Path) -> Result<Retained<VZStorageDeviceConfiguration>, Exception> {
+fn disk(disk: &Path, config: &VmConfig) -> Result<Retained<VZStorageDeviceConfiguration>, Exception> {
     unsafe {
         let attachment = catch(|| {
             VZDiskImageStorageDeviceAttachment::initWithURL_readOnly_cachingMode_synchronizationMode_error(
                 VZDiskImageStorageDeviceAttachment::alloc(),
                 &disk.to_ns_url(),
                 false,
-                VZDiskImageCachingMode::Automatic,
-                VZDiskImageSynchronizationMode::Fsync,
+                config.disk_caching_mode(),
+                config.disk_synchronization_mode(),
             )
         })??;
         let disk = VZVirtioBlockDeviceConfiguration::initWithAttachment(VZVirtioBlockDeviceConfiguration::alloc(), &attachment);
@@ -115,24 +191,47 @@ fn disk(disk: &Path) -> Result<Retained<VZStorageDeviceConfiguration>, Exception
     }
 }
 
-fn display(width: isize, height: isize, marker: MainThreadMarker) -> Retained<VZGraphicsDeviceConfiguration> {
-    let screen = NSScreen::mainScreen(marker).unwrap();
+// Virtualization.framework validates count of displays it supports
+fn display(graphics: &MacGraphics, marker: MainThreadMarker) -> Retained<VZGraphicsDeviceConfiguration> {
     unsafe {
         let display = VZMacGraphicsDeviceConfiguration::new();
-        display.setDisplays(&NSArray::from_vec(vec![VZMacGraphicsDisplayConfiguration::initForScreen_sizeInPoints(
-            VZMacGraphicsDisplayConfiguration::alloc(),
-            &screen,
-            NSSize::new(width as CGFloat, height as CGFloat),
-        )]));
+        let displays = (0..graphics.displays)
+            .map(|_| match graphics.pixels_per_inch {
+                Some(pixels_per_inch) => VZMacGraphicsDisplayConfiguration::initWithWidthInPixels_heightInPixels_pixelsPerInch(
+                    VZMacGraphicsDisplayConfiguration::alloc(),
+                    graphics.width,
+                    graphics.height,
+                    pixels_per_inch,
+                ),
+                None => VZMacGraphicsDisplayConfiguration::initForScreen_sizeInPoints(
+                    VZMacGraphicsDisplayConfiguration::alloc(),
+                    &NSScreen::mainScreen(marker).unwrap(),
+                    NSSize::new(graphics.width as CGFloat, graphics.height as CGFloat),
+                ),
+            })
+            .collect();
+        display.setDisplays(&NSArray::from_vec(displays));
         Id::into_super(display)
     }
 }
 
 fn machine_identifier(base64_string: &str) -> Retained<VZMacMachineIdentifier> {
+    parse_machine_identifier(base64_string).unwrap()
+}
+
+pub fn parse_machine_identifier(base64_string: &str) -> Option<Retained<VZMacMachineIdentifier>> {
     unsafe {
         let data_representation =
-            NSData::initWithBase64EncodedString_options(NSData::alloc(), &NSString::from_str(base64_string), NSDataBase64DecodingOptions::empty())
-                .unwrap();
-        VZMacMachineIdentifier::initWithDataRepresentation(VZMacMachineIdentifier::alloc(), &data_representation).unwrap()
+            NSData::initWithBase64EncodedString_options(NSData::alloc(), &NSString::from_str(base64_string), NSDataBase64DecodingOptions::empty())?;
+        VZMacMachineIdentifier::initWithDataRepresentation(VZMacMachineIdentifier::alloc(), &data_representation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn format_version() {
+        assert_eq!(super::format_version(14, 5, 0), "14.5");
+        assert_eq!(super::format_version(14, 6, 1), "14.6.1");
     }
 }
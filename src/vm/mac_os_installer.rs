@@ -1,7 +1,14 @@
 use core::ffi::c_void;
 use core::ptr;
+use std::cell::Cell;
+use std::fs;
+use std::io;
+use std::io::Write;
 use std::path::Path;
+use std::path::PathBuf;
 use std::process;
+use std::time::Duration;
+use std::time::Instant;
 
 use block2::StackBlock;
 use dispatch::ffi::dispatch_main;
@@ -34,8 +41,14 @@ use tracing::info;
 
 use crate::util::exception::Exception;
 use crate::util::path::PathExtension;
+use crate::util::terminal;
 
-pub fn install(vm: Retained<VZVirtualMachine>, ipsw: &Path, marker: MainThreadMarker) -> Result<(), Exception> {
+const PROGRESS_BAR_WIDTH: usize = 30;
+// non interactive output logs one line per step
+const PROGRESS_LOG_STEP: u32 = 5;
+
+// uninstalled marker is removed once installation succeeds, so run can tell vm is bootable
+pub fn install(vm: Retained<VZVirtualMachine>, ipsw: &Path, uninstalled_path: PathBuf, marker: MainThreadMarker) -> Result<(), Exception> {
     let installer = unsafe { VZMacOSInstaller::initWithVirtualMachine_restoreImageURL(VZMacOSInstaller::alloc(), &vm, &ipsw.to_ns_url()) };
     let _observer = VZMacOSInstallerObserver::new(unsafe { installer.progress() });
     let installer = MainThreadBound::new(installer, marker);
@@ -43,10 +56,23 @@ pub fn install(vm: Retained<VZVirtualMachine>, ipsw: &Path, marker: MainThreadMa
     run_on_main(|marker| {
         let installer = installer.get(marker);
         let block = &StackBlock::new(move |err: *mut NSError| {
+            if terminal::interactive() {
+                eprintln!();
+            }
             if !err.is_null() {
                 error!("failed to install, error={}", unsafe { (*err).localizedDescription() });
                 process::exit(1);
             } else {
+                if let Err(err) = fs::remove_file(&uninstalled_path).or_else(|err| match err.kind() {
+                    io::ErrorKind::NotFound => Ok(()),
+                    _ => Err(err),
+                }) {
+                    error!(
+                        "failed to remove uninstalled marker, path={}, error={err}",
+                        uninstalled_path.to_string_lossy()
+                    );
+                    process::exit(1);
+                }
                 info!("instal macOS done");
                 process::exit(0);
             }
@@ -61,6 +87,8 @@ pub fn install(vm: Retained<VZVirtualMachine>, ipsw: &Path, marker: MainThreadMa
 
 struct Ivars {
     progress: Retained<NSProgress>,
+    started: Instant,
+    last_logged: Cell<Option<u32>>,
 }
 
 declare_class!(
@@ -81,8 +109,18 @@ declare_class!(
         fn observe_value(&self, _key_path: Option<&NSString>, _object: Option<&AnyObject>, change: Option<&NSDictionary<NSKeyValueChangeKey, AnyObject>>, _context: *mut c_void) {
             if let Some(change) = change {
                 let new_value = change.get_retained(ns_string!("new")).unwrap();
-                let percent: Retained<NSNumber> = unsafe {Id::cast(new_value)};
-                info!("instal progress: {:.2}%", percent.floatValue() * 100.0);
+                let fraction: Retained<NSNumber> = unsafe {Id::cast(new_value)};
+                let fraction = fraction.doubleValue().clamp(0.0, 1.0);
+                let progress = format_progress(fraction, self.ivars().started.elapsed());
+                if terminal::interactive() {
+                    eprint!("\r{progress}");
+                    let _ = io::stderr().flush();
+                } else {
+                    let step = (fraction * 100.0) as u32 / PROGRESS_LOG_STEP;
+                    if self.ivars().last_logged.replace(Some(step)) != Some(step) {
+                        info!("instal progress: {progress}");
+                    }
+                }
             }
         }
     }
@@ -92,7 +130,11 @@ declare_class!(
 
 impl VZMacOSInstallerObserver {
     fn new(progress: Retained<NSProgress>) -> Retained<Self> {
-        let observer = Self::alloc().set_ivars(Ivars { progress });
+        let observer = Self::alloc().set_ivars(Ivars {
+            progress,
+            started: Instant::now(),
+            last_logged: Cell::new(None),
+        });
         let observer: Retained<Self> = unsafe { msg_send_id![super(observer), init] };
         let progress = &observer.ivars().progress;
         unsafe {
@@ -114,3 +156,45 @@ impl Drop for VZMacOSInstallerObserver {
         }
     }
 }
+
+// eta is estimated from average speed so far, e.g. "[#########---------------------] 30.00% eta 21m"
+fn format_progress(fraction: f64, elapsed: Duration) -> String {
+    let filled = (fraction * PROGRESS_BAR_WIDTH as f64) as usize;
+    let bar = format!("{}{}", "#".repeat(filled), "-".repeat(PROGRESS_BAR_WIDTH - filled));
+    let eta = if fraction > 0.0 && fraction < 1.0 {
+        let seconds = (elapsed.as_secs_f64() * (1.0 - fraction) / fraction).round() as u64;
+        if seconds >= 60 {
+            format!(" eta {}m", seconds.div_ceil(60))
+        } else {
+            format!(" eta {seconds}s")
+        }
+    } else {
+        String::new()
+    };
+    format!("[{bar}] {:.2}%{eta}", fraction * 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[test]
+    fn format_progress() {
+        assert_eq!(
+            super::format_progress(0.0, Duration::from_secs(0)),
+            "[------------------------------] 0.00%"
+        );
+        assert_eq!(
+            super::format_progress(0.3, Duration::from_secs(540)),
+            "[#########---------------------] 30.00% eta 21m"
+        );
+        assert_eq!(
+            super::format_progress(0.9, Duration::from_secs(90)),
+            "[###########################---] 90.00% eta 10s"
+        );
+        assert_eq!(
+            super::format_progress(1.0, Duration::from_secs(900)),
+            "[##############################] 100.00%"
+        );
+    }
+}
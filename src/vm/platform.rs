@@ -0,0 +1,212 @@
+use std::env;
+use std::path::Path;
+use std::time::Duration;
+
+use libc::pid_t;
+use objc2::exception::catch;
+use objc2::ClassType;
+use objc2_foundation::MainThreadMarker;
+use objc2_virtualization::VZEFIVariableStore;
+use objc2_virtualization::VZEFIVariableStoreInitializationOptions;
+use objc2_virtualization::VZMACAddress;
+use objc2_virtualization::VZVirtualMachineConfiguration;
+
+use crate::config::vm_config::VmConfig;
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+use crate::util::path::PathExtension;
+use crate::vm::control;
+use crate::vm::control::Response;
+use crate::vm::mac_os;
+use crate::vm::mac_os_installer;
+use crate::vm::runner;
+
+// boundary of Virtualization.framework calls and runner processes hosting them used by command logic,
+// so it can be tested on host without entitlement or running vm
+pub trait Platform {
+    fn create_efi_variable_store(&self, path: &Path) -> Result<(), Exception>;
+
+    fn random_mac_address(&self) -> String;
+
+    fn cpu_range(&self) -> (usize, usize);
+
+    fn memory_range(&self) -> (u64, u64);
+
+    // pid of runner holding vm dir, none if vm is stopped
+    fn runner_pid(&self, dir: &VmDir) -> Option<pid_t>;
+
+    // none if runner doesn't respond in time
+    fn runner_status(&self, dir: &VmDir) -> Option<Response>;
+
+    // launch runner in background with args of vz, e.g. run <name>
+    fn start(&self, dir: &VmDir, args: &[String]) -> Result<(), Exception>;
+
+    fn request_stop(&self, dir: &VmDir, force: bool, timeout: Duration);
+
+    // false if runner is still alive after seconds
+    fn wait_until_stopped(&self, dir: &VmDir, seconds: u32) -> bool;
+
+    // install restore image into disk of macOS vm, must be called on main thread
+    fn install(&self, dir: &VmDir, config: &VmConfig, ipsw: &Path) -> Result<(), Exception>;
+}
+
+pub struct Virtualization;
+
+impl Platform for Virtualization {
+    fn create_efi_variable_store(&self, path: &Path) -> Result<(), Exception> {
+        unsafe {
+            catch(|| {
+                VZEFIVariableStore::initCreatingVariableStoreAtURL_options_error(
+                    VZEFIVariableStore::alloc(),
+                    &path.to_ns_url(),
+                    VZEFIVariableStoreInitializationOptions::empty(),
+                )
+            })??;
+        }
+        Ok(())
+    }
+
+    fn random_mac_address(&self) -> String {
+        unsafe { VZMACAddress::randomLocallyAdministeredAddress().string().to_string() }
+    }
+
+    fn cpu_range(&self) -> (usize, usize) {
+        unsafe {
+            (
+                VZVirtualMachineConfiguration::minimumAllowedCPUCount(),
+                VZVirtualMachineConfiguration::maximumAllowedCPUCount(),
+            )
+        }
+    }
+
+    fn memory_range(&self) -> (u64, u64) {
+        unsafe {
+            (
+                VZVirtualMachineConfiguration::minimumAllowedMemorySize(),
+                VZVirtualMachineConfiguration::maximumAllowedMemorySize(),
+            )
+        }
+    }
+
+    fn runner_pid(&self, dir: &VmDir) -> Option<pid_t> {
+        dir.pid()
+    }
+
+    fn runner_status(&self, dir: &VmDir) -> Option<Response> {
+        control::status(dir)
+    }
+
+    fn start(&self, dir: &VmDir, args: &[String]) -> Result<(), Exception> {
+        runner::spawn(&env::current_exe()?, dir, args)?;
+        Ok(())
+    }
+
+    fn request_stop(&self, dir: &VmDir, force: bool, timeout: Duration) {
+        runner::request_stop(dir, force, timeout);
+    }
+
+    fn wait_until_stopped(&self, dir: &VmDir, seconds: u32) -> bool {
+        runner::wait_until_stopped(dir, seconds)
+    }
+
+    fn install(&self, dir: &VmDir, config: &VmConfig, ipsw: &Path) -> Result<(), Exception> {
+        let marker = MainThreadMarker::new().unwrap();
+        let vm = mac_os::create_vm(dir, config, marker)?;
+        mac_os_installer::install(vm, ipsw, dir.uninstalled_path.clone(), marker)
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use libc::pid_t;
+
+    use super::Platform;
+    use crate::config::vm_config::VmConfig;
+    use crate::config::vm_dir::VmDir;
+    use crate::util::exception::Exception;
+    use crate::vm::control::Response;
+
+    // host with 4 cpu and 8G memory, efi variable store is empty file
+    #[derive(Default)]
+    pub struct MockPlatform {
+        pub efi_variable_stores: RefCell<Vec<PathBuf>>,
+        // name of running vm to state reported by its runner, e.g. paused
+        pub running: HashMap<String, String>,
+        // names of running vms which don't stop in time
+        pub stuck: Vec<String>,
+        // vm lifecycle calls in order, e.g. "start debian: run debian", "stop debian", "wait debian"
+        pub calls: RefCell<Vec<String>>,
+    }
+
+    impl MockPlatform {
+        pub fn running(names: &[(&str, &str)]) -> Self {
+            MockPlatform {
+                running: names.iter().map(|(name, state)| (name.to_string(), state.to_string())).collect(),
+                ..MockPlatform::default()
+            }
+        }
+    }
+
+    impl Platform for MockPlatform {
+        fn create_efi_variable_store(&self, path: &Path) -> Result<(), Exception> {
+            fs::write(path, [])?;
+            self.efi_variable_stores.borrow_mut().push(path.to_path_buf());
+            Ok(())
+        }
+
+        fn random_mac_address(&self) -> String {
+            "02:00:00:00:00:01".to_string()
+        }
+
+        fn cpu_range(&self) -> (usize, usize) {
+            (1, 4)
+        }
+
+        fn memory_range(&self) -> (u64, u64) {
+            (128 * 1024 * 1024, 8 * 1024 * 1024 * 1024)
+        }
+
+        fn runner_pid(&self, dir: &VmDir) -> Option<pid_t> {
+            self.running.contains_key(&dir.name()).then_some(1000)
+        }
+
+        fn runner_status(&self, dir: &VmDir) -> Option<Response> {
+            self.running.get(&dir.name()).map(|state| Response {
+                ok: true,
+                error: None,
+                state: Some(state.clone()),
+                uptime: Some(60),
+                devices: None,
+            })
+        }
+
+        fn start(&self, dir: &VmDir, args: &[String]) -> Result<(), Exception> {
+            self.calls.borrow_mut().push(format!("start {}: {}", dir.name(), args.join(" ")));
+            Ok(())
+        }
+
+        fn request_stop(&self, dir: &VmDir, force: bool, _timeout: Duration) {
+            let force = if force { " --force" } else { "" };
+            self.calls.borrow_mut().push(format!("stop {}{force}", dir.name()));
+        }
+
+        fn wait_until_stopped(&self, dir: &VmDir, _seconds: u32) -> bool {
+            self.calls.borrow_mut().push(format!("wait {}", dir.name()));
+            !self.stuck.contains(&dir.name())
+        }
+
+        fn install(&self, dir: &VmDir, _config: &VmConfig, ipsw: &Path) -> Result<(), Exception> {
+            self.calls
+                .borrow_mut()
+                .push(format!("install {}: {}", dir.name(), ipsw.to_string_lossy()));
+            Ok(())
+        }
+    }
+}
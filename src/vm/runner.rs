@@ -0,0 +1,123 @@
+use std::fs::File;
+use std::mem;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+use std::process::Stdio;
+use std::ptr;
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::Instant;
+
+use libc::pid_t;
+use tracing::info;
+
+use crate::config::vm_dir::VmDir;
+use crate::util::exception::Exception;
+use crate::vm::control;
+use crate::vm::control::Request;
+
+// time for runner to exit after vm is force stopped
+pub const FORCE_STOP_TIMEOUT: u32 = 5;
+
+// launch runner in background with output to vz.log of vm dir, e.g. vz run <name>, return pid of runner
+pub fn spawn(executable: &Path, dir: &VmDir, args: &[String]) -> Result<u32, Exception> {
+    let log_path = &dir.log_path;
+    if let Ok(metadata) = log_path.metadata() {
+        if !metadata.is_file() || metadata.permissions().readonly() {
+            return Err(Exception::ValidationError(format!(
+                "log file is not writable, path={}",
+                log_path.to_string_lossy()
+            )));
+        }
+    }
+    let mut command = Command::new(executable);
+    command.args(args);
+    command.stdin(Stdio::null());
+    command.stdout(Stdio::from(File::options().create(true).append(true).open(log_path)?));
+    command.stderr(Stdio::from(File::options().create(true).append(true).open(log_path)?));
+    // new session without controlling terminal, runner keeps running after terminal is closed
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    let child = command.spawn()?;
+    info!(
+        "vm launched in background, name={}, pid={}, log={}",
+        dir.name(),
+        child.id(),
+        log_path.to_string_lossy()
+    );
+    Ok(child.id())
+}
+
+// runner takes vm lock shortly after launch, none if it didn't in time, e.g. failed to start
+pub fn wait_until_started(dir: &VmDir, timeout: Duration) -> Option<pid_t> {
+    let start = Instant::now();
+    loop {
+        if let Some(pid) = dir.pid() {
+            return Some(pid);
+        }
+        if start.elapsed() >= timeout {
+            return None;
+        }
+        sleep(Duration::from_millis(100));
+    }
+}
+
+// guest is asked to shut down within timeout, then vm is force stopped by runner
+pub fn request_stop(dir: &VmDir, force: bool, timeout: Duration) {
+    let Some(pid) = dir.pid() else {
+        return;
+    };
+    info!("stop vm, name={}, pid={pid}, force={force}", dir.name());
+    let request = if force {
+        Request::ForceStop
+    } else {
+        Request::Stop {
+            timeout: Some(timeout.as_secs()),
+        }
+    };
+    // runner without control socket, e.g. started by older version, is stopped by signal
+    if let Err(err) = control::send(dir, &request) {
+        info!("failed to request stop by control socket, stop by signal, error={err}");
+        unsafe {
+            libc::kill(pid, if force { libc::SIGKILL } else { libc::SIGINT });
+        }
+    }
+}
+
+// kqueue notifies exit of runner process, so it returns as soon as vm stopped
+pub fn wait_until_stopped(dir: &VmDir, seconds: u32) -> bool {
+    let Some(pid) = dir.pid() else {
+        return true;
+    };
+    unsafe {
+        let queue = libc::kqueue();
+        if queue < 0 {
+            return false;
+        }
+        let change = libc::kevent {
+            ident: pid as usize,
+            filter: libc::EVFILT_PROC,
+            flags: libc::EV_ADD | libc::EV_ONESHOT,
+            fflags: libc::NOTE_EXIT,
+            data: 0,
+            udata: ptr::null_mut(),
+        };
+        let mut event: libc::kevent = mem::zeroed();
+        let timeout = libc::timespec {
+            tv_sec: seconds as libc::time_t,
+            tv_nsec: 0,
+        };
+        let count = libc::kevent(queue, &change, 1, &mut event, 1, &timeout);
+        libc::close(queue);
+        // process exited before registered, EV_ERROR with ESRCH, or timeout, count is 0
+        if count > 0 && event.flags & libc::EV_ERROR == 0 {
+            return true;
+        }
+    }
+    dir.pid().is_none()
+}
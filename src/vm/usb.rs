@@ -0,0 +1,90 @@
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+
+use block2::StackBlock;
+use objc2::exception::catch;
+use objc2::msg_send;
+use objc2::msg_send_id;
+use objc2::rc::Allocated;
+use objc2::rc::Retained;
+use objc2::runtime::AnyClass;
+use objc2::runtime::AnyObject;
+use objc2::sel;
+use objc2::ClassType;
+use objc2_foundation::run_on_main;
+use objc2_foundation::MainThreadBound;
+use objc2_foundation::NSArray;
+use objc2_foundation::NSError;
+use objc2_virtualization::VZDiskImageStorageDeviceAttachment;
+use objc2_virtualization::VZUSBMassStorageDeviceConfiguration;
+use objc2_virtualization::VZVirtualMachine;
+use objc2_virtualization::VZVirtualMachineConfiguration;
+use tracing::info;
+
+use crate::util::exception::Exception;
+use crate::util::path::PathExtension;
+
+// usb controller and hot plug are added in macOS 15 and not in bindings, detected by class and selector so same binary runs on older macOS
+
+// xhci controller lets devices attach to running vm, e.g. vz usb attach, vm without it is same as before
+pub fn add_controller(vz_config: &VZVirtualMachineConfiguration) {
+    let Some(class) = AnyClass::get("VZXHCIControllerConfiguration") else {
+        return;
+    };
+    if !VZVirtualMachineConfiguration::class().responds_to(sel!(setUsbControllers:)) {
+        return;
+    }
+    unsafe {
+        let controller: Retained<AnyObject> = msg_send_id![class, new];
+        let controllers = NSArray::from_vec(vec![controller]);
+        let _: () = msg_send![vz_config, setUsbControllers: &*controllers];
+    }
+}
+
+// attach disk image as usb mass storage to running vm, it's detached when vm stops, must not be called on main thread
+pub fn attach(vm: &Arc<MainThreadBound<Retained<VZVirtualMachine>>>, image: PathBuf, read_only: bool) -> Result<(), Exception> {
+    let (tx, rx) = channel();
+    run_on_main(|marker| {
+        let completion = tx.clone();
+        let block = &StackBlock::new(move |err: *mut NSError| {
+            let result = if err.is_null() { Ok(()) } else { Err(Exception::from_ns_error(err)) };
+            let _ = completion.send(result);
+        });
+        let result = controller(vm.get(marker)).and_then(|controller| {
+            let device = mass_storage_device(&image, read_only)?;
+            info!("attach usb device, image={}, read_only={read_only}", image.to_string_lossy());
+            unsafe {
+                let _: () = msg_send![&controller, attachDevice: &*device, completionHandler: &**block];
+            }
+            Ok(())
+        });
+        if let Err(err) = result {
+            let _ = tx.send(Err(err));
+        }
+    });
+    rx.recv()?
+}
+
+fn controller(vm: &VZVirtualMachine) -> Result<Retained<AnyObject>, Exception> {
+    if !VZVirtualMachine::class().responds_to(sel!(usbControllers)) || AnyClass::get("VZUSBMassStorageDevice").is_none() {
+        return Err(Exception::ValidationError("usb hot plug requires macOS 15".to_string()));
+    }
+    let controllers: Retained<NSArray<AnyObject>> = unsafe { msg_send_id![vm, usbControllers] };
+    controllers
+        .get_retained(0)
+        .ok_or_else(|| Exception::ValidationError("vm has no usb controller, restart it on macOS 15 to add one".to_string()))
+}
+
+fn mass_storage_device(image: &Path, read_only: bool) -> Result<Retained<AnyObject>, Exception> {
+    unsafe {
+        let attachment = catch(|| {
+            VZDiskImageStorageDeviceAttachment::initWithURL_readOnly_error(VZDiskImageStorageDeviceAttachment::alloc(), &image.to_ns_url(), read_only)
+        })??;
+        let configuration = VZUSBMassStorageDeviceConfiguration::initWithAttachment(VZUSBMassStorageDeviceConfiguration::alloc(), &attachment);
+        let class = AnyClass::get("VZUSBMassStorageDevice").unwrap();
+        let device: Allocated<AnyObject> = msg_send_id![class, alloc];
+        Ok(msg_send_id![device, initWithConfiguration: &*configuration])
+    }
+}
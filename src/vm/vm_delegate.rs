@@ -1,5 +1,3 @@
-use std::process;
-
 use objc2::declare_class;
 use objc2::msg_send_id;
 use objc2::mutability;
@@ -15,6 +13,11 @@ use objc2_virtualization::VZVirtualMachineDelegate;
 use tracing::error;
 use tracing::info;
 
+use crate::util::notification;
+use crate::util::os_log;
+use crate::util::otlp;
+use crate::vm;
+
 declare_class!(
     pub struct VmDelegate;
 
@@ -32,20 +35,29 @@ declare_class!(
     unsafe impl VZVirtualMachineDelegate for VmDelegate {
         #[method(guestDidStopVirtualMachine:)]
         fn guest_did_stop_virtual_machine(&self, _: &VZVirtualMachine) {
+            // deliberate poweroff in guest is normal stop, not notified
             info!("guest has stopped the vm");
-            process::exit(0);
+            os_log::info("guest has stopped the vm");
+            vm::exit(0);
         }
 
         #[method(virtualMachine:didStopWithError:)]
         fn virtual_machine_did_stop_with_error(&self, _: &VZVirtualMachine, err: &NSError) {
-            error!("guest has stopped the vm due to error, error={}", err.localizedDescription());
-            process::exit(1);
+            let message = format!("guest has stopped the vm due to error, error={}", err.localizedDescription());
+            error!("{message}");
+            os_log::error(&message);
+            notification::notify(&message);
+            otlp::count("vz.vm.crashes");
+            vm::exit(1);
         }
 
         #[method(virtualMachine:networkDevice:attachmentWasDisconnectedWithError:)]
         fn virtual_machine_network_device_attachment_was_disconnected_with_error(&self, _: &VZVirtualMachine, network_device: &VZNetworkDevice, err: &NSError) {
-            error!("vm network disconnected, device={network_device:?}, error={}", err.localizedDescription());
-            process::exit(1);
+            let message = format!("vm network disconnected, device={network_device:?}, error={}", err.localizedDescription());
+            error!("{message}");
+            os_log::error(&message);
+            notification::notify(&message);
+            vm::exit(1);
         }
     }
 );
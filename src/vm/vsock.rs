@@ -0,0 +1,248 @@
+use std::fs;
+use std::io;
+use std::mem;
+use std::net::Shutdown;
+use std::os::fd::FromRawFd;
+use std::os::fd::RawFd;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+
+use block2::StackBlock;
+use objc2::declare_class;
+use objc2::msg_send_id;
+use objc2::mutability;
+use objc2::rc::Id;
+use objc2::rc::Retained;
+use objc2::runtime::ProtocolObject;
+use objc2::ClassType;
+use objc2::DeclaredClass;
+use objc2_foundation::run_on_main;
+use objc2_foundation::MainThreadBound;
+use objc2_foundation::NSError;
+use objc2_foundation::NSObject;
+use objc2_foundation::NSObjectProtocol;
+use objc2_virtualization::VZSocketDeviceConfiguration;
+use objc2_virtualization::VZVirtioSocketConnection;
+use objc2_virtualization::VZVirtioSocketDevice;
+use objc2_virtualization::VZVirtioSocketDeviceConfiguration;
+use objc2_virtualization::VZVirtioSocketListener;
+use objc2_virtualization::VZVirtioSocketListenerDelegate;
+use objc2_virtualization::VZVirtualMachine;
+use tracing::error;
+use tracing::info;
+
+use crate::config::vm_config::VsockSocket;
+use crate::util::exception::Exception;
+
+pub fn socket_device() -> Retained<VZSocketDeviceConfiguration> {
+    unsafe { Id::into_super(VZVirtioSocketDeviceConfiguration::new()) }
+}
+
+// run in guest, listen on vsock port for connections of host, e.g. by agents of vz in macOS guest
+pub fn listen_in_guest(port: u32) -> Result<RawFd, Exception> {
+    unsafe {
+        let fd = libc::socket(libc::AF_VSOCK, libc::SOCK_STREAM, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let mut address: libc::sockaddr_vm = mem::zeroed();
+        address.svm_family = libc::AF_VSOCK as libc::sa_family_t;
+        address.svm_port = port;
+        address.svm_cid = libc::VMADDR_CID_ANY;
+        let length = mem::size_of::<libc::sockaddr_vm>() as libc::socklen_t;
+        if libc::bind(fd, &address as *const _ as *const libc::sockaddr, length) != 0 || libc::listen(fd, 1) != 0 {
+            let err = io::Error::last_os_error();
+            libc::close(fd);
+            return Err(err.into());
+        }
+        Ok(fd)
+    }
+}
+
+pub fn accept_in_guest(listener: RawFd) -> Result<UnixStream, Exception> {
+    let fd = unsafe { libc::accept(listener, ptr::null_mut(), ptr::null_mut()) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(unsafe { UnixStream::from_raw_fd(fd) })
+}
+
+// listen on host unix socket, and connect to guest vsock port for each accepted connection
+pub fn forward(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>, forward: &VsockSocket) -> Result<(), Exception> {
+    let port = forward.port;
+    let path = forward.socket_path()?;
+    if path.exists() {
+        fs::remove_file(&path)?;
+    }
+    let listener = UnixListener::bind(&path)?;
+    info!("forward vsock, port={port}, socket={}", path.to_string_lossy());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => connect(Arc::clone(&vm), port, Arc::new(stream)),
+                Err(err) => error!("failed to accept vsock forward connection, port={port}, error={err}"),
+            }
+        }
+    });
+    Ok(())
+}
+
+pub fn connect(vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>, port: u32, host: Arc<UnixStream>) {
+    run_on_main(move |marker| {
+        let vm = vm.get(marker);
+        let Some(device) = (unsafe { vm.socketDevices() }).get_retained(0) else {
+            error!("vm has no socket device");
+            return;
+        };
+        let device: Retained<VZVirtioSocketDevice> = unsafe { Id::cast(device) };
+        let block = &StackBlock::new(move |connection: *mut VZVirtioSocketConnection, err: *mut NSError| {
+            if !err.is_null() {
+                error!("failed to connect guest vsock, port={port}, error={}", unsafe {
+                    (*err).localizedDescription()
+                });
+                let _ = host.shutdown(Shutdown::Both);
+                return;
+            }
+            // connection closes its fd once released
+            let guest = unsafe { UnixStream::from_raw_fd(libc::dup((*connection).fileDescriptor())) };
+            proxy(Arc::clone(&host), Arc::new(guest));
+        });
+        unsafe {
+            device.connectToPort_completionHandler(port, block);
+        }
+    });
+}
+
+// listen on guest vsock port, and connect to host unix socket for each accepted connection
+pub fn expose(vm: &VZVirtualMachine, expose: &VsockSocket) -> Result<Retained<VsockListenerDelegate>, Exception> {
+    let port = expose.port;
+    let socket = expose.socket_path()?;
+    let device = unsafe { vm.socketDevices() }
+        .get_retained(0)
+        .ok_or_else(|| Exception::ValidationError("vm has no socket device".to_string()))?;
+    let device: Retained<VZVirtioSocketDevice> = unsafe { Id::cast(device) };
+    info!("expose vsock, port={port}, socket={}", socket.to_string_lossy());
+    let delegate = VsockListenerDelegate::new(Target::Socket(socket));
+    unsafe {
+        let listener = VZVirtioSocketListener::new();
+        listener.setDelegate(Some(ProtocolObject::from_ref(&*delegate)));
+        device.setSocketListener_forPort(&listener, port);
+    }
+    // listener holds weak reference of delegate, caller must keep it
+    Ok(delegate)
+}
+
+// listen on guest vsock port until first connection, and bridge it to host stream, e.g. stdio of vz vsock listen,
+// ready is called before listening, so guest data never precedes it
+pub fn listen(
+    vm: Arc<MainThreadBound<Retained<VZVirtualMachine>>>,
+    port: u32,
+    host: UnixStream,
+    ready: impl FnOnce(&UnixStream) -> io::Result<()> + Send,
+) -> Result<(), String> {
+    run_on_main(move |marker| {
+        let vm = vm.get(marker);
+        let Some(device) = (unsafe { vm.socketDevices() }).get_retained(0) else {
+            return Err("vm has no socket device".to_string());
+        };
+        let device: Retained<VZVirtioSocketDevice> = unsafe { Id::cast(device) };
+        ready(&host).map_err(|err| format!("failed to respond, error={err}"))?;
+        info!("listen on guest vsock, port={port}");
+        let delegate = VsockListenerDelegate::new(Target::Stream {
+            port,
+            stream: Mutex::new(Some(host)),
+        });
+        unsafe {
+            let listener = VZVirtioSocketListener::new();
+            listener.setDelegate(Some(ProtocolObject::from_ref(&*delegate)));
+            device.setSocketListener_forPort(&listener, port);
+        }
+        // listener holds weak reference of delegate, it's small and kept for life of runner
+        mem::forget(delegate);
+        Ok(())
+    })
+}
+
+enum Target {
+    // connect host unix socket for each guest connection
+    Socket(PathBuf),
+    // bridge first guest connection to stream, then stop listening
+    Stream { port: u32, stream: Mutex<Option<UnixStream>> },
+}
+
+pub struct Ivars {
+    target: Target,
+}
+
+declare_class!(
+    pub struct VsockListenerDelegate;
+
+    unsafe impl ClassType for VsockListenerDelegate {
+        type Super = NSObject;
+        type Mutability = mutability::Immutable;
+        const NAME: &'static str = "VsockListenerDelegate";
+    }
+
+    impl DeclaredClass for VsockListenerDelegate {
+        type Ivars = Ivars;
+    }
+
+    unsafe impl NSObjectProtocol for VsockListenerDelegate {}
+
+    unsafe impl VZVirtioSocketListenerDelegate for VsockListenerDelegate {
+        #[method(listener:shouldAcceptNewConnection:fromSocketDevice:)]
+        fn should_accept_new_connection(&self, _: &VZVirtioSocketListener, connection: &VZVirtioSocketConnection, device: &VZVirtioSocketDevice) -> bool {
+            self.accept(connection, device)
+        }
+    }
+);
+
+impl VsockListenerDelegate {
+    fn new(target: Target) -> Retained<Self> {
+        let this = Self::alloc().set_ivars(Ivars { target });
+        unsafe { msg_send_id![super(this), init] }
+    }
+
+    fn accept(&self, connection: &VZVirtioSocketConnection, device: &VZVirtioSocketDevice) -> bool {
+        // connection closes its fd once released
+        let guest = || unsafe { UnixStream::from_raw_fd(libc::dup(connection.fileDescriptor())) };
+        match &self.ivars().target {
+            Target::Socket(socket) => match UnixStream::connect(socket) {
+                Ok(host) => {
+                    proxy(Arc::new(host), Arc::new(guest()));
+                    true
+                }
+                Err(err) => {
+                    error!("failed to connect host socket, socket={}, error={err}", socket.to_string_lossy());
+                    false
+                }
+            },
+            Target::Stream { port, stream } => {
+                let Some(host) = stream.lock().unwrap().take() else {
+                    return false;
+                };
+                unsafe { device.removeSocketListenerForPort(*port) };
+                info!("accept guest vsock connection, port={port}");
+                proxy(Arc::new(host), Arc::new(guest()));
+                true
+            }
+        }
+    }
+}
+
+fn proxy(host: Arc<UnixStream>, guest: Arc<UnixStream>) {
+    copy(Arc::clone(&host), Arc::clone(&guest));
+    copy(guest, host);
+}
+
+fn copy(from: Arc<UnixStream>, to: Arc<UnixStream>) {
+    thread::spawn(move || {
+        let _ = io::copy(&mut from.as_ref(), &mut to.as_ref());
+        let _ = to.shutdown(Shutdown::Write);
+    });
+}